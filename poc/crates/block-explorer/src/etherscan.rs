@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use ethers::{abi::Abi, addressbook::Chain, etherscan::Client, types::H160};
 
 use crate::blockexplorerapi::BlockExplorerApi;
@@ -26,11 +26,15 @@ impl BlockExplorerApi for EtherscanBlockExplorer {
         match abi {
             Ok(abi) => Ok(abi),
             Err(_) => {
-                let etherscan_api_key =
-                    dotenv::var("ETHERSCAN_API_KEY").expect("ETHERSCAN_API_KEY missing");
+                let etherscan_api_key = dotenv::var("ETHERSCAN_API_KEY").map_err(|_| {
+                    anyhow!(
+                        "ABI of {:?} isn't cached and ETHERSCAN_API_KEY is missing",
+                        contract_address
+                    )
+                })?;
                 // Fetch the ABI from Etherscan
                 let etherscan = Client::new(Chain::Mainnet, etherscan_api_key)
-                    .expect("Could not create etherscan client");
+                    .context("Could not create etherscan client")?;
 
                 // let rt = tokio::runtime::Runtime::new()?;
                 // let abi = rt.block_on(etherscan.contract_abi(contract_address))?;
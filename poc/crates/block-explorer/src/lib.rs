@@ -46,3 +46,35 @@ pub fn cache_abi(cache_path: PathBuf, contract_address: H160, abi: Abi) -> Resul
 
     Ok(())
 }
+
+/// Delete cached ABIs older than `cache_ttl` seconds
+///
+/// Returns how many ABIs were deleted, or with `dry_run` set how many would have been.
+///
+/// # Errors
+///
+/// This function will return an error if the cache directory could not be read or cleaned
+pub fn expire_cache(cache_path: PathBuf, cache_ttl: usize, dry_run: bool) -> Result<u64> {
+    if !cache_path.exists() {
+        return Ok(0);
+    }
+
+    let mut expired = 0;
+    for entry in std::fs::read_dir(cache_path)? {
+        let path = entry?.path();
+        if path.extension() != Some(std::ffi::OsStr::new("json")) {
+            continue;
+        }
+
+        let elapsed = std::fs::metadata(path.clone())?.modified()?.elapsed()?;
+        if elapsed.as_secs() >= cache_ttl as u64 {
+            debug!("Expiring cached ABI {:?}", path);
+            if !dry_run {
+                std::fs::remove_file(path)?;
+            }
+            expired += 1;
+        }
+    }
+
+    Ok(expired)
+}
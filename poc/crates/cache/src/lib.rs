@@ -8,8 +8,10 @@ use log::info;
 use tokio::sync::{broadcast::Receiver, Mutex};
 use tx_caching::TxCaching;
 
+pub mod memory;
 #[cfg(feature = "redis")]
 pub mod redis;
+pub mod token_caching;
 pub mod tx_caching;
 
 pub async fn tx_cache_updates<C: TxCaching>(
@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use dex::token::TokenMetadata;
+use ethers::types::{Address, Transaction, H256};
+use log::debug;
+
+use crate::token_caching::TokenCaching;
+use crate::tx_caching::TxCaching;
+
+/// A tx cache kept in process memory
+///
+/// Clones share the same entries, so a cache handed to a task can be inspected from a test.
+/// Like Redis, entries older than the TTL are gone: they are never reported as cached and are
+/// removed the next time the cache is touched. `MemoryCache::shared` returns the process wide
+/// cache used in single-process dev mode.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryCache {
+    entries: Arc<Mutex<HashMap<H256, Instant>>>,
+    /// How long cached txs are kept, `None` to keep them until deleted
+    ttl: Option<Duration>,
+}
+
+impl MemoryCache {
+    pub fn new(ttl: Option<Duration>) -> Self {
+        Self {
+            entries: Arc::default(),
+            ttl,
+        }
+    }
+
+    /// The cache shared by the whole process
+    ///
+    /// The TTL is the one given on first use.
+    pub fn shared(ttl: Option<Duration>) -> Self {
+        static SHARED: OnceLock<MemoryCache> = OnceLock::new();
+
+        SHARED.get_or_init(|| MemoryCache::new(ttl)).clone()
+    }
+
+    fn entries(&self) -> MutexGuard<'_, HashMap<H256, Instant>> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn is_live(&self, cached_at: Instant, now: Instant) -> bool {
+        self.ttl
+            .is_none_or(|ttl| now.saturating_duration_since(cached_at) < ttl)
+    }
+
+    /// Remove the expired entries, returning how many there were
+    pub fn purge_expired(&self) -> usize {
+        let now = Instant::now();
+        let mut entries = self.entries();
+        let before = entries.len();
+
+        entries.retain(|_, cached_at| self.is_live(*cached_at, now));
+
+        before - entries.len()
+    }
+
+    /// Number of live entries
+    pub fn len(&self) -> usize {
+        self.purge_expired();
+        self.entries().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Hashes of the live entries, in no particular order
+    pub fn hashes(&self) -> Vec<H256> {
+        self.purge_expired();
+        self.entries().keys().copied().collect()
+    }
+
+    /// Time left before a cached tx expires, `None` if it isn't cached or never expires
+    pub fn expires_in(&self, hash: H256) -> Option<Duration> {
+        let cached_at = *self.entries().get(&hash)?;
+        let ttl = self.ttl?;
+
+        ttl.checked_sub(cached_at.elapsed())
+    }
+
+    /// Remove every entry
+    pub fn clear(&self) {
+        self.entries().clear();
+    }
+}
+
+impl TxCaching for MemoryCache {
+    async fn cache(&mut self, tx: Transaction) -> Result<()> {
+        self.purge_expired();
+        // Caching again restarts the TTL, like SETEX
+        self.entries().insert(tx.hash, Instant::now());
+
+        debug!("Cached tx: {:#?}", tx.hash);
+
+        Ok(())
+    }
+
+    async fn is_cached(&mut self, tx: Transaction) -> Result<bool> {
+        let now = Instant::now();
+
+        Ok(self
+            .entries()
+            .get(&tx.hash)
+            .is_some_and(|cached_at| self.is_live(*cached_at, now)))
+    }
+
+    async fn delete(&mut self, tx: Transaction) -> Result<()> {
+        self.entries().remove(&tx.hash);
+
+        Ok(())
+    }
+}
+
+/// A token metadata cache kept in process memory
+///
+/// Clones share the same entries. Entries are never expired, stale metadata is refreshed by
+/// the resolver.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryTokenCache {
+    entries: Arc<Mutex<HashMap<Address, TokenMetadata>>>,
+}
+
+impl MemoryTokenCache {
+    /// The cache shared by the whole process
+    pub fn shared() -> Self {
+        static SHARED: OnceLock<MemoryTokenCache> = OnceLock::new();
+
+        SHARED.get_or_init(MemoryTokenCache::default).clone()
+    }
+
+    fn entries(&self) -> MutexGuard<'_, HashMap<Address, TokenMetadata>> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl TokenCaching for MemoryTokenCache {
+    async fn cached_token(&mut self, address: Address) -> Result<Option<TokenMetadata>> {
+        Ok(self.entries().get(&address).cloned())
+    }
+
+    async fn cache_token(&mut self, token: &TokenMetadata) -> Result<()> {
+        self.entries().insert(token.address, token.clone());
+
+        Ok(())
+    }
+}
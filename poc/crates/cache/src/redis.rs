@@ -2,30 +2,35 @@ use regex::Regex;
 
 use std::{
     sync::Arc,
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use tokio::sync::Mutex;
 
 use anyhow::{Error, Result};
-use ethers::types::Transaction;
+use dex::token::TokenMetadata;
+use ethers::types::{Address, Transaction};
 use log::{debug, warn};
 use redis::{Commands, ConnectionAddr};
 use redis::{ConnectionInfo, RedisConnectionInfo};
 
+use crate::token_caching::TokenCaching;
 use crate::tx_caching::TxCaching;
 
 pub struct TxCacheRedis {
     redis_connection: Arc<Mutex<redis::Client>>,
+    /// How long cached txs are kept, `None` to keep them until deleted
+    ttl: Option<Duration>,
 }
 
 impl TxCacheRedis {
-    pub fn new(config: settings::Redis) -> Self {
+    pub fn new(config: settings::Redis, ttl: Option<Duration>) -> Self {
         let connection = get_connection(&config);
 
         log::info!("Connecting to Redis at {}", config.url);
 
         Self {
             redis_connection: Arc::new(Mutex::new(connection)),
+            ttl,
         }
     }
 }
@@ -76,7 +81,14 @@ impl TxCaching for TxCacheRedis {
         let tx_hash = format!("{:#?}", tx.hash).to_string();
         let mut conn = self.redis_connection.lock().await;
 
-        if let Err(e) = conn.set_ex::<String, u64, bool>(tx_hash.clone(), now, 60 * 60 * 24) {
+        let cached = match self.ttl {
+            Some(ttl) => {
+                conn.set_ex::<String, u64, bool>(tx_hash.clone(), now, ttl.as_secs() as usize)
+            }
+            None => conn.set::<String, u64, bool>(tx_hash.clone(), now),
+        };
+
+        if let Err(e) = cached {
             warn!("Failed to cache tx: {}", e);
             return Err(Error::msg(e.to_string()));
         } else {
@@ -109,3 +121,52 @@ impl TxCaching for TxCacheRedis {
         Ok(())
     }
 }
+
+/// Token metadata cached in Redis as JSON, under `token:<address>`
+///
+/// Entries don't expire, stale metadata is refreshed by the resolver.
+pub struct TokenCacheRedis {
+    redis_connection: Arc<Mutex<redis::Client>>,
+}
+
+impl TokenCacheRedis {
+    pub fn new(config: settings::Redis) -> Self {
+        let connection = get_connection(&config);
+
+        log::info!("Caching tokens in Redis at {}", config.url);
+
+        Self {
+            redis_connection: Arc::new(Mutex::new(connection)),
+        }
+    }
+}
+
+fn token_key(address: Address) -> String {
+    format!("token:{:#x}", address)
+}
+
+impl TokenCaching for TokenCacheRedis {
+    async fn cached_token(&mut self, address: Address) -> Result<Option<TokenMetadata>> {
+        let mut conn = self.redis_connection.lock().await;
+
+        match conn.get::<String, Option<String>>(token_key(address)) {
+            Ok(Some(json)) => Ok(Some(serde_json::from_str(&json)?)),
+            Ok(None) => Ok(None),
+            Err(e) => Err(Error::msg(e.to_string())),
+        }
+    }
+
+    async fn cache_token(&mut self, token: &TokenMetadata) -> Result<()> {
+        let json = serde_json::to_string(token)?;
+        let mut conn = self.redis_connection.lock().await;
+
+        if let Err(e) = conn.set::<String, String, bool>(token_key(token.address), json) {
+            warn!("Failed to cache token: {}", e);
+            return Err(Error::msg(e.to_string()));
+        }
+
+        debug!("Cached token: {:?}", token.address);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,66 @@
+use anyhow::Result;
+use dex::token::TokenMetadata;
+use ethers::types::Address;
+
+use crate::memory::MemoryTokenCache;
+#[cfg(feature = "redis")]
+use crate::redis::TokenCacheRedis;
+
+pub trait TokenCaching {
+    /// Cached metadata of a token, however old it is
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the cache could not be read
+    async fn cached_token(&mut self, address: Address) -> Result<Option<TokenMetadata>>;
+
+    /// Cache the metadata of a token, replacing what was cached
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the metadata could not be cached
+    async fn cache_token(&mut self, token: &TokenMetadata) -> Result<()>;
+}
+
+/// The token cache of the configured backend
+pub enum TokenCacheEngine {
+    #[cfg(feature = "redis")]
+    Redis(TokenCacheRedis),
+    Memory(MemoryTokenCache),
+}
+
+impl TokenCacheEngine {
+    pub fn new(backend: settings::CacheBackend, redis: &settings::Redis) -> Self {
+        match backend {
+            #[cfg(feature = "redis")]
+            settings::CacheBackend::Redis => Self::Redis(TokenCacheRedis::new(redis.clone())),
+            #[cfg(not(feature = "redis"))]
+            settings::CacheBackend::Redis => {
+                log::warn!(
+                    "Redis support isn't built in, caching tokens in memory instead of {}",
+                    redis.url
+                );
+                Self::Memory(MemoryTokenCache::shared())
+            }
+            settings::CacheBackend::Memory => Self::Memory(MemoryTokenCache::shared()),
+        }
+    }
+}
+
+impl TokenCaching for TokenCacheEngine {
+    async fn cached_token(&mut self, address: Address) -> Result<Option<TokenMetadata>> {
+        match self {
+            #[cfg(feature = "redis")]
+            Self::Redis(cache) => cache.cached_token(address).await,
+            Self::Memory(cache) => cache.cached_token(address).await,
+        }
+    }
+
+    async fn cache_token(&mut self, token: &TokenMetadata) -> Result<()> {
+        match self {
+            #[cfg(feature = "redis")]
+            Self::Redis(cache) => cache.cache_token(token).await,
+            Self::Memory(cache) => cache.cache_token(token).await,
+        }
+    }
+}
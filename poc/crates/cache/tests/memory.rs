@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+use cache::memory::{MemoryCache, MemoryTokenCache};
+use cache::token_caching::TokenCaching;
+use cache::tx_caching::TxCaching;
+use dex::token::TokenMetadata;
+use ethers::types::{Address, Transaction, H256, U256};
+
+const TTL: Duration = Duration::from_secs(1);
+
+fn tx(byte: u8) -> Transaction {
+    Transaction {
+        hash: H256::repeat_byte(byte),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn cached_txs_expire_after_the_ttl() {
+    let mut cache = MemoryCache::new(Some(TTL));
+    cache.cache(tx(1)).await.unwrap();
+    tokio::time::sleep(TTL / 2).await;
+    cache.cache(tx(2)).await.unwrap();
+
+    // Clones share the entries
+    let mut other = cache.clone();
+    assert!(other.is_cached(tx(1)).await.unwrap());
+    assert!(other.expires_in(tx(1).hash).unwrap() <= TTL / 2);
+    assert_eq!(other.len(), 2);
+
+    // Only the tx cached first outlived the TTL
+    tokio::time::sleep(TTL * 3 / 4).await;
+    assert!(!cache.is_cached(tx(1)).await.unwrap());
+    assert!(cache.is_cached(tx(2)).await.unwrap());
+    assert_eq!(cache.hashes(), vec![tx(2).hash]);
+
+    // Caching again restarts the TTL
+    cache.cache(tx(2)).await.unwrap();
+    assert!(cache.expires_in(tx(2).hash).unwrap() > TTL / 2);
+
+    cache.delete(tx(2)).await.unwrap();
+    assert!(cache.is_empty());
+}
+
+#[tokio::test]
+async fn cached_txs_without_ttl_are_kept() {
+    let mut cache = MemoryCache::new(None);
+    cache.cache(tx(1)).await.unwrap();
+
+    assert!(cache.is_cached(tx(1)).await.unwrap());
+    assert_eq!(cache.expires_in(tx(1).hash), None);
+    assert_eq!(cache.purge_expired(), 0);
+}
+
+#[tokio::test]
+async fn cached_tokens_are_replaced() {
+    let mut cache = MemoryTokenCache::default();
+    let address = Address::repeat_byte(1);
+    let token = TokenMetadata {
+        address,
+        name: Some(String::from("Wrapped Ether")),
+        symbol: Some(String::from("WETH")),
+        decimals: Some(18),
+        total_supply: Some(U256::exp10(24)),
+        fetched_at: 1_700_000_000,
+    };
+
+    assert_eq!(cache.cached_token(address).await.unwrap(), None);
+    cache.cache_token(&token).await.unwrap();
+    let refreshed = TokenMetadata {
+        total_supply: Some(U256::exp10(25)),
+        fetched_at: 1_700_003_600,
+        ..token
+    };
+    cache.cache_token(&refreshed).await.unwrap();
+
+    assert_eq!(cache.cached_token(address).await.unwrap(), Some(refreshed));
+    assert_eq!(cache.len(), 1);
+}
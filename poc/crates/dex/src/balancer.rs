@@ -0,0 +1,178 @@
+use ethers::abi::AbiDecode;
+use ethers::contract::parse_log;
+use ethers::types::{Address, Log, Transaction, I256, U256};
+use log::trace;
+
+use crate::bindings::balancer_v2_vault::{
+    BalancerV2VaultCalls, BatchSwapCall, BatchSwapStep, SwapCall, SwapFilter,
+};
+use crate::protocol::{ProtocolAction, ProtocolDecoder};
+use crate::swap::{clamp_deadline, swap_record, SwapKind, SwapRecord};
+
+/// Version recorded with the swaps of the Vault
+pub const VERSION: u8 = 2;
+
+/// `SwapKind` of the Vault whose amounts are the amounts in
+const GIVEN_IN: u8 = 0;
+
+/// Balancer V2, whose pools all trade through a single Vault
+///
+/// Pools are identified by the `poolId` of the calls and logs, ETH is the zero address as in
+/// the Vault.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BalancerV2 {
+    pub name: String,
+    pub vault: Address,
+}
+
+impl BalancerV2 {
+    pub fn new(name: String, vault: Address) -> Self {
+        Self { name, vault }
+    }
+
+    /// Swap of a `swap` call, through a single pool
+    fn single_swap(&self, tx: &Transaction, call: SwapCall) -> SwapRecord {
+        let swap = call.single_swap;
+        let (kind, amount_in, amount_out) = if swap.kind == GIVEN_IN {
+            (SwapKind::ExactIn, swap.amount, call.limit)
+        } else {
+            (SwapKind::ExactOut, call.limit, swap.amount)
+        };
+
+        SwapRecord {
+            recipient: Some(call.funds.recipient),
+            path: vec![swap.asset_in, swap.asset_out],
+            kind,
+            amount_in,
+            amount_out,
+            deadline: Some(clamp_deadline(call.deadline)),
+            ..swap_record(&self.name, VERSION, tx, "swap")
+        }
+    }
+
+    /// Swaps of a `batchSwap` call, one per route
+    ///
+    /// Steps with a zero amount trade the output of the previous step, or for exact output
+    /// batches whose steps go from the token out, its input. Chained steps are one multi-hop
+    /// swap. The limits of the batch are per asset, they are the limits of the swaps of the
+    /// assets they trade.
+    fn batch_swaps(&self, tx: &Transaction, call: BatchSwapCall) -> Vec<SwapRecord> {
+        let given_in = call.kind == GIVEN_IN;
+        let asset = |index: U256| {
+            (index < U256::from(call.assets.len())).then(|| call.assets[index.as_usize()])
+        };
+        let limit = |asset: Address| {
+            let index = call.assets.iter().position(|a| *a == asset)?;
+            call.limits.get(index).copied()
+        };
+
+        let mut routes: Vec<(Vec<Address>, U256)> = Vec::new();
+        for BatchSwapStep {
+            asset_in_index,
+            asset_out_index,
+            amount,
+            ..
+        } in &call.swaps
+        {
+            let (Some(asset_in), Some(asset_out)) =
+                (asset(*asset_in_index), asset(*asset_out_index))
+            else {
+                trace!("Batch swap of tx {:?} has an unknown asset", tx.hash);
+                return Vec::new();
+            };
+
+            match routes.last_mut() {
+                Some((path, _))
+                    if amount.is_zero() && given_in && path.last() == Some(&asset_in) =>
+                {
+                    path.push(asset_out)
+                }
+                Some((path, _))
+                    if amount.is_zero() && !given_in && path.first() == Some(&asset_out) =>
+                {
+                    path.insert(0, asset_in)
+                }
+                _ => routes.push((vec![asset_in, asset_out], *amount)),
+            }
+        }
+
+        routes
+            .into_iter()
+            .map(|(path, amount)| {
+                let (kind, amount_in, amount_out) = if given_in {
+                    // Limits of the assets received are negative
+                    let min_out = path
+                        .last()
+                        .and_then(|asset| limit(*asset))
+                        .filter(|limit| limit.is_negative())
+                        .map(I256::unsigned_abs);
+                    (SwapKind::ExactIn, amount, min_out.unwrap_or_default())
+                } else {
+                    let max_in = path
+                        .first()
+                        .and_then(|asset| limit(*asset))
+                        .filter(|limit| limit.is_positive())
+                        .map(I256::into_raw);
+                    (SwapKind::ExactOut, max_in.unwrap_or_default(), amount)
+                };
+
+                SwapRecord {
+                    recipient: Some(call.funds.recipient),
+                    path,
+                    kind,
+                    amount_in,
+                    amount_out,
+                    deadline: Some(clamp_deadline(call.deadline)),
+                    ..swap_record(&self.name, VERSION, tx, "batchSwap")
+                }
+            })
+            .collect()
+    }
+}
+
+impl ProtocolDecoder for BalancerV2 {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn matches(&self, address: Address) -> bool {
+        address == self.vault
+    }
+
+    fn decode_calldata(&self, tx: &Transaction) -> Vec<ProtocolAction> {
+        if tx.to != Some(self.vault) {
+            return Vec::new();
+        }
+
+        let mut swaps = match BalancerV2VaultCalls::decode(&tx.input) {
+            Ok(BalancerV2VaultCalls::Swap(call)) => vec![self.single_swap(tx, call)],
+            Ok(BalancerV2VaultCalls::BatchSwap(call)) => self.batch_swaps(tx, call),
+            Ok(_) => Vec::new(),
+            Err(e) => {
+                trace!("Failed to decode Vault call of tx {:?}: {}", tx.hash, e);
+                Vec::new()
+            }
+        };
+        for (index, swap) in swaps.iter_mut().enumerate() {
+            swap.swap_index = index as u32;
+        }
+
+        swaps.into_iter().map(ProtocolAction::Swap).collect()
+    }
+
+    /// Swap of a `Swap` log of the Vault, with the amounts actually traded
+    fn decode_log(&self, tx: &Transaction, log: &Log) -> Option<ProtocolAction> {
+        if log.address != self.vault {
+            return None;
+        }
+        let swap = parse_log::<SwapFilter>(log.clone()).ok()?;
+
+        Some(ProtocolAction::Swap(SwapRecord {
+            path: vec![swap.token_in, swap.token_out],
+            kind: SwapKind::ExactIn,
+            amount_in: swap.amount_in,
+            amount_out: swap.amount_out,
+            ..swap_record(&self.name, VERSION, tx, "Swap")
+        }))
+    }
+}
@@ -0,0 +1,297 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use block_explorer::blockexplorerapi::BlockExplorerApi;
+use ethers::abi::Abi;
+use ethers::contract::abigen;
+use ethers::types::Address;
+use serde::Deserialize;
+
+abigen!(
+    UniswapV2Factory,
+    r#"[
+        event PairCreated(address indexed token0, address indexed token1, address pair, uint256)
+        function allPairs(uint256) external view returns (address pair)
+        function allPairsLength() external view returns (uint256)
+        function createPair(address tokenA, address tokenB) external returns (address pair)
+        function feeTo() external view returns (address)
+        function feeToSetter() external view returns (address)
+        function getPair(address tokenA, address tokenB) external view returns (address pair)
+        function setFeeTo(address _feeTo) external
+        function setFeeToSetter(address _feeToSetter) external
+    ]"#
+);
+
+abigen!(
+    UniswapV2Router02,
+    r#"[
+        function WETH() external pure returns (address)
+        function factory() external pure returns (address)
+        function addLiquidity(address tokenA, address tokenB, uint256 amountADesired, uint256 amountBDesired, uint256 amountAMin, uint256 amountBMin, address to, uint256 deadline) external returns (uint256 amountA, uint256 amountB, uint256 liquidity)
+        function addLiquidityETH(address token, uint256 amountTokenDesired, uint256 amountTokenMin, uint256 amountETHMin, address to, uint256 deadline) external payable returns (uint256 amountToken, uint256 amountETH, uint256 liquidity)
+        function removeLiquidity(address tokenA, address tokenB, uint256 liquidity, uint256 amountAMin, uint256 amountBMin, address to, uint256 deadline) external returns (uint256 amountA, uint256 amountB)
+        function removeLiquidityETH(address token, uint256 liquidity, uint256 amountTokenMin, uint256 amountETHMin, address to, uint256 deadline) external returns (uint256 amountToken, uint256 amountETH)
+        function removeLiquidityWithPermit(address tokenA, address tokenB, uint256 liquidity, uint256 amountAMin, uint256 amountBMin, address to, uint256 deadline, bool approveMax, uint8 v, bytes32 r, bytes32 s) external returns (uint256 amountA, uint256 amountB)
+        function removeLiquidityETHWithPermit(address token, uint256 liquidity, uint256 amountTokenMin, uint256 amountETHMin, address to, uint256 deadline, bool approveMax, uint8 v, bytes32 r, bytes32 s) external returns (uint256 amountToken, uint256 amountETH)
+        function removeLiquidityETHSupportingFeeOnTransferTokens(address token, uint256 liquidity, uint256 amountTokenMin, uint256 amountETHMin, address to, uint256 deadline) external returns (uint256 amountETH)
+        function removeLiquidityETHWithPermitSupportingFeeOnTransferTokens(address token, uint256 liquidity, uint256 amountTokenMin, uint256 amountETHMin, address to, uint256 deadline, bool approveMax, uint8 v, bytes32 r, bytes32 s) external returns (uint256 amountETH)
+        function swapExactTokensForTokens(uint256 amountIn, uint256 amountOutMin, address[] path, address to, uint256 deadline) external returns (uint256[] amounts)
+        function swapTokensForExactTokens(uint256 amountOut, uint256 amountInMax, address[] path, address to, uint256 deadline) external returns (uint256[] amounts)
+        function swapExactETHForTokens(uint256 amountOutMin, address[] path, address to, uint256 deadline) external payable returns (uint256[] amounts)
+        function swapTokensForExactETH(uint256 amountOut, uint256 amountInMax, address[] path, address to, uint256 deadline) external returns (uint256[] amounts)
+        function swapExactTokensForETH(uint256 amountIn, uint256 amountOutMin, address[] path, address to, uint256 deadline) external returns (uint256[] amounts)
+        function swapETHForExactTokens(uint256 amountOut, address[] path, address to, uint256 deadline) external payable returns (uint256[] amounts)
+        function swapExactTokensForTokensSupportingFeeOnTransferTokens(uint256 amountIn, uint256 amountOutMin, address[] path, address to, uint256 deadline) external
+        function swapExactETHForTokensSupportingFeeOnTransferTokens(uint256 amountOutMin, address[] path, address to, uint256 deadline) external payable
+        function swapExactTokensForETHSupportingFeeOnTransferTokens(uint256 amountIn, uint256 amountOutMin, address[] path, address to, uint256 deadline) external
+        function quote(uint256 amountA, uint256 reserveA, uint256 reserveB) external pure returns (uint256 amountB)
+        function getAmountOut(uint256 amountIn, uint256 reserveIn, uint256 reserveOut) external pure returns (uint256 amountOut)
+        function getAmountIn(uint256 amountOut, uint256 reserveIn, uint256 reserveOut) external pure returns (uint256 amountIn)
+        function getAmountsOut(uint256 amountIn, address[] path) external view returns (uint256[] amounts)
+        function getAmountsIn(uint256 amountOut, address[] path) external view returns (uint256[] amounts)
+    ]"#
+);
+
+abigen!(
+    UniswapV3Factory,
+    r#"[
+        event PoolCreated(address indexed token0, address indexed token1, uint24 indexed fee, int24 tickSpacing, address pool)
+        event FeeAmountEnabled(uint24 indexed fee, int24 indexed tickSpacing)
+        event OwnerChanged(address indexed oldOwner, address indexed newOwner)
+        function createPool(address tokenA, address tokenB, uint24 fee) external returns (address pool)
+        function enableFeeAmount(uint24 fee, int24 tickSpacing) external
+        function feeAmountTickSpacing(uint24 fee) external view returns (int24)
+        function getPool(address tokenA, address tokenB, uint24 fee) external view returns (address pool)
+        function owner() external view returns (address)
+        function setOwner(address _owner) external
+    ]"#
+);
+
+abigen!(
+    UniswapV3SwapRouter,
+    r#"[
+        struct ExactInputSingleParams { address tokenIn; address tokenOut; uint24 fee; address recipient; uint256 deadline; uint256 amountIn; uint256 amountOutMinimum; uint160 sqrtPriceLimitX96; }
+        struct ExactInputParams { bytes path; address recipient; uint256 deadline; uint256 amountIn; uint256 amountOutMinimum; }
+        struct ExactOutputSingleParams { address tokenIn; address tokenOut; uint24 fee; address recipient; uint256 deadline; uint256 amountOut; uint256 amountInMaximum; uint160 sqrtPriceLimitX96; }
+        struct ExactOutputParams { bytes path; address recipient; uint256 deadline; uint256 amountOut; uint256 amountInMaximum; }
+        function WETH9() external view returns (address)
+        function factory() external view returns (address)
+        function exactInputSingle(ExactInputSingleParams params) external payable returns (uint256 amountOut)
+        function exactInput(ExactInputParams params) external payable returns (uint256 amountOut)
+        function exactOutputSingle(ExactOutputSingleParams params) external payable returns (uint256 amountIn)
+        function exactOutput(ExactOutputParams params) external payable returns (uint256 amountIn)
+        function multicall(bytes[] data) external payable returns (bytes[] results)
+        function refundETH() external payable
+        function selfPermit(address token, uint256 value, uint256 deadline, uint8 v, bytes32 r, bytes32 s) external payable
+        function selfPermitIfNecessary(address token, uint256 value, uint256 deadline, uint8 v, bytes32 r, bytes32 s) external payable
+        function selfPermitAllowed(address token, uint256 nonce, uint256 expiry, uint8 v, bytes32 r, bytes32 s) external payable
+        function selfPermitAllowedIfNecessary(address token, uint256 nonce, uint256 expiry, uint8 v, bytes32 r, bytes32 s) external payable
+        function sweepToken(address token, uint256 amountMinimum, address recipient) external payable
+        function sweepTokenWithFee(address token, uint256 amountMinimum, address recipient, uint256 feeBips, address feeRecipient) external payable
+        function unwrapWETH9(uint256 amountMinimum, address recipient) external payable
+        function unwrapWETH9WithFee(uint256 amountMinimum, address recipient, uint256 feeBips, address feeRecipient) external payable
+        function uniswapV3SwapCallback(int256 amount0Delta, int256 amount1Delta, bytes _data) external
+    ]"#
+);
+
+abigen!(
+    UniswapV3SwapRouter02,
+    r#"[
+        struct ExactInputSingleParams { address tokenIn; address tokenOut; uint24 fee; address recipient; uint256 amountIn; uint256 amountOutMinimum; uint160 sqrtPriceLimitX96; }
+        struct ExactInputParams { bytes path; address recipient; uint256 amountIn; uint256 amountOutMinimum; }
+        struct ExactOutputSingleParams { address tokenIn; address tokenOut; uint24 fee; address recipient; uint256 amountOut; uint256 amountInMaximum; uint160 sqrtPriceLimitX96; }
+        struct ExactOutputParams { bytes path; address recipient; uint256 amountOut; uint256 amountInMaximum; }
+        function WETH9() external view returns (address)
+        function factory() external view returns (address)
+        function factoryV2() external view returns (address)
+        function positionManager() external view returns (address)
+        function exactInputSingle(ExactInputSingleParams params) external payable returns (uint256 amountOut)
+        function exactInput(ExactInputParams params) external payable returns (uint256 amountOut)
+        function exactOutputSingle(ExactOutputSingleParams params) external payable returns (uint256 amountIn)
+        function exactOutput(ExactOutputParams params) external payable returns (uint256 amountIn)
+        function swapExactTokensForTokens(uint256 amountIn, uint256 amountOutMin, address[] path, address to) external payable returns (uint256 amountOut)
+        function swapTokensForExactTokens(uint256 amountOut, uint256 amountInMax, address[] path, address to) external payable returns (uint256 amountIn)
+        function multicall(bytes[] data) external payable returns (bytes[] results)
+        function multicall(uint256 deadline, bytes[] data) external payable returns (bytes[] results)
+        function multicall(bytes32 previousBlockhash, bytes[] data) external payable returns (bytes[] results)
+        function pull(address token, uint256 value) external payable
+        function refundETH() external payable
+        function wrapETH(uint256 value) external payable
+        function selfPermit(address token, uint256 value, uint256 deadline, uint8 v, bytes32 r, bytes32 s) external payable
+        function selfPermitIfNecessary(address token, uint256 value, uint256 deadline, uint8 v, bytes32 r, bytes32 s) external payable
+        function selfPermitAllowed(address token, uint256 nonce, uint256 expiry, uint8 v, bytes32 r, bytes32 s) external payable
+        function selfPermitAllowedIfNecessary(address token, uint256 nonce, uint256 expiry, uint8 v, bytes32 r, bytes32 s) external payable
+        function sweepToken(address token, uint256 amountMinimum, address recipient) external payable
+        function sweepToken(address token, uint256 amountMinimum) external payable
+        function sweepTokenWithFee(address token, uint256 amountMinimum, address recipient, uint256 feeBips, address feeRecipient) external payable
+        function sweepTokenWithFee(address token, uint256 amountMinimum, uint256 feeBips, address feeRecipient) external payable
+        function unwrapWETH9(uint256 amountMinimum, address recipient) external payable
+        function unwrapWETH9(uint256 amountMinimum) external payable
+        function unwrapWETH9WithFee(uint256 amountMinimum, address recipient, uint256 feeBips, address feeRecipient) external payable
+        function unwrapWETH9WithFee(uint256 amountMinimum, uint256 feeBips, address feeRecipient) external payable
+        function uniswapV3SwapCallback(int256 amount0Delta, int256 amount1Delta, bytes _data) external
+    ]"#
+);
+
+abigen!(
+    UniversalRouter,
+    r#"[
+        function execute(bytes commands, bytes[] inputs, uint256 deadline) external payable
+        function execute(bytes commands, bytes[] inputs) external payable
+    ]"#
+);
+
+abigen!(
+    BalancerV2Vault,
+    r#"[
+        struct SingleSwap { bytes32 poolId; uint8 kind; address assetIn; address assetOut; uint256 amount; bytes userData; }
+        struct BatchSwapStep { bytes32 poolId; uint256 assetInIndex; uint256 assetOutIndex; uint256 amount; bytes userData; }
+        struct FundManagement { address sender; bool fromInternalBalance; address recipient; bool toInternalBalance; }
+        event Swap(bytes32 indexed poolId, address indexed tokenIn, address indexed tokenOut, uint256 amountIn, uint256 amountOut)
+        function WETH() external view returns (address)
+        function getPool(bytes32 poolId) external view returns (address, uint8)
+        function getPoolTokens(bytes32 poolId) external view returns (address[] tokens, uint256[] balances, uint256 lastChangeBlock)
+        function swap(SingleSwap singleSwap, FundManagement funds, uint256 limit, uint256 deadline) external payable returns (uint256 amountCalculated)
+        function batchSwap(uint8 kind, BatchSwapStep[] swaps, address[] assets, FundManagement funds, int256[] limits, uint256 deadline) external payable returns (int256[] assetDeltas)
+        function queryBatchSwap(uint8 kind, BatchSwapStep[] swaps, address[] assets, FundManagement funds) external returns (int256[] assetDeltas)
+    ]"#
+);
+
+abigen!(
+    CurvePool,
+    r#"[
+        event TokenExchange(address indexed buyer, int128 sold_id, uint256 tokens_sold, int128 bought_id, uint256 tokens_bought)
+        event TokenExchangeUnderlying(address indexed buyer, int128 sold_id, uint256 tokens_sold, int128 bought_id, uint256 tokens_bought)
+        function coins(uint256 i) external view returns (address)
+        function underlying_coins(uint256 i) external view returns (address)
+        function get_dy(int128 i, int128 j, uint256 dx) external view returns (uint256)
+        function exchange(int128 i, int128 j, uint256 dx, uint256 min_dy) external payable returns (uint256)
+        function exchange(int128 i, int128 j, uint256 dx, uint256 min_dy, address receiver) external payable returns (uint256)
+        function exchange_underlying(int128 i, int128 j, uint256 dx, uint256 min_dy) external payable returns (uint256)
+        function exchange_underlying(int128 i, int128 j, uint256 dx, uint256 min_dy, address receiver) external payable returns (uint256)
+    ]"#
+);
+
+abigen!(
+    CurveCryptoPool,
+    r#"[
+        event TokenExchange(address indexed buyer, uint256 sold_id, uint256 tokens_sold, uint256 bought_id, uint256 tokens_bought)
+        function coins(uint256 i) external view returns (address)
+        function get_dy(uint256 i, uint256 j, uint256 dx) external view returns (uint256)
+        function exchange(uint256 i, uint256 j, uint256 dx, uint256 min_dy) external payable returns (uint256)
+        function exchange(uint256 i, uint256 j, uint256 dx, uint256 min_dy, bool use_eth) external payable returns (uint256)
+        function exchange(uint256 i, uint256 j, uint256 dx, uint256 min_dy, bool use_eth, address receiver) external payable returns (uint256)
+        function exchange_underlying(uint256 i, uint256 j, uint256 dx, uint256 min_dy) external payable returns (uint256)
+        function exchange_underlying(uint256 i, uint256 j, uint256 dx, uint256 min_dy, address receiver) external payable returns (uint256)
+    ]"#
+);
+
+/// A well-known protocol whose router and factory ABIs are bundled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Protocol {
+    UniswapV2,
+    UniswapV3,
+    /// A fork of Uniswap V2 with the same ABIs
+    Sushiswap,
+}
+
+impl Protocol {
+    /// ABI of the routers of the protocol
+    ///
+    /// Uniswap V3 has two routers, `SwapRouter` and `SwapRouter02`, with different parameters
+    /// for the same functions, and swaps through the `UniversalRouter`. All three are merged,
+    /// selectors are still unique.
+    pub fn router_abi(&self) -> Abi {
+        match self {
+            Self::UniswapV2 | Self::Sushiswap => UNISWAPV2ROUTER02_ABI.clone(),
+            Self::UniswapV3 => merge_abis(&[
+                &UNISWAPV3SWAPROUTER_ABI,
+                &UNISWAPV3SWAPROUTER02_ABI,
+                &UNIVERSALROUTER_ABI,
+            ]),
+        }
+    }
+
+    /// ABI of the factory of the protocol
+    pub fn factory_abi(&self) -> Abi {
+        match self {
+            Self::UniswapV2 | Self::Sushiswap => UNISWAPV2FACTORY_ABI.clone(),
+            Self::UniswapV3 => UNISWAPV3FACTORY_ABI.clone(),
+        }
+    }
+}
+
+/// Functions and events of several ABIs in one, duplicates are kept once
+pub(crate) fn merge_abis(abis: &[&Abi]) -> Abi {
+    let mut merged = Abi::default();
+
+    for abi in abis {
+        for function in abi.functions() {
+            let overloads = merged.functions.entry(function.name.clone()).or_default();
+            if !overloads
+                .iter()
+                .any(|f| f.short_signature() == function.short_signature())
+            {
+                overloads.push(function.clone());
+            }
+        }
+        for event in abi.events() {
+            let overloads = merged.events.entry(event.name.clone()).or_default();
+            if !overloads.iter().any(|e| e.signature() == event.signature()) {
+                overloads.push(event.clone());
+            }
+        }
+    }
+
+    merged
+}
+
+/// Where the ABI of a contract comes from
+#[derive(Debug, Clone, PartialEq)]
+pub enum AbiSource {
+    /// Fetched from the block explorer, and cached
+    BlockExplorer,
+    /// Bundled with a well-known protocol
+    Builtin(Abi),
+    /// A local JSON file
+    File(PathBuf),
+}
+
+/// Read a JSON ABI file, either a bare ABI or a compiler artifact with an `abi` field
+///
+/// # Errors
+///
+/// This function will return an error if the file could not be read or doesn't hold an ABI
+pub fn read_abi_file(path: &Path) -> Result<Abi> {
+    let json = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read ABI file {:?}", path))?;
+    let value: serde_json::Value = serde_json::from_str(&json)
+        .with_context(|| format!("ABI file {:?} isn't valid JSON", path))?;
+    let abi = match value {
+        serde_json::Value::Object(mut artifact) => artifact
+            .remove("abi")
+            .ok_or_else(|| anyhow!("ABI file {:?} has no abi field", path))?,
+        abi => abi,
+    };
+
+    serde_json::from_value(abi).with_context(|| format!("ABI file {:?} isn't a valid ABI", path))
+}
+
+impl AbiSource {
+    /// ABI of the contract at `address`
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the ABI could not be fetched or read
+    pub async fn load<T: BlockExplorerApi>(&self, indexer: &T, address: Address) -> Result<Abi> {
+        match self {
+            Self::BlockExplorer => indexer
+                .get_abi(address)
+                .await
+                .with_context(|| format!("Failed to fetch the ABI of {:?}", address)),
+            Self::Builtin(abi) => Ok(abi.clone()),
+            Self::File(path) => read_abi_file(path),
+        }
+    }
+}
@@ -0,0 +1,308 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use anyhow::{bail, Result};
+use ethers::abi::{self, ParamType, Token};
+use ethers::types::{Address, Log, H256, I256, U256};
+use ethers::utils::keccak256;
+use serde::{Deserialize, Serialize};
+
+/// `Swap(address,uint256,uint256,uint256,uint256,address)` of Uniswap V2 style pairs
+pub fn v2_swap_topic() -> H256 {
+    H256::from(keccak256(
+        "Swap(address,uint256,uint256,uint256,uint256,address)",
+    ))
+}
+
+/// `Swap(address,address,int256,int256,uint160,uint128,int24)` of Uniswap V3 style pools
+pub fn v3_swap_topic() -> H256 {
+    H256::from(keccak256(
+        "Swap(address,address,int256,int256,uint160,uint128,int24)",
+    ))
+}
+
+/// A swap executed by a pair, as reported by its `Swap` event
+///
+/// Amounts are from the point of view of the pair: what it received and what it sent. V3 pools
+/// report signed deltas, which are split the same way.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Trade {
+    pub pair: Address,
+    pub tx_hash: H256,
+    pub block_number: u64,
+    pub block_hash: H256,
+    /// Position of the event in its block
+    pub log_index: u64,
+    /// Unix time of the block
+    pub timestamp: u64,
+    pub amount0_in: U256,
+    pub amount1_in: U256,
+    pub amount0_out: U256,
+    pub amount1_out: U256,
+}
+
+/// Convert a raw token amount to a float in whole tokens
+pub fn to_units(amount: U256, decimals: u8) -> f64 {
+    let high = (amount >> 128).low_u128() as f64 * 2f64.powi(128);
+
+    (high + amount.low_u128() as f64) / 10f64.powi(decimals as i32)
+}
+
+fn abs_diff(a: U256, b: U256) -> U256 {
+    if a > b {
+        a - b
+    } else {
+        b - a
+    }
+}
+
+impl Trade {
+    /// Amounts of token0 and token1 that changed hands, in whole tokens
+    pub fn volumes(&self, decimals0: u8, decimals1: u8) -> (f64, f64) {
+        (
+            to_units(abs_diff(self.amount0_in, self.amount0_out), decimals0),
+            to_units(abs_diff(self.amount1_in, self.amount1_out), decimals1),
+        )
+    }
+
+    /// Execution price of token0 in token1, `None` if one side is empty
+    pub fn price(&self, decimals0: u8, decimals1: u8) -> Option<f64> {
+        let (volume0, volume1) = self.volumes(decimals0, decimals1);
+
+        (volume0 > 0.0 && volume1 > 0.0).then(|| volume1 / volume0)
+    }
+}
+
+/// Split a V3 signed amount into what the pool received and what it sent
+fn split_delta(delta: I256) -> (U256, U256) {
+    if delta.is_negative() {
+        (U256::zero(), delta.unsigned_abs())
+    } else {
+        (delta.into_raw(), U256::zero())
+    }
+}
+
+/// Decode the `Swap` event of a V2 pair or V3 pool mined in a block with `timestamp`
+///
+/// Logs of other events, or of blocks not yet mined, decode to `None`. The emitter isn't
+/// checked, callers should only keep trades of pairs they know.
+pub fn decode_trade(log: &Log, timestamp: u64) -> Option<Trade> {
+    let topic = *log.topics.first()?;
+    let (amount0_in, amount1_in, amount0_out, amount1_out) = if topic == v2_swap_topic() {
+        let mut amounts = abi::decode(&vec![ParamType::Uint(256); 4], &log.data)
+            .ok()?
+            .into_iter()
+            .map(Token::into_uint);
+
+        (
+            amounts.next()??,
+            amounts.next()??,
+            amounts.next()??,
+            amounts.next()??,
+        )
+    } else if topic == v3_swap_topic() {
+        let mut deltas = abi::decode(
+            &[
+                ParamType::Int(256),
+                ParamType::Int(256),
+                ParamType::Uint(160),
+                ParamType::Uint(128),
+                ParamType::Int(24),
+            ],
+            &log.data,
+        )
+        .ok()?
+        .into_iter()
+        .map(|token| token.into_int().map(I256::from_raw));
+        let (amount0_in, amount0_out) = split_delta(deltas.next()??);
+        let (amount1_in, amount1_out) = split_delta(deltas.next()??);
+
+        (amount0_in, amount1_in, amount0_out, amount1_out)
+    } else {
+        return None;
+    };
+
+    Some(Trade {
+        pair: log.address,
+        tx_hash: log.transaction_hash?,
+        block_number: log.block_number?.as_u64(),
+        block_hash: log.block_hash?,
+        log_index: log.log_index?.as_u64(),
+        timestamp,
+        amount0_in,
+        amount1_in,
+        amount0_out,
+        amount1_out,
+    })
+}
+
+/// Width of a candle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Interval {
+    #[serde(rename = "1m")]
+    OneMinute,
+    #[serde(rename = "5m")]
+    FiveMinutes,
+    #[serde(rename = "1h")]
+    OneHour,
+    #[serde(rename = "1d")]
+    OneDay,
+}
+
+impl Interval {
+    pub const ALL: [Interval; 4] = [
+        Interval::OneMinute,
+        Interval::FiveMinutes,
+        Interval::OneHour,
+        Interval::OneDay,
+    ];
+
+    pub fn seconds(&self) -> u64 {
+        match self {
+            Self::OneMinute => 60,
+            Self::FiveMinutes => 5 * 60,
+            Self::OneHour => 60 * 60,
+            Self::OneDay => 24 * 60 * 60,
+        }
+    }
+
+    /// Start of the candle containing `timestamp`
+    pub fn start(&self, timestamp: u64) -> u64 {
+        timestamp - timestamp % self.seconds()
+    }
+
+    /// Last second of the candle starting at `start`
+    pub fn end(&self, start: u64) -> u64 {
+        start + self.seconds() - 1
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::OneMinute => "1m",
+            Self::FiveMinutes => "5m",
+            Self::OneHour => "1h",
+            Self::OneDay => "1d",
+        }
+    }
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for Interval {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "1m" => Ok(Self::OneMinute),
+            "5m" => Ok(Self::FiveMinutes),
+            "1h" => Ok(Self::OneHour),
+            "1d" => Ok(Self::OneDay),
+            _ => bail!("Unknown candle interval {}", s),
+        }
+    }
+}
+
+/// Price of token0 in token1 over an interval, with the volume of both tokens
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Candle {
+    pub pair: Address,
+    pub interval: Interval,
+    /// Unix time of the first second of the candle
+    pub start: u64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume0: f64,
+    pub volume1: f64,
+    pub trades: u32,
+}
+
+impl Candle {
+    fn open_at(pair: Address, interval: Interval, start: u64, price: f64) -> Self {
+        Self {
+            pair,
+            interval,
+            start,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            volume0: 0.0,
+            volume1: 0.0,
+            trades: 0,
+        }
+    }
+
+    /// Merge the candles of a shorter interval into the one of `interval` starting at `start`
+    ///
+    /// `candles` must be in time order, `None` if there are none.
+    pub fn merge(interval: Interval, start: u64, candles: &[Candle]) -> Option<Self> {
+        let first = candles.first()?;
+        let mut merged = Self::open_at(first.pair, interval, start, first.open);
+
+        for candle in candles {
+            merged.high = merged.high.max(candle.high);
+            merged.low = merged.low.min(candle.low);
+            merged.close = candle.close;
+            merged.volume0 += candle.volume0;
+            merged.volume1 += candle.volume1;
+            merged.trades += candle.trades;
+        }
+
+        Some(merged)
+    }
+}
+
+/// Candles of `interval` for the trades of one pair, in chain order
+///
+/// Trades without a price, e.g. with an empty side, are skipped.
+pub fn build_candles(
+    interval: Interval,
+    trades: &[Trade],
+    decimals0: u8,
+    decimals1: u8,
+) -> Vec<Candle> {
+    let mut candles: BTreeMap<u64, Candle> = BTreeMap::new();
+
+    for trade in trades {
+        let Some(price) = trade.price(decimals0, decimals1) else {
+            continue;
+        };
+        let (volume0, volume1) = trade.volumes(decimals0, decimals1);
+        let start = interval.start(trade.timestamp);
+        let candle = candles
+            .entry(start)
+            .or_insert_with(|| Candle::open_at(trade.pair, interval, start, price));
+
+        candle.high = candle.high.max(price);
+        candle.low = candle.low.min(price);
+        candle.close = price;
+        candle.volume0 += volume0;
+        candle.volume1 += volume1;
+        candle.trades += 1;
+    }
+
+    candles.into_values().collect()
+}
+
+/// Candles of `interval` merged from shorter ones of one pair, in time order
+pub fn merge_candles(interval: Interval, candles: &[Candle]) -> Vec<Candle> {
+    let mut buckets: BTreeMap<u64, Vec<Candle>> = BTreeMap::new();
+
+    for candle in candles {
+        buckets
+            .entry(interval.start(candle.start))
+            .or_default()
+            .push(candle.clone());
+    }
+
+    buckets
+        .into_iter()
+        .filter_map(|(start, candles)| Candle::merge(interval, start, &candles))
+        .collect()
+}
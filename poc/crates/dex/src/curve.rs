@@ -0,0 +1,243 @@
+use anyhow::Result;
+use ethers::abi::{Event, ParamType, RawLog, Token};
+use ethers::types::{Address, Log, Transaction, I256, U256};
+use log::trace;
+use serde::Deserialize;
+
+use crate::bindings::curve_crypto_pool::CURVECRYPTOPOOL_ABI;
+use crate::bindings::curve_pool::CURVEPOOL_ABI;
+use crate::bindings::merge_abis;
+use crate::decoded::Selectors;
+use crate::protocol::{ProtocolAction, ProtocolDecoder};
+use crate::swap::{swap_record, SwapKind, SwapRecord};
+
+/// Version recorded with the swaps of StableSwap pools, whose coin indexes are `int128`
+pub const STABLE_SWAP_VERSION: u8 = 1;
+/// Version recorded with the swaps of CryptoSwap pools, whose coin indexes are `uint256`
+pub const CRYPTO_SWAP_VERSION: u8 = 2;
+
+#[derive(Debug, Deserialize, Clone)]
+#[allow(unused)]
+pub struct CurvePoolSettings {
+    pub address: String,
+    /// Coins of the pool in pool order, as `coins(i)` returns them
+    pub coins: Vec<String>,
+    /// Underlying coins of lending and meta pools, traded by `exchange_underlying`
+    pub underlying_coins: Option<Vec<String>>,
+}
+
+/// A Curve pool with its coins, which swaps refer to by index
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CurvePool {
+    pub address: Address,
+    pub coins: Vec<Address>,
+    pub underlying_coins: Vec<Address>,
+}
+
+impl CurvePool {
+    /// # Errors
+    ///
+    /// This function will return an error if an address of the settings is invalid
+    pub fn from_settings(settings: &CurvePoolSettings) -> Result<Self> {
+        let parse = |addresses: &[String]| {
+            addresses
+                .iter()
+                .map(|address| address.parse::<Address>())
+                .collect::<Result<Vec<Address>, _>>()
+        };
+
+        Ok(Self {
+            address: settings.address.parse()?,
+            coins: parse(&settings.coins)?,
+            underlying_coins: parse(settings.underlying_coins.as_deref().unwrap_or_default())?,
+        })
+    }
+
+    /// Tokens traded by coin indexes `sold` and `bought`, of the underlying coins for
+    /// `exchange_underlying` and its logs
+    fn path(&self, sold: Token, bought: Token, underlying: bool) -> Option<Vec<Address>> {
+        let coins = if underlying {
+            &self.underlying_coins
+        } else {
+            &self.coins
+        };
+
+        Some(vec![
+            *coins.get(coin_index(sold)?)?,
+            *coins.get(coin_index(bought)?)?,
+        ])
+    }
+}
+
+/// Index of a coin, an `int128` or a `uint256` depending on the pool
+fn coin_index(token: Token) -> Option<usize> {
+    let index = match token {
+        Token::Int(raw) if !I256::from_raw(raw).is_negative() => raw,
+        Token::Uint(index) => index,
+        _ => return None,
+    };
+
+    (index <= U256::from(u32::MAX)).then(|| index.as_usize())
+}
+
+/// Version of a pool from the type of the coin indexes of a function or event
+fn version(index: &ParamType) -> u8 {
+    match index {
+        ParamType::Int(_) => STABLE_SWAP_VERSION,
+        _ => CRYPTO_SWAP_VERSION,
+    }
+}
+
+/// Curve, whose pools are traded with directly
+///
+/// StableSwap and CryptoSwap pools are both decoded, the coins of each pool come from the
+/// settings since calls and logs only have their indexes.
+#[derive(Debug, Clone)]
+pub struct Curve {
+    pub name: String,
+    pub pools: Vec<CurvePool>,
+    /// Functions of both kinds of pools by selector
+    selectors: Selectors,
+    /// `TokenExchange` and `TokenExchangeUnderlying` of both kinds of pools
+    events: Vec<Event>,
+}
+
+impl Curve {
+    pub fn new(name: String, pools: Vec<CurvePool>) -> Self {
+        let abi = merge_abis(&[&CURVEPOOL_ABI, &CURVECRYPTOPOOL_ABI]);
+
+        Self {
+            name,
+            pools,
+            selectors: Selectors::new(&abi),
+            events: abi.events().cloned().collect(),
+        }
+    }
+
+    /// # Errors
+    ///
+    /// This function will return an error if an address of the settings is invalid
+    pub fn from_settings(name: String, pools: &[CurvePoolSettings]) -> Result<Self> {
+        let pools = pools
+            .iter()
+            .map(CurvePool::from_settings)
+            .collect::<Result<Vec<CurvePool>>>()?;
+
+        Ok(Self::new(name, pools))
+    }
+
+    pub fn pool(&self, address: Address) -> Option<&CurvePool> {
+        self.pools.iter().find(|pool| pool.address == address)
+    }
+}
+
+impl ProtocolDecoder for Curve {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn matches(&self, address: Address) -> bool {
+        self.pool(address).is_some()
+    }
+
+    /// Swap of an `exchange` or `exchange_underlying` call
+    ///
+    /// `min_dy` is the minimum accepted, the swaps are exact input ones.
+    fn decode_calldata(&self, tx: &Transaction) -> Vec<ProtocolAction> {
+        let Some(pool) = tx.to.and_then(|to| self.pool(to)) else {
+            return Vec::new();
+        };
+        let Ok(function) = self.selectors.lookup(&tx.input) else {
+            return Vec::new();
+        };
+        let underlying = match function.name.as_str() {
+            "exchange" => false,
+            "exchange_underlying" => true,
+            _ => return Vec::new(),
+        };
+        let tokens = match function.decode_input(&tx.input[4..]) {
+            Ok(tokens) => tokens,
+            Err(e) => {
+                trace!(
+                    "Failed to decode {} of tx {:?}: {}",
+                    function.name,
+                    tx.hash,
+                    e
+                );
+                return Vec::new();
+            }
+        };
+
+        let mut arguments = tokens.into_iter();
+        let (Some(sold), Some(bought), Some(dx), Some(min_dy)) = (
+            arguments.next(),
+            arguments.next(),
+            arguments.next().and_then(Token::into_uint),
+            arguments.next().and_then(Token::into_uint),
+        ) else {
+            return Vec::new();
+        };
+        // `use_eth` of the CryptoSwap pools comes before the receiver
+        let receiver = arguments.find_map(Token::into_address);
+        let Some(path) = pool.path(sold, bought, underlying) else {
+            trace!(
+                "Tx {:?} trades unknown coins of {:?}",
+                tx.hash,
+                pool.address
+            );
+            return Vec::new();
+        };
+
+        vec![ProtocolAction::Swap(SwapRecord {
+            recipient: Some(receiver.unwrap_or(tx.from)),
+            path,
+            kind: SwapKind::ExactIn,
+            amount_in: dx,
+            amount_out: min_dy,
+            ..swap_record(
+                &self.name,
+                version(&function.inputs[0].kind),
+                tx,
+                &function.name,
+            )
+        })]
+    }
+
+    /// Swap of a `TokenExchange` or `TokenExchangeUnderlying` log, with the amounts actually
+    /// traded
+    fn decode_log(&self, tx: &Transaction, log: &Log) -> Option<ProtocolAction> {
+        let pool = self.pool(log.address)?;
+        let event = self
+            .events
+            .iter()
+            .find(|event| log.topics.first() == Some(&event.signature()))?;
+        let parsed = event
+            .parse_log(RawLog {
+                topics: log.topics.clone(),
+                data: log.data.to_vec(),
+            })
+            .ok()?;
+        let param = |name: &str| {
+            parsed
+                .params
+                .iter()
+                .find(|param| param.name == name)
+                .map(|param| param.value.clone())
+        };
+
+        let path = pool.path(
+            param("sold_id")?,
+            param("bought_id")?,
+            event.name == "TokenExchangeUnderlying",
+        )?;
+
+        Some(ProtocolAction::Swap(SwapRecord {
+            recipient: param("buyer").and_then(Token::into_address),
+            path,
+            kind: SwapKind::ExactIn,
+            amount_in: param("tokens_sold").and_then(Token::into_uint)?,
+            amount_out: param("tokens_bought").and_then(Token::into_uint)?,
+            ..swap_record(&self.name, version(&event.inputs[1].kind), tx, &event.name)
+        }))
+    }
+}
@@ -0,0 +1,418 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use ethers::abi::{Abi, Function, Token};
+use ethers::types::{Address, Bytes, Transaction, H256, H32, I256, U256};
+use serde::{Deserialize, Serialize};
+
+use crate::universal_router::{commands_of, Command};
+
+/// A decoded ABI value
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum Value {
+    Address(Address),
+    Uint(U256),
+    Int(I256),
+    Bool(bool),
+    String(String),
+    /// Fixed and dynamic byte arrays
+    Bytes(Bytes),
+    /// Fixed and dynamic arrays
+    Array(Vec<Value>),
+    Tuple(Vec<Value>),
+}
+
+impl From<Token> for Value {
+    fn from(token: Token) -> Self {
+        match token {
+            Token::Address(address) => Self::Address(address),
+            Token::Uint(value) => Self::Uint(value),
+            Token::Int(value) => Self::Int(I256::from_raw(value)),
+            Token::Bool(value) => Self::Bool(value),
+            Token::String(value) => Self::String(value),
+            Token::Bytes(bytes) | Token::FixedBytes(bytes) => Self::Bytes(bytes.into()),
+            Token::Array(tokens) | Token::FixedArray(tokens) => {
+                Self::Array(tokens.into_iter().map(Value::from).collect())
+            }
+            Token::Tuple(tokens) => Self::Tuple(tokens.into_iter().map(Value::from).collect()),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |values: &[Value]| {
+            values
+                .iter()
+                .map(Value::to_string)
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+
+        match self {
+            Self::Address(address) => write!(f, "{:?}", address),
+            Self::Uint(value) => write!(f, "{}", value),
+            Self::Int(value) => write!(f, "{}", value),
+            Self::Bool(value) => write!(f, "{}", value),
+            Self::String(value) => write!(f, "{:?}", value),
+            Self::Bytes(bytes) => write!(f, "{}", bytes),
+            Self::Array(values) => write!(f, "[{}]", join(values)),
+            Self::Tuple(values) => write!(f, "({})", join(values)),
+        }
+    }
+}
+
+/// A named argument of a decoded call
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DecodedParam {
+    pub name: String,
+    /// Solidity type, e.g. `address[]`
+    pub kind: String,
+    pub value: Value,
+}
+
+/// A call to a known contract decoded with its ABI
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DecodedCall {
+    pub tx_hash: H256,
+    pub contract: Address,
+    /// Name of the protocol the contract belongs to, e.g. `uniswap`
+    pub protocol: String,
+    pub function: String,
+    pub selector: H32,
+    pub params: Vec<DecodedParam>,
+    /// Calls or commands batched by a `multicall` or Universal Router `execute`, in order
+    pub actions: Vec<Action>,
+}
+
+impl DecodedCall {
+    /// Value of the argument called `name`
+    pub fn param(&self, name: &str) -> Option<&Value> {
+        self.params
+            .iter()
+            .find(|param| param.name == name)
+            .map(|param| &param.value)
+    }
+}
+
+/// A step of a batched call: an inner call of a `multicall` or a command of a Universal Router
+/// `execute`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Action {
+    /// Function of an inner call, or name of a command, e.g. `V3_SWAP_EXACT_IN`
+    pub name: String,
+    /// Selector of an inner call
+    pub selector: Option<H32>,
+    /// Type of a command
+    pub command: Option<u8>,
+    /// Whether the command can revert without reverting the whole call
+    pub allow_revert: bool,
+    pub params: Vec<DecodedParam>,
+    /// Why the action could not be decoded, its params are then empty
+    pub not_decodable: Option<NotDecodable>,
+    /// Steps batched by this one, e.g. the commands of a sub-plan
+    pub actions: Vec<Action>,
+}
+
+impl Action {
+    /// Value of the argument called `name`
+    pub fn param(&self, name: &str) -> Option<&Value> {
+        self.params
+            .iter()
+            .find(|param| param.name == name)
+            .map(|param| &param.value)
+    }
+}
+
+/// Why a tx could not be decoded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum NotDecodable {
+    /// The tx creates a contract
+    ContractCreation,
+    /// The tx isn't sent to the decoding contract
+    UnknownContract { contract: Address },
+    /// The calldata is too short to hold a selector, e.g. a plain ether transfer
+    NoSelector,
+    /// The ABI has no function with the selector
+    UnknownSelector { selector: H32 },
+    /// The function is known but its arguments don't match the ABI
+    InvalidArguments { function: String, error: String },
+    /// The Universal Router command type isn't one that is decoded
+    UnknownCommand { command: u8 },
+}
+
+impl fmt::Display for NotDecodable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ContractCreation => f.write_str("contract creation"),
+            Self::UnknownContract { contract } => write!(f, "unknown contract {:?}", contract),
+            Self::NoSelector => f.write_str("no function selector"),
+            Self::UnknownSelector { selector } => write!(f, "unknown selector {:?}", selector),
+            Self::InvalidArguments { function, error } => {
+                write!(f, "invalid arguments for {}: {}", function, error)
+            }
+            Self::UnknownCommand { command } => write!(f, "unknown command {:#04x}", command),
+        }
+    }
+}
+
+/// Outcome of decoding a tx
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum Decoded {
+    Call(DecodedCall),
+    NotDecodable(NotDecodable),
+}
+
+impl Decoded {
+    pub fn call(self) -> Option<DecodedCall> {
+        match self {
+            Self::Call(call) => Some(call),
+            Self::NotDecodable(_) => None,
+        }
+    }
+}
+
+/// Functions of an ABI indexed by their 4-byte selector
+///
+/// Overloads have different selectors, so each selector maps to exactly one function.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Selectors(HashMap<H32, Function>);
+
+impl Selectors {
+    pub fn new(abi: &Abi) -> Self {
+        Self(
+            abi.functions()
+                .map(|function| (H32::from(function.short_signature()), function.clone()))
+                .collect(),
+        )
+    }
+
+    pub fn get(&self, selector: H32) -> Option<&Function> {
+        self.0.get(&selector)
+    }
+
+    /// Function called by `calldata`, from its first 4 bytes
+    ///
+    /// # Errors
+    ///
+    /// This function will return the reason the calldata can't be decoded if it is shorter than a
+    /// selector or if no function has its selector
+    pub fn lookup(&self, calldata: &[u8]) -> Result<&Function, NotDecodable> {
+        let selector = calldata
+            .get(..4)
+            .map(H32::from_slice)
+            .ok_or(NotDecodable::NoSelector)?;
+
+        self.get(selector)
+            .ok_or(NotDecodable::UnknownSelector { selector })
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Arguments of a call to `function`, with the names and types of its inputs
+fn named_params(function: &Function, tokens: Vec<Token>) -> Vec<DecodedParam> {
+    function
+        .inputs
+        .iter()
+        .zip(tokens)
+        .map(|(input, token)| DecodedParam {
+            name: input.name.clone(),
+            kind: input.kind.to_string(),
+            value: token.into(),
+        })
+        .collect()
+}
+
+/// Decode the calldata of `tx` with `function`, whose selector it starts with
+pub fn decode_with(
+    function: &Function,
+    tx: &Transaction,
+    contract: Address,
+    protocol: &str,
+) -> Decoded {
+    let tokens = match function.decode_input(&tx.input[4..]) {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            return Decoded::NotDecodable(NotDecodable::InvalidArguments {
+                function: function.name.clone(),
+                error: e.to_string(),
+            })
+        }
+    };
+
+    Decoded::Call(DecodedCall {
+        tx_hash: tx.hash,
+        contract,
+        protocol: protocol.to_string(),
+        function: function.name.clone(),
+        selector: H32::from(function.short_signature()),
+        params: named_params(function, tokens),
+        actions: Vec::new(),
+    })
+}
+
+/// Decode a tx sent to `contract` with the functions of its ABI
+///
+/// Batched calls are expanded into their actions, recursively.
+pub fn decode_call(
+    selectors: &Selectors,
+    tx: &Transaction,
+    contract: Address,
+    protocol: &str,
+) -> Decoded {
+    let function = match selectors.lookup(&tx.input) {
+        Ok(function) => function,
+        Err(reason) => return Decoded::NotDecodable(reason),
+    };
+
+    match decode_with(function, tx, contract, protocol) {
+        Decoded::Call(mut call) => {
+            call.actions = batched_actions(selectors, &call.function, &call.params, 0);
+            Decoded::Call(call)
+        }
+        not_decodable => not_decodable,
+    }
+}
+
+/// Deepest nesting of batched calls that is expanded
+pub(crate) const MAX_DEPTH: usize = 4;
+
+fn param_of_kind<'a>(params: &'a [DecodedParam], kind: &str) -> Option<&'a Value> {
+    params
+        .iter()
+        .find(|param| param.kind == kind)
+        .map(|param| &param.value)
+}
+
+fn bytes_array(value: &Value) -> Option<Vec<Bytes>> {
+    match value {
+        Value::Array(values) => values
+            .iter()
+            .map(|value| match value {
+                Value::Bytes(bytes) => Some(bytes.clone()),
+                _ => None,
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+/// Actions batched by a call to `function` with `params`, empty for calls that don't batch
+///
+/// `multicall` calls of the SwapRouter02 style are calls to the same contract, they are decoded
+/// with its `selectors`. Universal Router `execute` calls are streams of commands.
+fn batched_actions(
+    selectors: &Selectors,
+    function: &str,
+    params: &[DecodedParam],
+    depth: usize,
+) -> Vec<Action> {
+    if depth >= MAX_DEPTH {
+        return Vec::new();
+    }
+
+    let inputs = param_of_kind(params, "bytes[]").and_then(bytes_array);
+    match (function, param_of_kind(params, "bytes"), inputs) {
+        ("multicall", _, Some(calls)) => calls
+            .iter()
+            .map(|calldata| inner_call(selectors, calldata, depth + 1))
+            .collect(),
+        ("execute", Some(Value::Bytes(commands)), Some(inputs)) => commands_of(commands, &inputs)
+            .iter()
+            .map(|command| command_action(command, depth + 1))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn not_decodable_action(
+    name: String,
+    selector: Option<H32>,
+    command: Option<&Command>,
+    reason: NotDecodable,
+) -> Action {
+    Action {
+        name,
+        selector,
+        command: command.map(|command| command.command_type),
+        allow_revert: command.is_some_and(|command| command.allow_revert),
+        params: Vec::new(),
+        not_decodable: Some(reason),
+        actions: Vec::new(),
+    }
+}
+
+/// Decode an inner call of a `multicall`
+fn inner_call(selectors: &Selectors, calldata: &[u8], depth: usize) -> Action {
+    let function = match selectors.lookup(calldata) {
+        Ok(function) => function,
+        Err(reason) => {
+            let selector = calldata.get(..4).map(H32::from_slice);
+            return not_decodable_action("unknown".to_string(), selector, None, reason);
+        }
+    };
+    let selector = Some(H32::from(function.short_signature()));
+    let params = match function.decode_input(&calldata[4..]) {
+        Ok(tokens) => named_params(function, tokens),
+        Err(e) => {
+            let reason = NotDecodable::InvalidArguments {
+                function: function.name.clone(),
+                error: e.to_string(),
+            };
+            return not_decodable_action(function.name.clone(), selector, None, reason);
+        }
+    };
+
+    Action {
+        name: function.name.clone(),
+        selector,
+        command: None,
+        allow_revert: false,
+        actions: batched_actions(selectors, &function.name, &params, depth),
+        params,
+        not_decodable: None,
+    }
+}
+
+/// Decode a Universal Router command, sub-plans are expanded into their commands
+fn command_action(command: &Command, depth: usize) -> Action {
+    let name = command.name().unwrap_or("UNKNOWN").to_string();
+    let arguments = match command.arguments() {
+        Ok(arguments) => arguments,
+        Err(reason) => return not_decodable_action(name, None, Some(command), reason),
+    };
+    let actions = match command.sub_plan() {
+        Some(commands) if depth < MAX_DEPTH => commands
+            .iter()
+            .map(|command| command_action(command, depth + 1))
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    Action {
+        name,
+        selector: None,
+        command: Some(command.command_type),
+        allow_revert: command.allow_revert,
+        params: arguments
+            .into_iter()
+            .map(|(name, kind, token)| DecodedParam {
+                name: name.to_string(),
+                kind: kind.to_string(),
+                value: token.into(),
+            })
+            .collect(),
+        not_decodable: None,
+        actions,
+    }
+}
@@ -12,14 +12,7 @@ pub async fn load_dex_routers<T: BlockExplorerApi>(
     for router in router_settings {
         debug!("Loading router: {}", router.name);
 
-        let r = get_router(
-            &block_exlorer,
-            router.addresses.clone(),
-            router.factory,
-            router.name.clone(),
-            router.version,
-        )
-        .await?;
+        let r = get_router(&block_exlorer, &router).await?;
 
         debug!("Loaded router: {}", r);
 
@@ -9,12 +9,16 @@ use std::fmt;
 use std::fmt::Display;
 use std::fmt::Formatter;
 
+use crate::bindings::AbiSource;
+use crate::decoded::{decode_call, Decoded, NotDecodable, Selectors};
 use crate::{decode_debug, DecodableTransaction};
 
 #[derive(Clone, Debug)]
 pub struct Factory {
     pub address: Address,
     pub abi: Abi,
+    /// Functions of `abi` by selector
+    pub selectors: Selectors,
     pub name: String,
     pub version: u8,
 }
@@ -23,6 +27,7 @@ impl Factory {
     pub fn new(address: Address, abi: Abi, name: String, version: u8) -> Self {
         Self {
             address,
+            selectors: Selectors::new(&abi),
             abi,
             name,
             version,
@@ -31,40 +36,28 @@ impl Factory {
 }
 
 impl DecodableTransaction for Factory {
-    async fn decode_tx(&self, tx: Transaction) -> Result<()> {
-        let data = tx.input.0.to_vec();
-
-        if tx.to.is_none() {
-            return Ok(());
+    async fn decode_tx(&self, tx: Transaction) -> Result<Decoded> {
+        let Some(to) = tx.to else {
+            return Ok(Decoded::NotDecodable(NotDecodable::ContractCreation));
+        };
+        if to != self.address {
+            return Ok(Decoded::NotDecodable(NotDecodable::UnknownContract {
+                contract: to,
+            }));
         }
 
-        let abi = self.abi.clone();
         trace!("Decoding TX: {}", tx.hash);
-        abi.functions.iter().for_each(|(name, functions)| {
-            functions.iter().for_each(|f| {
-                if let Ok(tokens) = f.decode_input(&data) {
-                    debug!(
-                        "{} {}",
-                        Colour::Yellow.bold().paint("Input: "),
-                        decode_debug(f, tokens)
-                    );
-                } else {
-                    trace!("Failed to decode TX output with function: {}", name);
-                }
-
-                if let Ok(tokens) = f.decode_output(&data) {
-                    debug!(
-                        "{} {}",
-                        Colour::Purple.bold().paint("Output: "),
-                        decode_debug(f, tokens)
-                    );
-                } else {
-                    trace!("Failed to decode TX output with function: {}", name);
-                }
-            });
-        });
+        let decoded = decode_call(&self.selectors, &tx, to, &self.name);
+        match &decoded {
+            Decoded::Call(call) => debug!(
+                "{} {}",
+                Colour::Yellow.bold().paint("Input: "),
+                decode_debug(call)
+            ),
+            Decoded::NotDecodable(reason) => trace!("Failed to decode TX {}: {}", tx.hash, reason),
+        }
 
-        Ok(())
+        Ok(decoded)
     }
 }
 
@@ -85,6 +78,7 @@ impl Display for Factory {
 ///
 /// * `indexer` - An indexer that implements the IndexerAPI trait
 /// * `address` - The address of the factory
+/// * `source` - Where to get the ABI of the factory from
 /// * `name` - The name of the factory
 /// * `version` - The version of the factory
 ///
@@ -94,18 +88,14 @@ impl Display for Factory {
 pub async fn get_factory<T: BlockExplorerApi>(
     indexer: &T,
     address: String,
+    source: &AbiSource,
     name: String,
     version: u8,
 ) -> Result<Factory> {
     let address = address.parse::<Address>()?;
-    let abi = indexer.get_abi(address).await?;
+    let abi = source.load(indexer, address).await?;
 
-    Ok(Factory {
-        address,
-        abi,
-        name,
-        version,
-    })
+    Ok(Factory::new(address, abi, name, version))
 }
 
 /// Get a vector of factories from an indexer
@@ -114,6 +104,7 @@ pub async fn get_factory<T: BlockExplorerApi>(
 ///
 /// * `indexer` - An indexer that implements the IndexerAPI trait
 /// * `addresses` - A vector of addresses of the factories
+/// * `source` - Where to get the ABIs of the factories from
 /// * `name` - The name of the factory
 /// * `version` - The version of the factory
 ///
@@ -123,13 +114,14 @@ pub async fn get_factory<T: BlockExplorerApi>(
 pub async fn get_factories<T: BlockExplorerApi>(
     indexer: T,
     addresses: Vec<String>,
+    source: &AbiSource,
     name: String,
     version: u8,
 ) -> Result<Vec<Factory>> {
     let mut factories: Vec<Factory> = Vec::new();
 
     for address in addresses {
-        let factory = get_factory(&indexer, address, name.clone(), version).await?;
+        let factory = get_factory(&indexer, address, source, name.clone(), version).await?;
         factories.push(factory);
     }
 
@@ -1,32 +1,184 @@
 #![feature(async_fn_in_trait)]
 
+use std::collections::HashMap;
+
 use ansi_term::Colour;
 use anyhow::Result;
-use ethers::types::Transaction;
+use ethers::types::{Address, Transaction};
+
+use crate::decoded::{Action, Decoded, DecodedCall, DecodedParam, Value};
+use crate::token::TokenMetadata;
 
+pub mod balancer;
+pub mod bindings;
+pub mod candle;
+pub mod curve;
+pub mod decoded;
 pub mod dex;
 pub mod factory;
+pub mod liquidity;
+pub mod protocol;
+pub mod registry;
+pub mod reserves;
 pub mod router;
+pub mod scoring;
+pub mod swap;
+pub mod token;
+pub mod universal_router;
+pub mod v3;
+pub mod v3_math;
+pub mod v3_pool;
 
 pub trait DecodableTransaction {
-    async fn decode_tx(&self, tx: Transaction) -> Result<()>;
+    /// Decode the call made by `tx`
+    ///
+    /// Txs that can't be decoded aren't errors, they decode to `Decoded::NotDecodable` with the
+    /// reason.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the decoder failed to run
+    async fn decode_tx(&self, tx: Transaction) -> Result<Decoded>;
 }
 
-pub fn decode_debug(f: &ethers::abi::Function, tokens: Vec<ethers::abi::Token>) -> String {
-    format!("{} ({})", Colour::White.bold().paint(f.name.clone()), {
-        let args = f
-            .outputs
+/// Token of an `amount...` argument, told by the naming of the V2 router functions
+///
+/// `amountIn...` and `amountOut...` are amounts of the first and last tokens of `path` (or of
+/// `tokenIn`/`tokenOut`), `amountA...`, `amountB...` and `amountToken...` of `tokenA`, `tokenB`
+/// and `token`.
+fn amount_token(params: &[DecodedParam], name: &str) -> Option<Address> {
+    let address = |name: &str| {
+        params
             .iter()
-            .zip(tokens.iter())
-            .map(|(i, d)| {
-                format!(
-                    "{}: {}",
-                    Colour::Green.paint(i.name.clone()),
-                    Colour::Green.dimmed().paint(d.to_string())
-                )
+            .find(|param| param.name == name)
+            .and_then(|param| match param.value {
+                Value::Address(address) => Some(address),
+                _ => None,
             })
+    };
+    let path = || {
+        params
+            .iter()
+            .find(|param| param.name == "path")
+            .and_then(|param| match &param.value {
+                Value::Array(values) => values
+                    .iter()
+                    .map(|value| match value {
+                        Value::Address(address) => Some(*address),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<Address>>>(),
+                _ => None,
+            })
+    };
+
+    let amount = name.strip_prefix("amount")?;
+    if amount.starts_with("In") {
+        path()
+            .and_then(|path| path.first().copied())
+            .or_else(|| address("tokenIn"))
+    } else if amount.starts_with("Out") {
+        path()
+            .and_then(|path| path.last().copied())
+            .or_else(|| address("tokenOut"))
+    } else if amount.starts_with("Token") {
+        address("token")
+    } else if amount.starts_with('A') {
+        address("tokenA")
+    } else if amount.starts_with('B') {
+        address("tokenB")
+    } else {
+        None
+    }
+}
+
+/// A value with the symbols of the known tokens it holds
+fn value_debug(value: &Value, tokens: &HashMap<Address, TokenMetadata>) -> String {
+    let join = |values: &[Value]| {
+        values
+            .iter()
+            .map(|value| value_debug(value, tokens))
             .collect::<Vec<String>>()
-            .join(", ");
-        args
-    })
+            .join(", ")
+    };
+
+    match value {
+        Value::Address(address) => match tokens.get(address) {
+            Some(token) => format!("{:?} ({})", address, token.label()),
+            None => value.to_string(),
+        },
+        Value::Array(values) => format!("[{}]", join(values)),
+        Value::Tuple(values) => format!("({})", join(values)),
+        _ => value.to_string(),
+    }
+}
+
+fn params_debug(params: &[DecodedParam], tokens: &HashMap<Address, TokenMetadata>) -> String {
+    params
+        .iter()
+        .map(|param| {
+            let value = match &param.value {
+                Value::Uint(amount) => amount_token(params, &param.name)
+                    .and_then(|token| tokens.get(&token))
+                    .filter(|token| token.decimals.is_some())
+                    .map(|token| token.format_amount(*amount)),
+                _ => None,
+            }
+            .unwrap_or_else(|| value_debug(&param.value, tokens));
+
+            format!(
+                "{}: {}",
+                Colour::Green.paint(param.name.clone()),
+                Colour::Green.dimmed().paint(value)
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn actions_debug(actions: &[Action], tokens: &HashMap<Address, TokenMetadata>) -> String {
+    if actions.is_empty() {
+        return String::new();
+    }
+
+    let actions = actions
+        .iter()
+        .map(|action| match &action.not_decodable {
+            Some(reason) => format!(
+                "{} ({})",
+                Colour::Red.paint(action.name.clone()),
+                Colour::Red.dimmed().paint(reason.to_string())
+            ),
+            None => format!(
+                "{} ({}){}",
+                Colour::White.bold().paint(action.name.clone()),
+                params_debug(&action.params, tokens),
+                actions_debug(&action.actions, tokens)
+            ),
+        })
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    format!(" => [{}]", actions)
+}
+
+pub fn decode_debug(call: &DecodedCall) -> String {
+    decode_debug_with_tokens(call, &HashMap::new())
+}
+
+/// Like `decode_debug`, with the symbols of the known `tokens` and their amounts in whole
+/// tokens
+///
+/// Amounts are only converted when their token can be told from the names of the arguments,
+/// which the tuples of the V3 routers don't have.
+pub fn decode_debug_with_tokens(
+    call: &DecodedCall,
+    tokens: &HashMap<Address, TokenMetadata>,
+) -> String {
+    format!(
+        "{} ({}){}",
+        Colour::White.bold().paint(call.function.clone()),
+        params_debug(&call.params, tokens),
+        actions_debug(&call.actions, tokens)
+    )
 }
@@ -0,0 +1,384 @@
+use std::fmt;
+
+use ethers::abi::{self, ParamType};
+use ethers::types::{Address, Log, Transaction, H160, H256, U256};
+use ethers::utils::keccak256;
+use log::trace;
+use serde::{Deserialize, Serialize};
+
+use crate::candle::to_units;
+use crate::registry::PairInfo;
+use crate::reserves::decode_sync;
+use crate::router::Router;
+use crate::swap::{Arguments, SwapStatus};
+
+/// Liquidity the first `mint` of a Uniswap V2 pair locks at the zero address
+pub const MINIMUM_LIQUIDITY: u64 = 1_000;
+
+/// `0x...dEaD`, where LP tokens are sent to be burned when not to the zero address
+pub const DEAD: Address = H160([
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xde, 0xad,
+]);
+
+/// `Mint(address,uint256,uint256)` of Uniswap V2 style pairs
+pub fn pair_mint_topic() -> H256 {
+    H256::from(keccak256("Mint(address,uint256,uint256)"))
+}
+
+/// `Burn(address,uint256,uint256,address)` of Uniswap V2 style pairs
+pub fn pair_burn_topic() -> H256 {
+    H256::from(keccak256("Burn(address,uint256,uint256,address)"))
+}
+
+/// `Transfer(address,address,uint256)` of ERC-20 tokens, the LP tokens of the pairs included
+pub fn transfer_topic() -> H256 {
+    H256::from(keccak256("Transfer(address,address,uint256)"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LiquidityKind {
+    Add,
+    Remove,
+}
+
+impl LiquidityKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Add => "add",
+            Self::Remove => "remove",
+        }
+    }
+}
+
+/// What became of the LP tokens of an add
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LpFate {
+    /// Kept by their recipient, who can remove the liquidity at any time
+    Held,
+    /// Sent to the zero or the dead address
+    Burned,
+    /// Sent to one of the configured lockers
+    Locked,
+}
+
+impl LpFate {
+    /// Fate of LP tokens sent to `recipient`
+    pub fn of(recipient: Address, lockers: &[Address]) -> Self {
+        if recipient.is_zero() || recipient == DEAD {
+            Self::Burned
+        } else if lockers.contains(&recipient) {
+            Self::Locked
+        } else {
+            Self::Held
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Held => "held",
+            Self::Burned => "burned",
+            Self::Locked => "locked",
+        }
+    }
+}
+
+/// Liquidity added to or removed from a Uniswap V2 style pair, pending or mined
+///
+/// The first liquidity of a pair is a token launch, a removal can be a rug pull.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LiquidityEvent {
+    pub tx_hash: H256,
+    /// Name of the router or of the DEX of the pair, e.g. `uniswap`
+    pub router: String,
+    /// Router function that was called, or `Mint`/`Burn` for the logs of the pair
+    pub method: String,
+    pub kind: LiquidityKind,
+    pub sender: Address,
+    /// Address of the pair, `None` while pending
+    pub pair: Option<Address>,
+    pub token: Address,
+    /// Asset `token` is paired with, the zero address for ETH until the WETH of the router is
+    /// resolved
+    pub paired_token: Address,
+    /// Desired amounts for pending adds, minimum amounts for pending removes, exact amounts once
+    /// mined
+    pub token_amount: U256,
+    pub paired_amount: U256,
+    /// LP tokens minted or burned, `None` for pending adds whose LP tokens depend on the reserves
+    pub liquidity: Option<U256>,
+    /// Who receives the LP tokens of an add, or the tokens of a remove
+    pub recipient: Address,
+    /// What became of the LP tokens of an add, `None` for removes
+    pub lp_fate: Option<LpFate>,
+    /// Whether the add is the first liquidity of the pair
+    pub initial: bool,
+    /// Share of the reserves of the pair a mined remove took, close to 1 for a rug pull
+    pub removed_share: Option<f64>,
+    pub status: SwapStatus,
+    pub block_number: Option<u64>,
+}
+
+impl LiquidityEvent {
+    /// First liquidity of a pair, the token can be traded from then on
+    pub fn is_launch(&self) -> bool {
+        self.kind == LiquidityKind::Add && self.initial
+    }
+
+    /// Swap `token` and `paired_token` with their amounts if `token` is a base token, e.g.
+    /// WETH, and `paired_token` isn't
+    pub fn orient(&mut self, is_base: impl Fn(Address) -> bool) {
+        if is_base(self.token) && !is_base(self.paired_token) {
+            std::mem::swap(&mut self.token, &mut self.paired_token);
+            std::mem::swap(&mut self.token_amount, &mut self.paired_amount);
+        }
+    }
+}
+
+impl fmt::Display for LiquidityEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {:?} {} / {:?} {} to {:?}",
+            self.kind.as_str(),
+            self.token,
+            self.token_amount,
+            self.paired_token,
+            self.paired_amount,
+            self.recipient
+        )?;
+        if let Some(fate) = self.lp_fate {
+            write!(f, ", LP {}", fate.as_str())?;
+        }
+        if let Some(share) = self.removed_share {
+            write!(f, ", {:.1}% of the reserves", share * 100.0)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Decode the liquidity added or removed by a tx sent to one of the addresses of `router`
+///
+/// `addLiquidity`, `addLiquidityETH` and the `removeLiquidity` functions of Uniswap V2 style
+/// routers are recognized by their name and the names of their arguments. Whether an add is
+/// initial depends on the state of the pair, it's left to the caller.
+pub fn decode_liquidity(router: &Router, tx: &Transaction) -> Option<LiquidityEvent> {
+    let address = tx.to.and_then(|to| router.get_address(to))?;
+    let function = address.selectors.lookup(&tx.input).ok()?;
+    let kind = if function.name.starts_with("addLiquidity") {
+        LiquidityKind::Add
+    } else if function.name.starts_with("removeLiquidity") {
+        LiquidityKind::Remove
+    } else {
+        return None;
+    };
+    let arguments = match function.decode_input(&tx.input[4..]) {
+        Ok(tokens) => Arguments { function, tokens },
+        Err(e) => {
+            trace!(
+                "Failed to decode {} of tx {:?}: {}",
+                function.name,
+                tx.hash,
+                e
+            );
+            return None;
+        }
+    };
+
+    let eth = function.name.contains("ETH");
+    let (token, paired_token) = if eth {
+        (arguments.address("token")?, Address::zero())
+    } else {
+        (arguments.address("tokenA")?, arguments.address("tokenB")?)
+    };
+    let (token_amount, paired_amount, liquidity) = match (kind, eth) {
+        (LiquidityKind::Add, true) => (arguments.uint("amountTokenDesired")?, tx.value, None),
+        (LiquidityKind::Add, false) => (
+            arguments.uint("amountADesired")?,
+            arguments.uint("amountBDesired")?,
+            None,
+        ),
+        (LiquidityKind::Remove, true) => (
+            arguments.uint("amountTokenMin")?,
+            arguments.uint("amountETHMin")?,
+            arguments.uint("liquidity"),
+        ),
+        (LiquidityKind::Remove, false) => (
+            arguments.uint("amountAMin")?,
+            arguments.uint("amountBMin")?,
+            arguments.uint("liquidity"),
+        ),
+    };
+    let recipient = arguments.address("to")?;
+
+    Some(LiquidityEvent {
+        tx_hash: tx.hash,
+        router: router.name.clone(),
+        method: function.name.clone(),
+        kind,
+        sender: tx.from,
+        pair: None,
+        token,
+        paired_token,
+        token_amount,
+        paired_amount,
+        liquidity,
+        recipient,
+        lp_fate: (kind == LiquidityKind::Add).then(|| LpFate::of(recipient, &[])),
+        initial: false,
+        removed_share: None,
+        status: if tx.block_hash.is_some() {
+            SwapStatus::Mined
+        } else {
+            SwapStatus::Pending
+        },
+        block_number: tx.block_number.map(|n| n.as_u64()),
+    })
+}
+
+/// A `Transfer` of LP tokens
+struct Transfer {
+    from: Address,
+    to: Address,
+    value: U256,
+}
+
+fn decode_transfer(log: &Log) -> Option<Transfer> {
+    match log.topics.as_slice() {
+        [topic, from, to] if *topic == transfer_topic() => Some(Transfer {
+            from: Address::from(*from),
+            to: Address::from(*to),
+            value: abi::decode(&[ParamType::Uint(256)], &log.data)
+                .ok()?
+                .pop()?
+                .into_uint()?,
+        }),
+        _ => None,
+    }
+}
+
+fn decode_amounts(log: &Log) -> Option<(U256, U256)> {
+    let mut tokens = abi::decode(&[ParamType::Uint(256), ParamType::Uint(256)], &log.data)
+        .ok()?
+        .into_iter();
+
+    Some((tokens.next()?.into_uint()?, tokens.next()?.into_uint()?))
+}
+
+/// Fate of LP tokens minted to `recipient`, who may pass them on later in the tx, e.g. to a
+/// locker
+fn lp_fate(recipient: Address, later: &[&Log], lockers: &[Address]) -> LpFate {
+    match LpFate::of(recipient, lockers) {
+        LpFate::Held => later
+            .iter()
+            .filter_map(|log| decode_transfer(log))
+            .find(|transfer| transfer.from == recipient)
+            .map_or(LpFate::Held, |transfer| LpFate::of(transfer.to, lockers)),
+        fate => fate,
+    }
+}
+
+/// Liquidity added to and removed from `pair` by a mined tx, from the logs of its receipt
+///
+/// The LP tokens minted by an add are the last ones minted before its `Mint` log, those burned
+/// by a remove the last ones burned before its `Burn` log. An add is initial when the
+/// `MINIMUM_LIQUIDITY` locked by the first `mint` of a pair is minted with it. Events are
+/// ordered as `token0` and `token1` of the pair, see `LiquidityEvent::orient`.
+pub fn decode_pair_liquidity(
+    tx: &Transaction,
+    logs: &[Log],
+    pair: &PairInfo,
+    router: &str,
+    lockers: &[Address],
+) -> Vec<LiquidityEvent> {
+    let logs = logs
+        .iter()
+        .filter(|log| log.address == pair.address)
+        .collect::<Vec<&Log>>();
+    let event = |kind, method: &str, amounts: (U256, U256), liquidity, recipient| LiquidityEvent {
+        tx_hash: tx.hash,
+        router: router.to_string(),
+        method: method.to_string(),
+        kind,
+        sender: tx.from,
+        pair: Some(pair.address),
+        token: pair.token0,
+        paired_token: pair.token1,
+        token_amount: amounts.0,
+        paired_amount: amounts.1,
+        liquidity,
+        recipient,
+        lp_fate: None,
+        initial: false,
+        removed_share: None,
+        status: SwapStatus::Mined,
+        block_number: tx.block_number.map(|n| n.as_u64()),
+    };
+
+    let mut events = Vec::new();
+    let (mut minted, mut burned, mut locked) = (None, None, false);
+    for (index, log) in logs.iter().enumerate() {
+        if let Some(transfer) = decode_transfer(log) {
+            match (transfer.from.is_zero(), transfer.to.is_zero()) {
+                (true, true) => locked = transfer.value == U256::from(MINIMUM_LIQUIDITY),
+                (true, false) => minted = Some((transfer.to, transfer.value)),
+                (false, true) => burned = Some(transfer.value),
+                (false, false) => {}
+            }
+            continue;
+        }
+
+        let topic = log.topics.first();
+        if topic == Some(&pair_mint_topic()) {
+            let (Some(amounts), Some((recipient, liquidity))) =
+                (decode_amounts(log), minted.take())
+            else {
+                continue;
+            };
+
+            events.push(LiquidityEvent {
+                lp_fate: Some(lp_fate(recipient, &logs[index + 1..], lockers)),
+                initial: std::mem::take(&mut locked),
+                ..event(
+                    LiquidityKind::Add,
+                    "Mint",
+                    amounts,
+                    Some(liquidity),
+                    recipient,
+                )
+            });
+        } else if topic == Some(&pair_burn_topic()) {
+            let (Some(amounts), Some(to)) = (decode_amounts(log), log.topics.get(2)) else {
+                continue;
+            };
+            // The pair syncs its reserves right before logging the burn
+            let removed_share = logs[..index]
+                .iter()
+                .rev()
+                .find_map(|log| decode_sync(log))
+                .map(|reserves| {
+                    let before = to_units(amounts.0.saturating_add(reserves.reserve0), 0);
+                    if before == 0.0 {
+                        0.0
+                    } else {
+                        to_units(amounts.0, 0) / before
+                    }
+                });
+
+            events.push(LiquidityEvent {
+                removed_share,
+                ..event(
+                    LiquidityKind::Remove,
+                    "Burn",
+                    amounts,
+                    burned.take(),
+                    Address::from(*to),
+                )
+            });
+        }
+    }
+
+    events
+}
@@ -0,0 +1,216 @@
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use ethers::types::{Address, Log, Transaction};
+use serde::{Deserialize, Serialize};
+
+use crate::balancer::BalancerV2;
+use crate::curve::{Curve, CurvePoolSettings};
+use crate::liquidity::{decode_liquidity, LiquidityEvent};
+use crate::router::Router;
+use crate::swap::{decode_swaps, SwapRecord};
+
+/// What a call or a log of a protocol does, in the same terms for every protocol
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+// Actions are unwrapped into their records right after decoding, boxing isn't worth it
+#[allow(clippy::large_enum_variant)]
+pub enum ProtocolAction {
+    Swap(SwapRecord),
+    Liquidity(LiquidityEvent),
+}
+
+impl ProtocolAction {
+    pub fn swap(self) -> Option<SwapRecord> {
+        match self {
+            Self::Swap(swap) => Some(swap),
+            _ => None,
+        }
+    }
+
+    pub fn liquidity(self) -> Option<LiquidityEvent> {
+        match self {
+            Self::Liquidity(event) => Some(event),
+            _ => None,
+        }
+    }
+}
+
+/// Decoder of the calls and logs of a DEX protocol into `ProtocolAction`s
+///
+/// A protocol answers for a set of contracts: routers, a vault, pools. Decoding is best effort,
+/// what a decoder doesn't understand makes no action.
+pub trait ProtocolDecoder: fmt::Debug + Send + Sync {
+    /// Name of the protocol as configured, e.g. `uniswap`, recorded as the router of its swaps
+    fn name(&self) -> &str;
+
+    /// Whether `address` is one of the contracts of the protocol
+    fn matches(&self, address: Address) -> bool;
+
+    /// Actions of `tx`, sent to one of the contracts of the protocol, in order
+    fn decode_calldata(&self, tx: &Transaction) -> Vec<ProtocolAction>;
+
+    /// Action of `log`, emitted by one of the contracts of the protocol during `tx`
+    fn decode_log(&self, tx: &Transaction, log: &Log) -> Option<ProtocolAction>;
+}
+
+impl ProtocolDecoder for Router {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn matches(&self, address: Address) -> bool {
+        self.get_address(address).is_some()
+    }
+
+    /// Swaps of the call, or the liquidity it adds or removes
+    fn decode_calldata(&self, tx: &Transaction) -> Vec<ProtocolAction> {
+        decode_swaps(self, tx)
+            .into_iter()
+            .map(ProtocolAction::Swap)
+            .chain(decode_liquidity(self, tx).map(ProtocolAction::Liquidity))
+            .collect()
+    }
+
+    /// Routers don't log swaps, their pairs and pools do
+    fn decode_log(&self, _tx: &Transaction, _log: &Log) -> Option<ProtocolAction> {
+        None
+    }
+}
+
+/// A protocol that isn't a Uniswap style router and factory, configured as `[[dex.protocols]]`
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ProtocolSettings {
+    /// The Vault every Balancer V2 pool trades through
+    BalancerV2 { name: String, vault: String },
+    /// Curve pools, which are traded with directly
+    Curve {
+        name: String,
+        pools: Vec<CurvePoolSettings>,
+    },
+}
+
+impl ProtocolSettings {
+    pub fn name(&self) -> &str {
+        match self {
+            Self::BalancerV2 { name, .. } | Self::Curve { name, .. } => name,
+        }
+    }
+
+    /// # Errors
+    ///
+    /// This function will return an error if an address of the settings is invalid
+    pub fn decoder(&self) -> Result<Arc<dyn ProtocolDecoder>> {
+        Ok(match self {
+            Self::BalancerV2 { name, vault } => {
+                Arc::new(BalancerV2::new(name.clone(), vault.parse()?))
+            }
+            Self::Curve { name, pools } => Arc::new(Curve::from_settings(name.clone(), pools)?),
+        })
+    }
+}
+
+/// The protocols txs and logs are decoded with, looked up by address
+#[derive(Debug, Clone, Default)]
+pub struct ProtocolRegistry {
+    decoders: Vec<Arc<dyn ProtocolDecoder>>,
+}
+
+impl ProtocolRegistry {
+    /// Registry of the loaded `routers` and of the protocols of `settings`
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the settings of a protocol are invalid
+    pub fn new(routers: &[Router], settings: &[ProtocolSettings]) -> Result<Self> {
+        let mut registry = Self::default();
+        for router in routers {
+            registry.register(Arc::new(router.clone()));
+        }
+        for protocol in settings {
+            let decoder = protocol
+                .decoder()
+                .with_context(|| format!("Invalid settings for protocol {}", protocol.name()))?;
+            registry.register(decoder);
+        }
+
+        Ok(registry)
+    }
+
+    /// Add a decoder, addresses already matched by another decoder stay with it
+    pub fn register(&mut self, decoder: Arc<dyn ProtocolDecoder>) {
+        self.decoders.push(decoder);
+    }
+
+    /// Decoder of the protocol `address` belongs to
+    pub fn decoder(&self, address: Address) -> Option<&dyn ProtocolDecoder> {
+        self.decoders
+            .iter()
+            .find(|decoder| decoder.matches(address))
+            .map(|decoder| decoder.as_ref())
+    }
+
+    /// Actions of `tx`, none if it isn't sent to a known protocol
+    pub fn decode_calldata(&self, tx: &Transaction) -> Vec<ProtocolAction> {
+        tx.to
+            .and_then(|to| self.decoder(to))
+            .map(|decoder| decoder.decode_calldata(tx))
+            .unwrap_or_default()
+    }
+
+    /// Actions of the logs of `tx` emitted by known protocols, in order
+    pub fn decode_logs(&self, tx: &Transaction, logs: &[Log]) -> Vec<ProtocolAction> {
+        logs.iter()
+            .filter_map(|log| self.decoder(log.address)?.decode_log(tx, log))
+            .collect()
+    }
+
+    /// Swaps made by `tx`, from its calldata
+    pub fn swaps(&self, tx: &Transaction) -> Vec<SwapRecord> {
+        self.decode_calldata(tx)
+            .into_iter()
+            .filter_map(ProtocolAction::swap)
+            .collect()
+    }
+
+    /// Swaps made by a mined `tx`, from its calldata and from the `logs` it emitted
+    ///
+    /// Logs add the swaps of the protocols the tx wasn't sent to, e.g. Curve pools traded
+    /// through an aggregator. The protocol the tx was sent to is only decoded from the calldata,
+    /// so its swaps aren't counted twice. Swaps are numbered in the tx in that order, whichever
+    /// protocol made them.
+    pub fn mined_swaps(&self, tx: &Transaction, logs: &[Log]) -> Vec<SwapRecord> {
+        let sent_to = tx.to.and_then(|to| self.decoder(to));
+        let logged = logs
+            .iter()
+            .filter(|log| sent_to.is_none_or(|decoder| !decoder.matches(log.address)))
+            .filter_map(|log| self.decoder(log.address)?.decode_log(tx, log))
+            .filter_map(ProtocolAction::swap);
+
+        let mut swaps = self.swaps(tx);
+        swaps.extend(logged);
+        for (index, swap) in swaps.iter_mut().enumerate() {
+            swap.swap_index = index as u32;
+        }
+
+        swaps
+    }
+
+    /// Liquidity added or removed by `tx`, from its calldata
+    pub fn liquidity(&self, tx: &Transaction) -> Vec<LiquidityEvent> {
+        self.decode_calldata(tx)
+            .into_iter()
+            .filter_map(ProtocolAction::liquidity)
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.decoders.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.decoders.is_empty()
+    }
+}
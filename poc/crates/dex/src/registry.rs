@@ -0,0 +1,490 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context, Result};
+use ethers::abi::{self, RawLog, Token};
+use ethers::providers::Middleware;
+use ethers::types::{Address, Filter, Log, H256};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::decoded::{DecodedCall, Value};
+use crate::factory::Factory;
+use crate::token::fetch_metadata;
+
+/// An ERC-20 token known to the registry
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenInfo {
+    pub address: Address,
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+    /// Block of the first pair created with the token, `None` for tokens from the settings
+    pub first_seen_block: Option<u64>,
+}
+
+/// A V2 pair or V3 pool created by a factory
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PairInfo {
+    pub address: Address,
+    pub factory: Address,
+    pub protocol_version: u8,
+    /// The token with the lower address, as sorted by the factory
+    pub token0: Address,
+    pub token1: Address,
+    /// Fee tier in hundredths of a basis point, `None` for V2 pairs
+    pub fee: Option<u32>,
+    pub created_block: u64,
+}
+
+impl PairInfo {
+    pub fn has_token(&self, token: Address) -> bool {
+        self.token0 == token || self.token1 == token
+    }
+
+    /// The token paired with `token`, `None` if `token` isn't in the pair
+    pub fn other_token(&self, token: Address) -> Option<Address> {
+        if self.token0 == token {
+            Some(self.token1)
+        } else if self.token1 == token {
+            Some(self.token0)
+        } else {
+            None
+        }
+    }
+}
+
+/// A pair or pool seen being created, either pending in the mempool or mined
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NewMarket {
+    pub factory: Address,
+    pub protocol_version: u8,
+    /// Address of the pair or pool, `None` until the creation is mined
+    pub address: Option<Address>,
+    /// The token with the lower address, as sorted by the factory
+    pub token0: Address,
+    pub token1: Address,
+    /// Fee tier in hundredths of a basis point, `None` for V2 pairs
+    pub fee: Option<u32>,
+    pub tx_hash: H256,
+    /// Block the market was created in, `None` while the creation is pending
+    pub block_number: Option<u64>,
+}
+
+impl NewMarket {
+    /// Market of a pair created by a mined tx
+    pub fn mined(pair: &PairInfo, tx_hash: H256) -> Self {
+        Self {
+            factory: pair.factory,
+            protocol_version: pair.protocol_version,
+            address: Some(pair.address),
+            token0: pair.token0,
+            token1: pair.token1,
+            fee: pair.fee,
+            tx_hash,
+            block_number: Some(pair.created_block),
+        }
+    }
+
+    /// Market a pending `createPair`/`createPool` call to `factory` would create
+    ///
+    /// Other calls return `None`.
+    pub fn pending(factory: &Factory, call: &DecodedCall) -> Option<Self> {
+        if call.function != "createPair" && call.function != "createPool" {
+            return None;
+        }
+
+        let mut tokens = call.params.iter().filter_map(|param| match param.value {
+            Value::Address(address) => Some(address),
+            _ => None,
+        });
+        let (token0, token1) = sort_tokens(tokens.next()?, tokens.next()?);
+        let fee = call.param("fee").and_then(|value| match value {
+            Value::Uint(fee) => Some(fee.low_u32()),
+            _ => None,
+        });
+
+        Some(Self {
+            factory: factory.address,
+            protocol_version: factory.version,
+            address: None,
+            token0,
+            token1,
+            fee,
+            tx_hash: call.tx_hash,
+            block_number: None,
+        })
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.block_number.is_none()
+    }
+}
+
+/// Persistence of the token and pair registry
+///
+/// Implemented by the storage backends so the registry can be loaded at startup without the dex
+/// crate knowing about them.
+pub trait RegistryStorage {
+    /// Insert or update a token
+    ///
+    /// The earliest `first_seen_block` is kept when the token is already stored.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the token could not be stored
+    async fn store_token(&mut self, token: &TokenInfo) -> Result<()>;
+
+    /// Insert a pair, pairs never change once created
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the pair could not be stored
+    async fn store_pair(&mut self, pair: &PairInfo) -> Result<()>;
+
+    /// All stored tokens
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the tokens could not be queried
+    async fn tokens(&mut self) -> Result<Vec<TokenInfo>>;
+
+    /// All stored pairs, in creation order
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the pairs could not be queried
+    async fn pairs(&mut self) -> Result<Vec<PairInfo>>;
+}
+
+/// Tokens and pairs indexed by address
+#[derive(Debug, Clone, Default)]
+pub struct Registry {
+    tokens: HashMap<Address, TokenInfo>,
+    pairs: HashMap<Address, PairInfo>,
+    /// Pair addresses by factory, sorted tokens and fee tier
+    markets: HashMap<(Address, Address, Address, Option<u32>), Address>,
+}
+
+/// Sort two tokens the way the factories do
+fn sort_tokens(a: Address, b: Address) -> (Address, Address) {
+    if a < b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+impl Registry {
+    /// Load every stored token and pair
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the storage could not be queried
+    pub async fn load<S: RegistryStorage>(storage: &mut S) -> Result<Self> {
+        let mut registry = Self::default();
+
+        for token in storage.tokens().await? {
+            registry.insert_token(token);
+        }
+        for pair in storage.pairs().await? {
+            registry.insert_pair(pair);
+        }
+
+        info!(
+            "Loaded {} tokens and {} pairs",
+            registry.tokens.len(),
+            registry.pairs.len()
+        );
+
+        Ok(registry)
+    }
+
+    pub fn token(&self, address: Address) -> Option<&TokenInfo> {
+        self.tokens.get(&address)
+    }
+
+    pub fn tokens(&self) -> impl Iterator<Item = &TokenInfo> {
+        self.tokens.values()
+    }
+
+    pub fn pair(&self, address: Address) -> Option<&PairInfo> {
+        self.pairs.get(&address)
+    }
+
+    pub fn pairs(&self) -> impl Iterator<Item = &PairInfo> {
+        self.pairs.values()
+    }
+
+    /// The pair of `factory` for two tokens, in any order
+    pub fn find_pair(
+        &self,
+        factory: Address,
+        a: Address,
+        b: Address,
+        fee: Option<u32>,
+    ) -> Option<&PairInfo> {
+        let (token0, token1) = sort_tokens(a, b);
+
+        self.markets
+            .get(&(factory, token0, token1, fee))
+            .and_then(|address| self.pairs.get(address))
+    }
+
+    /// Pairs trading `token`, on any factory
+    pub fn pairs_with(&self, token: Address) -> Vec<&PairInfo> {
+        self.pairs
+            .values()
+            .filter(|pair| pair.has_token(token))
+            .collect()
+    }
+
+    /// Add or update a token, returning whether it was new
+    ///
+    /// Like in storage, the earliest `first_seen_block` is kept.
+    pub fn insert_token(&mut self, mut token: TokenInfo) -> bool {
+        match self.tokens.get(&token.address) {
+            Some(known) => {
+                token.first_seen_block = match (known.first_seen_block, token.first_seen_block) {
+                    (Some(a), Some(b)) => Some(a.min(b)),
+                    (a, b) => a.or(b),
+                };
+                self.tokens.insert(token.address, token);
+
+                false
+            }
+            None => {
+                self.tokens.insert(token.address, token);
+
+                true
+            }
+        }
+    }
+
+    /// Add a pair, returning whether it was new
+    pub fn insert_pair(&mut self, pair: PairInfo) -> bool {
+        let (token0, token1) = sort_tokens(pair.token0, pair.token1);
+
+        self.markets
+            .insert((pair.factory, token0, token1, pair.fee), pair.address);
+
+        self.pairs.insert(pair.address, pair).is_none()
+    }
+
+    /// Block of the newest known pair of `factory`
+    pub fn last_created_block(&self, factory: Address) -> Option<u64> {
+        self.pairs
+            .values()
+            .filter(|pair| pair.factory == factory)
+            .map(|pair| pair.created_block)
+            .max()
+    }
+
+    pub fn token_count(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn pair_count(&self) -> usize {
+        self.pairs.len()
+    }
+}
+
+/// Event emitted by `factory` when it creates a pair or pool
+///
+/// Uniswap V2 style factories emit `PairCreated`, V3 style ones `PoolCreated`.
+pub fn creation_event(factory: &Factory) -> Option<&abi::Event> {
+    ["PairCreated", "PoolCreated"]
+        .iter()
+        .find_map(|name| factory.abi.event(name).ok())
+}
+
+/// Decode the pair or pool created by a `PairCreated`/`PoolCreated` log of `factory`
+pub fn decode_pair_created(factory: &Factory, log: &Log) -> Option<PairInfo> {
+    if log.address != factory.address {
+        return None;
+    }
+
+    let event = creation_event(factory)?;
+    if log.topics.first() != Some(&event.signature()) {
+        return None;
+    }
+
+    let parsed = match event.parse_log(RawLog {
+        topics: log.topics.clone(),
+        data: log.data.to_vec(),
+    }) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            warn!(
+                "Failed to decode {} of tx {:?}: {}",
+                event.name, log.transaction_hash, e
+            );
+            return None;
+        }
+    };
+    let param = |name: &str| {
+        parsed
+            .params
+            .iter()
+            .find(|param| param.name == name)
+            .map(|param| param.value.clone())
+    };
+
+    Some(PairInfo {
+        address: param("pair")
+            .or_else(|| param("pool"))
+            .and_then(Token::into_address)?,
+        factory: factory.address,
+        protocol_version: factory.version,
+        token0: param("token0").and_then(Token::into_address)?,
+        token1: param("token1").and_then(Token::into_address)?,
+        fee: param("fee")
+            .and_then(Token::into_uint)
+            .map(|fee| fee.low_u32()),
+        created_block: log.block_number?.as_u64(),
+    })
+}
+
+/// Read the metadata of an ERC-20 token
+///
+/// Tokens without a readable `name` or `symbol` get an empty one, since they can still be
+/// traded. `first_seen_block` is left for the caller to fill in.
+///
+/// # Errors
+///
+/// This function will return an error if `decimals` could not be read
+pub async fn fetch_token<M: Middleware>(
+    provider: &M,
+    address: Address,
+    block: Option<u64>,
+) -> Result<TokenInfo> {
+    let metadata = fetch_metadata(provider, address, block).await?;
+
+    Ok(TokenInfo {
+        address,
+        name: metadata.name.unwrap_or_default(),
+        symbol: metadata.symbol.unwrap_or_default(),
+        decimals: metadata
+            .decimals
+            .with_context(|| format!("Invalid decimals for token {:?}", address))?,
+        first_seen_block: None,
+    })
+}
+
+/// Register the pairs created by `factory` in blocks `from..=to`
+///
+/// Logs are fetched `chunk_size` blocks at a time. Tokens seen for the first time are read from
+/// their contract, pairs with a token that isn't an ERC-20 are skipped. Everything registered is
+/// stored as well, so a sync can resume from `Registry::last_created_block`.
+///
+/// Returns the number of new pairs.
+///
+/// # Errors
+///
+/// This function will return an error if the logs could not be fetched or the registry could
+/// not be stored
+pub async fn sync_factory<M: Middleware, S: RegistryStorage>(
+    registry: &mut Registry,
+    storage: &mut S,
+    provider: &M,
+    factory: &Factory,
+    from: u64,
+    to: u64,
+    chunk_size: u64,
+) -> Result<usize> {
+    let Some(event) = creation_event(factory) else {
+        warn!("{} has no pair creation event, it can't be synced", factory);
+        return Ok(0);
+    };
+    let topic: H256 = event.signature();
+    let chunk_size = chunk_size.max(1);
+    let mut created = 0;
+    let mut start = from;
+
+    while start <= to {
+        let end = to.min(start.saturating_add(chunk_size - 1));
+        let filter = Filter::new()
+            .address(factory.address)
+            .topic0(topic)
+            .from_block(start)
+            .to_block(end);
+        let logs = provider
+            .get_logs(&filter)
+            .await
+            .map_err(|e| anyhow!("Failed to get logs of {}: {}", factory, e))?;
+
+        for pair in logs
+            .iter()
+            .filter_map(|log| decode_pair_created(factory, log))
+        {
+            if register_pair(registry, storage, provider, pair).await? {
+                created += 1;
+            }
+        }
+
+        debug!(
+            "Synced {} blocks {}..={}, {} new pairs",
+            factory, start, end, created
+        );
+
+        start = end + 1;
+    }
+
+    Ok(created)
+}
+
+/// Register and store a pair with its tokens, returning whether it was new
+///
+/// Pairs with a token that isn't an ERC-20 are skipped.
+///
+/// # Errors
+///
+/// This function will return an error if the pair or its tokens could not be stored
+pub async fn register_pair<M: Middleware, S: RegistryStorage>(
+    registry: &mut Registry,
+    storage: &mut S,
+    provider: &M,
+    pair: PairInfo,
+) -> Result<bool> {
+    if registry.pair(pair.address).is_some() {
+        return Ok(false);
+    }
+    if !register_tokens(registry, storage, provider, &pair).await? {
+        return Ok(false);
+    }
+
+    storage.store_pair(&pair).await?;
+
+    Ok(registry.insert_pair(pair))
+}
+
+/// Make sure both tokens of `pair` are registered, `false` if one isn't an ERC-20
+async fn register_tokens<M: Middleware, S: RegistryStorage>(
+    registry: &mut Registry,
+    storage: &mut S,
+    provider: &M,
+    pair: &PairInfo,
+) -> Result<bool> {
+    for address in [pair.token0, pair.token1] {
+        let token = match registry.token(address) {
+            Some(known) if known.first_seen_block.is_some() => continue,
+            // Tokens from the settings are known but have never been seen in a pair
+            Some(known) => known.clone(),
+            None => match fetch_token(provider, address, Some(pair.created_block)).await {
+                Ok(token) => token,
+                Err(e) => {
+                    debug!("Skipping pair {:?}: {}", pair.address, e);
+                    return Ok(false);
+                }
+            },
+        };
+        let token = TokenInfo {
+            first_seen_block: Some(pair.created_block),
+            ..token
+        };
+
+        storage.store_token(&token).await?;
+        registry.insert_token(token);
+    }
+
+    Ok(true)
+}
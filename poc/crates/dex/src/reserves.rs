@@ -0,0 +1,368 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use ethers::abi::{self, ParamType, Token};
+use ethers::providers::Middleware;
+use ethers::types::{Address, BlockId, Log, TransactionRequest, H256, U256};
+use ethers::utils::{id, keccak256};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::candle::to_units;
+use crate::registry::{PairInfo, Registry};
+use crate::swap::{SwapKind, SwapRecord};
+use crate::v3_pool::V3Pool;
+
+/// Fee of Uniswap V2 style pairs, in basis points
+pub const V2_FEE_BPS: u32 = 30;
+const BPS: u32 = 10_000;
+
+/// `Sync(uint112,uint112)` of Uniswap V2 style pairs
+pub fn sync_topic() -> H256 {
+    H256::from(keccak256("Sync(uint112,uint112)"))
+}
+
+/// Reserves of a V2 pair
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reserves {
+    pub reserve0: U256,
+    pub reserve1: U256,
+    /// Block the reserves were read at, `None` when read at the latest block
+    pub block_number: Option<u64>,
+}
+
+impl Reserves {
+    /// Reserves of `token_in` and of the other token of `pair`, `None` if `token_in` isn't in
+    /// the pair
+    pub fn oriented(&self, pair: &PairInfo, token_in: Address) -> Option<(U256, U256)> {
+        if token_in == pair.token0 {
+            Some((self.reserve0, self.reserve1))
+        } else if token_in == pair.token1 {
+            Some((self.reserve1, self.reserve0))
+        } else {
+            None
+        }
+    }
+}
+
+/// Decode the reserves of a `Sync` log
+pub fn decode_sync(log: &Log) -> Option<Reserves> {
+    if log.topics.first() != Some(&sync_topic()) {
+        return None;
+    }
+
+    let mut tokens = abi::decode(&[ParamType::Uint(112), ParamType::Uint(112)], &log.data)
+        .ok()?
+        .into_iter();
+
+    Some(Reserves {
+        reserve0: tokens.next()?.into_uint()?,
+        reserve1: tokens.next()?.into_uint()?,
+        block_number: log.block_number.map(|number| number.as_u64()),
+    })
+}
+
+/// Output of a constant product pool for an exact `amount_in`, as computed by
+/// `UniswapV2Library.getAmountOut`
+///
+/// Returns `None` if the pool has no liquidity or the amounts overflow.
+pub fn amount_out(
+    amount_in: U256,
+    reserve_in: U256,
+    reserve_out: U256,
+    fee_bps: u32,
+) -> Option<U256> {
+    if amount_in.is_zero() || reserve_in.is_zero() || reserve_out.is_zero() {
+        return None;
+    }
+
+    let amount_in_with_fee = amount_in.checked_mul(U256::from(BPS - fee_bps))?;
+    let numerator = amount_in_with_fee.checked_mul(reserve_out)?;
+    let denominator = reserve_in
+        .checked_mul(U256::from(BPS))?
+        .checked_add(amount_in_with_fee)?;
+
+    Some(numerator / denominator)
+}
+
+/// Input a constant product pool needs for an exact `amount_out`, as computed by
+/// `UniswapV2Library.getAmountIn`
+///
+/// Returns `None` if the pool has no liquidity, doesn't hold `amount_out` or the amounts
+/// overflow.
+pub fn amount_in(
+    amount_out: U256,
+    reserve_in: U256,
+    reserve_out: U256,
+    fee_bps: u32,
+) -> Option<U256> {
+    if amount_out.is_zero() || reserve_in.is_zero() || amount_out >= reserve_out {
+        return None;
+    }
+
+    let numerator = reserve_in
+        .checked_mul(amount_out)?
+        .checked_mul(U256::from(BPS))?;
+    let denominator = (reserve_out - amount_out).checked_mul(U256::from(BPS - fee_bps))?;
+
+    Some(numerator / denominator + 1)
+}
+
+/// How much worse than the spot price `amount_in` is swapped at, from 0 to 1
+///
+/// The fee is left out, so this only measures how far the swap moves the pool.
+pub fn price_impact(amount_in: U256, reserve_in: U256) -> Option<f64> {
+    let total = reserve_in.checked_add(amount_in)?;
+    if total.is_zero() {
+        return None;
+    }
+
+    Some(to_units(amount_in, 0) / to_units(total, 0))
+}
+
+/// Words of the tick bitmap read on each side of the current tick when a V3 pool is fetched
+pub const V3_BITMAP_WORDS: i32 = 2;
+
+/// Latest known reserves of the V2 pairs and state of the V3 pools
+///
+/// Reserves come from the `Sync` events of the pairs, or are read from the pair with
+/// `getReserves` the first time they're needed. V3 pools are read the first time they're
+/// needed, then follow their `Mint`, `Burn` and `Swap` events.
+#[derive(Debug, Clone, Default)]
+pub struct ReserveTracker {
+    reserves: HashMap<Address, Reserves>,
+    /// V3 pools with the block they were read at
+    pools: HashMap<Address, (V3Pool, u64)>,
+}
+
+impl ReserveTracker {
+    pub fn get(&self, pair: Address) -> Option<&Reserves> {
+        self.reserves.get(&pair)
+    }
+
+    pub fn v3_pool(&self, pool: Address) -> Option<&V3Pool> {
+        self.pools.get(&pool).map(|(pool, _)| pool)
+    }
+
+    pub fn len(&self) -> usize {
+        self.reserves.len() + self.pools.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.reserves.is_empty() && self.pools.is_empty()
+    }
+
+    /// Set the reserves of `pair`, unless the known ones are from a later block
+    ///
+    /// Returns whether the reserves were updated.
+    pub fn update(&mut self, pair: Address, reserves: Reserves) -> bool {
+        if let Some(known) = self.reserves.get(&pair) {
+            if let (Some(known), Some(new)) = (known.block_number, reserves.block_number) {
+                if known > new {
+                    return false;
+                }
+            }
+        }
+
+        self.reserves.insert(pair, reserves);
+
+        true
+    }
+
+    /// Apply a `Sync` log of a registered V2 pair, returning the pair it updated
+    pub fn apply_sync(&mut self, registry: &Registry, log: &Log) -> Option<Address> {
+        registry
+            .pair(log.address)
+            .filter(|pair| pair.protocol_version == 2)?;
+        let reserves = decode_sync(log)?;
+
+        self.update(log.address, reserves).then_some(log.address)
+    }
+
+    /// Apply a log of a tracked V3 pool, returning the pool it updated
+    ///
+    /// Logs of blocks up to the one the pool was read at are already in its state and are
+    /// skipped.
+    pub fn apply_v3_log(&mut self, log: &Log) -> Option<Address> {
+        let (pool, read_at) = self.pools.get_mut(&log.address)?;
+        if log.block_number? <= (*read_at).into() {
+            return None;
+        }
+
+        match pool.apply_log(log) {
+            Ok(updated) => updated.then_some(log.address),
+            Err(e) => {
+                // Logs were missed, the pool is read again when next needed
+                warn!("Dropping V3 pool {:?}: {}", log.address, e);
+                self.pools.remove(&log.address);
+                None
+            }
+        }
+    }
+
+    /// Track the reserves of `pair` read with `getReserves`, unless a `Sync` event was applied
+    /// while they were read
+    pub fn insert_fetched(&mut self, pair: Address, reserves: Reserves) -> Reserves {
+        *self.reserves.entry(pair).or_insert(reserves)
+    }
+
+    /// Track a V3 pool read at `block`, unless it was already read meanwhile
+    pub fn insert_v3_pool(&mut self, pool: V3Pool, block: u64) -> &V3Pool {
+        &self.pools.entry(pool.address).or_insert((pool, block)).0
+    }
+}
+
+/// Read the state of a V3 pool at the latest block, with the block it was read at
+///
+/// # Errors
+///
+/// This function will return an error if the pool could not be read
+pub async fn fetch_v3_pool<M: Middleware>(provider: &M, pool: Address) -> Result<(V3Pool, u64)> {
+    let block = provider
+        .get_block_number()
+        .await
+        .map_err(|e| anyhow!("Failed to get the block number: {}", e))?
+        .as_u64();
+    let state = V3Pool::fetch(provider, pool, Some(block), V3_BITMAP_WORDS).await?;
+
+    Ok((state, block))
+}
+
+/// Read the reserves of a V2 pair with `getReserves`
+///
+/// # Errors
+///
+/// This function will return an error if the call failed or didn't return reserves
+pub async fn fetch_reserves<M: Middleware>(
+    provider: &M,
+    pair: Address,
+    block: Option<u64>,
+) -> Result<Reserves> {
+    let tx = TransactionRequest::new()
+        .to(pair)
+        .data(id("getReserves()").to_vec());
+    let output = provider
+        .call(&tx.into(), block.map(BlockId::from))
+        .await
+        .map_err(|e| anyhow!("getReserves of {:?} failed: {}", pair, e))?;
+    let tokens = abi::decode(
+        &[
+            ParamType::Uint(112),
+            ParamType::Uint(112),
+            ParamType::Uint(32),
+        ],
+        &output,
+    )?;
+
+    match tokens.as_slice() {
+        [Token::Uint(reserve0), Token::Uint(reserve1), _] => Ok(Reserves {
+            reserve0: *reserve0,
+            reserve1: *reserve1,
+            block_number: block,
+        }),
+        _ => Err(anyhow!("getReserves of {:?} returned no reserves", pair)),
+    }
+}
+
+/// Outcome of a V2 swap simulated against the tracked reserves
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SwapSimulation {
+    /// Pair of each hop of the swap
+    pub pairs: Vec<Address>,
+    /// Amount of each token of the path, from the amount in to the amount out
+    pub amounts: Vec<U256>,
+    /// Price impact of each hop
+    pub price_impacts: Vec<f64>,
+    /// Whether the simulated amounts are within the limit of the swap, i.e. it wouldn't revert
+    /// for slippage
+    pub within_limit: bool,
+}
+
+impl SwapSimulation {
+    pub fn amount_in(&self) -> U256 {
+        self.amounts.first().copied().unwrap_or_default()
+    }
+
+    pub fn amount_out(&self) -> U256 {
+        self.amounts.last().copied().unwrap_or_default()
+    }
+
+    /// Price impact of the whole route
+    pub fn price_impact(&self) -> f64 {
+        1.0 - self
+            .price_impacts
+            .iter()
+            .map(|impact| 1.0 - impact)
+            .product::<f64>()
+    }
+}
+
+/// Pairs of `factory` for each hop of `path`, `None` if one isn't registered
+pub fn v2_pairs<'a>(
+    registry: &'a Registry,
+    factory: Address,
+    path: &[Address],
+) -> Option<Vec<&'a PairInfo>> {
+    path.windows(2)
+        .map(|tokens| registry.find_pair(factory, tokens[0], tokens[1], None))
+        .collect()
+}
+
+/// Simulate a V2 swap through `pairs` with `reserves`
+///
+/// Exact input swaps are simulated from the amount in, exact output ones from the amount out.
+/// Returns `None` for V3 swaps, or if a pair has no known reserves or can't fill the swap.
+pub fn simulate_swap(
+    swap: &SwapRecord,
+    pairs: &[&PairInfo],
+    reserves: &ReserveTracker,
+) -> Option<SwapSimulation> {
+    if !swap.fees.is_empty() || pairs.len() + 1 != swap.path.len() {
+        return None;
+    }
+
+    // Reserves of the token in and out of each hop
+    let hops = pairs
+        .iter()
+        .zip(&swap.path)
+        .map(|(pair, token_in)| reserves.get(pair.address)?.oriented(pair, *token_in))
+        .collect::<Option<Vec<(U256, U256)>>>()?;
+
+    let amounts = match swap.kind {
+        SwapKind::ExactIn => {
+            let mut amounts = vec![swap.amount_in];
+            for (reserve_in, reserve_out) in &hops {
+                let amount = *amounts.last()?;
+                amounts.push(amount_out(amount, *reserve_in, *reserve_out, V2_FEE_BPS)?);
+            }
+
+            amounts
+        }
+        SwapKind::ExactOut => {
+            let mut amounts = vec![swap.amount_out];
+            for (reserve_in, reserve_out) in hops.iter().rev() {
+                let amount = *amounts.last()?;
+                amounts.push(amount_in(amount, *reserve_in, *reserve_out, V2_FEE_BPS)?);
+            }
+            amounts.reverse();
+
+            amounts
+        }
+    };
+    let price_impacts = hops
+        .iter()
+        .zip(&amounts)
+        .map(|((reserve_in, _), amount)| price_impact(*amount, *reserve_in))
+        .collect::<Option<Vec<f64>>>()?;
+    let within_limit = match swap.kind {
+        SwapKind::ExactIn => amounts.last()? >= &swap.amount_out,
+        SwapKind::ExactOut => amounts.first()? <= &swap.amount_in,
+    };
+
+    Some(SwapSimulation {
+        pairs: pairs.iter().map(|pair| pair.address).collect(),
+        amounts,
+        price_impacts,
+        within_limit,
+    })
+}
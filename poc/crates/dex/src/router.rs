@@ -3,12 +3,15 @@ use anyhow::Result;
 use block_explorer::blockexplorerapi::BlockExplorerApi;
 use ethers::types::{Transaction, H160};
 use ethers::{abi::Abi, types::Address};
-use log::{debug, trace};
+use log::trace;
 use serde::Deserialize;
 use std::fmt;
 use std::fmt::Display;
 use std::fmt::Formatter;
+use std::path::PathBuf;
 
+use crate::bindings::{AbiSource, Protocol};
+use crate::decoded::{decode_call, Decoded, NotDecodable, Selectors};
 use crate::factory::get_factory;
 use crate::factory::Factory;
 use crate::{decode_debug, DecodableTransaction};
@@ -17,6 +20,18 @@ use crate::{decode_debug, DecodableTransaction};
 pub struct RouterAddress {
     pub address: Address,
     pub abi: Abi,
+    /// Functions of `abi` by selector
+    pub selectors: Selectors,
+}
+
+impl RouterAddress {
+    pub fn new(address: Address, abi: Abi) -> Self {
+        Self {
+            address,
+            selectors: Selectors::new(&abi),
+            abi,
+        }
+    }
 }
 
 impl PartialEq<H160> for RouterAddress {
@@ -62,47 +77,28 @@ impl Router {
 }
 
 impl DecodableTransaction for Router {
-    async fn decode_tx(&self, tx: Transaction) -> Result<()> {
-        let data = tx.input.0.to_vec();
+    async fn decode_tx(&self, tx: Transaction) -> Result<Decoded> {
+        let Some(to) = tx.to else {
+            return Ok(Decoded::NotDecodable(NotDecodable::ContractCreation));
+        };
+        let Some(address) = self.get_address(to) else {
+            return Ok(Decoded::NotDecodable(NotDecodable::UnknownContract {
+                contract: to,
+            }));
+        };
 
-        if tx.to.is_none() {
-            return Ok(());
+        trace!("Decoding TX: {}", tx.hash);
+        let decoded = decode_call(&address.selectors, &tx, to, &self.name);
+        match &decoded {
+            Decoded::Call(call) => trace!(
+                "{} {}",
+                Colour::Yellow.bold().paint("Input: "),
+                decode_debug(call)
+            ),
+            Decoded::NotDecodable(reason) => trace!("Failed to decode TX {}: {}", tx.hash, reason),
         }
 
-        let abi = self
-            .addresses
-            .iter()
-            .find(|a| *a == &tx.to.unwrap())
-            .unwrap()
-            .abi
-            .clone();
-
-        trace!("Decoding TX: {}", tx.hash);
-        abi.functions.iter().for_each(|(name, functions)| {
-            functions.iter().for_each(|f| {
-                if let Ok(tokens) = f.decode_input(&data) {
-                    debug!(
-                        "{} {}",
-                        Colour::Yellow.bold().paint("Input: "),
-                        decode_debug(f, tokens)
-                    );
-                } else {
-                    trace!("Failed to decode TX output with function: {}", name);
-                }
-
-                if let Ok(tokens) = f.decode_output(&data) {
-                    debug!(
-                        "{} {}",
-                        Colour::Purple.bold().paint("Output: "),
-                        decode_debug(f, tokens)
-                    );
-                } else {
-                    trace!("Failed to decode TX output with function: {}", name);
-                }
-            });
-        });
-
-        Ok(())
+        Ok(decoded)
     }
 }
 
@@ -113,37 +109,67 @@ pub struct RouterSettings {
     pub version: u8,
     pub factory: String,
     pub addresses: Vec<String>,
+    /// Use the bundled ABIs of a well-known protocol instead of fetching them
+    pub protocol: Option<Protocol>,
+    /// Local JSON ABI of the router addresses, takes precedence over `protocol`
+    pub router_abi: Option<String>,
+    /// Local JSON ABI of the factory, takes precedence over `protocol`
+    pub factory_abi: Option<String>,
+}
+
+impl RouterSettings {
+    /// Where the ABI of the router addresses comes from
+    pub fn router_abi_source(&self) -> AbiSource {
+        match (&self.router_abi, self.protocol) {
+            (Some(path), _) => AbiSource::File(PathBuf::from(path)),
+            (None, Some(protocol)) => AbiSource::Builtin(protocol.router_abi()),
+            (None, None) => AbiSource::BlockExplorer,
+        }
+    }
+
+    /// Where the ABI of the factory comes from
+    pub fn factory_abi_source(&self) -> AbiSource {
+        match (&self.factory_abi, self.protocol) {
+            (Some(path), _) => AbiSource::File(PathBuf::from(path)),
+            (None, Some(protocol)) => AbiSource::Builtin(protocol.factory_abi()),
+            (None, None) => AbiSource::BlockExplorer,
+        }
+    }
 }
 
-/// Get a router from an indexer
+/// Get a router, with the ABIs from the sources configured in its settings
 ///
 /// # Arguments
 ///
-/// * `indexer` - An indexer that implements the IndexerAPI trait
-/// * `addresses` - A vector of addresses of the routers
-/// * `name` - The name of the router
-/// * `version` - The version of the router
+/// * `indexer` - An indexer that implements the IndexerAPI trait, used for the ABIs that aren't
+///   built in or local
+/// * `settings` - The settings of the router
 ///
 /// # Returns
 ///
 /// A router
 pub async fn get_router<T: BlockExplorerApi>(
     indexer: &T,
-    addresses: Vec<String>,
-    factory: String,
-    name: String,
-    version: u8,
+    settings: &RouterSettings,
 ) -> Result<Router> {
-    let factory = get_factory(indexer, factory, name.clone(), version).await?;
+    let factory = get_factory(
+        indexer,
+        settings.factory.clone(),
+        &settings.factory_abi_source(),
+        settings.name.clone(),
+        settings.version,
+    )
+    .await?;
 
     let router_addresses = {
         let mut ra = Vec::<RouterAddress>::new();
+        let source = settings.router_abi_source();
 
-        for address in addresses.clone().iter() {
+        for address in settings.addresses.iter() {
             let address = address.parse::<Address>()?;
-            let abi = indexer.get_abi(address).await?;
+            let abi = source.load(indexer, address).await?;
 
-            ra.push(RouterAddress { address, abi });
+            ra.push(RouterAddress::new(address, abi));
         }
 
         ra
@@ -152,7 +178,7 @@ pub async fn get_router<T: BlockExplorerApi>(
     Ok(Router {
         addresses: router_addresses,
         factory,
-        name,
-        version,
+        name: settings.name.clone(),
+        version: settings.version,
     })
 }
@@ -0,0 +1,330 @@
+use anyhow::{anyhow, bail, Result};
+use ethers::types::{Address, U256};
+use serde::{Deserialize, Serialize};
+
+use crate::candle::to_units;
+use crate::registry::PairInfo;
+use crate::reserves::{self, Reserves, V2_FEE_BPS};
+use crate::swap::{SwapKind, SwapRecord};
+use crate::v3_math::FEE_PIPS;
+use crate::v3_pool::V3Pool;
+
+/// Doublings of the front-run searched before giving up, the front-run can't exceed 2^128
+/// times the swap
+const MAX_DOUBLINGS: u32 = 128;
+/// Bisection steps refining the largest front-run
+const BISECTION_STEPS: u32 = 64;
+
+/// A pool of a swap route with the state it is simulated against
+///
+/// Swapping changes the state, so a front-run, the swap and a back-run can be simulated one
+/// after the other on the same route.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PoolState {
+    V2 { pair: PairInfo, reserves: Reserves },
+    V3(V3Pool),
+}
+
+impl PoolState {
+    pub fn address(&self) -> Address {
+        match self {
+            Self::V2 { pair, .. } => pair.address,
+            Self::V3(pool) => pool.address,
+        }
+    }
+
+    /// Fee taken on the input, from 0 to 1
+    pub fn fee(&self) -> f64 {
+        match self {
+            Self::V2 { .. } => V2_FEE_BPS as f64 / 10_000.0,
+            Self::V3(pool) => pool.fee as f64 / FEE_PIPS as f64,
+        }
+    }
+
+    /// Marginal price of `token_in` in the other token, in raw amounts
+    pub fn spot_price(&self, token_in: Address) -> Result<f64> {
+        match self {
+            Self::V2 { pair, reserves } => {
+                let (reserve_in, reserve_out) = reserves
+                    .oriented(pair, token_in)
+                    .ok_or_else(|| anyhow!("Token {:?} is not in {:?}", token_in, pair.address))?;
+
+                Ok(to_units(reserve_out, 0) / to_units(reserve_in, 0))
+            }
+            Self::V3(pool) => {
+                let price = (to_units(pool.sqrt_price_x96, 0) / 2f64.powi(96)).powi(2);
+                if token_in == pool.token0 {
+                    Ok(price)
+                } else if token_in == pool.token1 {
+                    Ok(1.0 / price)
+                } else {
+                    bail!("Token {:?} is not in {:?}", token_in, pool.address)
+                }
+            }
+        }
+    }
+
+    /// Swap exactly `amount_in` of `token_in`, returning the amount out
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the pool can't fill the swap
+    pub fn swap_exact_in(&mut self, token_in: Address, amount_in: U256) -> Result<U256> {
+        match self {
+            Self::V2 { pair, reserves } => {
+                let (reserve_in, reserve_out) = reserves
+                    .oriented(pair, token_in)
+                    .ok_or_else(|| anyhow!("Token {:?} is not in {:?}", token_in, pair.address))?;
+                let amount_out =
+                    reserves::amount_out(amount_in, reserve_in, reserve_out, V2_FEE_BPS)
+                        .ok_or_else(|| anyhow!("Pair {:?} can't fill the swap", pair.address))?;
+                set_reserves(
+                    pair,
+                    reserves,
+                    token_in,
+                    reserve_in + amount_in,
+                    reserve_out - amount_out,
+                );
+
+                Ok(amount_out)
+            }
+            Self::V3(pool) => {
+                let quote = pool.quote_exact_in(token_in, amount_in)?;
+                if quote.amount_in != amount_in {
+                    bail!("Pool {:?} can't fill the swap", pool.address);
+                }
+                pool.sqrt_price_x96 = quote.sqrt_price_x96_after;
+                pool.tick = quote.tick_after;
+                pool.liquidity = quote.liquidity_after;
+
+                Ok(quote.amount_out)
+            }
+        }
+    }
+
+    /// Swap `token_in` for exactly `amount_out`, returning the amount in
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the pool can't fill the swap
+    pub fn swap_exact_out(&mut self, token_in: Address, amount_out: U256) -> Result<U256> {
+        match self {
+            Self::V2 { pair, reserves } => {
+                let (reserve_in, reserve_out) = reserves
+                    .oriented(pair, token_in)
+                    .ok_or_else(|| anyhow!("Token {:?} is not in {:?}", token_in, pair.address))?;
+                let amount_in =
+                    reserves::amount_in(amount_out, reserve_in, reserve_out, V2_FEE_BPS)
+                        .ok_or_else(|| anyhow!("Pair {:?} can't fill the swap", pair.address))?;
+                set_reserves(
+                    pair,
+                    reserves,
+                    token_in,
+                    reserve_in + amount_in,
+                    reserve_out - amount_out,
+                );
+
+                Ok(amount_in)
+            }
+            Self::V3(pool) => {
+                let quote = pool.quote_exact_out(token_in, amount_out)?;
+                pool.sqrt_price_x96 = quote.sqrt_price_x96_after;
+                pool.tick = quote.tick_after;
+                pool.liquidity = quote.liquidity_after;
+
+                Ok(quote.amount_in)
+            }
+        }
+    }
+}
+
+fn set_reserves(
+    pair: &PairInfo,
+    reserves: &mut Reserves,
+    token_in: Address,
+    reserve_in: U256,
+    reserve_out: U256,
+) {
+    if token_in == pair.token0 {
+        reserves.reserve0 = reserve_in;
+        reserves.reserve1 = reserve_out;
+    } else {
+        reserves.reserve0 = reserve_out;
+        reserves.reserve1 = reserve_in;
+    }
+}
+
+/// Swap exactly `amount_in` through `pools`, along `path` or back along it if `reverse`
+fn route_exact_in(
+    pools: &mut [PoolState],
+    path: &[Address],
+    amount_in: U256,
+    reverse: bool,
+) -> Result<U256> {
+    let mut amount = amount_in;
+    if reverse {
+        for (pool, token_in) in pools.iter_mut().rev().zip(path.iter().rev()) {
+            amount = pool.swap_exact_in(*token_in, amount)?;
+        }
+    } else {
+        for (pool, token_in) in pools.iter_mut().zip(path) {
+            amount = pool.swap_exact_in(*token_in, amount)?;
+        }
+    }
+
+    Ok(amount)
+}
+
+/// Amount of the first token of `path` needed to get exactly `amount_out` of the last one
+fn route_exact_out(pools: &mut [PoolState], path: &[Address], amount_out: U256) -> Result<U256> {
+    let mut amount = amount_out;
+    for (pool, token_in) in pools.iter_mut().zip(path).rev() {
+        amount = pool.swap_exact_out(*token_in, amount)?;
+    }
+
+    Ok(amount)
+}
+
+/// Slippage and sandwich exposure of a pending swap
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SwapScore {
+    /// Pools of the route the swap was scored against
+    pub pools: Vec<Address>,
+    /// Amount out of an exact input swap, or amount in of an exact output one, at the current
+    /// state of the pools
+    pub expected_amount: U256,
+    /// How much worse than expected the swap accepts to be filled, from 0 to 1
+    pub slippage_tolerance: f64,
+    /// How far the swap moves the price of the route, from 0 to 1, fees aside
+    pub price_impact: f64,
+    /// Largest front-run, in the token in, after which the swap still fills within its limit
+    pub front_run_amount: U256,
+    /// Profit of that front-run and the back-run selling its output, in the token in
+    pub sandwich_profit: U256,
+    /// Share of the slippage tolerance a sandwich extracts as profit, from 0 to 1
+    pub vulnerability: f64,
+}
+
+/// Amount the swap gets (exact in) or pays (exact out) after `front_run` of the token in went
+/// through the route, with the state of the pools after it
+fn fill(
+    swap: &SwapRecord,
+    pools: &[PoolState],
+    front_run: U256,
+) -> Result<(U256, U256, Vec<PoolState>)> {
+    let mut pools = pools.to_vec();
+    let front_run_out = if front_run.is_zero() {
+        U256::zero()
+    } else {
+        route_exact_in(&mut pools, &swap.path, front_run, false)?
+    };
+    let filled = match swap.kind {
+        SwapKind::ExactIn => route_exact_in(&mut pools, &swap.path, swap.amount_in, false)?,
+        SwapKind::ExactOut => route_exact_out(&mut pools, &swap.path, swap.amount_out)?,
+    };
+
+    Ok((front_run_out, filled, pools))
+}
+
+/// Whether a swap filled with `filled` is within its limit
+fn within_limit(swap: &SwapRecord, filled: U256) -> bool {
+    match swap.kind {
+        SwapKind::ExactIn => filled >= swap.amount_out,
+        SwapKind::ExactOut => filled <= swap.amount_in,
+    }
+}
+
+/// Profit of sandwiching the swap with `front_run`, `None` if the swap would then exceed its
+/// limit
+fn sandwich_profit(swap: &SwapRecord, pools: &[PoolState], front_run: U256) -> Option<U256> {
+    let (front_run_out, filled, mut pools) = fill(swap, pools, front_run).ok()?;
+    if !within_limit(swap, filled) {
+        return None;
+    }
+    let back_run_out = route_exact_in(&mut pools, &swap.path, front_run_out, true).ok()?;
+
+    Some(back_run_out.saturating_sub(front_run))
+}
+
+/// Score a pending swap against the current state of the pools of its route
+///
+/// The largest front-run is searched by doubling then bisection, the profit of a sandwich
+/// being highest when the swap is left right at its limit.
+///
+/// # Errors
+///
+/// This function will return an error if `pools` doesn't match the path of the swap or the
+/// swap can't be filled even without a front-run
+pub fn score_swap(swap: &SwapRecord, pools: &[PoolState]) -> Result<SwapScore> {
+    if pools.is_empty() || pools.len() + 1 != swap.path.len() {
+        bail!("Route of swap {:?} doesn't match its pools", swap.tx_hash);
+    }
+
+    let (_, expected, _) = fill(swap, pools, U256::zero())?;
+    let (amount_in, amount_out, limit) = match swap.kind {
+        SwapKind::ExactIn => (swap.amount_in, expected, swap.amount_out),
+        SwapKind::ExactOut => (expected, swap.amount_out, swap.amount_in),
+    };
+    let expected_units = to_units(expected, 0);
+    let slippage_tolerance = if expected.is_zero() {
+        0.0
+    } else {
+        match swap.kind {
+            SwapKind::ExactIn => (expected_units - to_units(limit, 0)) / expected_units,
+            SwapKind::ExactOut => (to_units(limit, 0) - expected_units) / expected_units,
+        }
+        .max(0.0)
+    };
+
+    // Spot prices of each hop, compared with what the swap gets after fees
+    let mut spot = 1.0;
+    let mut fees = 1.0;
+    for (pool, token_in) in pools.iter().zip(&swap.path) {
+        spot *= pool.spot_price(*token_in)?;
+        fees *= 1.0 - pool.fee();
+    }
+    let execution = to_units(amount_out, 0) / (to_units(amount_in, 0) * fees);
+    let price_impact = if spot > 0.0 {
+        (1.0 - execution / spot).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+
+    // Largest front-run still within the limit of the swap
+    let mut low = U256::zero();
+    let mut high = amount_in.max(U256::one());
+    let mut doublings = 0;
+    while sandwich_profit(swap, pools, high).is_some() && doublings < MAX_DOUBLINGS {
+        low = high;
+        high = high.saturating_mul(U256::from(2));
+        doublings += 1;
+    }
+    for _ in 0..BISECTION_STEPS {
+        if high - low <= U256::one() {
+            break;
+        }
+        let mid = low + (high - low) / 2;
+        if sandwich_profit(swap, pools, mid).is_some() {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    let sandwich_profit = sandwich_profit(swap, pools, low).unwrap_or_default();
+
+    let vulnerability = if slippage_tolerance > 0.0 && !amount_in.is_zero() {
+        (to_units(sandwich_profit, 0) / to_units(amount_in, 0) / slippage_tolerance).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+
+    Ok(SwapScore {
+        pools: pools.iter().map(PoolState::address).collect(),
+        expected_amount: expected,
+        slippage_tolerance,
+        price_impact,
+        front_run_amount: low,
+        sandwich_profit,
+        vulnerability,
+    })
+}
@@ -0,0 +1,490 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use anyhow::{bail, Result};
+use ethers::abi::{Function, ParamType, Token};
+use ethers::types::{Address, Bytes, Transaction, H256, U256};
+use log::trace;
+use serde::{Deserialize, Serialize};
+
+use crate::decoded::MAX_DEPTH;
+use crate::router::{Router, RouterAddress};
+use crate::scoring::SwapScore;
+use crate::token::{format_units, TokenMetadata};
+use crate::universal_router::{self, commands_of, resolve_recipient, Command};
+use crate::v3::{decode_route, decode_v3_swap, hops, Hop};
+
+/// Which side of a swap is fixed by the caller
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SwapKind {
+    /// `amount_in` is exact and `amount_out` is the minimum accepted
+    ExactIn,
+    /// `amount_out` is exact and `amount_in` is the maximum spent
+    ExactOut,
+}
+
+impl SwapKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::ExactIn => "exact_in",
+            Self::ExactOut => "exact_out",
+        }
+    }
+}
+
+impl fmt::Display for SwapKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for SwapKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "exact_in" => Ok(Self::ExactIn),
+            "exact_out" => Ok(Self::ExactOut),
+            _ => bail!("Unknown swap kind {}", s),
+        }
+    }
+}
+
+/// Whether a swap was decoded from a pending or a mined tx
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SwapStatus {
+    Pending,
+    Mined,
+}
+
+impl SwapStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Mined => "mined",
+        }
+    }
+}
+
+impl fmt::Display for SwapStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for SwapStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pending" => Ok(Self::Pending),
+            "mined" => Ok(Self::Mined),
+            _ => bail!("Unknown swap status {}", s),
+        }
+    }
+}
+
+/// A swap decoded from a router call
+///
+/// Amounts are the ones in the calldata, so one of them is a limit rather than what was
+/// actually traded, see `SwapKind`. Swaps paying with ether use the value of the tx as
+/// `amount_in`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SwapRecord {
+    pub tx_hash: H256,
+    /// Position of the swap in its tx, for txs making several swaps
+    pub swap_index: u32,
+    /// Name of the router, e.g. `uniswap`
+    pub router: String,
+    pub protocol_version: u8,
+    /// Router function that was called
+    pub method: String,
+    pub sender: Address,
+    /// Who receives the output tokens
+    pub recipient: Option<Address>,
+    /// Tokens swapped through, from the token in to the token out
+    pub path: Vec<Address>,
+    /// Fee tier of the pool of each hop of `path` for V3 swaps, empty for V2 style pairs
+    pub fees: Vec<u32>,
+    pub kind: SwapKind,
+    pub amount_in: U256,
+    pub amount_out: U256,
+    /// Unix time after which the router rejects the swap
+    pub deadline: Option<u64>,
+    pub status: SwapStatus,
+    pub block_number: Option<u64>,
+    pub block_hash: Option<H256>,
+    pub transaction_index: Option<u64>,
+    /// Slippage and sandwich exposure of a pending swap, it isn't stored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score: Option<SwapScore>,
+    /// Amounts in whole tokens with the metadata of the tokens, it isn't stored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amounts: Option<SwapAmounts>,
+}
+
+impl SwapRecord {
+    pub fn token_in(&self) -> Option<Address> {
+        self.path.first().copied()
+    }
+
+    pub fn token_out(&self) -> Option<Address> {
+        self.path.last().copied()
+    }
+
+    /// Pools of a V3 swap, empty for V2 style swaps
+    pub fn hops(&self) -> Vec<Hop> {
+        hops(&self.path, &self.fees)
+    }
+
+    /// What the swap trades, e.g. `1.5 WETH for 2804.12 USDC`, raw if the amounts aren't
+    /// resolved
+    pub fn amounts_debug(&self) -> String {
+        match &self.amounts {
+            Some(amounts) => format!(
+                "{} {} for {} {}",
+                amounts.amount_in,
+                amounts.token_in.label(),
+                amounts.amount_out,
+                amounts.token_out.label()
+            ),
+            None => format!(
+                "{} of {:?} for {} of {:?}",
+                self.amount_in,
+                self.token_in().unwrap_or_default(),
+                self.amount_out,
+                self.token_out().unwrap_or_default()
+            ),
+        }
+    }
+}
+
+/// Amounts of a swap in whole tokens
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SwapAmounts {
+    pub token_in: TokenMetadata,
+    pub token_out: TokenMetadata,
+    /// `amount_in` in whole tokens, e.g. `1.5`
+    pub amount_in: String,
+    pub amount_out: String,
+}
+
+impl SwapAmounts {
+    /// Amounts of `swap`, `None` unless the decimals of both its tokens are known
+    pub fn new(swap: &SwapRecord, tokens: &HashMap<Address, TokenMetadata>) -> Option<Self> {
+        let token_in = tokens.get(&swap.token_in()?)?;
+        let token_out = tokens.get(&swap.token_out()?)?;
+
+        Some(Self {
+            amount_in: format_units(swap.amount_in, token_in.decimals?),
+            amount_out: format_units(swap.amount_out, token_out.decimals?),
+            token_in: token_in.clone(),
+            token_out: token_out.clone(),
+        })
+    }
+}
+
+/// Named arguments of a decoded router call
+pub(crate) struct Arguments<'a> {
+    pub(crate) function: &'a Function,
+    pub(crate) tokens: Vec<Token>,
+}
+
+impl Arguments<'_> {
+    pub(crate) fn get(&self, name: &str) -> Option<&Token> {
+        self.function
+            .inputs
+            .iter()
+            .position(|input| input.name == name)
+            .and_then(|index| self.tokens.get(index))
+    }
+
+    pub(crate) fn uint(&self, name: &str) -> Option<U256> {
+        self.get(name).cloned().and_then(Token::into_uint)
+    }
+
+    pub(crate) fn address(&self, name: &str) -> Option<Address> {
+        self.get(name).cloned().and_then(Token::into_address)
+    }
+
+    fn addresses(&self, name: &str) -> Option<Vec<Address>> {
+        self.get(name)
+            .cloned()
+            .and_then(Token::into_array)?
+            .into_iter()
+            .map(Token::into_address)
+            .collect()
+    }
+
+    /// First argument of type `kind`, for batching arguments whose name varies between ABIs
+    fn of_kind(&self, kind: &ParamType) -> Option<&Token> {
+        self.function
+            .inputs
+            .iter()
+            .position(|input| &input.kind == kind)
+            .and_then(|index| self.tokens.get(index))
+    }
+
+    fn bytes_array(&self) -> Vec<Vec<u8>> {
+        self.of_kind(&ParamType::Array(Box::new(ParamType::Bytes)))
+            .cloned()
+            .and_then(Token::into_array)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(Token::into_bytes)
+            .collect()
+    }
+}
+
+/// Swap of `tx` through `router` with what the tx tells, the call specific fields are empty
+fn base_record(router: &Router, tx: &Transaction, method: &str) -> SwapRecord {
+    swap_record(&router.name, router.version, tx, method)
+}
+
+/// Swap of `tx` through the protocol `name` with what the tx tells, the call specific fields
+/// are empty
+pub(crate) fn swap_record(name: &str, version: u8, tx: &Transaction, method: &str) -> SwapRecord {
+    SwapRecord {
+        tx_hash: tx.hash,
+        swap_index: 0,
+        router: name.to_string(),
+        protocol_version: version,
+        method: method.to_string(),
+        sender: tx.from,
+        recipient: None,
+        path: Vec::new(),
+        fees: Vec::new(),
+        kind: SwapKind::ExactIn,
+        amount_in: U256::zero(),
+        amount_out: U256::zero(),
+        deadline: None,
+        status: if tx.block_hash.is_some() {
+            SwapStatus::Mined
+        } else {
+            SwapStatus::Pending
+        },
+        block_number: tx.block_number.map(|n| n.as_u64()),
+        block_hash: tx.block_hash,
+        transaction_index: tx.transaction_index.map(|i| i.as_u64()),
+        score: None,
+        amounts: None,
+    }
+}
+
+pub(crate) fn clamp_deadline(deadline: U256) -> u64 {
+    deadline.min(U256::from(u64::MAX)).as_u64()
+}
+
+/// Decode the swaps made by a tx sent to one of the addresses of `router`, in order
+///
+/// Uniswap V2 style swaps (`swapExactTokensForTokens`, `swapETHForExactTokens`, ...) are
+/// recognized by their selector in the router ABI and the names of their arguments. Uniswap V3
+/// swaps (`exactInputSingle`, `exactInput`, ...) are decoded with the bundled router bindings,
+/// their packed paths expanded into tokens and fee tiers. Swaps batched in a `multicall` or in
+/// the commands of a Universal Router `execute` are decoded one by one and numbered by
+/// `swap_index`. Calls that aren't swaps, or that the ABI doesn't describe, make no swaps.
+pub fn decode_swaps(router: &Router, tx: &Transaction) -> Vec<SwapRecord> {
+    let Some(address) = tx.to.and_then(|to| router.get_address(to)) else {
+        return Vec::new();
+    };
+
+    let mut swaps = Vec::new();
+    collect_swaps(router, address, tx, &tx.input, None, 0, &mut swaps);
+    for (index, swap) in swaps.iter_mut().enumerate() {
+        swap.swap_index = index as u32;
+        // Routers taking batches accept placeholders for the sender and themselves
+        swap.recipient = swap
+            .recipient
+            .map(|recipient| resolve_recipient(recipient, tx.from, address.address));
+    }
+
+    swaps
+}
+
+/// Add the swaps made by `calldata`, a call to `address` made by `tx` directly or in a batch
+fn collect_swaps(
+    router: &Router,
+    address: &RouterAddress,
+    tx: &Transaction,
+    calldata: &[u8],
+    deadline: Option<u64>,
+    depth: usize,
+    swaps: &mut Vec<SwapRecord>,
+) {
+    let Ok(function) = address.selectors.lookup(calldata) else {
+        return;
+    };
+    let arguments = match function.decode_input(&calldata[4..]) {
+        Ok(tokens) => Arguments { function, tokens },
+        Err(e) => {
+            trace!(
+                "Failed to decode {} of tx {:?}: {}",
+                function.name,
+                tx.hash,
+                e
+            );
+            return;
+        }
+    };
+    // Batches carry the deadline of the calls they make
+    let deadline = arguments.uint("deadline").map(clamp_deadline).or(deadline);
+
+    match function.name.as_str() {
+        "multicall" if depth < MAX_DEPTH => {
+            for calldata in arguments.bytes_array() {
+                collect_swaps(router, address, tx, &calldata, deadline, depth + 1, swaps);
+            }
+        }
+        "execute" if depth < MAX_DEPTH => {
+            let commands = arguments
+                .of_kind(&ParamType::Bytes)
+                .cloned()
+                .and_then(Token::into_bytes)
+                .unwrap_or_default();
+            let inputs = arguments
+                .bytes_array()
+                .into_iter()
+                .map(Bytes::from)
+                .collect::<Vec<Bytes>>();
+
+            for command in commands_of(&commands, &inputs) {
+                collect_command_swaps(router, tx, &command, deadline, depth + 1, swaps);
+            }
+        }
+        name if name.starts_with("exact") => {
+            let Some(swap) = decode_v3_swap(calldata) else {
+                trace!("Failed to decode {} of tx {:?}", name, tx.hash);
+                return;
+            };
+
+            swaps.push(SwapRecord {
+                recipient: Some(swap.recipient),
+                path: swap.path,
+                fees: swap.fees,
+                kind: swap.kind,
+                amount_in: swap.amount_in,
+                amount_out: swap.amount_out,
+                deadline: swap.deadline.map(clamp_deadline).or(deadline),
+                ..base_record(router, tx, swap.method)
+            });
+        }
+        name if name.starts_with("swap") => {
+            if let Some(swap) = v2_swap(router, tx, &arguments, deadline) {
+                swaps.push(swap);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Swap made by a call to a V2 style `swap...` function of a router
+///
+/// These trade through V2 pairs whatever the version of the router, e.g. the
+/// `swapExactTokensForTokens` of `SwapRouter02`.
+fn v2_swap(
+    router: &Router,
+    tx: &Transaction,
+    arguments: &Arguments,
+    deadline: Option<u64>,
+) -> Option<SwapRecord> {
+    let path = arguments.addresses("path").filter(|path| path.len() >= 2)?;
+    let (kind, amount_in, amount_out) = if let Some(amount_out) = arguments.uint("amountOut") {
+        // Ether swaps send the maximum input as the value of the tx
+        let amount_in_max = arguments.uint("amountInMax").unwrap_or(tx.value);
+        (SwapKind::ExactOut, amount_in_max, amount_out)
+    } else {
+        let amount_in = arguments.uint("amountIn").unwrap_or(tx.value);
+        (
+            SwapKind::ExactIn,
+            amount_in,
+            arguments.uint("amountOutMin")?,
+        )
+    };
+
+    Some(SwapRecord {
+        recipient: arguments.address("to"),
+        path,
+        kind,
+        amount_in,
+        amount_out,
+        deadline,
+        protocol_version: 2,
+        ..base_record(router, tx, &arguments.function.name)
+    })
+}
+
+/// Add the swaps made by a Universal Router command, sub-plans included
+fn collect_command_swaps(
+    router: &Router,
+    tx: &Transaction,
+    command: &Command,
+    deadline: Option<u64>,
+    depth: usize,
+    swaps: &mut Vec<SwapRecord>,
+) {
+    if let Some(commands) = command.sub_plan() {
+        if depth < MAX_DEPTH {
+            for command in commands {
+                collect_command_swaps(router, tx, &command, deadline, depth + 1, swaps);
+            }
+        }
+    } else if let Some(swap) = command_swap(router, tx, command, deadline) {
+        swaps.push(swap);
+    }
+}
+
+/// Swap made by a `V2_SWAP_*` or `V3_SWAP_*` Universal Router command
+fn command_swap(
+    router: &Router,
+    tx: &Transaction,
+    command: &Command,
+    deadline: Option<u64>,
+) -> Option<SwapRecord> {
+    let (protocol_version, kind) = match command.command_type {
+        universal_router::V2_SWAP_EXACT_IN => (2, SwapKind::ExactIn),
+        universal_router::V2_SWAP_EXACT_OUT => (2, SwapKind::ExactOut),
+        universal_router::V3_SWAP_EXACT_IN => (3, SwapKind::ExactIn),
+        universal_router::V3_SWAP_EXACT_OUT => (3, SwapKind::ExactOut),
+        _ => return None,
+    };
+    let mut arguments = command
+        .arguments()
+        .ok()?
+        .into_iter()
+        .map(|(_, _, token)| token);
+    let recipient = arguments.next()?.into_address()?;
+    let amount = arguments.next()?.into_uint()?;
+    let limit = arguments.next()?.into_uint()?;
+    let (path, fees) = match arguments.next()? {
+        // V2 paths are in swap order for both kinds
+        Token::Array(path) => (
+            path.into_iter()
+                .map(Token::into_address)
+                .collect::<Option<Vec<Address>>>()?,
+            Vec::new(),
+        ),
+        Token::Bytes(path) => decode_route(&path, kind)?,
+        _ => return None,
+    };
+    if path.len() < 2 {
+        return None;
+    }
+    let (amount_in, amount_out) = match kind {
+        SwapKind::ExactIn => (amount, limit),
+        SwapKind::ExactOut => (limit, amount),
+    };
+
+    Some(SwapRecord {
+        protocol_version,
+        recipient: Some(recipient),
+        path,
+        fees,
+        kind,
+        amount_in,
+        amount_out,
+        deadline,
+        ..base_record(router, tx, command.name()?)
+    })
+}
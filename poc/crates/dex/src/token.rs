@@ -0,0 +1,143 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use ethers::abi::{self, ParamType};
+use ethers::providers::{Middleware, MiddlewareError};
+use ethers::types::{Address, BlockId, Bytes, TransactionRequest, U256};
+use ethers::utils::{self, id};
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+/// Metadata of an ERC-20 token as read from its contract
+///
+/// Tokens don't all follow the standard: some return `name` and `symbol` as `bytes32`, some
+/// revert or don't implement a function at all. What couldn't be read is `None`, a contract
+/// that isn't a token has no `decimals`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenMetadata {
+    pub address: Address,
+    pub name: Option<String>,
+    pub symbol: Option<String>,
+    pub decimals: Option<u8>,
+    pub total_supply: Option<U256>,
+    /// Unix time the metadata was read at
+    pub fetched_at: u64,
+}
+
+impl TokenMetadata {
+    /// Whether the metadata was read `refresh_after` seconds or more before `now`
+    pub fn is_stale(&self, now: u64, refresh_after: u64) -> bool {
+        now.saturating_sub(self.fetched_at) >= refresh_after
+    }
+
+    /// Symbol of the token, or its address if it has none
+    pub fn label(&self) -> String {
+        self.symbol
+            .clone()
+            .filter(|symbol| !symbol.is_empty())
+            .unwrap_or_else(|| format!("{:?}", self.address))
+    }
+
+    /// A raw amount of the token in whole tokens with its symbol, e.g. `1.5 WETH`
+    ///
+    /// Amounts of tokens without decimals are left raw.
+    pub fn format_amount(&self, amount: U256) -> String {
+        match self.decimals {
+            Some(decimals) => format!("{} {}", format_units(amount, decimals), self.label()),
+            None => format!("{} of {}", amount, self.label()),
+        }
+    }
+}
+
+/// Convert a raw token amount to whole tokens without losing precision, e.g. `1.5`
+pub fn format_units(amount: U256, decimals: u8) -> String {
+    match utils::format_units(amount, u32::from(decimals)) {
+        Ok(units) => units
+            .trim_end_matches('0')
+            .trim_end_matches('.')
+            .to_string(),
+        Err(_) => amount.to_string(),
+    }
+}
+
+/// Decode a `name` or `symbol`, returned as a `string` or as a `bytes32` by older tokens
+pub fn decode_text(output: &[u8]) -> Option<String> {
+    let text = match abi::decode(&[ParamType::String], output)
+        .ok()
+        .and_then(|mut tokens| tokens.pop())
+        .and_then(|token| token.into_string())
+    {
+        Some(text) => text,
+        None if output.len() >= 32 => {
+            let bytes = &output[..32];
+            let end = bytes.iter().position(|b| *b == 0).unwrap_or(32);
+            String::from_utf8(bytes[..end].to_vec()).ok()?
+        }
+        None => return None,
+    };
+    let text = text.trim_matches(|c: char| c.is_control() || c.is_whitespace());
+
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/// Decode a `uint` output, `None` if it is malformed
+fn decode_uint(output: &[u8]) -> Option<U256> {
+    abi::decode(&[ParamType::Uint(256)], output)
+        .ok()?
+        .pop()?
+        .into_uint()
+}
+
+/// Call a view function of `token` that takes no arguments
+///
+/// Returns `None` if the call reverted, the node answering with an error.
+async fn call<M: Middleware>(
+    provider: &M,
+    token: Address,
+    signature: &str,
+    block: Option<u64>,
+) -> Result<Option<Bytes>> {
+    let tx = TransactionRequest::new()
+        .to(token)
+        .data(id(signature).to_vec());
+
+    match provider.call(&tx.into(), block.map(BlockId::from)).await {
+        Ok(output) => Ok(Some(output)),
+        Err(e) if e.is_error_response() => {
+            debug!("{} of {:?} reverted: {}", signature, token, e);
+            Ok(None)
+        }
+        Err(e) => Err(anyhow!("{} of {:?} failed: {}", signature, token, e)),
+    }
+}
+
+/// Read the metadata of an ERC-20 token at `block`, or at the latest block
+///
+/// Functions that revert or return something malformed leave their field empty.
+///
+/// # Errors
+///
+/// This function will return an error if the node could not be reached
+pub async fn fetch_metadata<M: Middleware>(
+    provider: &M,
+    address: Address,
+    block: Option<u64>,
+) -> Result<TokenMetadata> {
+    let name = call(provider, address, "name()", block).await?;
+    let symbol = call(provider, address, "symbol()", block).await?;
+    let decimals = call(provider, address, "decimals()", block).await?;
+    let total_supply = call(provider, address, "totalSupply()", block).await?;
+
+    Ok(TokenMetadata {
+        address,
+        name: name.as_deref().and_then(decode_text),
+        symbol: symbol.as_deref().and_then(decode_text),
+        decimals: decimals
+            .as_deref()
+            .and_then(decode_uint)
+            .filter(|decimals| *decimals <= U256::from(u8::MAX))
+            .map(|decimals| decimals.as_u32() as u8),
+        total_supply: total_supply.as_deref().and_then(decode_uint),
+        fetched_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+    })
+}
@@ -0,0 +1,249 @@
+use ethers::abi::{self, ParamType, Token};
+use ethers::types::{Address, Bytes, H160};
+
+use crate::decoded::NotDecodable;
+
+/// Bit of a command byte set when the command is allowed to revert without reverting `execute`
+const FLAG_ALLOW_REVERT: u8 = 0x80;
+/// Bits of a command byte holding the command type
+const COMMAND_TYPE_MASK: u8 = 0x3f;
+
+pub const V3_SWAP_EXACT_IN: u8 = 0x00;
+pub const V3_SWAP_EXACT_OUT: u8 = 0x01;
+pub const PERMIT2_TRANSFER_FROM: u8 = 0x02;
+pub const PERMIT2_PERMIT_BATCH: u8 = 0x03;
+pub const SWEEP: u8 = 0x04;
+pub const TRANSFER: u8 = 0x05;
+pub const PAY_PORTION: u8 = 0x06;
+pub const V2_SWAP_EXACT_IN: u8 = 0x08;
+pub const V2_SWAP_EXACT_OUT: u8 = 0x09;
+pub const PERMIT2_PERMIT: u8 = 0x0a;
+pub const WRAP_ETH: u8 = 0x0b;
+pub const UNWRAP_WETH: u8 = 0x0c;
+pub const PERMIT2_TRANSFER_FROM_BATCH: u8 = 0x0d;
+pub const BALANCE_CHECK_ERC20: u8 = 0x0e;
+pub const EXECUTE_SUB_PLAN: u8 = 0x21;
+
+const fn constant_address(last: u8) -> Address {
+    let mut bytes = [0; 20];
+    bytes[19] = last;
+    H160(bytes)
+}
+
+/// Recipient standing for the sender of the tx
+pub const MSG_SENDER: Address = constant_address(1);
+/// Recipient standing for the router itself
+pub const ADDRESS_THIS: Address = constant_address(2);
+
+/// Actual recipient of a swap sent by `sender` to `router`
+pub fn resolve_recipient(recipient: Address, sender: Address, router: Address) -> Address {
+    if recipient == MSG_SENDER {
+        sender
+    } else if recipient == ADDRESS_THIS {
+        router
+    } else {
+        recipient
+    }
+}
+
+/// `(token, amount, expiration, nonce)` of a Permit2 permit
+fn permit_details() -> ParamType {
+    ParamType::Tuple(vec![
+        ParamType::Address,
+        ParamType::Uint(160),
+        ParamType::Uint(48),
+        ParamType::Uint(48),
+    ])
+}
+
+/// Name and arguments of a command type, `None` for the ones that aren't decoded, e.g. NFT
+/// marketplace commands
+fn command_spec(command_type: u8) -> Option<(&'static str, Vec<(&'static str, ParamType)>)> {
+    use ParamType::{Address, Array, Bool, Bytes, Tuple, Uint};
+
+    let swap = |amount: &'static str, limit: &'static str, path: ParamType| {
+        vec![
+            ("recipient", Address),
+            (amount, Uint(256)),
+            (limit, Uint(256)),
+            ("path", path),
+            ("payerIsUser", Bool),
+        ]
+    };
+
+    Some(match command_type {
+        V3_SWAP_EXACT_IN => ("V3_SWAP_EXACT_IN", swap("amountIn", "amountOutMin", Bytes)),
+        V3_SWAP_EXACT_OUT => ("V3_SWAP_EXACT_OUT", swap("amountOut", "amountInMax", Bytes)),
+        PERMIT2_TRANSFER_FROM => (
+            "PERMIT2_TRANSFER_FROM",
+            vec![
+                ("token", Address),
+                ("recipient", Address),
+                ("amount", Uint(160)),
+            ],
+        ),
+        PERMIT2_PERMIT_BATCH => (
+            "PERMIT2_PERMIT_BATCH",
+            vec![
+                (
+                    "permitBatch",
+                    Tuple(vec![Array(Box::new(permit_details())), Address, Uint(256)]),
+                ),
+                ("signature", Bytes),
+            ],
+        ),
+        SWEEP => (
+            "SWEEP",
+            vec![
+                ("token", Address),
+                ("recipient", Address),
+                ("amountMin", Uint(256)),
+            ],
+        ),
+        TRANSFER => (
+            "TRANSFER",
+            vec![
+                ("token", Address),
+                ("recipient", Address),
+                ("value", Uint(256)),
+            ],
+        ),
+        PAY_PORTION => (
+            "PAY_PORTION",
+            vec![
+                ("token", Address),
+                ("recipient", Address),
+                ("bips", Uint(256)),
+            ],
+        ),
+        V2_SWAP_EXACT_IN => (
+            "V2_SWAP_EXACT_IN",
+            swap("amountIn", "amountOutMin", Array(Box::new(Address))),
+        ),
+        V2_SWAP_EXACT_OUT => (
+            "V2_SWAP_EXACT_OUT",
+            swap("amountOut", "amountInMax", Array(Box::new(Address))),
+        ),
+        PERMIT2_PERMIT => (
+            "PERMIT2_PERMIT",
+            vec![
+                (
+                    "permitSingle",
+                    Tuple(vec![permit_details(), Address, Uint(256)]),
+                ),
+                ("signature", Bytes),
+            ],
+        ),
+        WRAP_ETH => (
+            "WRAP_ETH",
+            vec![("recipient", Address), ("amountMin", Uint(256))],
+        ),
+        UNWRAP_WETH => (
+            "UNWRAP_WETH",
+            vec![("recipient", Address), ("amountMin", Uint(256))],
+        ),
+        PERMIT2_TRANSFER_FROM_BATCH => (
+            "PERMIT2_TRANSFER_FROM_BATCH",
+            vec![(
+                "batchDetails",
+                Array(Box::new(Tuple(vec![Address, Address, Uint(160), Address]))),
+            )],
+        ),
+        BALANCE_CHECK_ERC20 => (
+            "BALANCE_CHECK_ERC20",
+            vec![
+                ("owner", Address),
+                ("token", Address),
+                ("minBalance", Uint(256)),
+            ],
+        ),
+        EXECUTE_SUB_PLAN => (
+            "EXECUTE_SUB_PLAN",
+            vec![("commands", Bytes), ("inputs", Array(Box::new(Bytes)))],
+        ),
+        _ => return None,
+    })
+}
+
+/// A command of a Universal Router `execute` call with its ABI encoded input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Command {
+    /// Command type, without the flags
+    pub command_type: u8,
+    /// Whether the router carries on if the command reverts
+    pub allow_revert: bool,
+    pub input: Bytes,
+}
+
+/// Arguments of a decoded command, with their names and types
+pub type CommandArguments = Vec<(&'static str, ParamType, Token)>;
+
+impl Command {
+    /// Name of the command type, e.g. `V3_SWAP_EXACT_IN`
+    pub fn name(&self) -> Option<&'static str> {
+        command_spec(self.command_type).map(|(name, _)| name)
+    }
+
+    /// Decode the input of the command
+    ///
+    /// # Errors
+    ///
+    /// This function will return the reason the input can't be decoded if the command type is
+    /// unknown or the input doesn't match its arguments
+    pub fn arguments(&self) -> Result<CommandArguments, NotDecodable> {
+        let (name, params) =
+            command_spec(self.command_type).ok_or(NotDecodable::UnknownCommand {
+                command: self.command_type,
+            })?;
+        let kinds = params
+            .iter()
+            .map(|(_, kind)| kind.clone())
+            .collect::<Vec<ParamType>>();
+        let tokens =
+            abi::decode(&kinds, &self.input).map_err(|e| NotDecodable::InvalidArguments {
+                function: name.to_string(),
+                error: e.to_string(),
+            })?;
+
+        Ok(params
+            .into_iter()
+            .zip(tokens)
+            .map(|((name, kind), token)| (name, kind, token))
+            .collect())
+    }
+
+    /// Commands of an `EXECUTE_SUB_PLAN` command, `None` for other commands
+    pub fn sub_plan(&self) -> Option<Vec<Command>> {
+        if self.command_type != EXECUTE_SUB_PLAN {
+            return None;
+        }
+
+        let mut arguments = self.arguments().ok()?.into_iter();
+        let (_, _, commands) = arguments.next()?;
+        let (_, _, inputs) = arguments.next()?;
+
+        Some(commands_of(
+            &commands.into_bytes()?,
+            &inputs
+                .into_array()?
+                .into_iter()
+                .map(|input| input.into_bytes().map(Bytes::from))
+                .collect::<Option<Vec<Bytes>>>()?,
+        ))
+    }
+}
+
+/// Pair the command bytes of an `execute` call with their inputs
+///
+/// Commands without an input, in a malformed call, are left out.
+pub fn commands_of(commands: &[u8], inputs: &[Bytes]) -> Vec<Command> {
+    commands
+        .iter()
+        .zip(inputs)
+        .map(|(command, input)| Command {
+            command_type: command & COMMAND_TYPE_MASK,
+            allow_revert: command & FLAG_ALLOW_REVERT != 0,
+            input: input.clone(),
+        })
+        .collect()
+}
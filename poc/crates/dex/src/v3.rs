@@ -0,0 +1,248 @@
+use std::fmt;
+
+use ethers::abi::AbiDecode;
+use ethers::types::{Address, U256};
+use serde::{Deserialize, Serialize};
+
+use crate::bindings::uniswap_v3_swap_router::UniswapV3SwapRouterCalls;
+use crate::bindings::uniswap_v3_swap_router_02::UniswapV3SwapRouter02Calls;
+use crate::swap::SwapKind;
+
+/// Bytes of a token address in a packed V3 path
+const ADDRESS_SIZE: usize = 20;
+/// Bytes of a fee tier in a packed V3 path
+const FEE_SIZE: usize = 3;
+
+/// One pool of a V3 route
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hop {
+    pub token_in: Address,
+    pub token_out: Address,
+    /// Fee tier of the pool in hundredths of a bip, e.g. `3000` for 0.3%
+    pub fee: u32,
+}
+
+impl fmt::Display for Hop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} -({}%)-> {:?}",
+            self.token_in,
+            self.fee as f64 / 10_000.0,
+            self.token_out
+        )
+    }
+}
+
+/// Hops of a route through `path`, with the fee tier of each pool in `fees`
+pub fn hops(path: &[Address], fees: &[u32]) -> Vec<Hop> {
+    path.windows(2)
+        .zip(fees)
+        .map(|(tokens, fee)| Hop {
+            token_in: tokens[0],
+            token_out: tokens[1],
+            fee: *fee,
+        })
+        .collect()
+}
+
+/// Split a packed V3 path (token, fee, token, fee, ..., token) into its tokens and fee tiers
+///
+/// Returns `None` if the path doesn't hold at least one hop.
+pub fn decode_path(path: &[u8]) -> Option<(Vec<Address>, Vec<u32>)> {
+    let hop_size = FEE_SIZE + ADDRESS_SIZE;
+    if path.len() < ADDRESS_SIZE + hop_size || !(path.len() - ADDRESS_SIZE).is_multiple_of(hop_size)
+    {
+        return None;
+    }
+
+    let mut tokens = vec![Address::from_slice(&path[..ADDRESS_SIZE])];
+    let mut fees = Vec::new();
+    for hop in path[ADDRESS_SIZE..].chunks_exact(hop_size) {
+        let (fee, token) = hop.split_at(FEE_SIZE);
+        fees.push(u32::from_be_bytes([0, fee[0], fee[1], fee[2]]));
+        tokens.push(Address::from_slice(token));
+    }
+
+    Some((tokens, fees))
+}
+
+/// Tokens and fee tiers of a packed V3 path, from the token in to the token out
+///
+/// The paths of exact output swaps are encoded from the token out, they are reversed.
+pub fn decode_route(path: &[u8], kind: SwapKind) -> Option<(Vec<Address>, Vec<u32>)> {
+    let (mut path, mut fees) = decode_path(path)?;
+    if kind == SwapKind::ExactOut {
+        path.reverse();
+        fees.reverse();
+    }
+
+    Some((path, fees))
+}
+
+/// A swap made through a V3 router, from the token in to the token out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct V3Swap {
+    /// Router function that was called
+    pub method: &'static str,
+    pub recipient: Address,
+    pub path: Vec<Address>,
+    /// Fee tier of each hop of `path`
+    pub fees: Vec<u32>,
+    pub kind: SwapKind,
+    pub amount_in: U256,
+    pub amount_out: U256,
+    /// Only set by `SwapRouter`, `SwapRouter02` takes the deadline in `multicall`
+    pub deadline: Option<U256>,
+}
+
+impl V3Swap {
+    fn single(
+        method: &'static str,
+        (token_in, token_out, fee): (Address, Address, u32),
+        recipient: Address,
+        kind: SwapKind,
+        (amount_in, amount_out): (U256, U256),
+        deadline: Option<U256>,
+    ) -> Self {
+        Self {
+            method,
+            recipient,
+            path: vec![token_in, token_out],
+            fees: vec![fee],
+            kind,
+            amount_in,
+            amount_out,
+            deadline,
+        }
+    }
+
+    fn multi_hop(
+        method: &'static str,
+        path: &[u8],
+        recipient: Address,
+        kind: SwapKind,
+        (amount_in, amount_out): (U256, U256),
+        deadline: Option<U256>,
+    ) -> Option<Self> {
+        let (path, fees) = decode_route(path, kind)?;
+
+        Some(Self {
+            method,
+            recipient,
+            path,
+            fees,
+            kind,
+            amount_in,
+            amount_out,
+            deadline,
+        })
+    }
+}
+
+/// Decode an `exactInputSingle`, `exactInput`, `exactOutputSingle` or `exactOutput` call to a
+/// `SwapRouter` or `SwapRouter02`
+///
+/// The calldata is decoded against both routers whatever ABI the router was loaded with,
+/// the selectors of their functions differ. Other calls decode to `None`.
+pub fn decode_v3_swap(calldata: &[u8]) -> Option<V3Swap> {
+    use SwapKind::{ExactIn, ExactOut};
+
+    if let Ok(call) = UniswapV3SwapRouterCalls::decode(calldata) {
+        return match call {
+            UniswapV3SwapRouterCalls::ExactInputSingle(call) => {
+                let p = call.params;
+                Some(V3Swap::single(
+                    "exactInputSingle",
+                    (p.token_in, p.token_out, p.fee),
+                    p.recipient,
+                    ExactIn,
+                    (p.amount_in, p.amount_out_minimum),
+                    Some(p.deadline),
+                ))
+            }
+            UniswapV3SwapRouterCalls::ExactInput(call) => {
+                let p = call.params;
+                V3Swap::multi_hop(
+                    "exactInput",
+                    &p.path,
+                    p.recipient,
+                    ExactIn,
+                    (p.amount_in, p.amount_out_minimum),
+                    Some(p.deadline),
+                )
+            }
+            UniswapV3SwapRouterCalls::ExactOutputSingle(call) => {
+                let p = call.params;
+                Some(V3Swap::single(
+                    "exactOutputSingle",
+                    (p.token_in, p.token_out, p.fee),
+                    p.recipient,
+                    ExactOut,
+                    (p.amount_in_maximum, p.amount_out),
+                    Some(p.deadline),
+                ))
+            }
+            UniswapV3SwapRouterCalls::ExactOutput(call) => {
+                let p = call.params;
+                V3Swap::multi_hop(
+                    "exactOutput",
+                    &p.path,
+                    p.recipient,
+                    ExactOut,
+                    (p.amount_in_maximum, p.amount_out),
+                    Some(p.deadline),
+                )
+            }
+            _ => None,
+        };
+    }
+
+    match UniswapV3SwapRouter02Calls::decode(calldata).ok()? {
+        UniswapV3SwapRouter02Calls::ExactInputSingle(call) => {
+            let p = call.params;
+            Some(V3Swap::single(
+                "exactInputSingle",
+                (p.token_in, p.token_out, p.fee),
+                p.recipient,
+                ExactIn,
+                (p.amount_in, p.amount_out_minimum),
+                None,
+            ))
+        }
+        UniswapV3SwapRouter02Calls::ExactInput(call) => {
+            let p = call.params;
+            V3Swap::multi_hop(
+                "exactInput",
+                &p.path,
+                p.recipient,
+                ExactIn,
+                (p.amount_in, p.amount_out_minimum),
+                None,
+            )
+        }
+        UniswapV3SwapRouter02Calls::ExactOutputSingle(call) => {
+            let p = call.params;
+            Some(V3Swap::single(
+                "exactOutputSingle",
+                (p.token_in, p.token_out, p.fee),
+                p.recipient,
+                ExactOut,
+                (p.amount_in_maximum, p.amount_out),
+                None,
+            ))
+        }
+        UniswapV3SwapRouter02Calls::ExactOutput(call) => {
+            let p = call.params;
+            V3Swap::multi_hop(
+                "exactOutput",
+                &p.path,
+                p.recipient,
+                ExactOut,
+                (p.amount_in_maximum, p.amount_out),
+                None,
+            )
+        }
+        _ => None,
+    }
+}
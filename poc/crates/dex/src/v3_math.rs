@@ -0,0 +1,430 @@
+//! Fixed point math of Uniswap V3 pools
+//!
+//! Ports of `TickMath`, `FullMath`, `SqrtPriceMath` and `SwapMath` from `v3-core`, rounding the
+//! same way so quotes match what the pools compute to the wei.
+
+use anyhow::{anyhow, bail, Result};
+use ethers::types::{I256, U256, U512};
+
+pub const MIN_TICK: i32 = -887272;
+pub const MAX_TICK: i32 = 887272;
+/// Fee denominator, fees are in hundredths of a bip
+pub const FEE_PIPS: u32 = 1_000_000;
+/// Bits of the fractional part of the Q64.96 square root prices
+pub const RESOLUTION: usize = 96;
+
+/// `getSqrtRatioAtTick(MIN_TICK)`
+pub fn min_sqrt_ratio() -> U256 {
+    U256::from(4295128739u64)
+}
+
+/// `getSqrtRatioAtTick(MAX_TICK)`
+pub fn max_sqrt_ratio() -> U256 {
+    U256::from_dec_str("1461446703485210103287273052203988822378723970342").expect("valid constant")
+}
+
+fn q96() -> U256 {
+    U256::one() << RESOLUTION
+}
+
+fn max_uint160() -> U256 {
+    (U256::one() << 160) - 1
+}
+
+/// `ratio * multiplier >> 128` for each bit of the absolute tick, from `TickMath`
+const TICK_MULTIPLIERS: [(u32, u128); 19] = [
+    (0x2, 0xfff97272373d413259a46990580e213a),
+    (0x4, 0xfff2e50f5f656932ef12357cf3c7fdcc),
+    (0x8, 0xffe5caca7e10e4e61c3624eaa0941cd0),
+    (0x10, 0xffcb9843d60f6159c9db58835c926644),
+    (0x20, 0xff973b41fa98c081472e6896dfb254c0),
+    (0x40, 0xff2ea16466c96a3843ec78b326b52861),
+    (0x80, 0xfe5dee046a99a2a811c461f1969c3053),
+    (0x100, 0xfcbe86c7900a88aedcffc83b479aa3a4),
+    (0x200, 0xf987a7253ac413176f2b074cf7815e54),
+    (0x400, 0xf3392b0822b70005940c7a398e4b70f3),
+    (0x800, 0xe7159475a2c29b7443b29c7fa6e889d9),
+    (0x1000, 0xd097f3bdfd2022b8845ad8f792aa5825),
+    (0x2000, 0xa9f746462d870fdf8a65dc1f90e061e5),
+    (0x4000, 0x70d869a156d2a1b890bb3df62baf32f7),
+    (0x8000, 0x31be135f97d08fd981231505542fcfa6),
+    (0x10000, 0x9aa508b5b7a84e1c677de54f3e99bc9),
+    (0x20000, 0x5d6af8dedb81196699c329225ee604),
+    (0x40000, 0x2216e584f5fa1ea926041bedfe98),
+    (0x80000, 0x48a170391f7dc42444e8fa2),
+];
+
+/// Square root price of a tick, as a Q64.96
+///
+/// # Errors
+///
+/// This function will return an error if the tick is outside `MIN_TICK..=MAX_TICK`
+pub fn get_sqrt_ratio_at_tick(tick: i32) -> Result<U256> {
+    if !(MIN_TICK..=MAX_TICK).contains(&tick) {
+        bail!("Tick {} is out of range", tick);
+    }
+
+    let abs_tick = tick.unsigned_abs();
+    let mut ratio = if abs_tick & 0x1 != 0 {
+        U256::from(0xfffcb933bd6fad37aa2d162d1a594001u128)
+    } else {
+        U256::one() << 128
+    };
+    for (bit, multiplier) in TICK_MULTIPLIERS {
+        if abs_tick & bit != 0 {
+            ratio = (ratio * U256::from(multiplier)) >> 128;
+        }
+    }
+    if tick > 0 {
+        ratio = U256::MAX / ratio;
+    }
+
+    // Back from Q128.128 to Q64.96, rounding up so the tick of the result is always `tick`
+    let rounding = if (ratio % (U256::one() << 32)).is_zero() {
+        U256::zero()
+    } else {
+        U256::one()
+    };
+
+    Ok((ratio >> 32) + rounding)
+}
+
+/// Greatest tick whose square root price is at most `sqrt_price_x96`
+///
+/// Searches the ticks with `get_sqrt_ratio_at_tick`, which gives the same tick as the
+/// logarithm approximation of `TickMath.getTickAtSqrtRatio`.
+///
+/// # Errors
+///
+/// This function will return an error if the price is outside
+/// `min_sqrt_ratio()..max_sqrt_ratio()`
+pub fn get_tick_at_sqrt_ratio(sqrt_price_x96: U256) -> Result<i32> {
+    if sqrt_price_x96 < min_sqrt_ratio() || sqrt_price_x96 >= max_sqrt_ratio() {
+        bail!("Square root price {} is out of range", sqrt_price_x96);
+    }
+
+    let (mut low, mut high) = (MIN_TICK, MAX_TICK);
+    while low < high {
+        let mid = low + (high - low + 1) / 2;
+        if get_sqrt_ratio_at_tick(mid)? <= sqrt_price_x96 {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+
+    Ok(low)
+}
+
+/// `a * b / denominator` rounded down, with a full precision intermediate product
+///
+/// # Errors
+///
+/// This function will return an error if `denominator` is zero or the result overflows
+pub fn mul_div(a: U256, b: U256, denominator: U256) -> Result<U256> {
+    if denominator.is_zero() {
+        bail!("Division by zero");
+    }
+
+    U256::try_from(a.full_mul(b) / U512::from(denominator)).map_err(|_| anyhow!("mul_div overflow"))
+}
+
+/// `a * b / denominator` rounded up, with a full precision intermediate product
+///
+/// # Errors
+///
+/// This function will return an error if `denominator` is zero or the result overflows
+pub fn mul_div_rounding_up(a: U256, b: U256, denominator: U256) -> Result<U256> {
+    let result = mul_div(a, b, denominator)?;
+    if (a.full_mul(b) % U512::from(denominator)).is_zero() {
+        Ok(result)
+    } else {
+        result
+            .checked_add(U256::one())
+            .ok_or_else(|| anyhow!("mul_div overflow"))
+    }
+}
+
+fn div_rounding_up(a: U256, b: U256) -> U256 {
+    let rounding = if (a % b).is_zero() {
+        U256::zero()
+    } else {
+        U256::one()
+    };
+
+    a / b + rounding
+}
+
+/// Add a signed liquidity delta, as done when a tick is crossed
+///
+/// # Errors
+///
+/// This function will return an error if the liquidity underflows or overflows
+pub fn add_delta(liquidity: u128, delta: i128) -> Result<u128> {
+    if delta < 0 {
+        liquidity
+            .checked_sub(delta.unsigned_abs())
+            .ok_or_else(|| anyhow!("Liquidity underflow"))
+    } else {
+        liquidity
+            .checked_add(delta as u128)
+            .ok_or_else(|| anyhow!("Liquidity overflow"))
+    }
+}
+
+fn next_sqrt_price_from_amount0_rounding_up(
+    sqrt_price_x96: U256,
+    liquidity: u128,
+    amount: U256,
+    add: bool,
+) -> Result<U256> {
+    if amount.is_zero() {
+        return Ok(sqrt_price_x96);
+    }
+    let numerator1 = U256::from(liquidity) << RESOLUTION;
+
+    if add {
+        if let Some(product) = amount.checked_mul(sqrt_price_x96) {
+            if let Some(denominator) = numerator1.checked_add(product) {
+                return mul_div_rounding_up(numerator1, sqrt_price_x96, denominator);
+            }
+        }
+
+        Ok(div_rounding_up(
+            numerator1,
+            (numerator1 / sqrt_price_x96)
+                .checked_add(amount)
+                .ok_or_else(|| anyhow!("Price overflow"))?,
+        ))
+    } else {
+        let product = amount
+            .checked_mul(sqrt_price_x96)
+            .filter(|product| numerator1 > *product)
+            .ok_or_else(|| anyhow!("Not enough liquidity for the output"))?;
+        let price = mul_div_rounding_up(numerator1, sqrt_price_x96, numerator1 - product)?;
+        if price > max_uint160() {
+            bail!("Price overflow");
+        }
+
+        Ok(price)
+    }
+}
+
+fn next_sqrt_price_from_amount1_rounding_down(
+    sqrt_price_x96: U256,
+    liquidity: u128,
+    amount: U256,
+    add: bool,
+) -> Result<U256> {
+    let liquidity = U256::from(liquidity);
+
+    if add {
+        let quotient = if amount <= max_uint160() {
+            (amount << RESOLUTION) / liquidity
+        } else {
+            mul_div(amount, q96(), liquidity)?
+        };
+        let price = sqrt_price_x96
+            .checked_add(quotient)
+            .filter(|price| *price <= max_uint160())
+            .ok_or_else(|| anyhow!("Price overflow"))?;
+
+        Ok(price)
+    } else {
+        let quotient = if amount <= max_uint160() {
+            div_rounding_up(amount << RESOLUTION, liquidity)
+        } else {
+            mul_div_rounding_up(amount, q96(), liquidity)?
+        };
+        if sqrt_price_x96 <= quotient {
+            bail!("Not enough liquidity for the output");
+        }
+
+        Ok(sqrt_price_x96 - quotient)
+    }
+}
+
+/// Price after adding `amount_in` of token0 (`zero_for_one`) or token1
+///
+/// # Errors
+///
+/// This function will return an error if the pool has no liquidity or the price overflows
+pub fn get_next_sqrt_price_from_input(
+    sqrt_price_x96: U256,
+    liquidity: u128,
+    amount_in: U256,
+    zero_for_one: bool,
+) -> Result<U256> {
+    if sqrt_price_x96.is_zero() || liquidity == 0 {
+        bail!("Pool has no price or liquidity");
+    }
+
+    if zero_for_one {
+        next_sqrt_price_from_amount0_rounding_up(sqrt_price_x96, liquidity, amount_in, true)
+    } else {
+        next_sqrt_price_from_amount1_rounding_down(sqrt_price_x96, liquidity, amount_in, true)
+    }
+}
+
+/// Price after taking `amount_out` of token1 (`zero_for_one`) or token0
+///
+/// # Errors
+///
+/// This function will return an error if the pool has no liquidity or doesn't hold the output
+pub fn get_next_sqrt_price_from_output(
+    sqrt_price_x96: U256,
+    liquidity: u128,
+    amount_out: U256,
+    zero_for_one: bool,
+) -> Result<U256> {
+    if sqrt_price_x96.is_zero() || liquidity == 0 {
+        bail!("Pool has no price or liquidity");
+    }
+
+    if zero_for_one {
+        next_sqrt_price_from_amount1_rounding_down(sqrt_price_x96, liquidity, amount_out, false)
+    } else {
+        next_sqrt_price_from_amount0_rounding_up(sqrt_price_x96, liquidity, amount_out, false)
+    }
+}
+
+/// Amount of token0 between two prices for `liquidity`
+///
+/// # Errors
+///
+/// This function will return an error if a price is zero or the amount overflows
+pub fn get_amount0_delta(a: U256, b: U256, liquidity: u128, round_up: bool) -> Result<U256> {
+    let (a, b) = if a > b { (b, a) } else { (a, b) };
+    if a.is_zero() {
+        bail!("Square root price is zero");
+    }
+
+    let numerator1 = U256::from(liquidity) << RESOLUTION;
+    let numerator2 = b - a;
+
+    if round_up {
+        Ok(div_rounding_up(
+            mul_div_rounding_up(numerator1, numerator2, b)?,
+            a,
+        ))
+    } else {
+        Ok(mul_div(numerator1, numerator2, b)? / a)
+    }
+}
+
+/// Amount of token1 between two prices for `liquidity`
+///
+/// # Errors
+///
+/// This function will return an error if the amount overflows
+pub fn get_amount1_delta(a: U256, b: U256, liquidity: u128, round_up: bool) -> Result<U256> {
+    let (a, b) = if a > b { (b, a) } else { (a, b) };
+
+    if round_up {
+        mul_div_rounding_up(U256::from(liquidity), b - a, q96())
+    } else {
+        mul_div(U256::from(liquidity), b - a, q96())
+    }
+}
+
+/// Outcome of a swap within a single tick range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SwapStep {
+    pub sqrt_price_next_x96: U256,
+    pub amount_in: U256,
+    pub amount_out: U256,
+    pub fee_amount: U256,
+}
+
+/// Swap `amount_remaining` from `sqrt_price_current_x96` towards `sqrt_price_target_x96`
+///
+/// A positive `amount_remaining` is an exact input, a negative one an exact output.
+///
+/// # Errors
+///
+/// This function will return an error if the pool can't be swapped against, e.g. it has no
+/// liquidity
+pub fn compute_swap_step(
+    sqrt_price_current_x96: U256,
+    sqrt_price_target_x96: U256,
+    liquidity: u128,
+    amount_remaining: I256,
+    fee_pips: u32,
+) -> Result<SwapStep> {
+    let zero_for_one = sqrt_price_current_x96 >= sqrt_price_target_x96;
+    let exact_in = !amount_remaining.is_negative();
+    let remaining = amount_remaining.unsigned_abs();
+    let (current, target) = (sqrt_price_current_x96, sqrt_price_target_x96);
+
+    let mut amount_in = U256::zero();
+    let mut amount_out = U256::zero();
+    let next = if exact_in {
+        let remaining_less_fee = mul_div(
+            remaining,
+            U256::from(FEE_PIPS - fee_pips),
+            U256::from(FEE_PIPS),
+        )?;
+        amount_in = if zero_for_one {
+            get_amount0_delta(target, current, liquidity, true)?
+        } else {
+            get_amount1_delta(current, target, liquidity, true)?
+        };
+
+        if remaining_less_fee >= amount_in {
+            target
+        } else {
+            get_next_sqrt_price_from_input(current, liquidity, remaining_less_fee, zero_for_one)?
+        }
+    } else {
+        amount_out = if zero_for_one {
+            get_amount1_delta(target, current, liquidity, false)?
+        } else {
+            get_amount0_delta(current, target, liquidity, false)?
+        };
+
+        if remaining >= amount_out {
+            target
+        } else {
+            get_next_sqrt_price_from_output(current, liquidity, remaining, zero_for_one)?
+        }
+    };
+
+    let max = target == next;
+    if zero_for_one {
+        if !(max && exact_in) {
+            amount_in = get_amount0_delta(next, current, liquidity, true)?;
+        }
+        if !(max && !exact_in) {
+            amount_out = get_amount1_delta(next, current, liquidity, false)?;
+        }
+    } else {
+        if !(max && exact_in) {
+            amount_in = get_amount1_delta(current, next, liquidity, true)?;
+        }
+        if !(max && !exact_in) {
+            amount_out = get_amount0_delta(current, next, liquidity, false)?;
+        }
+    }
+
+    if !exact_in && amount_out > remaining {
+        amount_out = remaining;
+    }
+
+    let fee_amount = if exact_in && next != target {
+        // The rest of the input is taken as fee
+        remaining - amount_in
+    } else {
+        mul_div_rounding_up(
+            amount_in,
+            U256::from(fee_pips),
+            U256::from(FEE_PIPS - fee_pips),
+        )?
+    };
+
+    Ok(SwapStep {
+        sqrt_price_next_x96: next,
+        amount_in,
+        amount_out,
+        fee_amount,
+    })
+}
@@ -0,0 +1,733 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, bail, Result};
+use ethers::abi::{self, ParamType, Token};
+use ethers::providers::Middleware;
+use ethers::types::{Address, BlockId, Bytes, Filter, Log, TransactionRequest, H256, I256, U256};
+use ethers::utils::{id, keccak256};
+use serde::{Deserialize, Serialize};
+
+use crate::registry::PairInfo;
+use crate::swap::SwapKind;
+use crate::v3_math::{
+    add_delta, compute_swap_step, get_sqrt_ratio_at_tick, get_tick_at_sqrt_ratio, max_sqrt_ratio,
+    min_sqrt_ratio, MAX_TICK, MIN_TICK,
+};
+
+/// `Initialize(uint160,int24)` of Uniswap V3 pools
+pub fn initialize_topic() -> H256 {
+    H256::from(keccak256("Initialize(uint160,int24)"))
+}
+
+/// `Mint(address,address,int24,int24,uint128,uint256,uint256)` of Uniswap V3 pools
+pub fn mint_topic() -> H256 {
+    H256::from(keccak256(
+        "Mint(address,address,int24,int24,uint128,uint256,uint256)",
+    ))
+}
+
+/// `Burn(address,int24,int24,uint128,uint256,uint256)` of Uniswap V3 pools
+pub fn burn_topic() -> H256 {
+    H256::from(keccak256(
+        "Burn(address,int24,int24,uint128,uint256,uint256)",
+    ))
+}
+
+/// `Swap(address,address,int256,int256,uint160,uint128,int24)` of Uniswap V3 pools
+pub fn swap_topic() -> H256 {
+    crate::candle::v3_swap_topic()
+}
+
+/// Tick spacing the Uniswap V3 factory enables for a fee tier
+pub fn tick_spacing(fee: u32) -> Option<i32> {
+    match fee {
+        100 => Some(1),
+        500 => Some(10),
+        3000 => Some(60),
+        10000 => Some(200),
+        _ => None,
+    }
+}
+
+fn int24_of_topic(topic: &H256) -> i32 {
+    I256::from_raw(U256::from_big_endian(topic.as_bytes())).as_i32()
+}
+
+fn int_of(token: Token) -> Option<I256> {
+    token.into_int().map(I256::from_raw)
+}
+
+/// Liquidity referencing an initialized tick
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TickInfo {
+    /// Liquidity of the positions with the tick as a bound
+    pub liquidity_gross: u128,
+    /// Liquidity added when the tick is crossed from left to right
+    pub liquidity_net: i128,
+}
+
+/// Result of a swap against a pool, signed from the point of view of the pool
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SwapResult {
+    /// Token0 received by the pool, negative when it was sent
+    pub amount0: I256,
+    pub amount1: I256,
+    pub sqrt_price_x96: U256,
+    pub tick: i32,
+    pub liquidity: u128,
+    /// Initialized ticks crossed by the swap
+    pub ticks_crossed: u32,
+}
+
+/// A swap quoted against a pool
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct V3Quote {
+    pub amount_in: U256,
+    pub amount_out: U256,
+    /// Price of the pool after the swap
+    pub sqrt_price_x96_after: U256,
+    pub tick_after: i32,
+    pub liquidity_after: u128,
+    pub ticks_crossed: u32,
+}
+
+/// State of a Uniswap V3 pool, enough to quote swaps offline
+///
+/// Quotes are only exact if every initialized tick the swap reaches is known. Pools followed
+/// from their creation have them all, pools read with `V3Pool::fetch` have the ones within
+/// the words of the tick bitmap that were read.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct V3Pool {
+    pub address: Address,
+    pub token0: Address,
+    pub token1: Address,
+    /// Fee tier in hundredths of a basis point
+    pub fee: u32,
+    pub tick_spacing: i32,
+    /// Square root of the price of token0 in token1, as a Q64.96, zero until initialized
+    pub sqrt_price_x96: U256,
+    pub tick: i32,
+    /// Liquidity in range of the current tick
+    pub liquidity: u128,
+    pub ticks: BTreeMap<i32, TickInfo>,
+}
+
+impl V3Pool {
+    /// Uninitialized pool of a registered V3 pair, `None` for V2 pairs and unknown fee tiers
+    pub fn from_pair(pair: &PairInfo) -> Option<Self> {
+        let fee = pair.fee?;
+
+        Some(Self {
+            address: pair.address,
+            token0: pair.token0,
+            token1: pair.token1,
+            fee,
+            tick_spacing: tick_spacing(fee)?,
+            sqrt_price_x96: U256::zero(),
+            tick: 0,
+            liquidity: 0,
+            ticks: BTreeMap::new(),
+        })
+    }
+
+    /// Add liquidity between two ticks, removing it if `delta` is negative
+    fn update_position(&mut self, tick_lower: i32, tick_upper: i32, delta: i128) -> Result<()> {
+        for (tick, net) in [(tick_lower, delta), (tick_upper, -delta)] {
+            let info = self.ticks.entry(tick).or_default();
+            info.liquidity_gross = add_delta(info.liquidity_gross, delta)?;
+            info.liquidity_net = info
+                .liquidity_net
+                .checked_add(net)
+                .ok_or_else(|| anyhow!("Liquidity net overflow at tick {}", tick))?;
+            if info.liquidity_gross == 0 {
+                self.ticks.remove(&tick);
+            }
+        }
+
+        if (tick_lower..tick_upper).contains(&self.tick) {
+            self.liquidity = add_delta(self.liquidity, delta)?;
+        }
+
+        Ok(())
+    }
+
+    /// Apply an `Initialize`, `Mint`, `Burn` or `Swap` log of the pool
+    ///
+    /// Returns whether the log changed the state of the pool.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the log is inconsistent with the state, which
+    /// happens when logs were missed
+    pub fn apply_log(&mut self, log: &Log) -> Result<bool> {
+        let Some(topic) = log.topics.first() else {
+            return Ok(false);
+        };
+        if log.address != self.address {
+            return Ok(false);
+        }
+
+        if *topic == initialize_topic() {
+            let mut tokens =
+                abi::decode(&[ParamType::Uint(160), ParamType::Int(24)], &log.data)?.into_iter();
+            self.sqrt_price_x96 = tokens.next().and_then(Token::into_uint).unwrap_or_default();
+            self.tick = tokens.next().and_then(int_of).unwrap_or_default().as_i32();
+        } else if *topic == mint_topic() || *topic == burn_topic() {
+            let (Some(lower), Some(upper)) = (log.topics.get(2), log.topics.get(3)) else {
+                return Ok(false);
+            };
+            // Mint has the sender before the amount
+            let kinds = if *topic == mint_topic() {
+                vec![
+                    ParamType::Address,
+                    ParamType::Uint(128),
+                    ParamType::Uint(256),
+                    ParamType::Uint(256),
+                ]
+            } else {
+                vec![
+                    ParamType::Uint(128),
+                    ParamType::Uint(256),
+                    ParamType::Uint(256),
+                ]
+            };
+            let amount = abi::decode(&kinds, &log.data)?
+                .into_iter()
+                .find_map(|token| match token {
+                    Token::Uint(amount) => Some(amount.as_u128()),
+                    _ => None,
+                })
+                .unwrap_or_default();
+            if amount == 0 {
+                return Ok(false);
+            }
+            let delta = i128::try_from(amount)?;
+            let delta = if *topic == mint_topic() {
+                delta
+            } else {
+                -delta
+            };
+
+            self.update_position(int24_of_topic(lower), int24_of_topic(upper), delta)?;
+        } else if *topic == swap_topic() {
+            let tokens = abi::decode(
+                &[
+                    ParamType::Int(256),
+                    ParamType::Int(256),
+                    ParamType::Uint(160),
+                    ParamType::Uint(128),
+                    ParamType::Int(24),
+                ],
+                &log.data,
+            )?;
+            let [_, _, Token::Uint(sqrt_price_x96), Token::Uint(liquidity), Token::Int(tick)] =
+                tokens.as_slice()
+            else {
+                return Ok(false);
+            };
+            self.sqrt_price_x96 = *sqrt_price_x96;
+            self.liquidity = liquidity.as_u128();
+            self.tick = I256::from_raw(*tick).as_i32();
+        } else {
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    /// Next initialized tick in the word of the tick bitmap holding `tick`, or the bound of
+    /// the word if it has none, as in `TickBitmap.nextInitializedTickWithinOneWord`
+    fn next_initialized_tick_within_one_word(&self, tick: i32, lte: bool) -> (i32, bool) {
+        let spacing = self.tick_spacing;
+        let mut compressed = tick / spacing;
+        if tick < 0 && tick % spacing != 0 {
+            compressed -= 1;
+        }
+
+        if lte {
+            let word_start = compressed - compressed.rem_euclid(256);
+            match self
+                .ticks
+                .range(word_start * spacing..=compressed * spacing)
+                .next_back()
+            {
+                Some((next, _)) => (*next, true),
+                None => (word_start * spacing, false),
+            }
+        } else {
+            let compressed = compressed + 1;
+            let word_end = compressed + (255 - compressed.rem_euclid(256));
+            match self
+                .ticks
+                .range(compressed * spacing..=word_end * spacing)
+                .next()
+            {
+                Some((next, _)) => (*next, true),
+                None => (word_end * spacing, false),
+            }
+        }
+    }
+
+    /// Swap against the pool without changing its state, as `UniswapV3Pool.swap` would
+    ///
+    /// A positive `amount_specified` is an exact input, a negative one an exact output. The
+    /// swap stops at `sqrt_price_limit_x96`, or at the bounds of the price range when there is
+    /// no limit. Protocol fees don't change what the swapper pays or receives, they are left
+    /// out.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the pool isn't initialized or the price limit is
+    /// on the wrong side of the price
+    pub fn swap(
+        &self,
+        zero_for_one: bool,
+        amount_specified: I256,
+        sqrt_price_limit_x96: Option<U256>,
+    ) -> Result<SwapResult> {
+        if self.sqrt_price_x96.is_zero() {
+            bail!("Pool {:?} is not initialized", self.address);
+        }
+        if amount_specified.is_zero() {
+            bail!("Swap amount is zero");
+        }
+
+        let limit = sqrt_price_limit_x96.unwrap_or_else(|| {
+            if zero_for_one {
+                min_sqrt_ratio() + 1
+            } else {
+                max_sqrt_ratio() - 1
+            }
+        });
+        let valid_limit = if zero_for_one {
+            limit < self.sqrt_price_x96 && limit > min_sqrt_ratio()
+        } else {
+            limit > self.sqrt_price_x96 && limit < max_sqrt_ratio()
+        };
+        if !valid_limit {
+            bail!("Invalid price limit {}", limit);
+        }
+
+        let exact_in = !amount_specified.is_negative();
+        let mut remaining = amount_specified;
+        let mut calculated = I256::zero();
+        let mut sqrt_price_x96 = self.sqrt_price_x96;
+        let mut tick = self.tick;
+        let mut liquidity = self.liquidity;
+        let mut ticks_crossed = 0;
+
+        while !remaining.is_zero() && sqrt_price_x96 != limit {
+            let start = sqrt_price_x96;
+            let (next_tick, initialized) =
+                self.next_initialized_tick_within_one_word(tick, zero_for_one);
+            let next_tick = next_tick.clamp(MIN_TICK, MAX_TICK);
+            let next_price = get_sqrt_ratio_at_tick(next_tick)?;
+            let target =
+                if (zero_for_one && next_price < limit) || (!zero_for_one && next_price > limit) {
+                    limit
+                } else {
+                    next_price
+                };
+
+            let step = compute_swap_step(sqrt_price_x96, target, liquidity, remaining, self.fee)?;
+            sqrt_price_x96 = step.sqrt_price_next_x96;
+            let spent = I256::from_raw(step.amount_in + step.fee_amount);
+            let received = I256::from_raw(step.amount_out);
+            if exact_in {
+                remaining -= spent;
+                calculated -= received;
+            } else {
+                remaining += received;
+                calculated += spent;
+            }
+
+            if sqrt_price_x96 == next_price {
+                if initialized {
+                    let net = self
+                        .ticks
+                        .get(&next_tick)
+                        .map(|info| info.liquidity_net)
+                        .unwrap_or_default();
+                    liquidity = add_delta(liquidity, if zero_for_one { -net } else { net })?;
+                    ticks_crossed += 1;
+                }
+                tick = if zero_for_one {
+                    next_tick - 1
+                } else {
+                    next_tick
+                };
+            } else if sqrt_price_x96 != start {
+                tick = get_tick_at_sqrt_ratio(sqrt_price_x96)?;
+            }
+        }
+
+        let swapped = amount_specified - remaining;
+        let (amount0, amount1) = if zero_for_one == exact_in {
+            (swapped, calculated)
+        } else {
+            (calculated, swapped)
+        };
+
+        Ok(SwapResult {
+            amount0,
+            amount1,
+            sqrt_price_x96,
+            tick,
+            liquidity,
+            ticks_crossed,
+        })
+    }
+
+    /// Whether swapping `token_in` sells token0
+    fn zero_for_one(&self, token_in: Address) -> Result<bool> {
+        if token_in == self.token0 {
+            Ok(true)
+        } else if token_in == self.token1 {
+            Ok(false)
+        } else {
+            bail!("Token {:?} is not in pool {:?}", token_in, self.address)
+        }
+    }
+
+    fn quote(&self, zero_for_one: bool, result: SwapResult) -> V3Quote {
+        let (amount_in, amount_out) = if zero_for_one {
+            (result.amount0, result.amount1)
+        } else {
+            (result.amount1, result.amount0)
+        };
+
+        V3Quote {
+            amount_in: amount_in.unsigned_abs(),
+            amount_out: amount_out.unsigned_abs(),
+            sqrt_price_x96_after: result.sqrt_price_x96,
+            tick_after: result.tick,
+            liquidity_after: result.liquidity,
+            ticks_crossed: result.ticks_crossed,
+        }
+    }
+
+    /// Quote swapping exactly `amount_in` of `token_in`
+    ///
+    /// If the pool runs out of liquidity, only part of `amount_in` is swapped, as the pool
+    /// would.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `token_in` isn't in the pool or it can't be
+    /// swapped against
+    pub fn quote_exact_in(&self, token_in: Address, amount_in: U256) -> Result<V3Quote> {
+        let zero_for_one = self.zero_for_one(token_in)?;
+        let result = self.swap(zero_for_one, I256::try_from(amount_in)?, None)?;
+
+        Ok(self.quote(zero_for_one, result))
+    }
+
+    /// Quote receiving exactly `amount_out` for `token_in`
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `token_in` isn't in the pool, it can't be swapped
+    /// against or it doesn't have the liquidity for `amount_out`
+    pub fn quote_exact_out(&self, token_in: Address, amount_out: U256) -> Result<V3Quote> {
+        let zero_for_one = self.zero_for_one(token_in)?;
+        let result = self.swap(zero_for_one, -I256::try_from(amount_out)?, None)?;
+        let quote = self.quote(zero_for_one, result);
+        if quote.amount_out != amount_out {
+            bail!(
+                "Pool {:?} only has the liquidity for {} of {}",
+                self.address,
+                quote.amount_out,
+                amount_out
+            );
+        }
+
+        Ok(quote)
+    }
+
+    /// Read the state of a pool at `block`, with the initialized ticks of `words` words of the
+    /// tick bitmap on each side of the current tick
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a call to the pool failed
+    pub async fn fetch<M: Middleware>(
+        provider: &M,
+        address: Address,
+        block: Option<u64>,
+        words: i32,
+    ) -> Result<Self> {
+        use ParamType::{Address as Addr, Bool, Int, Uint};
+
+        let call = |signature: &'static str, args: Vec<Token>, outputs: Vec<ParamType>| {
+            pool_call(provider, address, signature, args, outputs, block)
+        };
+        let address_of =
+            |tokens: Vec<Token>| tokens.into_iter().next().and_then(Token::into_address);
+
+        let token0 = address_of(call("token0()", vec![], vec![Addr]).await?);
+        let token1 = address_of(call("token1()", vec![], vec![Addr]).await?);
+        let fee = call("fee()", vec![], vec![Uint(24)]).await?;
+        let spacing = call("tickSpacing()", vec![], vec![Int(24)]).await?;
+        let liquidity = call("liquidity()", vec![], vec![Uint(128)]).await?;
+        let slot0 = call(
+            "slot0()",
+            vec![],
+            vec![
+                Uint(160),
+                Int(24),
+                Uint(16),
+                Uint(16),
+                Uint(16),
+                Uint(8),
+                Bool,
+            ],
+        )
+        .await?;
+
+        let (Some(token0), Some(token1)) = (token0, token1) else {
+            bail!("Pool {:?} has no tokens", address);
+        };
+        let [Token::Uint(fee)] = fee.as_slice() else {
+            bail!("Pool {:?} has no fee", address);
+        };
+        let [Token::Int(spacing)] = spacing.as_slice() else {
+            bail!("Pool {:?} has no tick spacing", address);
+        };
+        let [Token::Uint(liquidity)] = liquidity.as_slice() else {
+            bail!("Pool {:?} has no liquidity", address);
+        };
+        let [Token::Uint(sqrt_price_x96), Token::Int(tick), ..] = slot0.as_slice() else {
+            bail!("Pool {:?} has no slot0", address);
+        };
+
+        let mut pool = Self {
+            address,
+            token0,
+            token1,
+            fee: fee.as_u32(),
+            tick_spacing: I256::from_raw(*spacing).as_i32(),
+            sqrt_price_x96: *sqrt_price_x96,
+            tick: I256::from_raw(*tick).as_i32(),
+            liquidity: liquidity.as_u128(),
+            ticks: BTreeMap::new(),
+        };
+
+        // Ticks are compressed rounding down, like the pool does, so a negative tick that isn't
+        // a multiple of the spacing stays in the word below
+        let word = pool.tick.div_euclid(pool.tick_spacing).div_euclid(256);
+        let min_word = MIN_TICK.div_euclid(pool.tick_spacing).div_euclid(256);
+        let max_word = (MAX_TICK / pool.tick_spacing).div_euclid(256);
+        for word in (word - words).max(min_word)..=(word + words).min(max_word) {
+            let bitmap = call(
+                "tickBitmap(int16)",
+                vec![Token::Int(I256::from(word).into_raw())],
+                vec![Uint(256)],
+            )
+            .await?
+            .into_iter()
+            .next()
+            .and_then(Token::into_uint)
+            .unwrap_or_default();
+
+            for bit in (0..256).filter(|bit| bitmap.bit(*bit as usize)) {
+                let tick = (word * 256 + bit) * pool.tick_spacing;
+                let info = call(
+                    "ticks(int24)",
+                    vec![Token::Int(I256::from(tick).into_raw())],
+                    vec![
+                        Uint(128),
+                        Int(128),
+                        Uint(256),
+                        Uint(256),
+                        Int(56),
+                        Uint(160),
+                        Uint(32),
+                        Bool,
+                    ],
+                )
+                .await?;
+                let [Token::Uint(gross), Token::Int(net), ..] = info.as_slice() else {
+                    bail!("Pool {:?} has no tick {}", address, tick);
+                };
+
+                pool.ticks.insert(
+                    tick,
+                    TickInfo {
+                        liquidity_gross: gross.as_u128(),
+                        liquidity_net: I256::from_raw(*net).as_i128(),
+                    },
+                );
+            }
+        }
+
+        Ok(pool)
+    }
+}
+
+/// Call a view function of a pool
+async fn pool_call<M: Middleware>(
+    provider: &M,
+    pool: Address,
+    signature: &str,
+    args: Vec<Token>,
+    outputs: Vec<ParamType>,
+    block: Option<u64>,
+) -> Result<Vec<Token>> {
+    let mut data = id(signature).to_vec();
+    data.extend(abi::encode(&args));
+    let tx = TransactionRequest::new().to(pool).data(Bytes::from(data));
+    let output = provider
+        .call(&tx.into(), block.map(BlockId::from))
+        .await
+        .map_err(|e| anyhow!("{} of {:?} failed: {}", signature, pool, e))?;
+
+    Ok(abi::decode(&outputs, &output)?)
+}
+
+/// A swap recorded on-chain with the state of its pool right before it
+///
+/// Replaying fixtures validates the quotes against what the pools actually computed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SwapFixture {
+    pub tx_hash: H256,
+    pub block_number: u64,
+    pub log_index: u64,
+    pub pool: V3Pool,
+    /// Amounts of the `Swap` event
+    pub amount0: I256,
+    pub amount1: I256,
+    /// State of the pool after the swap, from the `Swap` event
+    pub sqrt_price_x96: U256,
+    pub tick: i32,
+    pub liquidity: u128,
+}
+
+impl SwapFixture {
+    /// Fixture of the `Swap` log of `pool`, whose state must be the one right before the log
+    pub fn from_log(pool: V3Pool, log: &Log) -> Option<Self> {
+        if log.address != pool.address || log.topics.first() != Some(&swap_topic()) {
+            return None;
+        }
+
+        let mut after = pool.clone();
+        after.apply_log(log).ok()?;
+        let mut amounts = abi::decode(&[ParamType::Int(256), ParamType::Int(256)], &log.data)
+            .ok()?
+            .into_iter();
+
+        Some(Self {
+            tx_hash: log.transaction_hash?,
+            block_number: log.block_number?.as_u64(),
+            log_index: log.log_index?.as_u64(),
+            amount0: amounts.next().and_then(int_of)?,
+            amount1: amounts.next().and_then(int_of)?,
+            sqrt_price_x96: after.sqrt_price_x96,
+            tick: after.tick,
+            liquidity: after.liquidity,
+            pool,
+        })
+    }
+
+    /// Record the fixture of a mined `Swap` log, reading the pool at the previous block with
+    /// `words` words of its tick bitmap around the current tick
+    ///
+    /// Logs of the pool earlier in the same block are applied, so the state is the one right
+    /// before the swap. Needs an archive node for old blocks.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the log isn't a mined V3 `Swap` or the pool could
+    /// not be read
+    pub async fn record<M: Middleware>(provider: &M, log: &Log, words: i32) -> Result<Self> {
+        let (Some(block_number), Some(block_hash), Some(log_index)) =
+            (log.block_number, log.block_hash, log.log_index)
+        else {
+            bail!("Log {:?} is not mined", log.transaction_hash);
+        };
+
+        let mut pool = V3Pool::fetch(
+            provider,
+            log.address,
+            Some(block_number.as_u64().saturating_sub(1)),
+            words,
+        )
+        .await?;
+        let filter = Filter::new().at_block_hash(block_hash).address(log.address);
+        let mut earlier = provider
+            .get_logs(&filter)
+            .await
+            .map_err(|e| anyhow!("Failed to get logs of {:?}: {}", log.address, e))?
+            .into_iter()
+            .filter(|earlier| earlier.log_index.is_some_and(|index| index < log_index))
+            .collect::<Vec<Log>>();
+        earlier.sort_by_key(|earlier| earlier.log_index);
+        for earlier in &earlier {
+            pool.apply_log(earlier)?;
+        }
+
+        Self::from_log(pool, log)
+            .ok_or_else(|| anyhow!("Log {:?} is not a V3 swap", log.transaction_hash))
+    }
+
+    /// Replay the swap against the recorded pool
+    ///
+    /// The event doesn't tell whether the swap was an exact input or output, the one that
+    /// reproduces the recorded amounts and pool state is returned.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error describing the mismatch if neither reproduces the
+    /// swap
+    pub fn replay(&self) -> Result<SwapKind> {
+        let zero_for_one = self.amount0.is_positive();
+        let (amount_in, amount_out) = if zero_for_one {
+            (self.amount0, self.amount1)
+        } else {
+            (self.amount1, self.amount0)
+        };
+
+        let mut mismatches = Vec::new();
+        for (kind, amount_specified) in [
+            (SwapKind::ExactIn, amount_in),
+            (SwapKind::ExactOut, amount_out),
+        ] {
+            // A price limit may have stopped the swap, replaying up to the recorded price
+            // reproduces it
+            for limit in [None, Some(self.sqrt_price_x96)] {
+                match self.pool.swap(zero_for_one, amount_specified, limit) {
+                    Ok(result)
+                        if result.amount0 == self.amount0
+                            && result.amount1 == self.amount1
+                            && result.sqrt_price_x96 == self.sqrt_price_x96
+                            && result.tick == self.tick
+                            && result.liquidity == self.liquidity =>
+                    {
+                        return Ok(kind);
+                    }
+                    Ok(result) => mismatches.push(format!(
+                        "{} (limit {:?}): amounts {}/{} price {} tick {} liquidity {}",
+                        kind,
+                        limit,
+                        result.amount0,
+                        result.amount1,
+                        result.sqrt_price_x96,
+                        result.tick,
+                        result.liquidity
+                    )),
+                    Err(e) => mismatches.push(format!("{} (limit {:?}): {}", kind, limit, e)),
+                }
+            }
+        }
+
+        bail!(
+            "Swap {:?} recorded amounts {}/{} price {} tick {} liquidity {}, replayed {}",
+            self.tx_hash,
+            self.amount0,
+            self.amount1,
+            self.sqrt_price_x96,
+            self.tick,
+            self.liquidity,
+            mismatches.join(", ")
+        )
+    }
+}
@@ -0,0 +1,191 @@
+use dex::candle::{build_candles, merge_candles, Candle, Interval, Trade};
+use ethers::types::{Address, H256, U256};
+
+/// Start of a minute, and of a five minute and hour candle
+const MINUTE: u64 = 1_699_999_200;
+
+fn pair() -> Address {
+    Address::repeat_byte(0x11)
+}
+
+/// A trade of a pair with an 18 decimals token0 and a 6 decimals token1
+fn trade(timestamp: u64, amounts: [u64; 4]) -> Trade {
+    let [amount0_in, amount1_in, amount0_out, amount1_out] = amounts;
+
+    Trade {
+        pair: pair(),
+        tx_hash: H256::repeat_byte(0xaa),
+        block_number: 18_000_000,
+        block_hash: H256::repeat_byte(0xbb),
+        log_index: 0,
+        timestamp,
+        amount0_in: U256::from(amount0_in) * U256::exp10(12),
+        amount1_in: U256::from(amount1_in),
+        amount0_out: U256::from(amount0_out) * U256::exp10(12),
+        amount1_out: U256::from(amount1_out),
+    }
+}
+
+fn candle(interval: Interval, start: u64, prices: [f64; 4], volumes: (f64, f64)) -> Candle {
+    let [open, high, low, close] = prices;
+
+    Candle {
+        pair: pair(),
+        interval,
+        start,
+        open,
+        high,
+        low,
+        close,
+        volume0: volumes.0,
+        volume1: volumes.1,
+        trades: 1,
+    }
+}
+
+#[test]
+fn builds_minute_candles_from_trades() {
+    let trades = [
+        // 1 token0 sold for 2000 token1
+        trade(MINUTE, [1_000_000, 0, 0, 2_000_000_000]),
+        // 2 token0 bought for 4200 token1
+        trade(MINUTE + 30, [0, 4_200_000_000, 2_000_000, 0]),
+        // Nothing came out, there is no price
+        trade(MINUTE + 40, [1_000_000, 0, 0, 0]),
+        // 0.5 token0 sold for 950 token1
+        trade(MINUTE + 59, [500_000, 0, 0, 950_000_000]),
+        trade(MINUTE + 60, [1_000_000, 0, 0, 2_050_000_000]),
+    ];
+
+    let candles = build_candles(Interval::OneMinute, &trades, 18, 6);
+
+    assert_eq!(
+        candles,
+        vec![
+            Candle {
+                trades: 3,
+                ..candle(
+                    Interval::OneMinute,
+                    MINUTE,
+                    [2000.0, 2100.0, 1900.0, 1900.0],
+                    (3.5, 7150.0)
+                )
+            },
+            candle(
+                Interval::OneMinute,
+                MINUTE + 60,
+                [2050.0, 2050.0, 2050.0, 2050.0],
+                (1.0, 2050.0)
+            ),
+        ]
+    );
+}
+
+#[test]
+fn builds_no_candles_without_priced_trades() {
+    let trades = [trade(MINUTE, [1_000_000, 0, 0, 0])];
+
+    assert!(build_candles(Interval::OneMinute, &trades, 18, 6).is_empty());
+    assert!(build_candles(Interval::OneMinute, &[], 18, 6).is_empty());
+}
+
+#[test]
+fn merges_candles_in_time_order() {
+    let minutes = [
+        candle(
+            Interval::OneMinute,
+            MINUTE,
+            [10.0, 12.0, 9.0, 11.0],
+            (1.0, 10.0),
+        ),
+        candle(
+            Interval::OneMinute,
+            MINUTE + 60,
+            [11.0, 15.0, 11.0, 14.0],
+            (2.0, 28.0),
+        ),
+        candle(
+            Interval::OneMinute,
+            MINUTE + 120,
+            [14.0, 14.0, 8.0, 8.5],
+            (0.5, 4.0),
+        ),
+    ];
+
+    assert_eq!(Candle::merge(Interval::FiveMinutes, MINUTE, &[]), None);
+    assert_eq!(
+        Candle::merge(Interval::FiveMinutes, MINUTE, &minutes),
+        Some(Candle {
+            trades: 3,
+            ..candle(
+                Interval::FiveMinutes,
+                MINUTE,
+                [10.0, 15.0, 8.0, 8.5],
+                (3.5, 42.0)
+            )
+        })
+    );
+}
+
+#[test]
+fn merges_candles_into_the_interval_they_fall_in() {
+    let minutes = [
+        candle(
+            Interval::OneMinute,
+            MINUTE,
+            [10.0, 12.0, 9.0, 11.0],
+            (1.0, 10.0),
+        ),
+        candle(
+            Interval::OneMinute,
+            MINUTE + 240,
+            [11.0, 13.0, 10.0, 12.0],
+            (1.0, 12.0),
+        ),
+        candle(
+            Interval::OneMinute,
+            MINUTE + 300,
+            [12.0, 12.0, 7.0, 7.0],
+            (2.0, 16.0),
+        ),
+    ];
+
+    let five_minutes = merge_candles(Interval::FiveMinutes, &minutes);
+
+    assert_eq!(
+        five_minutes,
+        vec![
+            Candle {
+                trades: 2,
+                ..candle(
+                    Interval::FiveMinutes,
+                    MINUTE,
+                    [10.0, 13.0, 9.0, 12.0],
+                    (2.0, 22.0)
+                )
+            },
+            candle(
+                Interval::FiveMinutes,
+                MINUTE + 300,
+                [12.0, 12.0, 7.0, 7.0],
+                (2.0, 16.0)
+            ),
+        ]
+    );
+
+    // The hour candle is the same whether it's merged from minutes or five minutes
+    let hour = merge_candles(Interval::OneHour, &minutes);
+    assert_eq!(hour, merge_candles(Interval::OneHour, &five_minutes));
+    assert_eq!(
+        hour,
+        vec![Candle {
+            trades: 3,
+            ..candle(
+                Interval::OneHour,
+                MINUTE,
+                [10.0, 13.0, 7.0, 7.0],
+                (4.0, 38.0)
+            )
+        }]
+    );
+}
@@ -0,0 +1,418 @@
+#!/usr/bin/env python3
+"""Generate synthetic V3 swap fixtures with a port of the v3-core swap math.
+
+The port follows the Solidity of `UniswapV3Pool.swap`, `SwapMath`, `SqrtPriceMath`,
+`TickMath` and `TickBitmap` line by line with Python integers, so it shares no code with the
+Rust model it checks. Run it from this directory, the output is deterministic:
+
+    python3 generate.py > synthetic.json
+"""
+
+import hashlib
+import json
+
+MIN_TICK = -887272
+MAX_TICK = 887272
+MIN_SQRT_RATIO = 4295128739
+MAX_SQRT_RATIO = 1461446703485210103287273052203988822378723970342
+Q96 = 1 << 96
+UINT256 = 1 << 256
+UINT160_MAX = (1 << 160) - 1
+
+
+def mul_div(a, b, denominator):
+    return a * b // denominator
+
+
+def mul_div_rounding_up(a, b, denominator):
+    return -(-a * b // denominator)
+
+
+def div_rounding_up(a, b):
+    return -(-a // b)
+
+
+def get_sqrt_ratio_at_tick(tick):
+    abs_tick = abs(tick)
+    assert abs_tick <= MAX_TICK
+    ratio = (
+        0xFFFCB933BD6FAD37AA2D162D1A594001
+        if abs_tick & 0x1
+        else 0x100000000000000000000000000000000
+    )
+    for bit, factor in [
+        (0x2, 0xFFF97272373D413259A46990580E213A),
+        (0x4, 0xFFF2E50F5F656932EF12357CF3C7FDCC),
+        (0x8, 0xFFE5CACA7E10E4E61C3624EAA0941CD0),
+        (0x10, 0xFFCB9843D60F6159C9DB58835C926644),
+        (0x20, 0xFF973B41FA98C081472E6896DFB254C0),
+        (0x40, 0xFF2EA16466C96A3843EC78B326B52861),
+        (0x80, 0xFE5DEE046A99A2A811C461F1969C3053),
+        (0x100, 0xFCBE86C7900A88AEDCFFC83B479AA3A4),
+        (0x200, 0xF987A7253AC413176F2B074CF7815E54),
+        (0x400, 0xF3392B0822B70005940C7A398E4B70F3),
+        (0x800, 0xE7159475A2C29B7443B29C7FA6E889D9),
+        (0x1000, 0xD097F3BDFD2022B8845AD8F792AA5825),
+        (0x2000, 0xA9F746462D870FDF8A65DC1F90E061E5),
+        (0x4000, 0x70D869A156D2A1B890BB3DF62BAF32F7),
+        (0x8000, 0x31BE135F97D08FD981231505542FCFA6),
+        (0x10000, 0x9AA508B5B7A84E1C677DE54F3E99BC9),
+        (0x20000, 0x5D6AF8DEDB81196699C329225EE604),
+        (0x40000, 0x2216E584F5FA1EA926041BEDFE98),
+        (0x80000, 0x48A170391F7DC42444E8FA2),
+    ]:
+        if abs_tick & bit:
+            ratio = (ratio * factor) >> 128
+    if tick > 0:
+        ratio = (UINT256 - 1) // ratio
+    return (ratio >> 32) + (0 if ratio % (1 << 32) == 0 else 1)
+
+
+def get_tick_at_sqrt_ratio(sqrt_price_x96):
+    """Greatest tick whose ratio is at most the price, what the Solidity computes"""
+    assert MIN_SQRT_RATIO <= sqrt_price_x96 < MAX_SQRT_RATIO
+    low, high = MIN_TICK, MAX_TICK
+    while low < high:
+        middle = (low + high + 1) // 2
+        if get_sqrt_ratio_at_tick(middle) <= sqrt_price_x96:
+            low = middle
+        else:
+            high = middle - 1
+    return low
+
+
+def next_sqrt_price_from_amount0_rounding_up(sqrt_price_x96, liquidity, amount, add):
+    if amount == 0:
+        return sqrt_price_x96
+    numerator1 = liquidity << 96
+    product = (amount * sqrt_price_x96) % UINT256
+    if add:
+        if product // amount == sqrt_price_x96:
+            denominator = (numerator1 + product) % UINT256
+            if denominator >= numerator1:
+                return mul_div_rounding_up(numerator1, sqrt_price_x96, denominator)
+        return div_rounding_up(numerator1, numerator1 // sqrt_price_x96 + amount)
+    assert product // amount == sqrt_price_x96 and numerator1 > product
+    return mul_div_rounding_up(numerator1, sqrt_price_x96, numerator1 - product)
+
+
+def next_sqrt_price_from_amount1_rounding_down(sqrt_price_x96, liquidity, amount, add):
+    if add:
+        quotient = (amount << 96) // liquidity
+        result = sqrt_price_x96 + quotient
+        assert result <= UINT160_MAX
+        return result
+    quotient = div_rounding_up(amount << 96, liquidity)
+    assert sqrt_price_x96 > quotient
+    return sqrt_price_x96 - quotient
+
+
+def next_sqrt_price_from_input(sqrt_price_x96, liquidity, amount_in, zero_for_one):
+    if zero_for_one:
+        return next_sqrt_price_from_amount0_rounding_up(
+            sqrt_price_x96, liquidity, amount_in, True
+        )
+    return next_sqrt_price_from_amount1_rounding_down(
+        sqrt_price_x96, liquidity, amount_in, True
+    )
+
+
+def next_sqrt_price_from_output(sqrt_price_x96, liquidity, amount_out, zero_for_one):
+    if zero_for_one:
+        return next_sqrt_price_from_amount1_rounding_down(
+            sqrt_price_x96, liquidity, amount_out, False
+        )
+    return next_sqrt_price_from_amount0_rounding_up(
+        sqrt_price_x96, liquidity, amount_out, False
+    )
+
+
+def amount0_delta(price_a, price_b, liquidity, round_up):
+    price_a, price_b = sorted((price_a, price_b))
+    numerator1 = liquidity << 96
+    numerator2 = price_b - price_a
+    if round_up:
+        return div_rounding_up(
+            mul_div_rounding_up(numerator1, numerator2, price_b), price_a
+        )
+    return mul_div(numerator1, numerator2, price_b) // price_a
+
+
+def amount1_delta(price_a, price_b, liquidity, round_up):
+    price_a, price_b = sorted((price_a, price_b))
+    if round_up:
+        return mul_div_rounding_up(liquidity, price_b - price_a, Q96)
+    return mul_div(liquidity, price_b - price_a, Q96)
+
+
+def compute_swap_step(current, target, liquidity, remaining, fee):
+    zero_for_one = current >= target
+    exact_in = remaining >= 0
+
+    if exact_in:
+        remaining_less_fee = mul_div(remaining, 1_000_000 - fee, 1_000_000)
+        amount_in = (
+            amount0_delta(target, current, liquidity, True)
+            if zero_for_one
+            else amount1_delta(current, target, liquidity, True)
+        )
+        if remaining_less_fee >= amount_in:
+            next_price = target
+        else:
+            next_price = next_sqrt_price_from_input(
+                current, liquidity, remaining_less_fee, zero_for_one
+            )
+    else:
+        amount_out = (
+            amount1_delta(target, current, liquidity, False)
+            if zero_for_one
+            else amount0_delta(current, target, liquidity, False)
+        )
+        if -remaining >= amount_out:
+            next_price = target
+        else:
+            next_price = next_sqrt_price_from_output(
+                current, liquidity, -remaining, zero_for_one
+            )
+
+    reached = target == next_price
+    if zero_for_one:
+        if not (reached and exact_in):
+            amount_in = amount0_delta(next_price, current, liquidity, True)
+        if not (reached and not exact_in):
+            amount_out = amount1_delta(next_price, current, liquidity, False)
+    else:
+        if not (reached and exact_in):
+            amount_in = amount1_delta(current, next_price, liquidity, True)
+        if not (reached and not exact_in):
+            amount_out = amount0_delta(current, next_price, liquidity, False)
+
+    if not exact_in and amount_out > -remaining:
+        amount_out = -remaining
+
+    if exact_in and next_price != target:
+        fee_amount = remaining - amount_in
+    else:
+        fee_amount = mul_div_rounding_up(amount_in, fee, 1_000_000 - fee)
+
+    return next_price, amount_in, amount_out, fee_amount
+
+
+class Pool:
+    def __init__(self, address, fee, tick_spacing, tick, offset, positions):
+        self.address = address
+        self.fee = fee
+        self.tick_spacing = tick_spacing
+        # A price inside the tick rather than on its boundary
+        self.sqrt_price_x96 = get_sqrt_ratio_at_tick(tick) + offset
+        assert get_tick_at_sqrt_ratio(self.sqrt_price_x96) == tick
+        self.tick = tick
+        self.ticks = {}
+        self.liquidity = 0
+        for lower, upper, liquidity in positions:
+            assert lower % tick_spacing == 0 and upper % tick_spacing == 0
+            for bound, net in [(lower, liquidity), (upper, -liquidity)]:
+                gross, old_net = self.ticks.get(bound, (0, 0))
+                self.ticks[bound] = (gross + liquidity, old_net + net)
+            if lower <= tick < upper:
+                self.liquidity += liquidity
+
+    def next_initialized_tick_within_one_word(self, tick, lte):
+        compressed = tick // self.tick_spacing
+        initialized = sorted(
+            bound // self.tick_spacing
+            for bound, (gross, _) in self.ticks.items()
+            if gross > 0
+        )
+        if lte:
+            word_start = (compressed >> 8) << 8
+            found = [c for c in initialized if word_start <= c <= compressed]
+            if found:
+                return found[-1] * self.tick_spacing, True
+            return word_start * self.tick_spacing, False
+        compressed += 1
+        word_end = ((compressed >> 8) << 8) + 255
+        found = [c for c in initialized if compressed <= c <= word_end]
+        if found:
+            return found[0] * self.tick_spacing, True
+        return word_end * self.tick_spacing, False
+
+    def swap(self, zero_for_one, amount_specified, limit=None):
+        if limit is None:
+            limit = MIN_SQRT_RATIO + 1 if zero_for_one else MAX_SQRT_RATIO - 1
+        if zero_for_one:
+            assert MIN_SQRT_RATIO < limit < self.sqrt_price_x96
+        else:
+            assert self.sqrt_price_x96 < limit < MAX_SQRT_RATIO
+
+        exact_in = amount_specified > 0
+        remaining = amount_specified
+        calculated = 0
+        price, tick, liquidity = self.sqrt_price_x96, self.tick, self.liquidity
+
+        while remaining != 0 and price != limit:
+            start = price
+            tick_next, initialized = self.next_initialized_tick_within_one_word(
+                tick, zero_for_one
+            )
+            tick_next = min(max(tick_next, MIN_TICK), MAX_TICK)
+            price_next = get_sqrt_ratio_at_tick(tick_next)
+            if (zero_for_one and price_next < limit) or (
+                not zero_for_one and price_next > limit
+            ):
+                target = limit
+            else:
+                target = price_next
+
+            price, amount_in, amount_out, fee_amount = compute_swap_step(
+                price, target, liquidity, remaining, self.fee
+            )
+            if exact_in:
+                remaining -= amount_in + fee_amount
+                calculated -= amount_out
+            else:
+                remaining += amount_out
+                calculated += amount_in + fee_amount
+
+            if price == price_next:
+                if initialized:
+                    net = self.ticks[tick_next][1]
+                    liquidity += -net if zero_for_one else net
+                    assert liquidity >= 0
+                tick = tick_next - 1 if zero_for_one else tick_next
+            elif price != start:
+                tick = get_tick_at_sqrt_ratio(price)
+
+        swapped = amount_specified - remaining
+        if zero_for_one == exact_in:
+            amounts = (swapped, calculated)
+        else:
+            amounts = (calculated, swapped)
+
+        return amounts, price, tick, liquidity
+
+
+def address(byte):
+    return "0x" + ("%02x" % byte) * 20
+
+
+def word(value):
+    """A uint256 or int256 as serialized by ethers, in two's complement"""
+    return hex(value % UINT256)
+
+
+def pool_json(pool, token0, token1):
+    return {
+        "address": pool.address,
+        "token0": token0,
+        "token1": token1,
+        "fee": pool.fee,
+        "tick_spacing": pool.tick_spacing,
+        "sqrt_price_x96": word(pool.sqrt_price_x96),
+        "tick": pool.tick,
+        "liquidity": pool.liquidity,
+        "ticks": {
+            str(tick): {"liquidity_gross": gross, "liquidity_net": net}
+            for tick, (gross, net) in sorted(pool.ticks.items())
+        },
+    }
+
+
+def fixture(name, index, pool, tokens, zero_for_one, amount_specified, limit=None):
+    (amount0, amount1), price, tick, liquidity = pool.swap(
+        zero_for_one, amount_specified, limit
+    )
+    return {
+        # Not mined, a hash of the case name keeps the fixtures apart
+        "tx_hash": "0x" + hashlib.sha256(name.encode()).hexdigest(),
+        "block_number": 0,
+        "log_index": index,
+        "pool": pool_json(pool, *tokens),
+        "amount0": word(amount0),
+        "amount1": word(amount1),
+        "sqrt_price_x96": word(price),
+        "tick": tick,
+        "liquidity": liquidity,
+    }
+
+
+def main():
+    e18 = 10**18
+
+    # 0.3% pool with overlapping positions around the price
+    medium = Pool(
+        address(0xA1),
+        3000,
+        60,
+        200,
+        12345678901234567890,
+        [
+            (-600, 600, 1_000 * e18),
+            (-120, 240, 500 * e18),
+            (300, 900, 2_000 * e18),
+            (-3_000, -60, 300 * e18),
+            (-887_220, 887_220, 10 * e18),
+        ],
+    )
+    medium_tokens = (address(0x01), address(0x02))
+
+    # 0.05% pool of an 18 and a 6 decimals token, at a WETH/USDC like price
+    low = Pool(
+        address(0xA2),
+        500,
+        10,
+        -201_234,
+        987654321,
+        [
+            (-201_300, -201_200, 3 * 10**16),
+            (-201_250, -201_000, 5 * 10**16),
+            (-202_000, -200_000, 10**16),
+        ],
+    )
+    low_tokens = (address(0x03), address(0x04))
+
+    # 1% pool whose swaps run through empty words of the tick bitmap
+    high = Pool(
+        address(0xA3),
+        10_000,
+        200,
+        0,
+        1,
+        [
+            (-200, 200, 50 * e18),
+            (-120_000, 120_000, 5 * e18),
+        ],
+    )
+    high_tokens = (address(0x05), address(0x06))
+
+    cases = [
+        ("medium exact in, crosses down", medium, medium_tokens, True, 40 * e18, None),
+        ("medium exact in, crosses up", medium, medium_tokens, False, 60 * e18, None),
+        ("medium exact in, within tick", medium, medium_tokens, True, 10**15, None),
+        ("medium exact out, crosses down", medium, medium_tokens, True, -35 * e18, None),
+        ("medium exact out, crosses up", medium, medium_tokens, False, -45 * e18, None),
+        (
+            "medium exact in, price limit",
+            medium,
+            medium_tokens,
+            True,
+            1_000 * e18,
+            get_sqrt_ratio_at_tick(-180) + 7,
+        ),
+        ("low exact in, sells token0", low, low_tokens, True, 12 * e18, None),
+        ("low exact in, sells token1", low, low_tokens, False, 25_000 * 10**6, None),
+        ("low exact out, buys token1", low, low_tokens, True, -9_000 * 10**6, None),
+        ("low exact out, buys token0", low, low_tokens, False, -4 * e18, None),
+        ("high exact in, leaves the word", high, high_tokens, True, 100 * e18, None),
+        ("high exact out, leaves the word", high, high_tokens, False, -5_400 * 10**15, None),
+    ]
+
+    fixtures = [
+        fixture(name, index, pool, tokens, zero_for_one, amount, limit)
+        for index, (name, pool, tokens, zero_for_one, amount, limit) in enumerate(cases)
+    ]
+    print(json.dumps(fixtures, indent=2))
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,242 @@
+use dex::bindings::uniswap_v2_router_02::{AddLiquidityETHCall, RemoveLiquidityCall};
+use dex::bindings::Protocol;
+use dex::factory::Factory;
+use dex::liquidity::{
+    decode_pair_liquidity, pair_burn_topic, pair_mint_topic, transfer_topic, LiquidityKind, LpFate,
+    DEAD, MINIMUM_LIQUIDITY,
+};
+use dex::protocol::ProtocolRegistry;
+use dex::registry::PairInfo;
+use dex::reserves::sync_topic;
+use dex::router::{Router, RouterAddress};
+use dex::swap::SwapStatus;
+use ethers::abi::{encode, AbiEncode, Token};
+use ethers::types::{Address, Bytes, Log, Transaction, H256, U256};
+
+const DEADLINE: u64 = 1_700_000_000;
+
+fn address(byte: u8) -> Address {
+    Address::repeat_byte(byte)
+}
+
+fn tx(to: Address, input: Vec<u8>, value: u64) -> Transaction {
+    Transaction {
+        hash: H256::repeat_byte(0xaa),
+        from: address(0xf0),
+        to: Some(to),
+        value: U256::from(value),
+        input: Bytes::from(input),
+        ..Default::default()
+    }
+}
+
+fn router() -> Router {
+    Router {
+        addresses: vec![RouterAddress::new(
+            address(0xa0),
+            Protocol::UniswapV2.router_abi(),
+        )],
+        factory: Factory::new(
+            address(0xa1),
+            Protocol::UniswapV2.factory_abi(),
+            String::from("uniswap"),
+            2,
+        ),
+        name: String::from("uniswap"),
+        version: 2,
+    }
+}
+
+fn pair() -> PairInfo {
+    PairInfo {
+        address: address(0xb0),
+        factory: address(0xa1),
+        protocol_version: 2,
+        token0: address(1),
+        token1: address(2),
+        fee: None,
+        created_block: 1,
+    }
+}
+
+fn log(topics: Vec<H256>, data: Vec<Token>) -> Log {
+    Log {
+        address: address(0xb0),
+        topics,
+        data: Bytes::from(encode(&data)),
+        ..Default::default()
+    }
+}
+
+fn transfer(from: Address, to: Address, value: u64) -> Log {
+    log(
+        vec![transfer_topic(), H256::from(from), H256::from(to)],
+        vec![Token::Uint(U256::from(value))],
+    )
+}
+
+fn amounts(amount0: u64, amount1: u64) -> Vec<Token> {
+    vec![
+        Token::Uint(U256::from(amount0)),
+        Token::Uint(U256::from(amount1)),
+    ]
+}
+
+#[test]
+fn pending_liquidity_decodes() {
+    let registry = ProtocolRegistry::new(&[router()], &[]).unwrap();
+
+    let add = AddLiquidityETHCall {
+        token: address(1),
+        amount_token_desired: U256::from(1_000_000),
+        amount_token_min: U256::from(900_000),
+        amount_eth_min: U256::from(9),
+        to: DEAD,
+        deadline: U256::from(DEADLINE),
+    }
+    .encode();
+    let add = tx(address(0xa0), add, 10);
+    let events = registry.liquidity(&add);
+    assert_eq!(events.len(), 1);
+    let event = &events[0];
+    assert_eq!(event.router, "uniswap");
+    assert_eq!(event.method, "addLiquidityETH");
+    assert_eq!(event.kind, LiquidityKind::Add);
+    assert_eq!(event.status, SwapStatus::Pending);
+    // ETH is the zero address until the WETH of the router is resolved
+    assert_eq!(
+        (event.token, event.paired_token),
+        (address(1), Address::zero())
+    );
+    assert_eq!(
+        (event.token_amount, event.paired_amount),
+        (U256::from(1_000_000), U256::from(10))
+    );
+    assert_eq!(event.liquidity, None);
+    assert_eq!(event.recipient, DEAD);
+    assert_eq!(event.lp_fate, Some(LpFate::Burned));
+    // The router makes no swap of it
+    assert!(registry.swaps(&add).is_empty());
+
+    let remove = RemoveLiquidityCall {
+        token_a: address(2),
+        token_b: address(1),
+        liquidity: U256::from(500),
+        amount_a_min: U256::from(40),
+        amount_b_min: U256::from(4_000),
+        to: address(0xf1),
+        deadline: U256::from(DEADLINE),
+    }
+    .encode();
+    let mut events = registry.liquidity(&tx(address(0xa0), remove, 0));
+    assert_eq!(events.len(), 1);
+    let event = &mut events[0];
+    assert_eq!(event.kind, LiquidityKind::Remove);
+    assert_eq!(event.liquidity, Some(U256::from(500)));
+    assert_eq!(event.lp_fate, None);
+
+    // Base tokens are moved to the paired side
+    event.orient(|token| token == address(2));
+    assert_eq!((event.token, event.paired_token), (address(1), address(2)));
+    assert_eq!(
+        (event.token_amount, event.paired_amount),
+        (U256::from(4_000), U256::from(40))
+    );
+}
+
+#[test]
+fn mined_launches_decode() {
+    let owner = address(0xf1);
+    let locker = address(0xe0);
+    let logs = vec![
+        transfer(Address::zero(), Address::zero(), MINIMUM_LIQUIDITY),
+        transfer(Address::zero(), owner, 99_000),
+        log(vec![sync_topic()], amounts(1_000_000, 10_000)),
+        log(
+            vec![pair_mint_topic(), H256::from(address(0xa0))],
+            amounts(1_000_000, 10_000),
+        ),
+        transfer(owner, locker, 99_000),
+    ];
+
+    let events = decode_pair_liquidity(
+        &tx(address(0xa0), Vec::new(), 0),
+        &logs,
+        &pair(),
+        "uniswap",
+        &[locker],
+    );
+    assert_eq!(events.len(), 1);
+    let event = &events[0];
+    assert!(event.is_launch());
+    assert_eq!(event.method, "Mint");
+    assert_eq!(event.pair, Some(address(0xb0)));
+    assert_eq!(
+        (event.token_amount, event.paired_amount),
+        (U256::from(1_000_000), U256::from(10_000))
+    );
+    assert_eq!(event.liquidity, Some(U256::from(99_000)));
+    assert_eq!(event.recipient, owner);
+    assert_eq!(event.lp_fate, Some(LpFate::Locked));
+
+    // Later adds mint no minimum liquidity, their LP tokens stay with the recipient
+    let events = decode_pair_liquidity(
+        &tx(address(0xa0), Vec::new(), 0),
+        &logs[1..4],
+        &pair(),
+        "uniswap",
+        &[locker],
+    );
+    assert!(!events[0].initial);
+    assert_eq!(events[0].lp_fate, Some(LpFate::Held));
+
+    // Logs of other contracts are ignored
+    let events = decode_pair_liquidity(
+        &tx(address(0xa0), Vec::new(), 0),
+        &logs,
+        &PairInfo {
+            address: address(0xb1),
+            ..pair()
+        },
+        "uniswap",
+        &[locker],
+    );
+    assert!(events.is_empty());
+}
+
+#[test]
+fn mined_removals_decode() {
+    let pair = pair();
+    let logs = vec![
+        transfer(address(0xb0), Address::zero(), 90_000),
+        log(vec![sync_topic()], amounts(100_000, 1_000)),
+        log(
+            vec![
+                pair_burn_topic(),
+                H256::from(address(0xa0)),
+                H256::from(address(0xf1)),
+            ],
+            amounts(900_000, 9_000),
+        ),
+    ];
+
+    let events = decode_pair_liquidity(
+        &tx(address(0xa0), Vec::new(), 0),
+        &logs,
+        &pair,
+        "uniswap",
+        &[],
+    );
+    assert_eq!(events.len(), 1);
+    let event = &events[0];
+    assert_eq!(event.kind, LiquidityKind::Remove);
+    assert_eq!(event.method, "Burn");
+    assert_eq!(event.recipient, address(0xf1));
+    assert_eq!(event.liquidity, Some(U256::from(90_000)));
+    assert_eq!(
+        (event.token_amount, event.paired_amount),
+        (U256::from(900_000), U256::from(9_000))
+    );
+    assert_eq!(event.removed_share, Some(0.9));
+    assert_eq!(event.lp_fate, None);
+}
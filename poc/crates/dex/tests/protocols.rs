@@ -0,0 +1,433 @@
+use std::sync::Arc;
+
+use dex::balancer::BalancerV2;
+use dex::bindings::balancer_v2_vault::{
+    BalancerV2VaultCalls, BatchSwapCall, BatchSwapStep, FundManagement, SingleSwap, SwapCall,
+    SwapFilter,
+};
+use dex::bindings::curve_crypto_pool::CURVECRYPTOPOOL_ABI;
+use dex::bindings::curve_pool::CURVEPOOL_ABI;
+use dex::bindings::Protocol;
+use dex::curve::{Curve, CurvePool, CRYPTO_SWAP_VERSION, STABLE_SWAP_VERSION};
+use dex::factory::Factory;
+use dex::protocol::{ProtocolAction, ProtocolDecoder, ProtocolRegistry, ProtocolSettings};
+use dex::router::{Router, RouterAddress};
+use dex::swap::{SwapKind, SwapRecord};
+use ethers::abi::{encode, Abi, AbiEncode, Token};
+use ethers::contract::EthEvent;
+use ethers::types::{Address, Bytes, Log, Transaction, H256, I256, U256};
+
+const DEADLINE: u64 = 1_700_000_000;
+
+fn address(byte: u8) -> Address {
+    Address::repeat_byte(byte)
+}
+
+fn tx(to: Address, input: Vec<u8>) -> Transaction {
+    Transaction {
+        hash: H256::repeat_byte(0xaa),
+        from: address(0xf0),
+        to: Some(to),
+        input: Bytes::from(input),
+        ..Default::default()
+    }
+}
+
+fn swaps(decoder: &dyn ProtocolDecoder, tx: &Transaction) -> Vec<SwapRecord> {
+    decoder
+        .decode_calldata(tx)
+        .into_iter()
+        .filter_map(ProtocolAction::swap)
+        .collect()
+}
+
+fn funds() -> FundManagement {
+    FundManagement {
+        sender: address(0xf0),
+        from_internal_balance: false,
+        recipient: address(0xf1),
+        to_internal_balance: false,
+    }
+}
+
+fn vault() -> BalancerV2 {
+    BalancerV2::new(String::from("balancer"), address(0xba))
+}
+
+fn curve() -> Curve {
+    Curve::new(
+        String::from("curve"),
+        vec![
+            CurvePool {
+                address: address(0xc1),
+                coins: vec![address(1), address(2), address(3)],
+                underlying_coins: Vec::new(),
+            },
+            CurvePool {
+                address: address(0xc2),
+                coins: vec![address(3), address(4), address(5)],
+                underlying_coins: Vec::new(),
+            },
+        ],
+    )
+}
+
+fn uniswap() -> Router {
+    Router {
+        addresses: vec![RouterAddress::new(
+            address(0xa0),
+            Protocol::UniswapV2.router_abi(),
+        )],
+        factory: Factory::new(
+            address(0xa1),
+            Protocol::UniswapV2.factory_abi(),
+            String::from("uniswap"),
+            2,
+        ),
+        name: String::from("uniswap"),
+        version: 2,
+    }
+}
+
+fn vault_swap_log(token_in: Address, token_out: Address, amount_in: u64, amount_out: u64) -> Log {
+    Log {
+        address: address(0xba),
+        topics: vec![
+            SwapFilter::signature(),
+            H256::repeat_byte(7),
+            H256::from(token_in),
+            H256::from(token_out),
+        ],
+        data: Bytes::from(encode(&[
+            Token::Uint(U256::from(amount_in)),
+            Token::Uint(U256::from(amount_out)),
+        ])),
+        ..Default::default()
+    }
+}
+
+fn encode_call(abi: &Abi, name: &str, tokens: &[Token]) -> Vec<u8> {
+    abi.functions_by_name(name)
+        .unwrap()
+        .iter()
+        .find(|function| function.inputs.len() == tokens.len())
+        .unwrap()
+        .encode_input(tokens)
+        .unwrap()
+}
+
+#[test]
+fn balancer_single_swaps_decode() {
+    let vault = vault();
+    let call = |kind: u8| SwapCall {
+        single_swap: SingleSwap {
+            pool_id: [7; 32],
+            kind,
+            asset_in: address(1),
+            asset_out: address(2),
+            amount: U256::from(1_000),
+            user_data: Bytes::new(),
+        },
+        funds: funds(),
+        limit: U256::from(900),
+        deadline: U256::from(DEADLINE),
+    };
+
+    let given_in = swaps(&vault, &tx(address(0xba), call(0).encode()));
+    assert_eq!(given_in.len(), 1);
+    let swap = &given_in[0];
+    assert_eq!(swap.router, "balancer");
+    assert_eq!(swap.protocol_version, dex::balancer::VERSION);
+    assert_eq!(swap.method, "swap");
+    assert_eq!(swap.path, vec![address(1), address(2)]);
+    assert_eq!(swap.recipient, Some(address(0xf1)));
+    assert_eq!(swap.kind, SwapKind::ExactIn);
+    assert_eq!(
+        (swap.amount_in, swap.amount_out),
+        (U256::from(1_000), U256::from(900))
+    );
+    assert_eq!(swap.deadline, Some(DEADLINE));
+
+    let given_out = swaps(&vault, &tx(address(0xba), call(1).encode()));
+    assert_eq!(given_out[0].kind, SwapKind::ExactOut);
+    assert_eq!(
+        (given_out[0].amount_in, given_out[0].amount_out),
+        (U256::from(900), U256::from(1_000))
+    );
+
+    // Other contracts and other functions of the Vault make no swaps
+    assert!(swaps(&vault, &tx(address(0xbb), call(0).encode())).is_empty());
+    assert!(swaps(&vault, &tx(address(0xba), vec![0x12, 0x34, 0x56, 0x78])).is_empty());
+}
+
+#[test]
+fn balancer_batch_swaps_chain_into_routes() {
+    let step = |asset_in_index: u64, asset_out_index: u64, amount: u64| BatchSwapStep {
+        pool_id: [7; 32],
+        asset_in_index: U256::from(asset_in_index),
+        asset_out_index: U256::from(asset_out_index),
+        amount: U256::from(amount),
+        user_data: Bytes::new(),
+    };
+    let call = BalancerV2VaultCalls::BatchSwap(BatchSwapCall {
+        kind: 0,
+        // 1 -> 2 -> 3, then 4 -> 2 on its own
+        swaps: vec![step(0, 1, 1_000), step(1, 2, 0), step(3, 1, 50)],
+        assets: vec![address(1), address(2), address(3), address(4)],
+        funds: funds(),
+        limits: vec![
+            I256::from(1_000),
+            I256::from(-40),
+            I256::from(-800),
+            I256::from(50),
+        ],
+        deadline: U256::from(DEADLINE),
+    });
+
+    let swaps = swaps(&vault(), &tx(address(0xba), call.encode()));
+    assert_eq!(swaps.len(), 2);
+    assert_eq!(swaps[0].path, vec![address(1), address(2), address(3)]);
+    assert_eq!(
+        (swaps[0].amount_in, swaps[0].amount_out),
+        (U256::from(1_000), U256::from(800))
+    );
+    assert_eq!(swaps[1].path, vec![address(4), address(2)]);
+    assert_eq!(
+        (swaps[1].amount_in, swaps[1].amount_out),
+        (U256::from(50), U256::from(40))
+    );
+    assert_eq!(
+        swaps.iter().map(|swap| swap.swap_index).collect::<Vec<_>>(),
+        vec![0, 1]
+    );
+    assert!(swaps.iter().all(|swap| swap.method == "batchSwap"));
+}
+
+#[test]
+fn balancer_swap_logs_decode() {
+    let vault = vault();
+    let log = vault_swap_log(address(1), address(2), 1_000, 950);
+
+    let Some(ProtocolAction::Swap(swap)) = vault.decode_log(&tx(address(0xba), Vec::new()), &log)
+    else {
+        panic!("The Swap log wasn't decoded");
+    };
+    assert_eq!(swap.method, "Swap");
+    assert_eq!(swap.path, vec![address(1), address(2)]);
+    assert_eq!(
+        (swap.amount_in, swap.amount_out),
+        (U256::from(1_000), U256::from(950))
+    );
+
+    let other = Log {
+        address: address(0xbb),
+        ..log
+    };
+    assert!(vault
+        .decode_log(&tx(address(0xba), Vec::new()), &other)
+        .is_none());
+}
+
+#[test]
+fn curve_exchanges_decode() {
+    let curve = curve();
+
+    let stable = encode_call(
+        &CURVEPOOL_ABI,
+        "exchange",
+        &[
+            Token::Int(U256::from(0)),
+            Token::Int(U256::from(2)),
+            Token::Uint(U256::from(1_000)),
+            Token::Uint(U256::from(990)),
+        ],
+    );
+    let decoded = swaps(&curve, &tx(address(0xc1), stable));
+    assert_eq!(decoded.len(), 1);
+    assert_eq!(decoded[0].protocol_version, STABLE_SWAP_VERSION);
+    assert_eq!(decoded[0].path, vec![address(1), address(3)]);
+    assert_eq!(decoded[0].recipient, Some(address(0xf0)));
+    assert_eq!(decoded[0].kind, SwapKind::ExactIn);
+    assert_eq!(
+        (decoded[0].amount_in, decoded[0].amount_out),
+        (U256::from(1_000), U256::from(990))
+    );
+
+    let crypto = encode_call(
+        &CURVECRYPTOPOOL_ABI,
+        "exchange",
+        &[
+            Token::Uint(U256::from(2)),
+            Token::Uint(U256::from(0)),
+            Token::Uint(U256::from(1_000)),
+            Token::Uint(U256::from(10)),
+            Token::Bool(true),
+            Token::Address(address(0xf1)),
+        ],
+    );
+    let decoded = swaps(&curve, &tx(address(0xc2), crypto));
+    assert_eq!(decoded.len(), 1);
+    assert_eq!(decoded[0].protocol_version, CRYPTO_SWAP_VERSION);
+    assert_eq!(decoded[0].path, vec![address(5), address(3)]);
+    assert_eq!(decoded[0].recipient, Some(address(0xf1)));
+
+    // Coins the settings don't list make no swaps
+    let unknown = encode_call(
+        &CURVEPOOL_ABI,
+        "exchange",
+        &[
+            Token::Int(U256::from(0)),
+            Token::Int(U256::from(7)),
+            Token::Uint(U256::from(1_000)),
+            Token::Uint(U256::from(990)),
+        ],
+    );
+    assert!(swaps(&curve, &tx(address(0xc1), unknown)).is_empty());
+}
+
+#[test]
+fn curve_token_exchange_logs_decode() {
+    let curve = curve();
+    let event = &CURVEPOOL_ABI.event("TokenExchange").unwrap();
+    let log = Log {
+        address: address(0xc1),
+        topics: vec![event.signature(), H256::from(address(0xf1))],
+        data: Bytes::from(encode(&[
+            Token::Int(U256::from(1)),
+            Token::Uint(U256::from(1_000)),
+            Token::Int(U256::from(0)),
+            Token::Uint(U256::from(999)),
+        ])),
+        ..Default::default()
+    };
+
+    let Some(ProtocolAction::Swap(swap)) = curve.decode_log(&tx(address(0xc1), Vec::new()), &log)
+    else {
+        panic!("The TokenExchange log wasn't decoded");
+    };
+    assert_eq!(swap.method, "TokenExchange");
+    assert_eq!(swap.protocol_version, STABLE_SWAP_VERSION);
+    assert_eq!(swap.path, vec![address(2), address(1)]);
+    assert_eq!(swap.recipient, Some(address(0xf1)));
+    assert_eq!(
+        (swap.amount_in, swap.amount_out),
+        (U256::from(1_000), U256::from(999))
+    );
+}
+
+#[test]
+fn registry_decodes_routers_and_configured_protocols() {
+    let router = uniswap();
+    let settings = serde_json::from_value::<Vec<ProtocolSettings>>(serde_json::json!([
+        {
+            "kind": "balancer_v2",
+            "name": "balancer",
+            "vault": format!("{:?}", address(0xba)),
+        },
+        {
+            "kind": "curve",
+            "name": "curve",
+            "pools": [{
+                "address": format!("{:?}", address(0xc1)),
+                "coins": [format!("{:?}", address(1)), format!("{:?}", address(2))],
+            }],
+        },
+    ]))
+    .unwrap();
+    let mut registry = ProtocolRegistry::new(&[router], &settings).unwrap();
+    assert_eq!(registry.len(), 3);
+    assert_eq!(registry.decoder(address(0xba)).unwrap().name(), "balancer");
+    assert_eq!(registry.decoder(address(0xc1)).unwrap().name(), "curve");
+    assert!(registry.decoder(address(0xc2)).is_none());
+
+    // The router swaps are the ones its own decoding makes
+    let input = dex::bindings::uniswap_v2_router_02::SwapExactTokensForTokensCall {
+        amount_in: U256::from(1_000),
+        amount_out_min: U256::from(900),
+        path: vec![address(1), address(2)],
+        to: address(0xf1),
+        deadline: U256::from(DEADLINE),
+    }
+    .encode();
+    let swaps = registry.swaps(&tx(address(0xa0), input));
+    assert_eq!(swaps.len(), 1);
+    assert_eq!(swaps[0].router, "uniswap");
+    assert_eq!(swaps[0].method, "swapExactTokensForTokens");
+    assert_eq!(swaps[0].path, vec![address(1), address(2)]);
+
+    // Addresses already matched stay with their decoder
+    registry.register(Arc::new(BalancerV2::new(
+        String::from("other"),
+        address(0xba),
+    )));
+    assert_eq!(registry.decoder(address(0xba)).unwrap().name(), "balancer");
+
+    let invalid = serde_json::from_value::<Vec<ProtocolSettings>>(serde_json::json!([
+        { "kind": "balancer_v2", "name": "balancer", "vault": "nope" },
+    ]))
+    .unwrap();
+    let error = ProtocolRegistry::new(&[], &invalid).unwrap_err();
+    assert!(error.to_string().contains("balancer"));
+}
+
+#[test]
+fn mined_swaps_are_numbered_per_tx() {
+    let settings = serde_json::from_value::<Vec<ProtocolSettings>>(serde_json::json!([{
+        "kind": "balancer_v2",
+        "name": "balancer",
+        "vault": format!("{:?}", address(0xba)),
+    }]))
+    .unwrap();
+    let registry = ProtocolRegistry::new(&[uniswap()], &settings).unwrap();
+
+    // A router swap followed by a swap of the Vault made along the way
+    let input = dex::bindings::uniswap_v2_router_02::SwapExactTokensForTokensCall {
+        amount_in: U256::from(1_000),
+        amount_out_min: U256::from(900),
+        path: vec![address(1), address(2)],
+        to: address(0xf1),
+        deadline: U256::from(DEADLINE),
+    }
+    .encode();
+    let logs = vec![
+        vault_swap_log(address(2), address(3), 900, 850),
+        vault_swap_log(address(3), address(4), 850, 800),
+    ];
+    let swaps = registry.mined_swaps(&tx(address(0xa0), input), &logs);
+    assert_eq!(
+        swaps
+            .iter()
+            .map(|swap| (swap.swap_index, swap.router.as_str(), swap.method.as_str()))
+            .collect::<Vec<_>>(),
+        vec![
+            (0, "uniswap", "swapExactTokensForTokens"),
+            (1, "balancer", "Swap"),
+            (2, "balancer", "Swap"),
+        ]
+    );
+    assert_eq!(swaps[2].path, vec![address(3), address(4)]);
+
+    // Txs sent to the Vault are decoded from their calldata only
+    let call = SwapCall {
+        single_swap: SingleSwap {
+            pool_id: [7; 32],
+            kind: 0,
+            asset_in: address(1),
+            asset_out: address(2),
+            amount: U256::from(1_000),
+            user_data: Bytes::new(),
+        },
+        funds: funds(),
+        limit: U256::from(900),
+        deadline: U256::from(DEADLINE),
+    };
+    let logs = vec![vault_swap_log(address(1), address(2), 1_000, 950)];
+    let swaps = registry.mined_swaps(&tx(address(0xba), call.encode()), &logs);
+    assert_eq!(swaps.len(), 1);
+    assert_eq!((swaps[0].swap_index, swaps[0].method.as_str()), (0, "swap"));
+
+    // Swaps made through other contracts are only known from their logs
+    let swaps = registry.mined_swaps(&tx(address(0xee), Vec::new()), &logs);
+    assert_eq!(swaps.len(), 1);
+    assert_eq!((swaps[0].swap_index, swaps[0].method.as_str()), (0, "Swap"));
+}
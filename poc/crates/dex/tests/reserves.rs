@@ -0,0 +1,260 @@
+use dex::registry::PairInfo;
+use dex::reserves::{
+    amount_in, amount_out, price_impact, simulate_swap, ReserveTracker, Reserves, V2_FEE_BPS,
+};
+use dex::swap::{SwapKind, SwapRecord, SwapStatus};
+use ethers::types::{Address, H256, U256};
+
+fn token(byte: u8) -> Address {
+    Address::repeat_byte(byte)
+}
+
+fn pair(byte: u8, token0: u8, token1: u8) -> PairInfo {
+    PairInfo {
+        address: Address::repeat_byte(byte),
+        factory: Address::repeat_byte(0xa1),
+        protocol_version: 2,
+        token0: token(token0),
+        token1: token(token1),
+        fee: None,
+        created_block: 1,
+    }
+}
+
+fn reserves(reserve0: u64, reserve1: u64) -> Reserves {
+    Reserves {
+        reserve0: U256::from(reserve0),
+        reserve1: U256::from(reserve1),
+        block_number: Some(1),
+    }
+}
+
+fn swap(path: &[u8], kind: SwapKind, amount_in: u64, amount_out: u64) -> SwapRecord {
+    SwapRecord {
+        tx_hash: H256::repeat_byte(0xaa),
+        swap_index: 0,
+        router: String::from("uniswap"),
+        protocol_version: 2,
+        method: String::from("swapExactTokensForTokens"),
+        sender: Address::repeat_byte(0xf0),
+        recipient: None,
+        path: path.iter().copied().map(token).collect(),
+        fees: Vec::new(),
+        kind,
+        amount_in: U256::from(amount_in),
+        amount_out: U256::from(amount_out),
+        deadline: None,
+        status: SwapStatus::Pending,
+        block_number: None,
+        block_hash: None,
+        transaction_index: None,
+        score: None,
+        amounts: None,
+    }
+}
+
+/// Pairs of tokens 1/2 and 2/3 with their reserves
+fn tracked_pairs() -> ([PairInfo; 2], ReserveTracker) {
+    let pairs = [pair(0x12, 1, 2), pair(0x23, 2, 3)];
+    let mut tracker = ReserveTracker::default();
+    tracker.update(pairs[0].address, reserves(100_000, 200_000));
+    tracker.update(pairs[1].address, reserves(400_000, 100_000));
+
+    (pairs, tracker)
+}
+
+fn u256(value: u64) -> U256 {
+    U256::from(value)
+}
+
+#[test]
+fn amount_out_takes_the_fee() {
+    // 1000 * 9970 * 200000 / (100000 * 10000 + 1000 * 9970) = 1974.3
+    assert_eq!(
+        amount_out(u256(1_000), u256(100_000), u256(200_000), V2_FEE_BPS),
+        Some(u256(1_974))
+    );
+    // Without the fee it would be 1980.2
+    assert_eq!(
+        amount_out(u256(1_000), u256(100_000), u256(200_000), 0),
+        Some(u256(1_980))
+    );
+}
+
+#[test]
+fn amount_in_rounds_up() {
+    // 100000 * 1974 * 10000 / ((200000 - 1974) * 9970) + 1 = 999.8 + 1
+    assert_eq!(
+        amount_in(u256(1_974), u256(100_000), u256(200_000), V2_FEE_BPS),
+        Some(u256(1_000))
+    );
+    // 200000 * 1000 * 10000 / ((100000 - 1000) * 9970) + 1 = 2026.3 + 1
+    assert_eq!(
+        amount_in(u256(1_000), u256(200_000), u256(100_000), V2_FEE_BPS),
+        Some(u256(2_027))
+    );
+}
+
+#[test]
+fn empty_pools_and_amounts_have_no_quote() {
+    let (reserve_in, reserve_out) = (u256(100_000), u256(200_000));
+
+    assert_eq!(
+        amount_out(U256::zero(), reserve_in, reserve_out, V2_FEE_BPS),
+        None
+    );
+    assert_eq!(
+        amount_out(u256(1_000), U256::zero(), reserve_out, V2_FEE_BPS),
+        None
+    );
+    assert_eq!(
+        amount_out(u256(1_000), reserve_in, U256::zero(), V2_FEE_BPS),
+        None
+    );
+    assert_eq!(
+        amount_out(U256::MAX, reserve_in, reserve_out, V2_FEE_BPS),
+        None
+    );
+
+    assert_eq!(
+        amount_in(U256::zero(), reserve_in, reserve_out, V2_FEE_BPS),
+        None
+    );
+    assert_eq!(
+        amount_in(u256(1_000), U256::zero(), reserve_out, V2_FEE_BPS),
+        None
+    );
+    assert_eq!(
+        amount_in(u256(1_000), reserve_in, U256::zero(), V2_FEE_BPS),
+        None
+    );
+    // The pool can't send all of its reserve
+    assert_eq!(
+        amount_in(reserve_out, reserve_in, reserve_out, V2_FEE_BPS),
+        None
+    );
+}
+
+#[test]
+fn amount_in_round_trips_with_amount_out() {
+    let (reserve_in, reserve_out) = (u256(1_234_567_890), u256(987_654_321_000));
+
+    for amount in [1, 7, 1_000, 123_456, 50_000_000, 900_000_000] {
+        let amount = u256(amount);
+
+        // Paying the quoted input always buys at least the wanted output
+        let input = amount_in(amount, reserve_in, reserve_out, V2_FEE_BPS).unwrap();
+        assert!(amount_out(input, reserve_in, reserve_out, V2_FEE_BPS).unwrap() >= amount);
+
+        // The output of an input never costs more than that input
+        if let Some(output) = amount_out(amount, reserve_in, reserve_out, V2_FEE_BPS) {
+            if !output.is_zero() {
+                assert!(amount_in(output, reserve_in, reserve_out, V2_FEE_BPS).unwrap() <= amount);
+            }
+        }
+    }
+}
+
+#[test]
+fn price_impact_is_the_share_of_the_new_reserve() {
+    let impact = price_impact(u256(1_000), u256(100_000)).unwrap();
+    assert!((impact - 1_000.0 / 101_000.0).abs() < 1e-12);
+
+    assert_eq!(price_impact(U256::zero(), u256(100_000)), Some(0.0));
+    assert_eq!(price_impact(u256(1_000), U256::zero()), Some(1.0));
+    assert_eq!(price_impact(U256::zero(), U256::zero()), None);
+    assert_eq!(price_impact(U256::MAX, U256::one()), None);
+}
+
+#[test]
+fn simulates_exact_input_swaps_through_every_hop() {
+    let (pairs, tracker) = tracked_pairs();
+    let pairs = pairs.iter().collect::<Vec<&PairInfo>>();
+
+    // 1000 of token 1 buys 1974 of token 2, which buy 489 of token 3
+    let simulation = simulate_swap(
+        &swap(&[1, 2, 3], SwapKind::ExactIn, 1_000, 480),
+        &pairs,
+        &tracker,
+    )
+    .unwrap();
+    assert_eq!(simulation.pairs, vec![pairs[0].address, pairs[1].address]);
+    assert_eq!(
+        simulation.amounts,
+        vec![u256(1_000), u256(1_974), u256(489)]
+    );
+    assert_eq!(simulation.amount_in(), u256(1_000));
+    assert_eq!(simulation.amount_out(), u256(489));
+    assert!(simulation.within_limit);
+
+    let impacts = [1_000.0 / 101_000.0, 1_974.0 / 401_974.0];
+    for (impact, expected) in simulation.price_impacts.iter().zip(impacts) {
+        assert!((impact - expected).abs() < 1e-12);
+    }
+    let total = 1.0 - (1.0 - impacts[0]) * (1.0 - impacts[1]);
+    assert!((simulation.price_impact() - total).abs() < 1e-12);
+
+    // The same swap reverts if it wants more than 489 out
+    let simulation = simulate_swap(
+        &swap(&[1, 2, 3], SwapKind::ExactIn, 1_000, 490),
+        &pairs,
+        &tracker,
+    )
+    .unwrap();
+    assert!(!simulation.within_limit);
+}
+
+#[test]
+fn simulates_exact_output_swaps_from_token1() {
+    let (pairs, tracker) = tracked_pairs();
+    let pairs = [&pairs[0]];
+
+    // Token 2 is token1 of the pair, 2027 of it buy 1000 of token 1
+    let simulation = simulate_swap(
+        &swap(&[2, 1], SwapKind::ExactOut, 2_100, 1_000),
+        &pairs,
+        &tracker,
+    )
+    .unwrap();
+    assert_eq!(simulation.amounts, vec![u256(2_027), u256(1_000)]);
+    assert!(simulation.within_limit);
+
+    let simulation = simulate_swap(
+        &swap(&[2, 1], SwapKind::ExactOut, 2_000, 1_000),
+        &pairs,
+        &tracker,
+    )
+    .unwrap();
+    assert!(!simulation.within_limit);
+}
+
+#[test]
+fn does_not_simulate_without_reserves_or_matching_pairs() {
+    let (pairs, tracker) = tracked_pairs();
+    let exact_in = swap(&[1, 2, 3], SwapKind::ExactIn, 1_000, 480);
+
+    // A pair without reserves
+    let untracked = pair(0x24, 2, 4);
+    let mut to_token4 = exact_in.clone();
+    to_token4.path[2] = token(4);
+    assert_eq!(
+        simulate_swap(&to_token4, &[&pairs[0], &untracked], &tracker),
+        None
+    );
+
+    // One pair for two hops
+    assert_eq!(simulate_swap(&exact_in, &[&pairs[0]], &tracker), None);
+
+    // V3 swaps have fees
+    let mut v3 = exact_in.clone();
+    v3.fees = vec![3_000, 500];
+    assert_eq!(simulate_swap(&v3, &[&pairs[0], &pairs[1]], &tracker), None);
+
+    // A pool that can't fill the swap
+    let drained = simulate_swap(
+        &swap(&[2, 1], SwapKind::ExactOut, 10_000_000, 100_000),
+        &[&pairs[0]],
+        &tracker,
+    );
+    assert_eq!(drained, None);
+}
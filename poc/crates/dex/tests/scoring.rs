@@ -0,0 +1,180 @@
+use std::collections::BTreeMap;
+
+use dex::registry::PairInfo;
+use dex::reserves::Reserves;
+use dex::scoring::{score_swap, PoolState};
+use dex::swap::{SwapKind, SwapRecord, SwapStatus};
+use dex::v3_pool::{TickInfo, V3Pool};
+use ethers::types::{Address, H256, U256};
+
+/// Full range ticks of the 0.3% fee tier
+const FULL_RANGE: i32 = 887_220;
+
+fn token(byte: u8) -> Address {
+    Address::repeat_byte(byte)
+}
+
+fn u256(value: u64) -> U256 {
+    U256::from(value)
+}
+
+fn swap(amount_in: u64, amount_out: u64) -> SwapRecord {
+    SwapRecord {
+        tx_hash: H256::repeat_byte(0xaa),
+        swap_index: 0,
+        router: String::from("uniswap"),
+        protocol_version: 2,
+        method: String::from("swapExactTokensForTokens"),
+        sender: Address::repeat_byte(0xf0),
+        recipient: None,
+        path: vec![token(1), token(2)],
+        fees: Vec::new(),
+        kind: SwapKind::ExactIn,
+        amount_in: u256(amount_in),
+        amount_out: u256(amount_out),
+        deadline: None,
+        status: SwapStatus::Pending,
+        block_number: None,
+        block_hash: None,
+        transaction_index: None,
+        score: None,
+        amounts: None,
+    }
+}
+
+/// A V2 pair of tokens 1 and 2 with a million of each
+fn v2_pool() -> PoolState {
+    PoolState::V2 {
+        pair: PairInfo {
+            address: Address::repeat_byte(0x12),
+            factory: Address::repeat_byte(0xa1),
+            protocol_version: 2,
+            token0: token(1),
+            token1: token(2),
+            fee: None,
+            created_block: 1,
+        },
+        reserves: Reserves {
+            reserve0: u256(1_000_000),
+            reserve1: u256(1_000_000),
+            block_number: Some(1),
+        },
+    }
+}
+
+/// A 0.3% V3 pool of tokens 1 and 2 at a price of 1, with 1e18 of liquidity over the full
+/// range, i.e. virtual reserves of 1e18 of each
+fn v3_pool() -> PoolState {
+    let liquidity = 1_000_000_000_000_000_000;
+    let bound = |net| TickInfo {
+        liquidity_gross: liquidity,
+        liquidity_net: net,
+    };
+
+    PoolState::V3(V3Pool {
+        address: Address::repeat_byte(0x13),
+        token0: token(1),
+        token1: token(2),
+        fee: 3_000,
+        tick_spacing: 60,
+        sqrt_price_x96: U256::one() << 96,
+        tick: 0,
+        liquidity,
+        ticks: BTreeMap::from([
+            (-FULL_RANGE, bound(liquidity as i128)),
+            (FULL_RANGE, bound(-(liquidity as i128))),
+        ]),
+    })
+}
+
+/// Amount the swap gets after `front_run` went through `pool` first
+fn filled_after(pool: &PoolState, swap: &SwapRecord, front_run: U256) -> U256 {
+    let mut pool = pool.clone();
+    pool.swap_exact_in(token(1), front_run).unwrap();
+
+    pool.swap_exact_in(token(1), swap.amount_in).unwrap()
+}
+
+#[test]
+fn scores_a_v2_swap() {
+    let pool = v2_pool();
+    let swap = swap(10_000, 9_000);
+
+    let score = score_swap(&swap, std::slice::from_ref(&pool)).unwrap();
+
+    assert_eq!(score.pools, vec![Address::repeat_byte(0x12)]);
+    // 10000 * 9970 * 1e6 / (1e6 * 10000 + 10000 * 9970) = 9871.6
+    assert_eq!(score.expected_amount, u256(9_871));
+    // 871 of the 9871 expected may be lost
+    assert!((score.slippage_tolerance - 871.0 / 9_871.0).abs() < 1e-12);
+    // 9871 out for the 9970 left after the fee at a spot price of 1
+    assert!((score.price_impact - (1.0 - 9_871.0 / 9_970.0)).abs() < 1e-12);
+
+    // The largest front-run leaves the swap right at its limit: the front-run gets 45314 B
+    // for 47608 A, the swap 9000 B for 10000 A, selling back 45314 B gets 48221 A
+    assert_eq!(score.front_run_amount, u256(47_608));
+    assert!(filled_after(&pool, &swap, score.front_run_amount) >= swap.amount_out);
+    assert!(filled_after(&pool, &swap, score.front_run_amount + 1) < swap.amount_out);
+    assert_eq!(score.sandwich_profit, u256(613));
+    assert!((score.vulnerability - 613.0 / 10_000.0 / score.slippage_tolerance).abs() < 1e-12);
+}
+
+#[test]
+fn scores_a_v3_swap() {
+    let pool = v3_pool();
+    let mut swap = swap(10_000_000_000_000_000, 9_400_000_000_000_000);
+    swap.protocol_version = 3;
+    swap.fees = vec![3_000];
+
+    let score = score_swap(&swap, std::slice::from_ref(&pool)).unwrap();
+
+    assert_eq!(score.pools, vec![Address::repeat_byte(0x13)]);
+    // The 9.97e15 left after the fee moves the price to 2^96 * 1e18 / (1e18 + 9.97e15), rounded
+    // up, and the pool sends 1e18 times the price change
+    assert_eq!(
+        score.expected_amount,
+        U256::from_dec_str("9871580343970612").unwrap()
+    );
+    let expected = 9_871_580_343_970_612.0;
+    assert!((score.slippage_tolerance - (expected - 9.4e15) / expected).abs() < 1e-12);
+    assert!((score.price_impact - (1.0 - expected / 9.97e15)).abs() < 1e-9);
+
+    // Over the full range the pool prices like a V2 pair with reserves of 1e18
+    assert_eq!(
+        score.front_run_amount,
+        U256::from_dec_str("24974941755477154").unwrap()
+    );
+    assert!(filled_after(&pool, &swap, score.front_run_amount) >= swap.amount_out);
+    assert!(filled_after(&pool, &swap, score.front_run_amount + 1) < swap.amount_out);
+    assert_eq!(
+        score.sandwich_profit,
+        U256::from_dec_str("331596228608495").unwrap()
+    );
+    assert!((score.vulnerability - 0.694_129_612_126).abs() < 1e-9);
+}
+
+#[test]
+fn swaps_with_little_slippage_are_not_vulnerable() {
+    let pool = v2_pool();
+
+    // Asking for exactly the expected amount only leaves room for rounding
+    let score = score_swap(&swap(10_000, 9_871), std::slice::from_ref(&pool)).unwrap();
+    assert_eq!(score.slippage_tolerance, 0.0);
+    assert_eq!(score.sandwich_profit, U256::zero());
+    assert_eq!(score.vulnerability, 0.0);
+
+    // A front-run of 80 fits in 1 of slippage, too little to make up for the fees
+    let score = score_swap(&swap(10_000, 9_870), &[pool]).unwrap();
+    assert!(score.slippage_tolerance > 0.0);
+    assert_eq!(score.front_run_amount, u256(80));
+    assert_eq!(score.sandwich_profit, U256::zero());
+    assert_eq!(score.vulnerability, 0.0);
+}
+
+#[test]
+fn routes_must_match_the_path() {
+    let swap = swap(10_000, 9_000);
+
+    assert!(score_swap(&swap, &[]).is_err());
+    assert!(score_swap(&swap, &[v2_pool(), v3_pool()]).is_err());
+}
@@ -0,0 +1,92 @@
+use dex::bindings::uniswap_v3_swap_router_02::{
+    ExactInputSingleCall, ExactInputSingleParams, MulticallWithDeadlineCall,
+    SwapExactTokensForTokensCall,
+};
+use dex::bindings::Protocol;
+use dex::factory::Factory;
+use dex::router::{Router, RouterAddress};
+use dex::swap::{decode_swaps, SwapKind};
+use ethers::abi::AbiEncode;
+use ethers::types::{Address, Bytes, Transaction, H256, U256};
+
+const DEADLINE: u64 = 1_700_000_000;
+
+fn address(byte: u8) -> Address {
+    Address::repeat_byte(byte)
+}
+
+fn tx(to: Address, input: Vec<u8>) -> Transaction {
+    Transaction {
+        hash: H256::repeat_byte(0xaa),
+        from: address(0xf0),
+        to: Some(to),
+        input: Bytes::from(input),
+        ..Default::default()
+    }
+}
+
+fn v3_router() -> Router {
+    Router {
+        addresses: vec![RouterAddress::new(
+            address(0xa0),
+            Protocol::UniswapV3.router_abi(),
+        )],
+        factory: Factory::new(
+            address(0xa1),
+            Protocol::UniswapV3.factory_abi(),
+            String::from("uniswap"),
+            3,
+        ),
+        name: String::from("uniswap"),
+        version: 3,
+    }
+}
+
+#[test]
+fn v3_router_v2_swaps_are_v2_swaps() {
+    let router = v3_router();
+    let v2 = SwapExactTokensForTokensCall {
+        amount_in: U256::from(1_000),
+        amount_out_min: U256::from(900),
+        path: vec![address(1), address(2), address(3)],
+        to: address(0xf1),
+    };
+    let v3 = ExactInputSingleCall {
+        params: ExactInputSingleParams {
+            token_in: address(3),
+            token_out: address(4),
+            fee: 500,
+            recipient: address(0xf1),
+            amount_in: U256::from(800),
+            amount_out_minimum: U256::from(700),
+            sqrt_price_limit_x96: U256::zero(),
+        },
+    };
+    let input = MulticallWithDeadlineCall {
+        deadline: U256::from(DEADLINE),
+        data: vec![Bytes::from(v2.encode()), Bytes::from(v3.encode())],
+    }
+    .encode();
+
+    let swaps = decode_swaps(&router, &tx(address(0xa0), input));
+    assert_eq!(swaps.len(), 2);
+
+    let swap = &swaps[0];
+    assert_eq!(swap.method, "swapExactTokensForTokens");
+    assert_eq!(swap.protocol_version, 2);
+    assert_eq!(swap.kind, SwapKind::ExactIn);
+    assert_eq!(swap.path, vec![address(1), address(2), address(3)]);
+    assert!(swap.fees.is_empty());
+    assert_eq!(
+        (swap.amount_in, swap.amount_out),
+        (U256::from(1_000), U256::from(900))
+    );
+    assert_eq!(swap.recipient, Some(address(0xf1)));
+    assert_eq!(swap.deadline, Some(DEADLINE));
+
+    // The V3 swaps of the same multicall keep the version of the router
+    let swap = &swaps[1];
+    assert_eq!(swap.method, "exactInputSingle");
+    assert_eq!(swap.protocol_version, 3);
+    assert_eq!(swap.fees, vec![500]);
+}
@@ -0,0 +1,129 @@
+use dex::bindings::uniswap_v3_swap_router_02::{
+    ExactInputCall, ExactInputParams, ExactOutputCall, ExactOutputParams,
+};
+use dex::swap::SwapKind;
+use dex::v3::{decode_path, decode_route, decode_v3_swap};
+use ethers::abi::AbiEncode;
+use ethers::types::{Address, Bytes, U256};
+
+fn address(byte: u8) -> Address {
+    Address::repeat_byte(byte)
+}
+
+/// Pack tokens and fee tiers the way the router expects them
+fn packed(tokens: &[u8], fees: &[u32]) -> Vec<u8> {
+    let mut path = address(tokens[0]).as_bytes().to_vec();
+    for (token, fee) in tokens[1..].iter().zip(fees) {
+        path.extend_from_slice(&fee.to_be_bytes()[1..]);
+        path.extend_from_slice(address(*token).as_bytes());
+    }
+
+    path
+}
+
+#[test]
+fn decodes_multi_hop_paths() {
+    let path = packed(&[1, 2, 3, 4], &[500, 3_000, 10_000]);
+    assert_eq!(path.len(), 20 + 3 * 23);
+
+    assert_eq!(
+        decode_path(&path),
+        Some((
+            vec![address(1), address(2), address(3), address(4)],
+            vec![500, 3_000, 10_000]
+        ))
+    );
+    // Fees take all three bytes
+    assert_eq!(
+        decode_path(&packed(&[1, 2], &[0xab_cd_ef])).map(|(_, fees)| fees),
+        Some(vec![0xab_cd_ef])
+    );
+}
+
+#[test]
+fn rejects_paths_without_whole_hops() {
+    let path = packed(&[1, 2, 3], &[500, 3_000]);
+
+    assert_eq!(decode_path(&[]), None);
+    // A token alone, and a token with a fee but nowhere to go
+    assert_eq!(decode_path(&path[..20]), None);
+    assert_eq!(decode_path(&path[..23]), None);
+    // Truncated in the middle of the last token
+    assert_eq!(decode_path(&path[..path.len() - 1]), None);
+    assert_eq!(decode_path(&path[..45]), None);
+    // A trailing byte after the last token
+    let mut odd = path.clone();
+    odd.push(0);
+    assert_eq!(odd.len() % 2, 1);
+    assert_eq!(decode_path(&odd), None);
+
+    // The whole hops of the same path still decode
+    assert!(decode_path(&path[..43]).is_some());
+}
+
+#[test]
+fn exact_output_routes_start_from_the_token_in() {
+    // Exact output paths are encoded from the token out
+    let path = packed(&[4, 3, 2, 1], &[10_000, 3_000, 500]);
+
+    assert_eq!(
+        decode_route(&path, SwapKind::ExactOut),
+        Some((
+            vec![address(1), address(2), address(3), address(4)],
+            vec![500, 3_000, 10_000]
+        ))
+    );
+    assert_eq!(decode_route(&path, SwapKind::ExactIn), decode_path(&path));
+    assert_eq!(
+        decode_route(&path[..path.len() - 1], SwapKind::ExactOut),
+        None
+    );
+}
+
+#[test]
+fn decodes_multi_hop_router_calls() {
+    let exact_input = |path: Vec<u8>| {
+        ExactInputCall {
+            params: ExactInputParams {
+                path: Bytes::from(path),
+                recipient: address(0xf1),
+                amount_in: U256::from(1_000),
+                amount_out_minimum: U256::from(900),
+            },
+        }
+        .encode()
+    };
+
+    let swap = decode_v3_swap(&exact_input(packed(&[1, 2, 3], &[500, 3_000]))).unwrap();
+    assert_eq!(swap.method, "exactInput");
+    assert_eq!(swap.path, vec![address(1), address(2), address(3)]);
+    assert_eq!(swap.fees, vec![500, 3_000]);
+    assert_eq!(swap.kind, SwapKind::ExactIn);
+    assert_eq!(
+        (swap.amount_in, swap.amount_out),
+        (U256::from(1_000), U256::from(900))
+    );
+
+    let exact_output = ExactOutputCall {
+        params: ExactOutputParams {
+            path: Bytes::from(packed(&[3, 2, 1], &[3_000, 500])),
+            recipient: address(0xf1),
+            amount_out: U256::from(900),
+            amount_in_maximum: U256::from(1_000),
+        },
+    }
+    .encode();
+    let swap = decode_v3_swap(&exact_output).unwrap();
+    assert_eq!(swap.method, "exactOutput");
+    assert_eq!(swap.path, vec![address(1), address(2), address(3)]);
+    assert_eq!(swap.fees, vec![500, 3_000]);
+    assert_eq!(
+        (swap.amount_in, swap.amount_out),
+        (U256::from(1_000), U256::from(900))
+    );
+
+    // A call with a truncated path is not a swap
+    let mut truncated = packed(&[1, 2, 3], &[500, 3_000]);
+    truncated.truncate(truncated.len() - 7);
+    assert_eq!(decode_v3_swap(&exact_input(truncated)), None);
+}
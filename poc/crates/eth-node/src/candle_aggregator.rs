@@ -0,0 +1,149 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use anyhow::Result;
+use dex::candle::{decode_trade, v2_swap_topic, v3_swap_topic, Interval, Trade};
+use dex::registry::Registry;
+use ethers::prelude::*;
+use log::{debug, info, warn};
+use storage::candle::{pair_decimals, refresh_candles, CandleStorage};
+use tokio::sync::{
+    broadcast::{error::RecvError, Receiver},
+    Mutex,
+};
+
+/// Maintains the candles of the registered pairs from the blocks of the `BlockWatcher`
+///
+/// The `Swap` events of every block are stored as trades and the candles they fall in are
+/// recomputed. When a block replaces another at the same height, the trades of the replaced
+/// blocks are removed first, so candles only ever reflect the canonical chain.
+pub struct CandleAggregator {
+    pub ws_url: Arc<String>,
+    pub registry: Arc<Mutex<Registry>>,
+    pub block_receiver: Arc<Mutex<Receiver<Block<H256>>>>,
+}
+
+impl CandleAggregator {
+    pub fn new(
+        ws_url: String,
+        registry: Arc<Mutex<Registry>>,
+        block_receiver: Receiver<Block<H256>>,
+    ) -> Self {
+        Self {
+            ws_url: Arc::new(ws_url),
+            registry,
+            block_receiver: Arc::new(Mutex::new(block_receiver)),
+        }
+    }
+
+    /// Trades of the registered pairs in a block
+    async fn trades(&self, provider: &Provider<Ws>, block: &Block<H256>) -> Result<Vec<Trade>> {
+        let Some(hash) = block.hash else {
+            return Ok(Vec::new());
+        };
+        let filter = Filter::new()
+            .at_block_hash(hash)
+            .topic0(vec![v2_swap_topic(), v3_swap_topic()]);
+        let logs = provider.get_logs(&filter).await?;
+        let registry = self.registry.lock().await;
+
+        Ok(logs
+            .iter()
+            .filter(|log| registry.pair(log.address).is_some())
+            .filter_map(|log| decode_trade(log, block.timestamp.as_u64()))
+            .collect())
+    }
+
+    pub async fn aggregate<C: CandleStorage>(&self, storage: Arc<Mutex<C>>) -> Result<()> {
+        let provider = Provider::<Ws>::connect(self.ws_url.as_ref()).await?;
+        let mut block_receiver = self.block_receiver.lock().await;
+
+        info!("Connected to {}, aggregating candles", self.ws_url);
+
+        loop {
+            let block = match block_receiver.recv().await {
+                Ok(block) => block,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!(
+                        "Candle aggregator lagged, {} blocks are missing from the candles",
+                        skipped
+                    );
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+            let (Some(number), Some(hash)) = (block.number, block.hash) else {
+                continue;
+            };
+
+            // Orphan first, so a block whose trades can't be fetched still drops the trades of
+            // the block it replaces
+            let orphaned = match storage
+                .lock()
+                .await
+                .orphan_trades(number.as_u64(), hash)
+                .await
+            {
+                Ok(orphaned) => orphaned,
+                Err(e) => {
+                    warn!(
+                        "Trades orphaned by block {:?} could not be removed: {}",
+                        number, e
+                    );
+                    continue;
+                }
+            };
+            let trades = match self.trades(&provider, &block).await {
+                Ok(trades) => trades,
+                Err(e) => {
+                    warn!("Trades of block {:?} were missed: {}", number, e);
+                    Vec::new()
+                }
+            };
+            let mut storage = storage.lock().await;
+            let trades = match storage.store_trades(&trades).await {
+                Ok(()) => trades,
+                Err(e) => {
+                    warn!("Trades of block {:?} could not be stored: {}", number, e);
+                    Vec::new()
+                }
+            };
+
+            // Every minute touched by a new or orphaned trade, which covers the longer intervals.
+            // The registry is only locked to look up the decimals, not while refreshing.
+            let touched = {
+                let registry = self.registry.lock().await;
+                trades
+                    .iter()
+                    .chain(orphaned.iter())
+                    .map(|trade| (trade.pair, Interval::OneMinute.start(trade.timestamp)))
+                    .collect::<BTreeSet<(Address, u64)>>()
+                    .into_iter()
+                    .filter_map(|(pair, minute)| {
+                        let decimals = registry
+                            .pair(pair)
+                            .and_then(|info| pair_decimals(&registry, info))?;
+                        Some((pair, minute, decimals))
+                    })
+                    .collect::<Vec<(Address, u64, (u8, u8))>>()
+            };
+            for (pair, minute, decimals) in touched {
+                if let Err(e) = refresh_candles(&mut *storage, pair, decimals, minute).await {
+                    warn!(
+                        "Candles of pair {:?} at {} could not be refreshed: {}",
+                        pair, minute, e
+                    );
+                }
+            }
+
+            debug!(
+                "Block {:?} has {} trades, {} were orphaned",
+                number,
+                trades.len(),
+                orphaned.len()
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,363 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+
+use ansi_term::Colour;
+use anyhow::{anyhow, Result};
+use dex::liquidity::{
+    decode_liquidity, decode_pair_liquidity, pair_burn_topic, pair_mint_topic, LiquidityEvent,
+    LiquidityKind, LpFate,
+};
+use dex::registry::{decode_pair_created, PairInfo, Registry};
+use dex::reserves::{fetch_reserves, ReserveTracker};
+use dex::router::Router;
+use ethers::abi::{self, ParamType, Token};
+use ethers::prelude::*;
+use ethers::utils::id;
+use log::{debug, info, warn};
+use tokio::sync::{
+    broadcast::{error::RecvError, Receiver, Sender},
+    Mutex,
+};
+
+/// Log a launch or a removal of liquidity
+pub fn log_liquidity(event: &LiquidityEvent) {
+    let hash = Colour::White.bold().paint(format!("{:?}", event.tx_hash));
+    let status = event.status.as_str();
+
+    match event.kind {
+        LiquidityKind::Add => info!(
+            "{} ({}) {} {} on {}",
+            Colour::Green.bold().paint("Launch"),
+            hash,
+            status,
+            event,
+            event.router
+        ),
+        LiquidityKind::Remove => info!(
+            "{} ({}) {} {} on {}",
+            Colour::Red.bold().paint("Liquidity removed"),
+            hash,
+            status,
+            event,
+            event.router
+        ),
+    }
+}
+
+/// Completes the liquidity events of pending router txs and tells launches apart
+///
+/// Pending adds only are launches when the pair doesn't exist yet or has no reserves, which is
+/// read from the registry and the tracked reserves or else from the node.
+pub struct LaunchDetector {
+    pub registry: Arc<Mutex<Registry>>,
+    pub reserves: Arc<Mutex<ReserveTracker>>,
+    /// Tokens launched tokens are paired with, e.g. WETH, from `dex.tokens`
+    pub base_tokens: Vec<Address>,
+    /// Contracts LP tokens are locked in, from `launch.lockers`
+    pub lockers: Vec<Address>,
+    /// `WETH()` of the router addresses
+    weth: Mutex<HashMap<Address, Address>>,
+    pub provider: Provider<Http>,
+}
+
+impl LaunchDetector {
+    /// # Errors
+    ///
+    /// This function will return an error if `node_http` isn't a valid URL
+    pub fn new(
+        node_http: String,
+        registry: Arc<Mutex<Registry>>,
+        reserves: Arc<Mutex<ReserveTracker>>,
+        base_tokens: Vec<Address>,
+        lockers: Vec<Address>,
+    ) -> Result<Self> {
+        Ok(Self {
+            registry,
+            reserves,
+            base_tokens,
+            lockers,
+            weth: Mutex::new(HashMap::new()),
+            provider: Provider::<Http>::try_from(node_http)?,
+        })
+    }
+
+    /// `WETH()` of a router address, read once
+    async fn weth(&self, router: Address) -> Result<Address> {
+        let mut weth = self.weth.lock().await;
+        if let Some(address) = weth.get(&router) {
+            return Ok(*address);
+        }
+
+        let tx = TransactionRequest::new()
+            .to(router)
+            .data(id("WETH()").to_vec());
+        let output = self
+            .provider
+            .call(&tx.into(), None)
+            .await
+            .map_err(|e| anyhow!("WETH of {:?} failed: {}", router, e))?;
+        let address = abi::decode(&[ParamType::Address], &output)?
+            .pop()
+            .and_then(Token::into_address)
+            .ok_or_else(|| anyhow!("WETH of {:?} returned no address", router))?;
+        weth.insert(router, address);
+
+        Ok(address)
+    }
+
+    /// Whether liquidity added to the pair of `factory` for `event` would be its first
+    async fn is_initial(&self, factory: Address, event: &LiquidityEvent) -> Result<bool> {
+        let registered = self
+            .registry
+            .lock()
+            .await
+            .find_pair(factory, event.token, event.paired_token, None)
+            .map(|pair| pair.address);
+        let pair = match registered {
+            Some(pair) => pair,
+            None => {
+                let tx = TransactionRequest::new().to(factory).data(
+                    [
+                        id("getPair(address,address)").to_vec(),
+                        abi::encode(&[
+                            Token::Address(event.token),
+                            Token::Address(event.paired_token),
+                        ]),
+                    ]
+                    .concat(),
+                );
+                let output = self
+                    .provider
+                    .call(&tx.into(), None)
+                    .await
+                    .map_err(|e| anyhow!("getPair of {:?} failed: {}", factory, e))?;
+                abi::decode(&[ParamType::Address], &output)?
+                    .pop()
+                    .and_then(Token::into_address)
+                    .unwrap_or_default()
+            }
+        };
+        if pair.is_zero() {
+            return Ok(true);
+        }
+
+        // Pairs that aren't tracked are read without being tracked, they'd never be updated
+        let tracked = self.reserves.lock().await.get(pair).copied();
+        let reserves = match tracked {
+            Some(reserves) => reserves,
+            None => fetch_reserves(&self.provider, pair, None).await?,
+        };
+
+        Ok(reserves.reserve0.is_zero() && reserves.reserve1.is_zero())
+    }
+
+    /// Complete the liquidity `event` of a pending tx to `router`, `None` for adds to pairs
+    /// that already have liquidity and for pairs of two base tokens
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the router or the pair could not be read
+    pub async fn pending(
+        &self,
+        router: &Router,
+        tx: &Transaction,
+        mut event: LiquidityEvent,
+    ) -> Result<Option<LiquidityEvent>> {
+        if event.paired_token.is_zero() {
+            if let Some(to) = tx.to {
+                event.paired_token = self.weth(to).await?;
+            }
+        }
+        event.orient(|token| self.base_tokens.contains(&token));
+        if self.base_tokens.contains(&event.token) {
+            return Ok(None);
+        }
+
+        if event.kind == LiquidityKind::Add {
+            event.lp_fate = Some(LpFate::of(event.recipient, &self.lockers));
+            event.initial = self.is_initial(router.factory.address, &event).await?;
+            if !event.initial {
+                debug!("Liquidity ({:?}) added to a live pair", tx.hash);
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(event))
+    }
+}
+
+/// Finds the launches and the removals of liquidity of the V2 pairs in the blocks from the
+/// `BlockWatcher`
+///
+/// Txs with a `Mint` or `Burn` log of a pair are decoded from their receipt, so launches done
+/// without the router and LP tokens locked in the same tx are seen too. Pairs created in the
+/// same tx don't have to be registered yet. Pending launches are published by the
+/// `TxProcessor`.
+pub struct LaunchWatcher {
+    pub ws_url: Arc<String>,
+    pub routers: Vec<Router>,
+    pub registry: Arc<Mutex<Registry>>,
+    /// Tokens launched tokens are paired with, e.g. WETH, from `dex.tokens`
+    pub base_tokens: Vec<Address>,
+    /// Contracts LP tokens are locked in, from `launch.lockers`
+    pub lockers: Vec<Address>,
+    pub block_receiver: Arc<Mutex<Receiver<Block<H256>>>>,
+    pub sender: Arc<Sender<LiquidityEvent>>,
+}
+
+impl LaunchWatcher {
+    pub fn new(
+        ws_url: String,
+        routers: Vec<Router>,
+        registry: Arc<Mutex<Registry>>,
+        base_tokens: Vec<Address>,
+        lockers: Vec<Address>,
+        block_receiver: Receiver<Block<H256>>,
+        sender: Sender<LiquidityEvent>,
+    ) -> Self {
+        Self {
+            ws_url: Arc::new(ws_url),
+            routers,
+            registry,
+            base_tokens,
+            lockers,
+            block_receiver: Arc::new(Mutex::new(block_receiver)),
+            sender: Arc::new(sender),
+        }
+    }
+
+    /// Pair of a `Mint` or `Burn` log, registered or created by the tx of `logs`
+    async fn pair(&self, address: Address, logs: &[Log]) -> Option<PairInfo> {
+        if let Some(pair) = self.registry.lock().await.pair(address) {
+            return Some(pair.clone());
+        }
+
+        logs.iter()
+            .filter_map(|log| {
+                self.routers
+                    .iter()
+                    .filter(|router| router.version == 2)
+                    .find_map(|router| decode_pair_created(&router.factory, log))
+            })
+            .find(|pair| pair.address == address)
+    }
+
+    /// Launches and removals of liquidity of a mined tx
+    async fn liquidity(&self, provider: &Provider<Ws>, hash: H256) -> Result<Vec<LiquidityEvent>> {
+        let (Some(tx), Some(receipt)) = (
+            provider.get_transaction(hash).await?,
+            provider.get_transaction_receipt(hash).await?,
+        ) else {
+            warn!("Tx {:?} is gone, it was probably reorged out", hash);
+            return Ok(Vec::new());
+        };
+        let call = self
+            .routers
+            .iter()
+            .find_map(|router| decode_liquidity(router, &tx));
+
+        let addresses = receipt
+            .logs
+            .iter()
+            .filter(|log| {
+                log.topics.first() == Some(&pair_mint_topic())
+                    || log.topics.first() == Some(&pair_burn_topic())
+            })
+            .map(|log| log.address)
+            .collect::<BTreeSet<Address>>();
+
+        let mut events = Vec::new();
+        for address in addresses {
+            let Some(pair) = self.pair(address, &receipt.logs).await else {
+                debug!("Liquidity ({:?}) of unknown pair {:?}", hash, address);
+                continue;
+            };
+            let Some(router) = self
+                .routers
+                .iter()
+                .find(|router| router.factory.address == pair.factory)
+            else {
+                continue;
+            };
+
+            for mut event in
+                decode_pair_liquidity(&tx, &receipt.logs, &pair, &router.name, &self.lockers)
+            {
+                match &call {
+                    Some(call) if pair.has_token(call.token) => {
+                        event.orient(|token| token != call.token)
+                    }
+                    _ => event.orient(|token| self.base_tokens.contains(&token)),
+                }
+                // Pairs of two base tokens, e.g. WETH/USDC, aren't launched nor rugged
+                if self.base_tokens.contains(&event.token)
+                    || (event.kind == LiquidityKind::Add && !event.initial)
+                {
+                    continue;
+                }
+                events.push(event);
+            }
+        }
+
+        Ok(events)
+    }
+
+    pub async fn watch(&self) -> Result<()> {
+        let provider = Provider::<Ws>::connect(self.ws_url.as_ref()).await?;
+        let mut block_receiver = self.block_receiver.lock().await;
+
+        info!("Connected to {}, watching for launches", self.ws_url);
+
+        loop {
+            let hash = match block_receiver.recv().await {
+                Ok(block) => match block.hash {
+                    Some(hash) => hash,
+                    None => continue,
+                },
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!(
+                        "Launch watcher lagged, {} blocks were not searched for launches",
+                        skipped
+                    );
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+
+            let filter = Filter::new()
+                .at_block_hash(hash)
+                .topic0(vec![pair_mint_topic(), pair_burn_topic()]);
+            let logs = match provider.get_logs(&filter).await {
+                Ok(logs) => logs,
+                Err(e) => {
+                    warn!("Launches of block {:?} were missed: {}", hash, e);
+                    continue;
+                }
+            };
+            // Txs are kept in block order, a tx may have several logs
+            let mut txs = Vec::new();
+            for tx in logs.into_iter().filter_map(|log| log.transaction_hash) {
+                if !txs.contains(&tx) {
+                    txs.push(tx);
+                }
+            }
+            debug!("Block {:?} has {} txs moving liquidity", hash, txs.len());
+
+            for tx in txs {
+                let events = match self.liquidity(&provider, tx).await {
+                    Ok(events) => events,
+                    Err(e) => {
+                        warn!("Liquidity ({:?}) could not be read: {}", tx, e);
+                        continue;
+                    }
+                };
+                for event in events {
+                    log_liquidity(&event);
+                    self.sender.send(event)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -2,6 +2,13 @@ use ethers::types::{NameOrAddress, TransactionRequest};
 
 pub mod block_processor;
 pub mod block_watcher;
+pub mod candle_aggregator;
+pub mod launch_watcher;
+pub mod market_watcher;
+pub mod mempool_tracker;
+pub mod reserve_watcher;
+pub mod swap_watcher;
+pub mod token_resolver;
 pub mod tx_pool;
 pub mod tx_processor;
 
@@ -0,0 +1,142 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use dex::factory::Factory;
+use dex::registry::{
+    creation_event, decode_pair_created, register_pair, NewMarket, Registry, RegistryStorage,
+};
+use ethers::prelude::*;
+use log::{debug, info, warn};
+use tokio::sync::{
+    broadcast::{error::RecvError, Receiver, Sender},
+    Mutex,
+};
+
+/// Discovers the pairs and pools created by the factories in the blocks from the `BlockWatcher`
+///
+/// `PairCreated`/`PoolCreated` logs are registered, so later swaps can be attributed to the new
+/// market, and published. Pending creations are published by the `TxProcessor`.
+pub struct MarketWatcher {
+    pub ws_url: Arc<String>,
+    pub factories: Vec<Factory>,
+    pub registry: Arc<Mutex<Registry>>,
+    pub block_receiver: Arc<Mutex<Receiver<Block<H256>>>>,
+    pub sender: Arc<Sender<NewMarket>>,
+}
+
+impl MarketWatcher {
+    pub fn new(
+        ws_url: String,
+        mut factories: Vec<Factory>,
+        registry: Arc<Mutex<Registry>>,
+        block_receiver: Receiver<Block<H256>>,
+        sender: Sender<NewMarket>,
+    ) -> Self {
+        // Routers of different versions can share a factory
+        factories.sort_by_key(|factory| factory.address);
+        factories.dedup_by_key(|factory| factory.address);
+
+        Self {
+            ws_url: Arc::new(ws_url),
+            factories,
+            registry,
+            block_receiver: Arc::new(Mutex::new(block_receiver)),
+            sender: Arc::new(sender),
+        }
+    }
+
+    /// Creation logs of the factories in a block
+    async fn creation_logs(&self, provider: &Provider<Ws>, hash: H256) -> Result<Vec<Log>> {
+        let topics = self
+            .factories
+            .iter()
+            .filter_map(creation_event)
+            .map(|event| event.signature())
+            .collect::<Vec<H256>>();
+        if topics.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let filter = Filter::new()
+            .at_block_hash(hash)
+            .address(
+                self.factories
+                    .iter()
+                    .map(|factory| factory.address)
+                    .collect::<Vec<Address>>(),
+            )
+            .topic0(topics);
+
+        Ok(provider.get_logs(&filter).await?)
+    }
+
+    pub async fn watch<C: RegistryStorage>(&self, storage: Arc<Mutex<C>>) -> Result<()> {
+        let provider = Provider::<Ws>::connect(self.ws_url.as_ref()).await?;
+        let mut block_receiver = self.block_receiver.lock().await;
+
+        info!("Connected to {}, discovering new markets", self.ws_url);
+
+        loop {
+            let hash = match block_receiver.recv().await {
+                Ok(block) => match block.hash {
+                    Some(hash) => hash,
+                    None => continue,
+                },
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!(
+                        "Market watcher lagged, {} blocks were not searched for new markets",
+                        skipped
+                    );
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+
+            let logs = match self.creation_logs(&provider, hash).await {
+                Ok(logs) => logs,
+                Err(e) => {
+                    warn!("New markets of block {:?} were missed: {}", hash, e);
+                    continue;
+                }
+            };
+            let mut registry = self.registry.lock().await;
+            let mut storage = storage.lock().await;
+
+            for log in logs {
+                let Some((factory, pair)) = self.factories.iter().find_map(|factory| {
+                    decode_pair_created(factory, &log).map(|pair| (factory, pair))
+                }) else {
+                    continue;
+                };
+                let market = NewMarket::mined(&pair, log.transaction_hash.unwrap_or_default());
+
+                match register_pair(&mut registry, &mut *storage, &provider, pair).await {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        debug!("Market {:?} already registered or skipped", market.address);
+                        continue;
+                    }
+                    Err(e) => {
+                        warn!(
+                            "Market {:?} of block {:?} could not be registered: {}",
+                            market.address, hash, e
+                        );
+                        continue;
+                    }
+                }
+
+                info!(
+                    "New market {:?} {:?}/{:?} fee {:?} created on {}",
+                    market.address.unwrap_or_default(),
+                    market.token0,
+                    market.token1,
+                    market.fee,
+                    factory
+                );
+                self.sender.send(market)?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use dex::router::Router;
+use ethers::types::{Address, Block, Transaction, H256, U256};
+use log::{debug, info, trace, warn};
+use storage::lifecycle::{TxLifecycle, TxStatus};
+use tokio::sync::{
+    broadcast::{error::RecvError, Receiver, Sender},
+    Mutex,
+};
+
+/// Pending txs that are neither mined nor replaced after this long are considered dropped
+pub const DEFAULT_DROP_AFTER: Duration = Duration::from_secs(600);
+
+/// Current time in unix milliseconds
+pub fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Txs being tracked, indexed by hash and by sender and nonce
+///
+/// Replaced txs stay tracked until their sender and nonce slot settles, as any tx of the slot
+/// can be the one that gets mined.
+#[derive(Debug, Default)]
+pub struct Mempool {
+    tracked: HashMap<H256, TxLifecycle>,
+    /// Txs seen for each sender and nonce, the last one is pending and replaced the others
+    by_sender: HashMap<(Address, U256), Vec<H256>>,
+}
+
+impl Mempool {
+    /// Number of pending txs
+    pub fn len(&self) -> usize {
+        self.by_sender.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_sender.is_empty()
+    }
+
+    /// Start tracking a pending tx, returning the lifecycle updates it causes
+    ///
+    /// A tx with the same sender and nonce as a pending one replaces it.
+    pub fn seen(&mut self, lifecycle: TxLifecycle) -> Vec<TxLifecycle> {
+        if self.tracked.contains_key(&lifecycle.hash) {
+            return Vec::new();
+        }
+
+        let mut updates = Vec::new();
+        let slot = self
+            .by_sender
+            .entry((lifecycle.from, lifecycle.nonce))
+            .or_default();
+
+        if let Some(replaced) = slot.last().and_then(|hash| self.tracked.get_mut(hash)) {
+            debug!("Tx {:?} replaced by {:?}", replaced.hash, lifecycle.hash);
+
+            replaced.status = TxStatus::Replaced;
+            replaced.replaced_by = Some(lifecycle.hash);
+            replaced.closed_at = Some(lifecycle.first_seen);
+            updates.push(replaced.clone());
+        }
+
+        slot.push(lifecycle.hash);
+        self.tracked.insert(lifecycle.hash, lifecycle.clone());
+        updates.push(lifecycle);
+
+        updates
+    }
+
+    /// Mark the tracked txs included in `block` as mined at `now`
+    ///
+    /// A mined tx settles its sender and nonce slot, if it had been replaced the pending tx that
+    /// replaced it is closed as replaced by the mined one.
+    pub fn mined(&mut self, block: &Block<H256>, now: u64) -> Vec<TxLifecycle> {
+        let mut updates = Vec::new();
+
+        for (index, hash) in block.transactions.iter().enumerate() {
+            let Some(key) = self
+                .tracked
+                .get(hash)
+                .map(|lifecycle| (lifecycle.from, lifecycle.nonce))
+            else {
+                continue;
+            };
+
+            for other in self.by_sender.remove(&key).unwrap_or_default() {
+                let Some(lifecycle) = self.tracked.remove(&other) else {
+                    continue;
+                };
+
+                if other == *hash {
+                    updates.push(TxLifecycle {
+                        status: TxStatus::Mined,
+                        block_number: block.number.map(|n| n.as_u64()),
+                        block_hash: block.hash,
+                        transaction_index: Some(index as u64),
+                        mined_at: Some(now),
+                        replaced_by: None,
+                        closed_at: None,
+                        ..lifecycle
+                    });
+                } else if lifecycle.status == TxStatus::Pending {
+                    debug!("Tx {:?} replaced by mined {:?}", other, hash);
+
+                    updates.push(TxLifecycle {
+                        status: TxStatus::Replaced,
+                        replaced_by: Some(*hash),
+                        closed_at: Some(now),
+                        ..lifecycle
+                    });
+                }
+            }
+        }
+
+        updates
+    }
+
+    /// Drop the pending txs first seen more than `drop_after` before `now`, along with the txs
+    /// they replaced
+    pub fn expire(&mut self, now: u64, drop_after: Duration) -> Vec<TxLifecycle> {
+        let cutoff = now.saturating_sub(drop_after.as_millis() as u64);
+        let expired = self
+            .by_sender
+            .iter()
+            .filter(|(_, slot)| {
+                slot.last()
+                    .and_then(|hash| self.tracked.get(hash))
+                    .is_some_and(|lifecycle| lifecycle.first_seen < cutoff)
+            })
+            .map(|(key, _)| *key)
+            .collect::<Vec<(Address, U256)>>();
+
+        let mut updates = Vec::new();
+        for key in expired {
+            // The replaced txs were already closed, only the pending one is dropped
+            let pending = self
+                .by_sender
+                .remove(&key)
+                .unwrap_or_default()
+                .iter()
+                .fold(None, |_, hash| self.tracked.remove(hash));
+
+            if let Some(lifecycle) = pending {
+                updates.push(TxLifecycle {
+                    status: TxStatus::Dropped,
+                    closed_at: Some(now),
+                    ..lifecycle
+                });
+            }
+        }
+
+        updates
+    }
+}
+
+/// Follows pending txs from the mempool until they are mined, replaced or dropped
+///
+/// Blocks from the `BlockWatcher` are matched against the tracked txs, and every change in a
+/// tx's lifecycle is published on `sender`.
+pub struct MempoolTracker {
+    pub node: String,
+    pub routers: Vec<Router>,
+    pub drop_after: Duration,
+    pub tx_receiver: Arc<Mutex<Receiver<Transaction>>>,
+    pub block_receiver: Arc<Mutex<Receiver<Block<H256>>>>,
+    pub sender: Arc<Sender<TxLifecycle>>,
+}
+
+impl MempoolTracker {
+    pub fn new(
+        node: String,
+        routers: Vec<Router>,
+        drop_after: Duration,
+        tx_receiver: Receiver<Transaction>,
+        block_receiver: Receiver<Block<H256>>,
+        sender: Sender<TxLifecycle>,
+    ) -> Self {
+        Self {
+            node,
+            routers,
+            drop_after,
+            tx_receiver: Arc::new(Mutex::new(tx_receiver)),
+            block_receiver: Arc::new(Mutex::new(block_receiver)),
+            sender: Arc::new(sender),
+        }
+    }
+
+    /// Router a tx is sent to, as `<name>-v<version>`
+    fn router(&self, tx: &Transaction) -> Option<String> {
+        let to = tx.to?;
+
+        self.routers
+            .iter()
+            .find(|r| r.addresses.iter().any(|a| *a == to))
+            .map(|r| format!("{}-v{}", r.name, r.version))
+    }
+
+    fn lifecycle(&self, tx: &Transaction, first_seen: u64) -> TxLifecycle {
+        TxLifecycle {
+            hash: tx.hash,
+            from: tx.from,
+            nonce: tx.nonce,
+            to: tx.to,
+            router: self.router(tx),
+            node: self.node.clone(),
+            first_seen,
+            status: TxStatus::Pending,
+            block_number: None,
+            block_hash: None,
+            transaction_index: None,
+            mined_at: None,
+            replaced_by: None,
+            closed_at: None,
+        }
+    }
+
+    pub async fn track(&self) -> Result<()> {
+        let mut tx_receiver = self.tx_receiver.lock().await;
+        let mut block_receiver = self.block_receiver.lock().await;
+        let mut expiry = tokio::time::interval(self.drop_after.min(Duration::from_secs(60)));
+        let mut mempool = Mempool::default();
+
+        info!("Tracking mempool txs seen on {}", self.node);
+
+        loop {
+            let updates = tokio::select! {
+                tx = tx_receiver.recv() => match tx {
+                    Ok(tx) => {
+                        trace!("Tracking tx: {:?}", tx.hash);
+                        mempool.seen(self.lifecycle(&tx, unix_millis()))
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Mempool tracker lagged, {} txs were not tracked", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                block = block_receiver.recv() => match block {
+                    Ok(block) => {
+                        let mined = mempool.mined(&block, unix_millis());
+                        debug!(
+                            "Block {:?} included {} of {} tracked txs",
+                            block.number,
+                            mined.len(),
+                            mined.len() + mempool.len()
+                        );
+                        mined
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Mempool tracker lagged, {} blocks were not matched", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                _ = expiry.tick() => mempool.expire(unix_millis(), self.drop_after),
+            };
+
+            for lifecycle in updates {
+                self.sender.send(lifecycle)?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,292 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use dex::registry::{PairInfo, Registry};
+use dex::reserves::{
+    fetch_reserves, fetch_v3_pool, simulate_swap, sync_topic, v2_pairs, ReserveTracker,
+    SwapSimulation,
+};
+use dex::router::Router;
+use dex::scoring::{score_swap, PoolState, SwapScore};
+use dex::swap::SwapRecord;
+use dex::v3_pool::{burn_topic, initialize_topic, mint_topic, swap_topic};
+use ethers::prelude::*;
+use log::{debug, info, warn};
+use tokio::sync::{
+    broadcast::{error::RecvError, Receiver},
+    Mutex, MutexGuard,
+};
+
+/// Follows the reserves of the registered V2 pairs and the state of the tracked V3 pools from
+/// the blocks of the `BlockWatcher`
+///
+/// Every `Sync` event of a block updates the pair it was emitted by, every `Mint`, `Burn` and
+/// `Swap` event the V3 pool. When a block replaces another at the same height, the reserves
+/// are corrected by the `Sync` events of the new block, V3 pools by their next `Swap`.
+pub struct ReserveWatcher {
+    pub ws_url: Arc<String>,
+    pub registry: Arc<Mutex<Registry>>,
+    pub reserves: Arc<Mutex<ReserveTracker>>,
+    pub block_receiver: Arc<Mutex<Receiver<Block<H256>>>>,
+}
+
+impl ReserveWatcher {
+    pub fn new(
+        ws_url: String,
+        registry: Arc<Mutex<Registry>>,
+        reserves: Arc<Mutex<ReserveTracker>>,
+        block_receiver: Receiver<Block<H256>>,
+    ) -> Self {
+        Self {
+            ws_url: Arc::new(ws_url),
+            registry,
+            reserves,
+            block_receiver: Arc::new(Mutex::new(block_receiver)),
+        }
+    }
+
+    pub async fn watch(&self) -> Result<()> {
+        let provider = Provider::<Ws>::connect(self.ws_url.as_ref()).await?;
+        let mut block_receiver = self.block_receiver.lock().await;
+
+        info!("Connected to {}, tracking pair reserves", self.ws_url);
+
+        loop {
+            let hash = match block_receiver.recv().await {
+                Ok(block) => match block.hash {
+                    Some(hash) => hash,
+                    None => continue,
+                },
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!(
+                        "Reserve watcher lagged, reserves of {} blocks were missed",
+                        skipped
+                    );
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+
+            let filter = Filter::new().at_block_hash(hash).topic0(vec![
+                sync_topic(),
+                initialize_topic(),
+                mint_topic(),
+                burn_topic(),
+                swap_topic(),
+            ]);
+            // Missed logs leave the reserves stale until the next log of the pair, the block is
+            // read again once before giving up on it
+            let logs = match provider.get_logs(&filter).await {
+                Ok(logs) => logs,
+                Err(e) => {
+                    warn!(
+                        "Logs of block {:?} could not be read, retrying: {}",
+                        hash, e
+                    );
+                    match provider.get_logs(&filter).await {
+                        Ok(logs) => logs,
+                        Err(e) => {
+                            warn!("Reserves of block {:?} were missed: {}", hash, e);
+                            continue;
+                        }
+                    }
+                }
+            };
+            let registry = self.registry.lock().await;
+            let mut reserves = self.reserves.lock().await;
+            let updated = logs
+                .iter()
+                .filter_map(|log| {
+                    reserves
+                        .apply_sync(&registry, log)
+                        .or_else(|| reserves.apply_v3_log(log))
+                })
+                .count();
+
+            debug!(
+                "Block {:?} updated the reserves of {} pairs, {} tracked",
+                hash,
+                updated,
+                reserves.len()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Simulates and scores pending swaps locally against the tracked reserves and pools
+///
+/// Pairs that aren't tracked yet are bootstrapped with `getReserves`, V3 pools are read from
+/// the pool.
+pub struct SwapSimulator {
+    pub registry: Arc<Mutex<Registry>>,
+    pub reserves: Arc<Mutex<ReserveTracker>>,
+    /// Factories of the V2 routers, searched for the pairs of swaps routed through other
+    /// routers, e.g. the Universal Router
+    pub factories: Vec<Address>,
+    pub provider: Provider<Http>,
+}
+
+impl SwapSimulator {
+    /// # Errors
+    ///
+    /// This function will return an error if `node_http` isn't a valid URL
+    pub fn new(
+        node_http: String,
+        registry: Arc<Mutex<Registry>>,
+        reserves: Arc<Mutex<ReserveTracker>>,
+        routers: &[Router],
+    ) -> Result<Self> {
+        let mut factories = Vec::new();
+        for router in routers.iter().filter(|router| router.version == 2) {
+            if !factories.contains(&router.factory.address) {
+                factories.push(router.factory.address);
+            }
+        }
+
+        Ok(Self {
+            registry,
+            reserves,
+            factories,
+            provider: Provider::<Http>::try_from(node_http)?,
+        })
+    }
+
+    /// Lock the tracker once the reserves of every V2 pair and the state of every V3 pool of
+    /// `pairs` are tracked
+    ///
+    /// Untracked ones are read before taking the lock, so reading them doesn't stall the
+    /// `ReserveWatcher` or other swaps. A pool read as a block is applied misses the events of
+    /// that block, which its next `Swap` corrects like after a reorg.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the state of a pair or pool could not be read
+    async fn track(&self, pairs: &[PairInfo]) -> Result<MutexGuard<'_, ReserveTracker>> {
+        let untracked = {
+            let reserves = self.reserves.lock().await;
+            pairs
+                .iter()
+                .filter(|pair| match pair.protocol_version {
+                    2 => reserves.get(pair.address).is_none(),
+                    _ => reserves.v3_pool(pair.address).is_none(),
+                })
+                .collect::<Vec<&PairInfo>>()
+        };
+
+        let mut fetched_reserves = Vec::new();
+        let mut fetched_pools = Vec::new();
+        for pair in untracked {
+            if pair.protocol_version == 2 {
+                let state = fetch_reserves(&self.provider, pair.address, None).await?;
+                fetched_reserves.push((pair.address, state));
+            } else {
+                fetched_pools.push(fetch_v3_pool(&self.provider, pair.address).await?);
+            }
+        }
+
+        let mut reserves = self.reserves.lock().await;
+        for (pair, state) in fetched_reserves {
+            reserves.insert_fetched(pair, state);
+        }
+        for (pool, block) in fetched_pools {
+            reserves.insert_v3_pool(pool, block);
+        }
+
+        Ok(reserves)
+    }
+
+    /// Simulate a V2 swap made through `router`, `None` if its pairs aren't registered
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the reserves of a pair could not be read
+    pub async fn simulate(
+        &self,
+        router: &Router,
+        swap: &SwapRecord,
+    ) -> Result<Option<SwapSimulation>> {
+        if swap.protocol_version != 2 {
+            return Ok(None);
+        }
+
+        let Some(pairs) = self.pairs(router, swap).await else {
+            return Ok(None);
+        };
+        let reserves = self.track(&pairs).await?;
+        let pairs = pairs.iter().collect::<Vec<&PairInfo>>();
+
+        Ok(simulate_swap(swap, &pairs, &reserves))
+    }
+
+    /// Registered pairs or pools of each hop of a swap made through `router`, `None` if one
+    /// isn't registered
+    async fn pairs(&self, router: &Router, swap: &SwapRecord) -> Option<Vec<PairInfo>> {
+        let registry = self.registry.lock().await;
+
+        let pairs = match swap.protocol_version {
+            2 => {
+                let own_factory = (router.version == 2).then_some(router.factory.address);
+                own_factory
+                    .iter()
+                    .chain(self.factories.iter())
+                    .find_map(|factory| v2_pairs(&registry, *factory, &swap.path))
+            }
+            3 if router.version == 3 => swap
+                .hops()
+                .iter()
+                .map(|hop| {
+                    registry.find_pair(
+                        router.factory.address,
+                        hop.token_in,
+                        hop.token_out,
+                        Some(hop.fee),
+                    )
+                })
+                .collect::<Option<Vec<&PairInfo>>>(),
+            _ => None,
+        };
+        if pairs.is_none() {
+            debug!("Swap ({:?}) has unregistered pairs", swap.tx_hash);
+        }
+
+        pairs.map(|pairs| pairs.into_iter().cloned().collect())
+    }
+
+    /// Score the slippage and sandwich exposure of a swap made through `router`, `None` if its
+    /// pairs aren't registered
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the state of a pair or pool could not be read
+    pub async fn score(&self, router: &Router, swap: &SwapRecord) -> Result<Option<SwapScore>> {
+        let Some(pairs) = self.pairs(router, swap).await else {
+            return Ok(None);
+        };
+
+        let reserves = self.track(&pairs).await?;
+        let route = pairs
+            .into_iter()
+            .map(|pair| match pair.protocol_version {
+                2 => reserves.get(pair.address).map(|state| PoolState::V2 {
+                    reserves: *state,
+                    pair,
+                }),
+                _ => reserves.v3_pool(pair.address).cloned().map(PoolState::V3),
+            })
+            .collect::<Option<Vec<PoolState>>>();
+        drop(reserves);
+        let Some(route) = route else {
+            return Ok(None);
+        };
+
+        match score_swap(swap, &route) {
+            Ok(score) => Ok(Some(score)),
+            Err(e) => {
+                debug!("Swap ({:?}) can't be scored: {}", swap.tx_hash, e);
+                Ok(None)
+            }
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::Result;
+use dex::protocol::ProtocolRegistry;
+use dex::swap::{SwapAmounts, SwapRecord};
+use ethers::prelude::*;
+use log::{debug, info, warn};
+use tokio::sync::{
+    broadcast::{error::RecvError, Receiver, Sender},
+    Mutex,
+};
+
+use crate::token_resolver::{resolve_shared, TokenResolver};
+
+/// Decodes the swaps mined in the blocks from the `BlockWatcher`
+///
+/// Blocks only carry tx hashes, so every block is fetched again with its txs and its logs.
+/// Swaps seen while pending are reported a second time as mined, with their inclusion.
+pub struct SwapWatcher {
+    pub ws_url: Arc<String>,
+    pub protocols: ProtocolRegistry,
+    pub block_receiver: Arc<Mutex<Receiver<Block<H256>>>>,
+    pub sender: Arc<Sender<SwapRecord>>,
+    /// Resolves the tokens of the swaps to show their amounts in whole tokens
+    pub tokens: Option<Arc<Mutex<TokenResolver>>>,
+}
+
+impl SwapWatcher {
+    pub fn new(
+        ws_url: String,
+        protocols: ProtocolRegistry,
+        block_receiver: Receiver<Block<H256>>,
+        sender: Sender<SwapRecord>,
+        tokens: Option<Arc<Mutex<TokenResolver>>>,
+    ) -> Self {
+        Self {
+            ws_url: Arc::new(ws_url),
+            protocols,
+            block_receiver: Arc::new(Mutex::new(block_receiver)),
+            sender: Arc::new(sender),
+            tokens,
+        }
+    }
+
+    /// Swaps made by the txs of a block through the routers and the other configured protocols,
+    /// from their calldata and the `logs` of the block
+    fn swaps(&self, block: &Block<Transaction>, logs: &[Log]) -> Vec<SwapRecord> {
+        let mut logs_by_tx = HashMap::<H256, Vec<Log>>::new();
+        for log in logs {
+            if let Some(hash) = log.transaction_hash {
+                logs_by_tx.entry(hash).or_default().push(log.clone());
+            }
+        }
+
+        block
+            .transactions
+            .iter()
+            .flat_map(|tx| {
+                let logs = logs_by_tx
+                    .get(&tx.hash)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default();
+                self.protocols.mined_swaps(tx, logs)
+            })
+            .collect()
+    }
+
+    pub async fn watch(&self) -> Result<()> {
+        let provider = Provider::<Ws>::connect(self.ws_url.as_ref()).await?;
+        let mut block_receiver = self.block_receiver.lock().await;
+
+        info!("Connected to {}, decoding mined swaps", self.ws_url);
+
+        loop {
+            let hash = match block_receiver.recv().await {
+                Ok(block) => match block.hash {
+                    Some(hash) => hash,
+                    None => continue,
+                },
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Swap watcher lagged, {} blocks were not decoded", skipped);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+
+            let block = match provider.get_block_with_txs(hash).await {
+                Ok(Some(block)) => block,
+                Ok(None) => {
+                    warn!("Block {:?} is gone, it was probably reorged out", hash);
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Swaps of block {:?} were missed: {}", hash, e);
+                    continue;
+                }
+            };
+
+            // Without the logs, swaps made through other contracts than the protocols are missed
+            let logs = match provider.get_logs(&Filter::new().at_block_hash(hash)).await {
+                Ok(logs) => logs,
+                Err(e) => {
+                    warn!("Logs of block {:?} could not be read: {}", hash, e);
+                    Vec::new()
+                }
+            };
+            let swaps = self.swaps(&block, &logs);
+            debug!("Block {:?} has {} swaps", block.number, swaps.len());
+
+            let addresses = swaps
+                .iter()
+                .flat_map(|swap| swap.path.clone())
+                .collect::<Vec<Address>>();
+            let tokens = match &self.tokens {
+                Some(tokens) => Some(resolve_shared(tokens, &addresses).await),
+                None => None,
+            };
+            for mut swap in swaps {
+                swap.amounts = tokens
+                    .as_ref()
+                    .and_then(|tokens| SwapAmounts::new(&swap, tokens));
+                self.sender.send(swap)?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use cache::token_caching::{TokenCacheEngine, TokenCaching};
+use dex::token::{fetch_metadata, TokenMetadata};
+use ethers::prelude::*;
+use log::{debug, warn};
+use tokio::sync::Mutex;
+
+/// Time after which token metadata is read again when `refresh_after` isn't configured
+pub const DEFAULT_REFRESH_AFTER: Duration = Duration::from_secs(60 * 60 * 24);
+
+/// Resolves the metadata of ERC-20 tokens, from memory, then the cache, then the token
+///
+/// Metadata older than `refresh_after` is read again, the total supply being the only field
+/// that is expected to change. When that fails the stale metadata is kept.
+pub struct TokenResolver {
+    pub provider: Provider<Http>,
+    pub cache: TokenCacheEngine,
+    pub refresh_after: Duration,
+    tokens: HashMap<Address, TokenMetadata>,
+}
+
+impl TokenResolver {
+    /// # Errors
+    ///
+    /// This function will return an error if `node_http` isn't a valid URL
+    pub fn new(
+        node_http: String,
+        cache: TokenCacheEngine,
+        refresh_after: Duration,
+    ) -> Result<Self> {
+        Ok(Self {
+            provider: Provider::<Http>::try_from(node_http)?,
+            cache,
+            refresh_after,
+            tokens: HashMap::new(),
+        })
+    }
+
+    /// Metadata of `address` known to the resolver or cached, `Err` with the stale metadata,
+    /// if any, when it has to be read from the token
+    async fn lookup(
+        &mut self,
+        address: Address,
+        now: u64,
+    ) -> std::result::Result<TokenMetadata, Option<TokenMetadata>> {
+        let mut stale = self.tokens.get(&address).cloned();
+        if stale.is_none() {
+            stale = match self.cache.cached_token(address).await {
+                Ok(cached) => cached,
+                Err(e) => {
+                    warn!("Failed to read token {:?} from the cache: {}", address, e);
+                    None
+                }
+            };
+        }
+        match stale {
+            Some(token) if !token.is_stale(now, self.refresh_after.as_secs()) => {
+                self.tokens.insert(address, token.clone());
+                Ok(token)
+            }
+            stale => Err(stale),
+        }
+    }
+
+    /// Keep the metadata of a token, caching it when it was just read from the token
+    async fn keep(&mut self, token: &TokenMetadata, read: bool) {
+        if read {
+            if let Err(e) = self.cache.cache_token(token).await {
+                warn!("Failed to cache token {:?}: {}", token.address, e);
+            }
+        }
+        self.tokens.insert(token.address, token.clone());
+    }
+
+    /// Metadata of the token at `address`
+    ///
+    /// Contracts that aren't tokens resolve too, with no decimals, so they are only read once
+    /// per refresh.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the token isn't cached and could not be read
+    pub async fn resolve(&mut self, address: Address) -> Result<TokenMetadata> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let stale = match self.lookup(address, now).await {
+            Ok(token) => return Ok(token),
+            Err(stale) => stale,
+        };
+
+        let (token, read) = read_token(&self.provider, address, stale).await?;
+        self.keep(&token, read).await;
+
+        Ok(token)
+    }
+
+    /// Metadata of every token of `addresses`, leaving out the ones that could not be resolved
+    pub async fn resolve_all(&mut self, addresses: &[Address]) -> HashMap<Address, TokenMetadata> {
+        let mut tokens = HashMap::new();
+
+        for address in addresses.iter().copied() {
+            if tokens.contains_key(&address) {
+                continue;
+            }
+            match self.resolve(address).await {
+                Ok(token) => {
+                    tokens.insert(address, token);
+                }
+                Err(e) => warn!("Failed to resolve token {:?}: {}", address, e),
+            }
+        }
+
+        tokens
+    }
+}
+
+/// Read the metadata of `address` from the token, falling back on its `stale` metadata
+///
+/// Returns whether the metadata was read.
+async fn read_token(
+    provider: &Provider<Http>,
+    address: Address,
+    stale: Option<TokenMetadata>,
+) -> Result<(TokenMetadata, bool)> {
+    match fetch_metadata(provider, address, None).await {
+        Ok(token) => {
+            debug!(
+                "Resolved token {:?}: {:?} ({:?}), {:?} decimals",
+                address, token.symbol, token.name, token.decimals
+            );
+            Ok((token, true))
+        }
+        Err(e) => match stale {
+            Some(token) => {
+                warn!("Failed to refresh token {:?}, keeping it: {}", address, e);
+                Ok((token, false))
+            }
+            None => Err(e),
+        },
+    }
+}
+
+/// Metadata of every token of `addresses` from a shared resolver, leaving out the ones that
+/// could not be resolved
+///
+/// The resolver is only locked to look the tokens up and to keep them, not while they are read
+/// from the node, so txs with new tokens don't hold up the others.
+pub async fn resolve_shared(
+    resolver: &Mutex<TokenResolver>,
+    addresses: &[Address],
+) -> HashMap<Address, TokenMetadata> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default();
+    let mut tokens = HashMap::new();
+    let mut unresolved = HashMap::new();
+
+    let provider = {
+        let mut resolver = resolver.lock().await;
+        for address in addresses.iter().copied() {
+            if tokens.contains_key(&address) || unresolved.contains_key(&address) {
+                continue;
+            }
+            match resolver.lookup(address, now).await {
+                Ok(token) => {
+                    tokens.insert(address, token);
+                }
+                Err(stale) => {
+                    unresolved.insert(address, stale);
+                }
+            }
+        }
+        resolver.provider.clone()
+    };
+    if unresolved.is_empty() {
+        return tokens;
+    }
+
+    let mut read = Vec::new();
+    for (address, stale) in unresolved {
+        match read_token(&provider, address, stale).await {
+            Ok(token) => read.push(token),
+            Err(e) => warn!("Failed to resolve token {:?}: {}", address, e),
+        }
+    }
+
+    let mut resolver = resolver.lock().await;
+    for (token, fetched) in read {
+        resolver.keep(&token, fetched).await;
+        tokens.insert(token.address, token);
+    }
+
+    tokens
+}
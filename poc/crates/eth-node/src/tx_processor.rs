@@ -1,79 +1,329 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use ansi_term::Colour;
 use anyhow::Result;
+use dex::decoded::{Decoded, DecodedCall};
+use dex::liquidity::LiquidityEvent;
+use dex::protocol::ProtocolRegistry;
+use dex::registry::NewMarket;
 use dex::router::Router;
-use ethers::types::Transaction;
-use log::{info, trace};
+use dex::swap::{SwapAmounts, SwapRecord};
+use dex::token::TokenMetadata;
+use dex::{decode_debug_with_tokens, DecodableTransaction};
+use ethers::types::{Address, Transaction};
+use log::{debug, info, trace, warn};
 use tokio::sync::{
-    broadcast::{Receiver, Sender},
+    broadcast::{error::RecvError, Receiver, Sender},
     Mutex,
 };
 
 use crate::check_contract_creation;
+use crate::launch_watcher::{log_liquidity, LaunchDetector};
+use crate::reserve_watcher::SwapSimulator;
+use crate::token_resolver::{resolve_shared, TokenResolver};
+
+/// Vulnerability from which a swap is highlighted as a sandwich target
+const VULNERABLE: f64 = 0.5;
 
 pub struct TxProcessor {
     pub receiver: Arc<Mutex<Receiver<Transaction>>>,
     pub sender: Arc<Mutex<Sender<Transaction>>>,
+    pub swap_sender: Arc<Sender<SwapRecord>>,
+    pub decoded_sender: Arc<Sender<DecodedCall>>,
+    pub market_sender: Arc<Sender<NewMarket>>,
+    pub liquidity_sender: Arc<Sender<LiquidityEvent>>,
     pub routers: Vec<Router>,
+    /// Decodes the swaps of the txs to the routers and the other configured protocols
+    pub protocols: ProtocolRegistry,
+    /// Scores pending swaps, `None` when the reserves aren't tracked
+    pub simulator: Option<SwapSimulator>,
+    /// Resolves the tokens of the swaps to show their amounts in whole tokens
+    pub tokens: Option<Arc<Mutex<TokenResolver>>>,
+    /// Tells pending launches apart, `None` to not look for them
+    pub launches: Option<LaunchDetector>,
 }
 
 impl TxProcessor {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         receiver: Receiver<Transaction>,
         sender: Sender<Transaction>,
+        swap_sender: Sender<SwapRecord>,
+        decoded_sender: Sender<DecodedCall>,
+        market_sender: Sender<NewMarket>,
+        liquidity_sender: Sender<LiquidityEvent>,
         routers: Vec<Router>,
+        protocols: ProtocolRegistry,
+        simulator: Option<SwapSimulator>,
+        tokens: Option<Arc<Mutex<TokenResolver>>>,
+        launches: Option<LaunchDetector>,
     ) -> Self {
         Self {
             receiver: Arc::new(Mutex::new(receiver)),
             sender: Arc::new(Mutex::new(sender)),
+            swap_sender: Arc::new(swap_sender),
+            decoded_sender: Arc::new(decoded_sender),
+            market_sender: Arc::new(market_sender),
+            liquidity_sender: Arc::new(liquidity_sender),
             routers,
+            protocols,
+            simulator,
+            tokens,
+            launches,
+        }
+    }
+
+    /// Publish the call made by a tx to a router or factory, if it can be decoded
+    ///
+    /// Returns the published call.
+    async fn publish_call<D: DecodableTransaction>(
+        &self,
+        decoder: &D,
+        tx: &Transaction,
+    ) -> Result<Option<DecodedCall>> {
+        match decoder.decode_tx(tx.clone()).await? {
+            Decoded::Call(call) => {
+                self.decoded_sender.send(call.clone())?;
+
+                Ok(Some(call))
+            }
+            Decoded::NotDecodable(reason) => {
+                debug!("TX Pool ({:?}) not decodable: {}", tx.hash, reason);
+
+                Ok(None)
+            }
+        }
+    }
+
+    /// Metadata of the tokens swapped by `swaps`, empty without a resolver
+    async fn resolve_tokens(&self, swaps: &[SwapRecord]) -> HashMap<Address, TokenMetadata> {
+        let addresses = swaps
+            .iter()
+            .flat_map(|swap| swap.path.clone())
+            .collect::<Vec<Address>>();
+
+        match &self.tokens {
+            Some(tokens) => resolve_shared(tokens, &addresses).await,
+            None => HashMap::new(),
+        }
+    }
+
+    /// Publish the swaps of a pending tx, scored when they go through one of the `router`s
+    async fn publish_swaps(
+        &self,
+        tx: &Transaction,
+        swaps: Vec<SwapRecord>,
+        tokens: &HashMap<Address, TokenMetadata>,
+        router: Option<&Router>,
+    ) -> Result<()> {
+        for mut swap in swaps {
+            swap.amounts = SwapAmounts::new(&swap, tokens);
+            info!(
+                "Swap ({}) {} via {}",
+                Colour::White.bold().paint(format!("{:?}", tx.hash)),
+                swap.amounts_debug(),
+                Colour::Green.paint(format!("{}-v{}", swap.router, swap.protocol_version))
+            );
+            for hop in swap.hops() {
+                debug!("Swap ({:?}) hop {}", tx.hash, hop);
+            }
+            if let (Some(simulator), Some(router)) = (&self.simulator, router) {
+                match simulator.score(router, &swap).await {
+                    Ok(Some(score)) => {
+                        info!(
+                            "Swap ({}) slippage {:.2}%, price impact {:.2}%, sandwich profit {} ({})",
+                            Colour::White.bold().paint(format!("{:?}", tx.hash)),
+                            score.slippage_tolerance * 100.0,
+                            score.price_impact * 100.0,
+                            score.sandwich_profit,
+                            if score.vulnerability >= VULNERABLE {
+                                Colour::Red
+                            } else {
+                                Colour::Green
+                            }
+                            .paint(format!("{:.2}", score.vulnerability))
+                        );
+                        swap.score = Some(score);
+                    }
+                    Ok(None) => {}
+                    Err(e) => warn!("Swap ({:?}) scoring failed: {}", tx.hash, e),
+                }
+            }
+            self.swap_sender.send(swap)?;
+        }
+
+        Ok(())
+    }
+
+    /// Resolve the tokens of the swaps of a pending tx, then score and publish them in a task of
+    /// their own
+    ///
+    /// The decoded `call` is logged with the amounts in whole tokens.
+    fn spawn_swaps(
+        self: &Arc<Self>,
+        tx: &Transaction,
+        call: Option<DecodedCall>,
+        swaps: Vec<SwapRecord>,
+        router: Option<Router>,
+    ) {
+        if swaps.is_empty() && call.is_none() {
+            return;
+        }
+
+        let processor = self.clone();
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            let tokens = processor.resolve_tokens(&swaps).await;
+            if let Some(call) = call {
+                debug!(
+                    "{} {}",
+                    Colour::Yellow.bold().paint("Input: "),
+                    decode_debug_with_tokens(&call, &tokens)
+                );
+            }
+            if let Err(e) = processor
+                .publish_swaps(&tx, swaps, &tokens, router.as_ref())
+                .await
+            {
+                warn!("Swaps ({:?}) could not be published: {}", tx.hash, e);
+            }
+        });
+    }
+
+    /// Check and publish the launches and the removals of liquidity of a pending tx to `router`
+    /// in a task of their own
+    fn spawn_liquidity(self: &Arc<Self>, router: &Router, tx: &Transaction) {
+        if self.launches.is_none() {
+            return;
+        }
+        let events = self.protocols.liquidity(tx);
+        if events.is_empty() {
+            return;
         }
+
+        let processor = self.clone();
+        let router = router.clone();
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            let Some(launches) = &processor.launches else {
+                return;
+            };
+            for event in events {
+                match launches.pending(&router, &tx, event).await {
+                    Ok(Some(event)) => {
+                        log_liquidity(&event);
+                        if let Err(e) = processor.liquidity_sender.send(event) {
+                            warn!("Liquidity ({:?}) could not be published: {}", tx.hash, e);
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => warn!("Liquidity ({:?}) can't be checked: {}", tx.hash, e),
+                }
+            }
+        });
     }
 
-    pub async fn process(&self) -> Result<()> {
+    /// Process the pending txs until the tx pool is gone
+    ///
+    /// Swaps are resolved and scored and launches are checked in tasks of their own, the next
+    /// txs don't wait on the node. A tx that fails to be processed is logged and skipped.
+    pub async fn process(self: Arc<Self>) -> Result<()> {
         let mut receiver = self.receiver.lock().await;
         let sender = self.sender.lock().await;
 
-        while let Ok(tx) = receiver.recv().await {
-            trace!("Received tx: {}", tx.hash);
-            trace!("{}", serde_json::to_string_pretty(&tx)?);
-
-            if let Some(to) = tx.to {
-                trace!("TX to: {:?}", to);
-
-                if let Some(router) = self
-                    .routers
-                    .iter()
-                    .find(|r| r.addresses.iter().any(|a| *a == to))
-                {
-                    info!(
-                        "TX Pool ({}) to: {}",
-                        Colour::White.bold().paint(format!("{:?}", tx.hash)),
-                        Colour::Green.paint(router.to_string())
-                    );
-
-                    sender.send(tx.clone())?;
-                } else if let Some(router) = self.routers.iter().find(|r| r.factory.address == to) {
-                    info!(
-                        "TX Pool ({}) to: {}",
-                        Colour::White.bold().paint(format!("{:#?}", tx.hash)),
-                        Colour::Blue.paint(router.factory.to_string())
-                    );
-
-                    sender.send(tx.clone())?;
-                } else if check_contract_creation(to)?.is_none() {
-                    info!(
-                        "TX Pool ({}) Create Contract",
-                        Colour::Red.bold().paint(format!("{:?}", tx.hash))
-                    );
-                    sender.send(tx)?;
-                } else {
-                    trace!("TX to: Unknown");
+        loop {
+            let tx = match receiver.recv().await {
+                Ok(tx) => tx,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("TX processor lagged, {} pending txs were skipped", skipped);
+                    continue;
                 }
-            } else {
-                trace!("TX to: None");
+                Err(RecvError::Closed) => break,
+            };
+
+            if let Err(e) = self.process_tx(&tx, &sender).await {
+                warn!("TX Pool ({:?}) processing failed: {}", tx.hash, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn process_tx(
+        self: &Arc<Self>,
+        tx: &Transaction,
+        sender: &Sender<Transaction>,
+    ) -> Result<()> {
+        trace!("Received tx: {}", tx.hash);
+        trace!("{}", serde_json::to_string_pretty(tx)?);
+
+        let Some(to) = tx.to else {
+            trace!("TX to: None");
+            return Ok(());
+        };
+        trace!("TX to: {:?}", to);
+
+        if let Some(router) = self
+            .routers
+            .iter()
+            .find(|r| r.addresses.iter().any(|a| *a == to))
+        {
+            info!(
+                "TX Pool ({}) to: {}",
+                Colour::White.bold().paint(format!("{:?}", tx.hash)),
+                Colour::Green.paint(router.to_string())
+            );
+
+            let call = self.publish_call(router, tx).await?;
+            let swaps = self.protocols.swaps(tx);
+            self.spawn_swaps(tx, call, swaps, Some(router.clone()));
+            self.spawn_liquidity(router, tx);
+
+            sender.send(tx.clone())?;
+        } else if let Some(protocol) = self.protocols.decoder(to) {
+            info!(
+                "TX Pool ({}) to: {}",
+                Colour::White.bold().paint(format!("{:?}", tx.hash)),
+                Colour::Purple.paint(protocol.name())
+            );
+
+            let swaps = self.protocols.swaps(tx);
+            self.spawn_swaps(tx, None, swaps, None);
+
+            sender.send(tx.clone())?;
+        } else if let Some(router) = self.routers.iter().find(|r| r.factory.address == to) {
+            info!(
+                "TX Pool ({}) to: {}",
+                Colour::White.bold().paint(format!("{:#?}", tx.hash)),
+                Colour::Blue.paint(router.factory.to_string())
+            );
+
+            if let Some(market) = self
+                .publish_call(&router.factory, tx)
+                .await?
+                .and_then(|call| NewMarket::pending(&router.factory, &call))
+            {
+                info!(
+                    "New market ({}) {:?}/{:?} fee {:?} pending on {}",
+                    Colour::White.bold().paint(format!("{:?}", tx.hash)),
+                    market.token0,
+                    market.token1,
+                    market.fee,
+                    Colour::Blue.paint(router.factory.to_string())
+                );
+                self.market_sender.send(market)?;
             }
+
+            sender.send(tx.clone())?;
+        } else if check_contract_creation(to)?.is_none() {
+            info!(
+                "TX Pool ({}) Create Contract",
+                Colour::Red.bold().paint(format!("{:?}", tx.hash))
+            );
+            sender.send(tx.clone())?;
+        } else {
+            trace!("TX to: Unknown");
         }
 
         Ok(())
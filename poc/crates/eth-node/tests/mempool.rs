@@ -0,0 +1,106 @@
+use std::time::Duration;
+
+use eth_node::mempool_tracker::Mempool;
+use ethers::types::{Address, Block, H256, U256, U64};
+use storage::lifecycle::{TxLifecycle, TxStatus};
+
+fn pending(byte: u8, first_seen: u64) -> TxLifecycle {
+    TxLifecycle {
+        hash: H256::repeat_byte(byte),
+        from: Address::repeat_byte(0xf0),
+        nonce: U256::from(7),
+        to: None,
+        router: None,
+        node: String::from("node"),
+        first_seen,
+        status: TxStatus::Pending,
+        block_number: None,
+        block_hash: None,
+        transaction_index: None,
+        mined_at: None,
+        replaced_by: None,
+        closed_at: None,
+    }
+}
+
+fn block(transactions: Vec<H256>) -> Block<H256> {
+    Block {
+        hash: Some(H256::repeat_byte(0xb1)),
+        number: Some(U64::from(1)),
+        transactions,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn replacing_tx_is_mined() {
+    let mut mempool = Mempool::default();
+    mempool.seen(pending(1, 1_000));
+
+    let updates = mempool.seen(pending(2, 2_000));
+    assert_eq!(updates.len(), 2);
+    assert_eq!(updates[0].hash, H256::repeat_byte(1));
+    assert_eq!(updates[0].status, TxStatus::Replaced);
+    assert_eq!(updates[0].replaced_by, Some(H256::repeat_byte(2)));
+    assert_eq!(updates[0].closed_at, Some(2_000));
+    assert_eq!(updates[1].status, TxStatus::Pending);
+    assert_eq!(mempool.len(), 1);
+
+    let updates = mempool.mined(&block(vec![H256::repeat_byte(2)]), 3_000);
+    assert_eq!(updates.len(), 1);
+    assert_eq!(updates[0].status, TxStatus::Mined);
+    assert_eq!(updates[0].mined_at, Some(3_000));
+    assert!(mempool.is_empty());
+}
+
+#[test]
+fn replaced_tx_is_mined() {
+    let mut mempool = Mempool::default();
+    mempool.seen(pending(1, 1_000));
+    mempool.seen(pending(2, 2_000));
+
+    // The first tx wins the race to the block after being replaced
+    let updates = mempool.mined(
+        &block(vec![H256::repeat_byte(0xaa), H256::repeat_byte(1)]),
+        3_000,
+    );
+    assert_eq!(updates.len(), 2);
+
+    assert_eq!(updates[0].hash, H256::repeat_byte(1));
+    assert_eq!(updates[0].status, TxStatus::Mined);
+    assert_eq!(updates[0].transaction_index, Some(1));
+    assert_eq!(updates[0].block_hash, Some(H256::repeat_byte(0xb1)));
+    assert_eq!((updates[0].replaced_by, updates[0].closed_at), (None, None));
+
+    assert_eq!(updates[1].hash, H256::repeat_byte(2));
+    assert_eq!(updates[1].status, TxStatus::Replaced);
+    assert_eq!(updates[1].replaced_by, Some(H256::repeat_byte(1)));
+    assert_eq!(updates[1].closed_at, Some(3_000));
+
+    // The slot is settled, nothing is left to drop
+    assert!(mempool.is_empty());
+    assert!(mempool.expire(1_000_000, Duration::from_secs(1)).is_empty());
+}
+
+#[test]
+fn expiry_drops_only_the_pending_tx() {
+    let mut mempool = Mempool::default();
+    mempool.seen(pending(1, 1_000));
+    mempool.seen(pending(2, 2_000));
+
+    // The replaced tx is old enough, but the pending one isn't
+    assert!(mempool
+        .expire(2_500, Duration::from_millis(1_000))
+        .is_empty());
+
+    let dropped = mempool.expire(4_000, Duration::from_millis(1_000));
+    assert_eq!(dropped.len(), 1);
+    assert_eq!(dropped[0].hash, H256::repeat_byte(2));
+    assert_eq!(dropped[0].status, TxStatus::Dropped);
+    assert!(mempool.is_empty());
+
+    // Once dropped, a late block doesn't bring the replaced tx back
+    assert!(mempool
+        .mined(&block(vec![H256::repeat_byte(1)]), 5_000)
+        .is_empty());
+}
@@ -0,0 +1,286 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use arrow_array::RecordBatch;
+use dex::swap::SwapRecord;
+use ethers::types::{Block, Transaction, H256};
+use log::{debug, info};
+use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+use storage::reader::ChainReader;
+use storage::swap::SwapStorage;
+
+pub mod schema;
+pub mod state;
+
+use state::ExportState;
+
+pub const DEFAULT_BLOCKS_PER_FILE: u64 = 1000;
+
+/// Which blocks to export
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportRange {
+    /// Everything stored since the last incremental export
+    Incremental,
+    /// Blocks with a number in `from..=to`
+    Blocks { from: u64, to: u64 },
+    /// Blocks with a timestamp in `from..=to` (unix seconds)
+    Time { from: u64, to: u64 },
+}
+
+#[derive(Debug, Default)]
+pub struct ExportSummary {
+    pub blocks: usize,
+    pub txs: usize,
+    pub swaps: usize,
+    pub files: Vec<PathBuf>,
+}
+
+/// Writes stored chain data to Parquet files partitioned by dataset and UTC date
+///
+/// Files are laid out as `<path>/<dataset>/date=YYYY-MM-DD/<dataset>_<from>_<to>.parquet`,
+/// which DuckDB and pandas both read as hive partitions.
+pub struct ParquetExporter {
+    pub path: PathBuf,
+    pub blocks_per_file: u64,
+}
+
+impl ParquetExporter {
+    pub fn new(path: String, blocks_per_file: Option<u64>) -> Result<Self> {
+        let path = PathBuf::from(path);
+        std::fs::create_dir_all(&path)?;
+
+        Ok(Self {
+            path,
+            blocks_per_file: blocks_per_file.unwrap_or(DEFAULT_BLOCKS_PER_FILE).max(1),
+        })
+    }
+
+    /// Export a range of stored blocks with their transactions and decoded swaps
+    ///
+    /// Incremental exports resume after the last exported block and record their progress after
+    /// every file, so an interrupted run picks up where it stopped. Explicit ranges are backfills
+    /// and leave the incremental progress untouched.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the data could not be read or written
+    pub async fn export<R: ChainReader + SwapStorage>(
+        &self,
+        reader: &mut R,
+        range: ExportRange,
+    ) -> Result<ExportSummary> {
+        let mut state = ExportState::load(&self.path)?;
+
+        let bounds = match range {
+            ExportRange::Incremental => {
+                let stored = reader.block_range_for_time(0, i64::MAX as u64).await?;
+                match (stored, state.last_block) {
+                    (Some((_, last)), Some(exported)) => Some((exported + 1, last)),
+                    (stored, None) => stored,
+                    (None, Some(_)) => None,
+                }
+            }
+            ExportRange::Blocks { from, to } => Some((from, to)),
+            ExportRange::Time { from, to } => reader.block_range_for_time(from, to).await?,
+        };
+
+        let mut summary = ExportSummary::default();
+        let Some((from, to)) = bounds.filter(|(from, to)| from <= to) else {
+            info!("Nothing to export");
+            return Ok(summary);
+        };
+
+        info!("Exporting blocks {}..={} to {:?}", from, to, self.path);
+
+        let mut start = from;
+        while start <= to {
+            let end = to.min(start.saturating_add(self.blocks_per_file - 1));
+
+            let blocks = reader.blocks_in_range(start, end).await?;
+            let txs = reader.txs_in_range(start, end).await?;
+            let swaps = reader.swaps_in_range(start, end).await?;
+            debug!(
+                "Read {} blocks, {} txs and {} swaps for {}..={}",
+                blocks.len(),
+                txs.len(),
+                swaps.len(),
+                start,
+                end
+            );
+
+            summary.blocks += blocks.len();
+            summary.txs += txs.len();
+            summary.swaps += swaps.len();
+            summary
+                .files
+                .extend(self.write_chunk(start, end, &blocks, &txs, &swaps)?);
+
+            if range == ExportRange::Incremental {
+                state.last_block = Some(end);
+                state.save(&self.path)?;
+            }
+
+            if end == u64::MAX {
+                break;
+            }
+            start = end + 1;
+        }
+
+        info!(
+            "Exported {} blocks, {} txs and {} swaps into {} files",
+            summary.blocks,
+            summary.txs,
+            summary.swaps,
+            summary.files.len()
+        );
+
+        Ok(summary)
+    }
+
+    /// Write one chunk of blocks, split into one file per dataset and UTC day
+    fn write_chunk(
+        &self,
+        start: u64,
+        end: u64,
+        blocks: &[Block<H256>],
+        txs: &[Transaction],
+        swaps: &[SwapRecord],
+    ) -> Result<Vec<PathBuf>> {
+        let timestamps = blocks
+            .iter()
+            .map(|b| (b.number.unwrap_or_default().as_u64(), b.timestamp.as_u64()))
+            .collect::<HashMap<u64, u64>>();
+
+        let mut files = Vec::new();
+        for (date, (first, last)) in partitions(start, end, blocks) {
+            let partition_blocks = blocks
+                .iter()
+                .filter(|b| (first..=last).contains(&b.number.unwrap_or_default().as_u64()))
+                .cloned()
+                .collect::<Vec<Block<H256>>>();
+            let partition_txs = txs
+                .iter()
+                .filter(|tx| (first..=last).contains(&tx.block_number.unwrap_or_default().as_u64()))
+                .cloned()
+                .collect::<Vec<Transaction>>();
+            let partition_swaps = swaps
+                .iter()
+                .filter(|swap| (first..=last).contains(&swap.block_number.unwrap_or_default()))
+                .cloned()
+                .collect::<Vec<SwapRecord>>();
+
+            if !partition_blocks.is_empty() {
+                files.push(self.write_file(
+                    "blocks",
+                    &date,
+                    first,
+                    last,
+                    schema::blocks_batch(&partition_blocks)?,
+                )?);
+            }
+
+            if !partition_txs.is_empty() {
+                files.push(self.write_file(
+                    "transactions",
+                    &date,
+                    first,
+                    last,
+                    schema::txs_batch(&partition_txs, &timestamps)?,
+                )?);
+            }
+
+            if !partition_swaps.is_empty() {
+                files.push(self.write_file(
+                    "swaps",
+                    &date,
+                    first,
+                    last,
+                    schema::swaps_batch(&partition_swaps, &timestamps)?,
+                )?);
+            }
+        }
+
+        Ok(files)
+    }
+
+    fn write_file(
+        &self,
+        dataset: &str,
+        date: &str,
+        first: u64,
+        last: u64,
+        batch: RecordBatch,
+    ) -> Result<PathBuf> {
+        let dir = self.path.join(dataset).join(format!("date={}", date));
+        std::fs::create_dir_all(&dir)?;
+
+        let path = dir.join(format!("{}_{}_{}.parquet", dataset, first, last));
+        write_parquet(&path, batch)?;
+
+        debug!("Wrote {:?}", path);
+        Ok(path)
+    }
+}
+
+/// Write a record batch to a parquet file, replacing any previous export of the same range
+fn write_parquet(path: &Path, batch: RecordBatch) -> Result<()> {
+    let tmp = path.with_extension("parquet.tmp");
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+
+    let mut writer = ArrowWriter::try_new(File::create(&tmp)?, batch.schema(), Some(properties))?;
+    writer.write(&batch)?;
+    writer.close()?;
+
+    std::fs::rename(tmp, path)?;
+
+    Ok(())
+}
+
+/// Split `start..=end` into contiguous block ranges that fall on the same UTC day
+///
+/// Blocks missing from storage are attributed to the preceding day so that every transaction and
+/// swap in the chunk lands in exactly one partition.
+fn partitions(start: u64, end: u64, blocks: &[Block<H256>]) -> Vec<(String, (u64, u64))> {
+    let mut partitions: Vec<(String, (u64, u64))> = Vec::new();
+
+    for block in blocks {
+        let number = block.number.unwrap_or_default().as_u64();
+        let date = utc_date(block.timestamp.as_u64());
+
+        match partitions.last_mut() {
+            Some((last_date, (_, last))) if *last_date == date => *last = number,
+            Some((_, (_, last))) => {
+                let first = *last + 1;
+                partitions.push((date, (first, number)));
+            }
+            None => partitions.push((date, (start, number))),
+        }
+    }
+
+    match partitions.last_mut() {
+        Some((_, (_, last))) => *last = end,
+        None => partitions.push((String::from("unknown"), (start, end))),
+    }
+
+    partitions
+}
+
+/// Format a unix timestamp as a `YYYY-MM-DD` UTC date
+fn utc_date(timestamp: u64) -> String {
+    // Civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let days = (timestamp / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
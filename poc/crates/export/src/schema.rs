@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::Result;
+use arrow_array::{
+    builder::{
+        BinaryBuilder, Decimal256Builder, FixedSizeBinaryBuilder, ListBuilder, StringBuilder,
+        TimestampSecondBuilder, UInt32Builder, UInt64Builder, UInt8Builder,
+    },
+    ArrayRef, RecordBatch,
+};
+use arrow_buffer::i256;
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use dex::swap::SwapRecord;
+use ethers::types::{Block, Transaction, H256, U256};
+use log::warn;
+
+/// Wei amounts are exported as `decimal(76, 0)`, the widest decimal arrow supports
+pub const WEI_PRECISION: u8 = 76;
+
+fn hash_type() -> DataType {
+    DataType::FixedSizeBinary(32)
+}
+
+fn address_type() -> DataType {
+    DataType::FixedSizeBinary(20)
+}
+
+fn wei_type() -> DataType {
+    DataType::Decimal256(WEI_PRECISION, 0)
+}
+
+fn timestamp_type() -> DataType {
+    DataType::Timestamp(TimeUnit::Second, Some("UTC".into()))
+}
+
+pub fn block_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("number", DataType::UInt64, false),
+        Field::new("hash", hash_type(), false),
+        Field::new("parent_hash", hash_type(), false),
+        Field::new("miner", address_type(), true),
+        Field::new("timestamp", timestamp_type(), false),
+        Field::new("difficulty", wei_type(), true),
+        Field::new("total_difficulty", wei_type(), true),
+        Field::new("size", DataType::UInt64, true),
+        Field::new("gas_limit", wei_type(), true),
+        Field::new("gas_used", wei_type(), true),
+        Field::new("base_fee_per_gas", wei_type(), true),
+        Field::new("extra_data", DataType::Binary, false),
+        Field::new(
+            "transactions",
+            DataType::List(Arc::new(Field::new("item", hash_type(), true))),
+            false,
+        ),
+    ]))
+}
+
+pub fn tx_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("hash", hash_type(), false),
+        Field::new("block_number", DataType::UInt64, false),
+        Field::new("block_hash", hash_type(), true),
+        Field::new("block_timestamp", timestamp_type(), true),
+        Field::new("transaction_index", DataType::UInt64, true),
+        Field::new("nonce", wei_type(), true),
+        Field::new("from_address", address_type(), false),
+        Field::new("to_address", address_type(), true),
+        Field::new("value", wei_type(), true),
+        Field::new("gas_price", wei_type(), true),
+        Field::new("gas", wei_type(), true),
+        Field::new("max_priority_fee_per_gas", wei_type(), true),
+        Field::new("max_fee_per_gas", wei_type(), true),
+        Field::new("type", DataType::UInt8, true),
+        Field::new("input", DataType::Binary, false),
+    ]))
+}
+
+/// Mined swaps, one row per swap of a tx
+pub fn swap_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("tx_hash", hash_type(), false),
+        Field::new("swap_index", DataType::UInt32, false),
+        Field::new("block_number", DataType::UInt64, false),
+        Field::new("block_hash", hash_type(), true),
+        Field::new("block_timestamp", timestamp_type(), true),
+        Field::new("transaction_index", DataType::UInt64, true),
+        Field::new("router", DataType::Utf8, false),
+        Field::new("protocol_version", DataType::UInt8, false),
+        Field::new("method", DataType::Utf8, false),
+        Field::new("sender", address_type(), false),
+        Field::new("recipient", address_type(), true),
+        Field::new(
+            "path",
+            DataType::List(Arc::new(Field::new("item", address_type(), true))),
+            false,
+        ),
+        Field::new(
+            "fees",
+            DataType::List(Arc::new(Field::new("item", DataType::UInt32, true))),
+            false,
+        ),
+        Field::new("kind", DataType::Utf8, false),
+        Field::new("token_in", address_type(), true),
+        Field::new("token_out", address_type(), true),
+        Field::new("amount_in", wei_type(), true),
+        Field::new("amount_out", wei_type(), true),
+        Field::new("deadline", timestamp_type(), true),
+    ]))
+}
+
+/// Convert a `U256` to an arrow `i256`, or `None` if it doesn't fit in the exported precision
+pub fn to_i256(value: U256) -> Option<i256> {
+    if value >= U256::exp10(WEI_PRECISION as usize) {
+        warn!("{} does not fit in decimal({}, 0)", value, WEI_PRECISION);
+        return None;
+    }
+
+    let mut bytes = [0u8; 32];
+    value.to_little_endian(&mut bytes);
+    Some(i256::from_le_bytes(bytes))
+}
+
+pub fn wei_builder(capacity: usize) -> Result<Decimal256Builder> {
+    Ok(Decimal256Builder::with_capacity(capacity).with_precision_and_scale(WEI_PRECISION, 0)?)
+}
+
+pub fn timestamp_builder(capacity: usize) -> TimestampSecondBuilder {
+    TimestampSecondBuilder::with_capacity(capacity).with_timezone("UTC")
+}
+
+pub fn append_fixed(builder: &mut FixedSizeBinaryBuilder, value: Option<&[u8]>) -> Result<()> {
+    match value {
+        Some(value) => builder.append_value(value)?,
+        None => builder.append_null(),
+    }
+
+    Ok(())
+}
+
+pub fn blocks_batch(blocks: &[Block<H256>]) -> Result<RecordBatch> {
+    let mut number = UInt64Builder::with_capacity(blocks.len());
+    let mut hash = FixedSizeBinaryBuilder::with_capacity(blocks.len(), 32);
+    let mut parent_hash = FixedSizeBinaryBuilder::with_capacity(blocks.len(), 32);
+    let mut miner = FixedSizeBinaryBuilder::with_capacity(blocks.len(), 20);
+    let mut timestamp = timestamp_builder(blocks.len());
+    let mut difficulty = wei_builder(blocks.len())?;
+    let mut total_difficulty = wei_builder(blocks.len())?;
+    let mut size = UInt64Builder::with_capacity(blocks.len());
+    let mut gas_limit = wei_builder(blocks.len())?;
+    let mut gas_used = wei_builder(blocks.len())?;
+    let mut base_fee_per_gas = wei_builder(blocks.len())?;
+    let mut extra_data = BinaryBuilder::new();
+    let mut transactions = ListBuilder::new(FixedSizeBinaryBuilder::new(32));
+
+    for block in blocks {
+        number.append_value(block.number.unwrap_or_default().as_u64());
+        hash.append_value(block.hash.unwrap_or_default())?;
+        parent_hash.append_value(block.parent_hash)?;
+        append_fixed(&mut miner, block.author.as_ref().map(|a| a.as_bytes()))?;
+        timestamp.append_value(block.timestamp.as_u64() as i64);
+        difficulty.append_option(to_i256(block.difficulty));
+        total_difficulty.append_option(block.total_difficulty.and_then(to_i256));
+        size.append_option(block.size.map(|s| s.as_u64()));
+        gas_limit.append_option(to_i256(block.gas_limit));
+        gas_used.append_option(to_i256(block.gas_used));
+        base_fee_per_gas.append_option(block.base_fee_per_gas.and_then(to_i256));
+        extra_data.append_value(&block.extra_data);
+        for tx in &block.transactions {
+            transactions.values().append_value(tx)?;
+        }
+        transactions.append(true);
+    }
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(number.finish()),
+        Arc::new(hash.finish()),
+        Arc::new(parent_hash.finish()),
+        Arc::new(miner.finish()),
+        Arc::new(timestamp.finish()),
+        Arc::new(difficulty.finish()),
+        Arc::new(total_difficulty.finish()),
+        Arc::new(size.finish()),
+        Arc::new(gas_limit.finish()),
+        Arc::new(gas_used.finish()),
+        Arc::new(base_fee_per_gas.finish()),
+        Arc::new(extra_data.finish()),
+        Arc::new(transactions.finish()),
+    ];
+
+    Ok(RecordBatch::try_new(block_schema(), columns)?)
+}
+
+/// Build a transactions batch, `timestamps` maps block numbers to block timestamps
+pub fn txs_batch(txs: &[Transaction], timestamps: &HashMap<u64, u64>) -> Result<RecordBatch> {
+    let mut hash = FixedSizeBinaryBuilder::with_capacity(txs.len(), 32);
+    let mut block_number = UInt64Builder::with_capacity(txs.len());
+    let mut block_hash = FixedSizeBinaryBuilder::with_capacity(txs.len(), 32);
+    let mut block_timestamp = timestamp_builder(txs.len());
+    let mut transaction_index = UInt64Builder::with_capacity(txs.len());
+    let mut nonce = wei_builder(txs.len())?;
+    let mut from_address = FixedSizeBinaryBuilder::with_capacity(txs.len(), 20);
+    let mut to_address = FixedSizeBinaryBuilder::with_capacity(txs.len(), 20);
+    let mut value = wei_builder(txs.len())?;
+    let mut gas_price = wei_builder(txs.len())?;
+    let mut gas = wei_builder(txs.len())?;
+    let mut max_priority_fee_per_gas = wei_builder(txs.len())?;
+    let mut max_fee_per_gas = wei_builder(txs.len())?;
+    let mut tx_type = UInt8Builder::with_capacity(txs.len());
+    let mut input = BinaryBuilder::new();
+
+    for tx in txs {
+        let number = tx.block_number.unwrap_or_default().as_u64();
+
+        hash.append_value(tx.hash)?;
+        block_number.append_value(number);
+        append_fixed(
+            &mut block_hash,
+            tx.block_hash.as_ref().map(|h| h.as_bytes()),
+        )?;
+        block_timestamp.append_option(timestamps.get(&number).map(|t| *t as i64));
+        transaction_index.append_option(tx.transaction_index.map(|i| i.as_u64()));
+        nonce.append_option(to_i256(tx.nonce));
+        from_address.append_value(tx.from)?;
+        append_fixed(&mut to_address, tx.to.as_ref().map(|a| a.as_bytes()))?;
+        value.append_option(to_i256(tx.value));
+        gas_price.append_option(tx.gas_price.and_then(to_i256));
+        gas.append_option(to_i256(tx.gas));
+        max_priority_fee_per_gas.append_option(tx.max_priority_fee_per_gas.and_then(to_i256));
+        max_fee_per_gas.append_option(tx.max_fee_per_gas.and_then(to_i256));
+        tx_type.append_option(tx.transaction_type.map(|t| t.as_u64() as u8));
+        input.append_value(&tx.input);
+    }
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(hash.finish()),
+        Arc::new(block_number.finish()),
+        Arc::new(block_hash.finish()),
+        Arc::new(block_timestamp.finish()),
+        Arc::new(transaction_index.finish()),
+        Arc::new(nonce.finish()),
+        Arc::new(from_address.finish()),
+        Arc::new(to_address.finish()),
+        Arc::new(value.finish()),
+        Arc::new(gas_price.finish()),
+        Arc::new(gas.finish()),
+        Arc::new(max_priority_fee_per_gas.finish()),
+        Arc::new(max_fee_per_gas.finish()),
+        Arc::new(tx_type.finish()),
+        Arc::new(input.finish()),
+    ];
+
+    Ok(RecordBatch::try_new(tx_schema(), columns)?)
+}
+
+/// Build a swaps batch, `timestamps` maps block numbers to block timestamps
+pub fn swaps_batch(swaps: &[SwapRecord], timestamps: &HashMap<u64, u64>) -> Result<RecordBatch> {
+    let mut tx_hash = FixedSizeBinaryBuilder::with_capacity(swaps.len(), 32);
+    let mut swap_index = UInt32Builder::with_capacity(swaps.len());
+    let mut block_number = UInt64Builder::with_capacity(swaps.len());
+    let mut block_hash = FixedSizeBinaryBuilder::with_capacity(swaps.len(), 32);
+    let mut block_timestamp = timestamp_builder(swaps.len());
+    let mut transaction_index = UInt64Builder::with_capacity(swaps.len());
+    let mut router = StringBuilder::new();
+    let mut protocol_version = UInt8Builder::with_capacity(swaps.len());
+    let mut method = StringBuilder::new();
+    let mut sender = FixedSizeBinaryBuilder::with_capacity(swaps.len(), 20);
+    let mut recipient = FixedSizeBinaryBuilder::with_capacity(swaps.len(), 20);
+    let mut path = ListBuilder::new(FixedSizeBinaryBuilder::new(20));
+    let mut fees = ListBuilder::new(UInt32Builder::new());
+    let mut kind = StringBuilder::new();
+    let mut token_in = FixedSizeBinaryBuilder::with_capacity(swaps.len(), 20);
+    let mut token_out = FixedSizeBinaryBuilder::with_capacity(swaps.len(), 20);
+    let mut amount_in = wei_builder(swaps.len())?;
+    let mut amount_out = wei_builder(swaps.len())?;
+    let mut deadline = timestamp_builder(swaps.len());
+
+    for swap in swaps {
+        let number = swap.block_number.unwrap_or_default();
+
+        tx_hash.append_value(swap.tx_hash)?;
+        swap_index.append_value(swap.swap_index);
+        block_number.append_value(number);
+        append_fixed(
+            &mut block_hash,
+            swap.block_hash.as_ref().map(|h| h.as_bytes()),
+        )?;
+        block_timestamp.append_option(timestamps.get(&number).map(|t| *t as i64));
+        transaction_index.append_option(swap.transaction_index);
+        router.append_value(&swap.router);
+        protocol_version.append_value(swap.protocol_version);
+        method.append_value(&swap.method);
+        sender.append_value(swap.sender)?;
+        append_fixed(
+            &mut recipient,
+            swap.recipient.as_ref().map(|a| a.as_bytes()),
+        )?;
+        for token in &swap.path {
+            path.values().append_value(token)?;
+        }
+        path.append(true);
+        fees.values().append_slice(&swap.fees);
+        fees.append(true);
+        kind.append_value(swap.kind.as_str());
+        append_fixed(
+            &mut token_in,
+            swap.token_in().as_ref().map(|a| a.as_bytes()),
+        )?;
+        append_fixed(
+            &mut token_out,
+            swap.token_out().as_ref().map(|a| a.as_bytes()),
+        )?;
+        amount_in.append_option(to_i256(swap.amount_in));
+        amount_out.append_option(to_i256(swap.amount_out));
+        deadline.append_option(swap.deadline.and_then(|d| i64::try_from(d).ok()));
+    }
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(tx_hash.finish()),
+        Arc::new(swap_index.finish()),
+        Arc::new(block_number.finish()),
+        Arc::new(block_hash.finish()),
+        Arc::new(block_timestamp.finish()),
+        Arc::new(transaction_index.finish()),
+        Arc::new(router.finish()),
+        Arc::new(protocol_version.finish()),
+        Arc::new(method.finish()),
+        Arc::new(sender.finish()),
+        Arc::new(recipient.finish()),
+        Arc::new(path.finish()),
+        Arc::new(fees.finish()),
+        Arc::new(kind.finish()),
+        Arc::new(token_in.finish()),
+        Arc::new(token_out.finish()),
+        Arc::new(amount_in.finish()),
+        Arc::new(amount_out.finish()),
+        Arc::new(deadline.finish()),
+    ];
+
+    Ok(RecordBatch::try_new(swap_schema(), columns)?)
+}
@@ -0,0 +1,50 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+const STATE_FILE: &str = "export_state.json";
+
+/// Progress of incremental exports, persisted next to the exported files
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ExportState {
+    /// Highest block number that has been fully exported
+    pub last_block: Option<u64>,
+}
+
+impl ExportState {
+    fn path(root: &Path) -> PathBuf {
+        root.join(STATE_FILE)
+    }
+
+    /// Load the export state, starting fresh if no export has run yet
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if an existing state file could not be read
+    pub fn load(root: &Path) -> Result<Self> {
+        let path = Self::path(root);
+        if !path.exists() {
+            debug!("No export state at {:?}, starting fresh", path);
+            return Ok(Self::default());
+        }
+
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    /// Persist the export state, replacing the previous file atomically
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the state file could not be written
+    pub fn save(&self, root: &Path) -> Result<()> {
+        let path = Self::path(root);
+        let tmp = path.with_extension("json.tmp");
+
+        std::fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(tmp, path)?;
+
+        Ok(())
+    }
+}
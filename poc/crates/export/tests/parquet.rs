@@ -0,0 +1,232 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use arrow_array::cast::AsArray;
+use arrow_array::types::{Decimal256Type, TimestampSecondType, UInt32Type, UInt64Type};
+use arrow_array::{RecordBatch, RecordBatchReader};
+use arrow_schema::SchemaRef;
+use dex::swap::{SwapKind, SwapRecord, SwapStatus};
+use ethers::types::{Address, Block, Bytes, Transaction, H256, U256, U64};
+use export::schema::{block_schema, swap_schema, to_i256, tx_schema};
+use export::{ExportRange, ParquetExporter};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use storage::block_storage::BlockStorage;
+use storage::memory::MemoryStorage;
+use storage::swap::SwapStorage;
+use storage::tx_storage::TxStorage;
+
+/// 2023-11-14 22:13:20 UTC
+const TIMESTAMP: u64 = 1_700_000_000;
+
+fn tx() -> Transaction {
+    Transaction {
+        hash: H256::repeat_byte(0x21),
+        nonce: U256::from(7),
+        block_hash: Some(H256::repeat_byte(0x01)),
+        block_number: Some(U64::from(1)),
+        transaction_index: Some(U64::zero()),
+        from: Address::repeat_byte(0xf0),
+        to: Some(Address::repeat_byte(0xa0)),
+        // Above 64 bits, wei amounts must survive as decimals
+        value: U256::exp10(20),
+        gas: U256::from(210_000),
+        gas_price: Some(U256::from(30_000_000_000u64)),
+        input: Bytes::from(vec![0x38, 0xed, 0x17, 0x39]),
+        ..Default::default()
+    }
+}
+
+fn block(number: u64, timestamp: u64, transactions: Vec<H256>) -> Block<H256> {
+    Block {
+        hash: Some(H256::repeat_byte(number as u8)),
+        number: Some(U64::from(number)),
+        parent_hash: H256::repeat_byte(number as u8 - 1),
+        timestamp: U256::from(timestamp),
+        gas_used: U256::from(21_000),
+        extra_data: Bytes::from(vec![0xbe, 0xef]),
+        transactions,
+        ..Default::default()
+    }
+}
+
+/// A two hop V3 swap made by `tx`
+fn swap() -> SwapRecord {
+    SwapRecord {
+        tx_hash: tx().hash,
+        swap_index: 0,
+        router: String::from("uniswap"),
+        protocol_version: 3,
+        method: String::from("exactInput"),
+        sender: Address::repeat_byte(0xf0),
+        recipient: None,
+        path: vec![
+            Address::repeat_byte(0x11),
+            Address::repeat_byte(0x12),
+            Address::repeat_byte(0x13),
+        ],
+        fees: vec![500, 3_000],
+        kind: SwapKind::ExactIn,
+        amount_in: U256::exp10(20),
+        amount_out: U256::from(900),
+        deadline: None,
+        status: SwapStatus::Mined,
+        block_number: tx().block_number.map(|n| n.as_u64()),
+        block_hash: tx().block_hash,
+        transaction_index: Some(0),
+        score: None,
+        amounts: None,
+    }
+}
+
+/// Read a whole parquet file back, checking it has the exported schema
+fn read(path: &Path, schema: SchemaRef) -> RecordBatch {
+    let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap())
+        .unwrap()
+        .build()
+        .unwrap();
+    assert_eq!(reader.schema(), schema);
+
+    let batches = reader.collect::<Result<Vec<RecordBatch>, _>>().unwrap();
+    assert_eq!(batches.len(), 1);
+
+    batches.into_iter().next().unwrap()
+}
+
+#[tokio::test]
+async fn exported_files_read_back_through_arrow() {
+    let dir = std::env::temp_dir().join(format!("export-parquet-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let mut storage = MemoryStorage::new();
+    BlockStorage::store(&mut storage, block(1, TIMESTAMP, vec![tx().hash]))
+        .await
+        .unwrap();
+    // The next block is mined a day later, into another partition
+    BlockStorage::store(&mut storage, block(2, TIMESTAMP + 86_400, Vec::new()))
+        .await
+        .unwrap();
+    TxStorage::store(&mut storage, tx()).await.unwrap();
+    storage.store_swap(&swap()).await.unwrap();
+
+    let exporter = ParquetExporter::new(dir.to_string_lossy().into_owned(), None).unwrap();
+    let summary = exporter
+        .export(&mut storage, ExportRange::Blocks { from: 1, to: 2 })
+        .await
+        .unwrap();
+
+    assert_eq!((summary.blocks, summary.txs, summary.swaps), (2, 1, 1));
+    assert_eq!(
+        summary.files,
+        [
+            "blocks/date=2023-11-14/blocks_1_1.parquet",
+            "transactions/date=2023-11-14/transactions_1_1.parquet",
+            "swaps/date=2023-11-14/swaps_1_1.parquet",
+            "blocks/date=2023-11-15/blocks_2_2.parquet",
+        ]
+        .iter()
+        .map(|file| dir.join(file))
+        .collect::<Vec<PathBuf>>()
+    );
+
+    let blocks = read(&summary.files[0], block_schema());
+    assert_eq!(blocks.num_rows(), 1);
+    assert_eq!(blocks["number"].as_primitive::<UInt64Type>().value(0), 1);
+    assert_eq!(
+        blocks["hash"].as_fixed_size_binary().value(0),
+        H256::repeat_byte(0x01).as_bytes()
+    );
+    assert_eq!(
+        blocks["timestamp"]
+            .as_primitive::<TimestampSecondType>()
+            .value(0),
+        TIMESTAMP as i64
+    );
+    assert_eq!(
+        blocks["gas_used"].as_primitive::<Decimal256Type>().value(0),
+        to_i256(U256::from(21_000)).unwrap()
+    );
+    assert!(blocks["base_fee_per_gas"].is_null(0));
+    assert_eq!(
+        blocks["extra_data"].as_binary::<i32>().value(0),
+        [0xbe, 0xef]
+    );
+    let transactions = blocks["transactions"].as_list::<i32>().value(0);
+    assert_eq!(
+        transactions.as_fixed_size_binary().value(0),
+        tx().hash.as_bytes()
+    );
+
+    let txs = read(&summary.files[1], tx_schema());
+    assert_eq!(txs.num_rows(), 1);
+    assert_eq!(
+        txs["hash"].as_fixed_size_binary().value(0),
+        tx().hash.as_bytes()
+    );
+    assert_eq!(
+        txs["block_timestamp"]
+            .as_primitive::<TimestampSecondType>()
+            .value(0),
+        TIMESTAMP as i64
+    );
+    assert_eq!(
+        txs["to_address"].as_fixed_size_binary().value(0),
+        Address::repeat_byte(0xa0).as_bytes()
+    );
+    assert_eq!(
+        txs["value"].as_primitive::<Decimal256Type>().value(0),
+        to_i256(U256::exp10(20)).unwrap()
+    );
+    assert!(txs["max_fee_per_gas"].is_null(0));
+    assert_eq!(
+        txs["input"].as_binary::<i32>().value(0),
+        tx().input.as_ref()
+    );
+
+    let swaps = read(&summary.files[2], swap_schema());
+    assert_eq!(swaps.num_rows(), 1);
+    assert_eq!(
+        swaps["tx_hash"].as_fixed_size_binary().value(0),
+        tx().hash.as_bytes()
+    );
+    assert_eq!(
+        swaps["block_number"].as_primitive::<UInt64Type>().value(0),
+        1
+    );
+    assert_eq!(
+        swaps["block_timestamp"]
+            .as_primitive::<TimestampSecondType>()
+            .value(0),
+        TIMESTAMP as i64
+    );
+    assert_eq!(swaps["kind"].as_string::<i32>().value(0), "exact_in");
+    let path = swaps["path"].as_list::<i32>().value(0);
+    assert_eq!(path.len(), 3);
+    assert_eq!(
+        path.as_fixed_size_binary().value(2),
+        Address::repeat_byte(0x13).as_bytes()
+    );
+    assert_eq!(
+        swaps["fees"]
+            .as_list::<i32>()
+            .value(0)
+            .as_primitive::<UInt32Type>()
+            .values(),
+        &[500, 3_000]
+    );
+    assert_eq!(
+        swaps["token_out"].as_fixed_size_binary().value(0),
+        Address::repeat_byte(0x13).as_bytes()
+    );
+    assert_eq!(
+        swaps["amount_in"].as_primitive::<Decimal256Type>().value(0),
+        to_i256(U256::exp10(20)).unwrap()
+    );
+    assert!(swaps["recipient"].is_null(0));
+    assert!(swaps["deadline"].is_null(0));
+
+    let blocks = read(&summary.files[3], block_schema());
+    assert_eq!(blocks["number"].as_primitive::<UInt64Type>().value(0), 2);
+    assert!(blocks["transactions"].as_list::<i32>().value(0).is_empty());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
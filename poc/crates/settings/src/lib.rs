@@ -1,8 +1,14 @@
-use anyhow::Result;
+use std::fmt;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
 use config::{Config, Environment, File};
 use serde::Deserialize;
 
+use dex::protocol::ProtocolSettings;
+use dex::registry::TokenInfo;
 use dex::router::RouterSettings;
+use dex::token::TokenMetadata;
 
 #[derive(Debug, Deserialize, Clone)]
 #[allow(unused)]
@@ -18,13 +24,54 @@ pub struct BlockExplorer {
     pub api_key: String,
 }
 
+/// A token configured by hand
+///
+/// Fields left out are resolved from the token contract.
 #[derive(Debug, Deserialize, Clone)]
 #[allow(unused)]
 pub struct Token {
     pub address: String,
-    pub name: String,
-    pub symbol: String,
-    pub decimals: u8,
+    pub name: Option<String>,
+    pub symbol: Option<String>,
+    pub decimals: Option<u8>,
+}
+
+impl Token {
+    /// Whether every field is configured, so the token doesn't need to be resolved
+    pub fn is_complete(&self) -> bool {
+        self.name.is_some() && self.symbol.is_some() && self.decimals.is_some()
+    }
+
+    /// The token as registered in the dex registry, the configured fields taking precedence
+    /// over the `resolved` ones
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the address is invalid or the decimals are
+    /// neither configured nor resolved
+    pub fn token_info(&self, resolved: Option<&TokenMetadata>) -> Result<TokenInfo> {
+        let address = self.address.parse()?;
+        let text = |field: fn(&TokenMetadata) -> Option<String>| {
+            resolved.and_then(field).unwrap_or_default()
+        };
+
+        Ok(TokenInfo {
+            address,
+            name: self
+                .name
+                .clone()
+                .unwrap_or_else(|| text(|token| token.name.clone())),
+            symbol: self
+                .symbol
+                .clone()
+                .unwrap_or_else(|| text(|token| token.symbol.clone())),
+            decimals: self
+                .decimals
+                .or_else(|| resolved.and_then(|token| token.decimals))
+                .ok_or_else(|| anyhow!("Token {} has no decimals", self.address))?,
+            first_seen_block: None,
+        })
+    }
 }
 
 // #[derive(Debug, Deserialize, Clone)]
@@ -41,6 +88,17 @@ pub struct Token {
 pub struct Dex {
     pub tokens: Vec<Token>,
     pub routers: Vec<RouterSettings>,
+    /// Protocols that don't fit the router and factory of `routers`, e.g. Balancer and Curve
+    pub protocols: Option<Vec<ProtocolSettings>>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+#[allow(unused)]
+pub struct Registry {
+    /// Block to backfill the pairs of the router factories from, no backfill if unset
+    pub backfill_from: Option<u64>,
+    /// Blocks per `eth_getLogs` request while backfilling
+    pub log_chunk_size: Option<u64>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -51,6 +109,13 @@ pub struct Nats {
     pub queue_group: Option<String>,
 }
 
+impl Nats {
+    /// Subject `name` under the configured prefix, e.g. `eth_sniper.swaps`
+    pub fn subject(&self, name: &str) -> String {
+        format!("{}.{}", self.subject_prefix, name)
+    }
+}
+
 #[derive(Debug, Deserialize, Clone)]
 #[allow(unused)]
 pub struct Scylla {
@@ -60,6 +125,93 @@ pub struct Scylla {
     pub password: Option<String>,
 }
 
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    #[default]
+    Scylla,
+    Postgres,
+    Sqlite,
+    /// Kept in process memory and lost on exit, for tests and single-process dev mode
+    Memory,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[allow(unused)]
+pub struct Storage {
+    pub backend: StorageBackend,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[allow(unused)]
+pub struct Postgres {
+    pub url: String,
+    pub schema: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[allow(unused)]
+pub struct Sqlite {
+    pub path: String,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+#[allow(unused)]
+pub struct Writer {
+    /// Number of concurrent writers, each with its own storage connection
+    pub workers: Option<usize>,
+    /// Txs queued per worker before the writer stops reading new ones
+    pub queue_size: Option<usize>,
+    /// Retries of a failed write before it is dead lettered
+    pub max_retries: Option<u32>,
+    /// Delay before the first retry in milliseconds, doubled on every further retry
+    pub backoff_ms: Option<u64>,
+    pub max_backoff_ms: Option<u64>,
+    /// JSON lines file that receives the txs that could not be written
+    pub dead_letter_path: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheBackend {
+    #[default]
+    Redis,
+    /// Kept in process memory and lost on exit, for tests and single-process dev mode
+    Memory,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[allow(unused)]
+pub struct Cache {
+    pub backend: CacheBackend,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+#[allow(unused)]
+pub struct Tokens {
+    /// Seconds after which the metadata of a token is read again from its contract
+    pub refresh_after: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+#[allow(unused)]
+pub struct Honeypot {
+    /// Name of the V2 router of `dex.routers` the round trip goes through
+    pub router: Option<String>,
+    /// ETH spent on the simulated buy
+    pub buy_amount: Option<f64>,
+    /// Times the state missed by a simulation is fetched before giving up
+    pub max_rounds: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+#[allow(unused)]
+pub struct Launch {
+    /// Contracts LP tokens are locked in, e.g. Unicrypt, adds sending their LP tokens to one
+    /// are reported as locked
+    pub lockers: Option<Vec<String>>,
+}
+
 #[derive(Debug, Deserialize, Clone)]
 #[allow(unused)]
 pub struct Redis {
@@ -71,6 +223,99 @@ pub struct Redis {
     pub password: Option<String>,
 }
 
+#[derive(Debug, Deserialize, Clone)]
+#[allow(unused)]
+pub struct Export {
+    pub path: String,
+    pub blocks_per_file: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[allow(unused)]
+pub struct Mempool {
+    /// Seconds after which a pending tx that was neither mined nor replaced counts as dropped
+    pub drop_after: Option<u64>,
+}
+
+/// Kinds of data with their own retention policy
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum DataClass {
+    /// Transactions seen in the mempool that were never mined
+    PendingTxs,
+    MinedTxs,
+    Traces,
+    DecodedEvents,
+    /// Contract ABIs cached from the block explorer
+    AbiCache,
+}
+
+impl DataClass {
+    pub const ALL: [DataClass; 5] = [
+        DataClass::PendingTxs,
+        DataClass::MinedTxs,
+        DataClass::Traces,
+        DataClass::DecodedEvents,
+        DataClass::AbiCache,
+    ];
+}
+
+impl fmt::Display for DataClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::PendingTxs => "pending_txs",
+            Self::MinedTxs => "mined_txs",
+            Self::Traces => "traces",
+            Self::DecodedEvents => "decoded_events",
+            Self::AbiCache => "abi_cache",
+        })
+    }
+}
+
+/// How long to keep each class of data, in seconds
+///
+/// Classes left unset are kept forever.
+#[derive(Debug, Deserialize, Clone)]
+#[allow(unused)]
+pub struct Retention {
+    pub pending_txs: Option<u64>,
+    pub mined_txs: Option<u64>,
+    /// Placeholder, no backend stores traces yet
+    pub traces: Option<u64>,
+    pub decoded_events: Option<u64>,
+    pub abi_cache: Option<u64>,
+    /// Seconds between scheduled cleanups of the backends without native TTLs
+    pub cleanup_interval: Option<u64>,
+}
+
+impl Default for Retention {
+    /// Keep pending txs for a day and ABIs for an hour, everything else forever
+    fn default() -> Self {
+        Self {
+            pending_txs: Some(60 * 60 * 24),
+            mined_txs: None,
+            traces: None,
+            decoded_events: None,
+            abi_cache: Some(60 * 60),
+            cleanup_interval: None,
+        }
+    }
+}
+
+impl Retention {
+    /// How long to keep `class`, `None` to keep it forever
+    pub fn ttl(&self, class: DataClass) -> Option<Duration> {
+        match class {
+            DataClass::PendingTxs => self.pending_txs,
+            DataClass::MinedTxs => self.mined_txs,
+            DataClass::Traces => self.traces,
+            DataClass::DecodedEvents => self.decoded_events,
+            DataClass::AbiCache => self.abi_cache,
+        }
+        .map(Duration::from_secs)
+    }
+}
+
 #[derive(Debug, Deserialize, Clone)]
 #[allow(unused)]
 pub struct Log {
@@ -83,10 +328,22 @@ pub struct Settings {
     pub ethereum: Ethereum,
     pub block_explorer: BlockExplorer,
     pub dex: Dex,
+    pub registry: Option<Registry>,
     pub nats: Nats,
+    pub storage: Option<Storage>,
     pub scylla: Scylla,
+    pub postgres: Option<Postgres>,
+    pub sqlite: Option<Sqlite>,
+    pub writer: Option<Writer>,
 
+    pub cache: Option<Cache>,
+    pub tokens: Option<Tokens>,
+    pub honeypot: Option<Honeypot>,
+    pub launch: Option<Launch>,
     pub redis: Redis,
+    pub export: Option<Export>,
+    pub mempool: Option<Mempool>,
+    pub retention: Option<Retention>,
     pub log: Log,
 }
 
@@ -99,4 +356,25 @@ impl Settings {
 
         Ok(s.try_deserialize()?)
     }
+
+    /// The configured storage backend, defaulting to Scylla
+    pub fn storage_backend(&self) -> StorageBackend {
+        self.storage
+            .as_ref()
+            .map(|storage| storage.backend)
+            .unwrap_or_default()
+    }
+
+    /// The configured tx cache backend, defaulting to Redis
+    pub fn cache_backend(&self) -> CacheBackend {
+        self.cache
+            .as_ref()
+            .map(|cache| cache.backend)
+            .unwrap_or_default()
+    }
+
+    /// The configured retention policies, or the defaults if there is no `[retention]` section
+    pub fn retention(&self) -> Retention {
+        self.retention.clone().unwrap_or_default()
+    }
 }
@@ -0,0 +1,297 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::Infallible;
+
+use anyhow::{anyhow, Result};
+use ethers::providers::Middleware;
+use ethers::types::{Address, BigEndianHash, BlockId, Bytes, H256, U256};
+use ethers::utils::keccak256;
+use log::debug;
+use revm::primitives::{self, AccountInfo, Bytecode, B256, KECCAK_EMPTY};
+use revm::DatabaseRef;
+use serde::{Deserialize, Serialize};
+
+pub(crate) fn to_revm_address(address: Address) -> primitives::Address {
+    primitives::Address::from(address.0)
+}
+
+pub(crate) fn from_revm_address(address: primitives::Address) -> Address {
+    Address::from(address.0 .0)
+}
+
+pub(crate) fn to_revm_u256(value: U256) -> primitives::U256 {
+    primitives::U256::from_limbs(value.0)
+}
+
+pub(crate) fn from_revm_u256(value: primitives::U256) -> U256 {
+    U256(value.into_limbs())
+}
+
+/// Header fields of the block a snapshot was read at
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockSnapshot {
+    pub number: u64,
+    pub timestamp: u64,
+    pub base_fee: U256,
+    pub gas_limit: U256,
+    pub coinbase: Address,
+    /// `mixHash` of the block, the `PREVRANDAO` since the merge
+    pub prevrandao: H256,
+    pub chain_id: u64,
+}
+
+/// An account as read from the node
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountSnapshot {
+    pub balance: U256,
+    pub nonce: u64,
+    pub code: Bytes,
+    /// Storage slots read so far, the others are unknown rather than empty
+    pub storage: BTreeMap<U256, U256>,
+}
+
+impl AccountSnapshot {
+    fn info(&self) -> AccountInfo {
+        if self.code.is_empty() {
+            return AccountInfo::new(
+                to_revm_u256(self.balance),
+                self.nonce,
+                KECCAK_EMPTY,
+                Bytecode::new(),
+            );
+        }
+
+        AccountInfo::new(
+            to_revm_u256(self.balance),
+            self.nonce,
+            B256::from(keccak256(&self.code)),
+            Bytecode::new_raw(self.code.to_vec().into()),
+        )
+    }
+}
+
+/// State a simulation needed that a snapshot doesn't have
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Misses {
+    pub accounts: BTreeSet<Address>,
+    pub slots: BTreeSet<(Address, U256)>,
+    pub block_hashes: BTreeSet<u64>,
+}
+
+impl Misses {
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty() && self.slots.is_empty() && self.block_hashes.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.accounts.len() + self.slots.len() + self.block_hashes.len()
+    }
+}
+
+/// The part of the chain state a simulation reads, forked at a block
+///
+/// Snapshots start empty and are filled with what simulations turn out to need, see
+/// `StateSnapshot::fetch`. Once complete for a simulation they replay it offline, which is how
+/// they are recorded as test fixtures.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateSnapshot {
+    pub block: BlockSnapshot,
+    pub accounts: BTreeMap<Address, AccountSnapshot>,
+    pub block_hashes: BTreeMap<u64, H256>,
+}
+
+impl StateSnapshot {
+    pub fn new(block: BlockSnapshot) -> Self {
+        Self {
+            block,
+            accounts: BTreeMap::new(),
+            block_hashes: BTreeMap::new(),
+        }
+    }
+
+    /// An empty snapshot of the state at `block`, or at the latest block
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the block could not be read
+    pub async fn at_block<M: Middleware>(provider: &M, block: Option<u64>) -> Result<Self> {
+        let block_id = match block {
+            Some(number) => BlockId::from(number),
+            None => BlockId::from(
+                provider
+                    .get_block_number()
+                    .await
+                    .map_err(|e| anyhow!("{}", e))?,
+            ),
+        };
+        let header = provider
+            .get_block(block_id)
+            .await
+            .map_err(|e| anyhow!("Failed to get block {:?}: {}", block_id, e))?
+            .ok_or_else(|| anyhow!("Block {:?} not found", block_id))?;
+        let chain_id = provider
+            .get_chainid()
+            .await
+            .map_err(|e| anyhow!("Failed to get the chain id: {}", e))?;
+
+        Ok(Self::new(BlockSnapshot {
+            number: header
+                .number
+                .ok_or_else(|| anyhow!("Block {:?} is pending", block_id))?
+                .as_u64(),
+            timestamp: header.timestamp.as_u64(),
+            base_fee: header.base_fee_per_gas.unwrap_or_default(),
+            gas_limit: header.gas_limit,
+            coinbase: header.author.unwrap_or_default(),
+            prevrandao: header.mix_hash.unwrap_or_default(),
+            chain_id: chain_id.as_u64(),
+        }))
+    }
+
+    /// Read what a simulation missed from the node, at the block of the snapshot
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the state could not be read
+    pub async fn fetch<M: Middleware>(&mut self, provider: &M, misses: &Misses) -> Result<()> {
+        let block = Some(BlockId::from(self.block.number));
+
+        for address in &misses.accounts {
+            let balance = provider.get_balance(*address, block).await;
+            let nonce = provider.get_transaction_count(*address, block).await;
+            let code = provider.get_code(*address, block).await;
+            let (balance, nonce, code) = match (balance, nonce, code) {
+                (Ok(balance), Ok(nonce), Ok(code)) => (balance, nonce, code),
+                (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+                    return Err(anyhow!("Failed to read account {:?}: {}", address, e))
+                }
+            };
+
+            let account = self.accounts.entry(*address).or_default();
+            account.balance = balance;
+            account.nonce = nonce.as_u64();
+            account.code = code;
+        }
+        for (address, slot) in &misses.slots {
+            let value = provider
+                .get_storage_at(*address, H256::from_uint(slot), block)
+                .await
+                .map_err(|e| anyhow!("Failed to read slot {} of {:?}: {}", slot, address, e))?;
+
+            self.accounts
+                .entry(*address)
+                .or_default()
+                .storage
+                .insert(*slot, value.into_uint());
+        }
+        for number in &misses.block_hashes {
+            let hash = provider
+                .get_block(*number)
+                .await
+                .map_err(|e| anyhow!("Failed to get block {}: {}", number, e))?
+                .and_then(|block| block.hash)
+                .unwrap_or_default();
+
+            self.block_hashes.insert(*number, hash);
+        }
+
+        debug!(
+            "Fetched {} accounts, {} slots and {} block hashes at block {}",
+            misses.accounts.len(),
+            misses.slots.len(),
+            misses.block_hashes.len(),
+            self.block.number
+        );
+
+        Ok(())
+    }
+}
+
+/// A snapshot as the database of the EVM, recording what it doesn't have
+///
+/// Missing accounts don't exist and missing slots are empty for the EVM, so a simulation with
+/// misses runs to the end and reports all of them at once.
+pub(crate) struct SnapshotDb<'a> {
+    snapshot: &'a StateSnapshot,
+    misses: RefCell<Misses>,
+}
+
+impl<'a> SnapshotDb<'a> {
+    pub(crate) fn new(snapshot: &'a StateSnapshot) -> Self {
+        Self {
+            snapshot,
+            misses: RefCell::default(),
+        }
+    }
+
+    pub(crate) fn into_misses(self) -> Misses {
+        self.misses.into_inner()
+    }
+}
+
+impl DatabaseRef for SnapshotDb<'_> {
+    type Error = Infallible;
+
+    fn basic_ref(&self, address: primitives::Address) -> Result<Option<AccountInfo>, Infallible> {
+        let address = from_revm_address(address);
+
+        match self.snapshot.accounts.get(&address) {
+            Some(account) => Ok(Some(account.info())),
+            None => {
+                self.misses.borrow_mut().accounts.insert(address);
+                Ok(None)
+            }
+        }
+    }
+
+    fn code_by_hash_ref(&self, code_hash: B256) -> Result<Bytecode, Infallible> {
+        // Accounts come with their code, so this is only reached for unknown hashes
+        Ok(self
+            .snapshot
+            .accounts
+            .values()
+            .map(AccountSnapshot::info)
+            .find(|info| info.code_hash == code_hash)
+            .and_then(|info| info.code)
+            .unwrap_or_default())
+    }
+
+    fn storage_ref(
+        &self,
+        address: primitives::Address,
+        index: primitives::U256,
+    ) -> Result<primitives::U256, Infallible> {
+        let address = from_revm_address(address);
+        let slot = from_revm_u256(index);
+
+        let value = self
+            .snapshot
+            .accounts
+            .get(&address)
+            .and_then(|account| account.storage.get(&slot));
+        match value {
+            Some(value) => Ok(to_revm_u256(*value)),
+            None => {
+                let mut misses = self.misses.borrow_mut();
+                if !self.snapshot.accounts.contains_key(&address) {
+                    misses.accounts.insert(address);
+                }
+                misses.slots.insert((address, slot));
+
+                Ok(primitives::U256::ZERO)
+            }
+        }
+    }
+
+    fn block_hash_ref(&self, number: primitives::U256) -> Result<B256, Infallible> {
+        let number = from_revm_u256(number).low_u64();
+
+        match self.snapshot.block_hashes.get(&number) {
+            Some(hash) => Ok(B256::from(hash.0)),
+            None => {
+                self.misses.borrow_mut().block_hashes.insert(number);
+                Ok(B256::ZERO)
+            }
+        }
+    }
+}
@@ -0,0 +1,563 @@
+use std::fmt;
+
+use anyhow::{anyhow, bail, Result};
+use dex::bindings::uniswap_v2_router_02::{
+    GetAmountsOutCall, GetAmountsOutReturn, SwapExactETHForTokensSupportingFeeOnTransferTokensCall,
+    SwapExactTokensForETHSupportingFeeOnTransferTokensCall, WethCall, WethReturn,
+};
+use dex::candle::to_units;
+use ethers::abi::{self, AbiDecode, AbiEncode, ParamType, Token};
+use ethers::providers::Middleware;
+use ethers::types::{Address, Bytes, H160, U256};
+use ethers::utils::{id, WEI_IN_ETHER};
+use log::debug;
+use revm::db::CacheDB;
+use revm::primitives::{
+    self, AccountInfo, ExecutionResult, Output, SpecId, TransactTo, B256, KECCAK_EMPTY,
+};
+use revm::{Database, Evm};
+use serde::{Deserialize, Serialize};
+
+use crate::fork::{
+    from_revm_u256, to_revm_address, to_revm_u256, Misses, SnapshotDb, StateSnapshot,
+};
+
+/// Account the round trip is made from, an EOA no token has a reason to treat specially
+pub const TRADER: Address = H160([
+    0x71, 0xc7, 0x65, 0x6e, 0xc7, 0xab, 0x88, 0xb0, 0x98, 0xde, 0xfb, 0x75, 0x1b, 0x74, 0x01, 0xb5,
+    0xf6, 0xd8, 0x97, 0x6f,
+]);
+
+/// Gas limit of each simulated tx, taxed tokens swapping their fees on sells need a lot
+const GAS_LIMIT: u64 = 5_000_000;
+/// Seconds between the snapshot block and the simulated one
+const BLOCK_TIME: u64 = 12;
+
+/// Getters of the max tx amount across the common token templates
+const MAX_TX_GETTERS: [&str; 4] = [
+    "_maxTxAmount()",
+    "maxTxAmount()",
+    "maxTransactionAmount()",
+    "_maxTransactionAmount()",
+];
+/// Getters of the max wallet balance across the common token templates
+const MAX_WALLET_GETTERS: [&str; 6] = [
+    "_maxWalletSize()",
+    "maxWalletSize()",
+    "_maxWalletToken()",
+    "maxWallet()",
+    "_maxWalletAmount()",
+    "maxWalletAmount()",
+];
+
+/// Outcome of the buy or the sell of a round trip
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TradeOutcome {
+    /// Amount out quoted by the router before the trade, tokens for a buy and ETH for a sell
+    pub expected: U256,
+    pub received: U256,
+    /// Share of the quoted amount that wasn't received, from 0 to 1
+    pub tax: f64,
+    pub gas_used: u64,
+    pub reverted: bool,
+    pub revert_reason: Option<String>,
+}
+
+impl TradeOutcome {
+    fn new(expected: U256, received: U256, execution: Execution) -> Self {
+        let tax = if expected.is_zero() || execution.revert_reason.is_some() {
+            0.0
+        } else {
+            (1.0 - to_units(received, 0) / to_units(expected, 0)).clamp(0.0, 1.0)
+        };
+
+        Self {
+            expected,
+            received,
+            tax,
+            gas_used: execution.gas_used,
+            reverted: execution.revert_reason.is_some(),
+            revert_reason: execution.revert_reason,
+        }
+    }
+}
+
+/// Result of buying a token with ETH and selling it right back through a V2 router
+///
+/// Taxes are measured against the router quotes, so the fee of the pair isn't counted. Tokens
+/// that swap their collected fees during sells move the price before the sell fills, which
+/// shows up in the sell tax.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HoneypotReport {
+    pub token: Address,
+    pub router: Address,
+    pub weth: Address,
+    /// Block the state was forked at, the trades are simulated in the next one
+    pub block: u64,
+    /// ETH spent on the buy
+    pub amount_in: U256,
+    /// Max tokens per tx, if the token exposes it
+    pub max_tx: Option<U256>,
+    /// Max tokens per wallet, if the token exposes it
+    pub max_wallet: Option<U256>,
+    pub buy: TradeOutcome,
+    /// `None` if the buy reverted
+    pub sell: Option<TradeOutcome>,
+}
+
+impl HoneypotReport {
+    /// Whether the token can be bought but not sold back
+    ///
+    /// A token whose buy reverts isn't one, trading may just not be open yet.
+    pub fn is_honeypot(&self) -> bool {
+        !self.buy.reverted
+            && self
+                .sell
+                .as_ref()
+                .is_none_or(|sell| sell.reverted || sell.received.is_zero())
+    }
+
+    /// Whether the buy or the sell is taxed `max_tax` or more, from 0 to 1
+    pub fn exceeds_tax(&self, max_tax: f64) -> bool {
+        self.buy.tax >= max_tax || self.sell.as_ref().is_some_and(|sell| sell.tax >= max_tax)
+    }
+}
+
+impl fmt::Display for HoneypotReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} at block {}: ", self.token, self.block)?;
+        if let Some(reason) = &self.buy.revert_reason {
+            return write!(f, "buy reverted ({})", reason);
+        }
+        write!(f, "buy tax {:.2}%", self.buy.tax * 100.0)?;
+        match &self.sell {
+            Some(TradeOutcome {
+                revert_reason: Some(reason),
+                ..
+            }) => write!(f, ", sell reverted ({})", reason)?,
+            Some(sell) => write!(f, ", sell tax {:.2}%", sell.tax * 100.0)?,
+            None => {}
+        }
+        if let Some(max_tx) = self.max_tx {
+            write!(f, ", max tx {}", max_tx)?;
+        }
+        if let Some(max_wallet) = self.max_wallet {
+            write!(f, ", max wallet {}", max_wallet)?;
+        }
+        if self.is_honeypot() {
+            write!(f, ", HONEYPOT")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Misses {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} accounts {:?}, {} storage slots and {} block hashes",
+            self.accounts.len(),
+            self.accounts,
+            self.slots.len(),
+            self.block_hashes.len()
+        )
+    }
+}
+
+/// A simulated tx that ran to the end
+struct Execution {
+    output: Vec<u8>,
+    gas_used: u64,
+    /// `Some` if the tx reverted or halted
+    revert_reason: Option<String>,
+}
+
+impl From<ExecutionResult> for Execution {
+    fn from(result: ExecutionResult) -> Self {
+        match result {
+            ExecutionResult::Success {
+                gas_used, output, ..
+            } => {
+                let output = match output {
+                    Output::Call(output) | Output::Create(output, _) => output.to_vec(),
+                };
+                Self {
+                    output,
+                    gas_used,
+                    revert_reason: None,
+                }
+            }
+            ExecutionResult::Revert { gas_used, output } => Self {
+                revert_reason: Some(revert_reason(&output)),
+                output: output.to_vec(),
+                gas_used,
+            },
+            ExecutionResult::Halt { reason, gas_used } => Self {
+                output: Vec::new(),
+                gas_used,
+                revert_reason: Some(format!("halted: {:?}", reason)),
+            },
+        }
+    }
+}
+
+/// Reason of a revert from its output, `Error(string)` and `Panic(uint256)` being decoded
+pub fn revert_reason(output: &[u8]) -> String {
+    if output.len() >= 4 {
+        let (selector, data) = output.split_at(4);
+        let decoded = |param: ParamType| abi::decode(&[param], data).ok()?.pop();
+
+        if selector == id("Error(string)") {
+            if let Some(reason) = decoded(ParamType::String).and_then(Token::into_string) {
+                return reason;
+            }
+        } else if selector == id("Panic(uint256)") {
+            if let Some(code) = decoded(ParamType::Uint(256)).and_then(Token::into_uint) {
+                return format!("panic {:#x}", code);
+            }
+        }
+    }
+
+    if output.is_empty() {
+        String::from("no reason")
+    } else {
+        Bytes::from(output.to_vec()).to_string()
+    }
+}
+
+/// An EVM over a snapshot, the state changes of each tx being kept for the next ones
+struct Fork<'a> {
+    evm: Evm<'a, (), CacheDB<SnapshotDb<'a>>>,
+}
+
+impl<'a> Fork<'a> {
+    fn new(snapshot: &'a StateSnapshot, balance: U256) -> Self {
+        let block = &snapshot.block;
+        let mut db = CacheDB::new(SnapshotDb::new(snapshot));
+        db.insert_account_info(
+            to_revm_address(TRADER),
+            AccountInfo::new(to_revm_u256(balance), 0, KECCAK_EMPTY, Default::default()),
+        );
+
+        let evm = Evm::builder()
+            .with_db(db)
+            .with_spec_id(SpecId::CANCUN)
+            .modify_cfg_env(|cfg| cfg.chain_id = block.chain_id)
+            .modify_block_env(|env| {
+                env.number = primitives::U256::from(block.number + 1);
+                env.timestamp = primitives::U256::from(block.timestamp + BLOCK_TIME);
+                env.coinbase = to_revm_address(block.coinbase);
+                env.gas_limit = to_revm_u256(block.gas_limit.max(U256::from(GAS_LIMIT)));
+                // Gas is free so ETH balances only change with the trades
+                env.basefee = primitives::U256::ZERO;
+                env.prevrandao = Some(B256::from(block.prevrandao.0));
+                env.set_blob_excess_gas_and_price(0);
+            })
+            .modify_tx_env(|tx| {
+                tx.caller = to_revm_address(TRADER);
+                tx.gas_limit = GAS_LIMIT;
+                tx.gas_price = primitives::U256::ZERO;
+                tx.chain_id = Some(block.chain_id);
+                tx.nonce = None;
+            })
+            .build();
+
+        Self { evm }
+    }
+
+    /// Run a tx from the trader, keeping its state changes if `commit`
+    fn execute(&mut self, to: Address, data: Vec<u8>, value: U256, commit: bool) -> Execution {
+        let tx = self.evm.tx_mut();
+        tx.transact_to = TransactTo::Call(to_revm_address(to));
+        tx.data = data.into();
+        tx.value = to_revm_u256(value);
+
+        let result = if commit {
+            self.evm.transact_commit()
+        } else {
+            self.evm.transact().map(|state| state.result)
+        };
+        match result {
+            Ok(result) => result.into(),
+            // The database can't fail and the tx env is valid, only the trader balance could
+            // be short
+            Err(e) => Execution {
+                output: Vec::new(),
+                gas_used: 0,
+                revert_reason: Some(format!("invalid tx: {:?}", e)),
+            },
+        }
+    }
+
+    /// Output of a view call, `None` if it reverted
+    fn view(&mut self, to: Address, data: Vec<u8>) -> Option<Vec<u8>> {
+        let execution = self.execute(to, data, U256::zero(), false);
+
+        execution
+            .revert_reason
+            .is_none()
+            .then_some(execution.output)
+    }
+
+    fn view_uint(&mut self, to: Address, data: Vec<u8>) -> Option<U256> {
+        let output = self.view(to, data)?;
+
+        abi::decode(&[ParamType::Uint(256)], &output)
+            .ok()?
+            .pop()?
+            .into_uint()
+    }
+
+    /// First of `getters` of `token` that returns a non-zero uint
+    fn first_uint(&mut self, token: Address, getters: &[&str]) -> Option<U256> {
+        getters.iter().find_map(|getter| {
+            self.view_uint(token, id(getter).to_vec())
+                .filter(|value| !value.is_zero())
+        })
+    }
+
+    fn amount_out(&mut self, router: Address, amount_in: U256, path: Vec<Address>) -> U256 {
+        self.view(router, GetAmountsOutCall { amount_in, path }.encode())
+            .and_then(|output| GetAmountsOutReturn::decode(output).ok())
+            .and_then(|amounts| amounts.amounts.last().copied())
+            .unwrap_or_default()
+    }
+
+    fn token_balance(&mut self, token: Address) -> U256 {
+        let mut data = id("balanceOf(address)").to_vec();
+        data.extend(abi::encode(&[Token::Address(TRADER)]));
+
+        self.view_uint(token, data).unwrap_or_default()
+    }
+
+    fn eth_balance(&mut self) -> U256 {
+        match self.evm.db_mut().basic(to_revm_address(TRADER)) {
+            Ok(Some(account)) => from_revm_u256(account.balance),
+            _ => U256::zero(),
+        }
+    }
+
+    fn into_misses(self) -> Misses {
+        let (db, _) = self.evm.into_db_and_env_with_handler_cfg();
+
+        db.db.into_misses()
+    }
+}
+
+/// Buy `token` with `amount_in` ETH and sell it all back through `router`
+///
+/// The state missed by the simulation is returned with its result, which is only meaningful
+/// without misses.
+fn round_trip(
+    snapshot: &StateSnapshot,
+    router: Address,
+    token: Address,
+    amount_in: U256,
+) -> (Result<HoneypotReport>, Misses) {
+    let mut fork = Fork::new(snapshot, amount_in + WEI_IN_ETHER);
+    let report = simulate(&mut fork, snapshot, router, token, amount_in);
+
+    (report, fork.into_misses())
+}
+
+fn simulate(
+    fork: &mut Fork,
+    snapshot: &StateSnapshot,
+    router: Address,
+    token: Address,
+    amount_in: U256,
+) -> Result<HoneypotReport> {
+    let weth = fork
+        .view(router, WethCall.encode())
+        .and_then(|output| WethReturn::decode(output).ok())
+        .ok_or_else(|| anyhow!("Router {:?} has no WETH()", router))?
+        .0;
+    let deadline = U256::from(snapshot.block.timestamp + BLOCK_TIME);
+
+    let max_tx = fork.first_uint(token, &MAX_TX_GETTERS);
+    let max_wallet = fork.first_uint(token, &MAX_WALLET_GETTERS);
+
+    let tokens_expected = fork.amount_out(router, amount_in, vec![weth, token]);
+    if tokens_expected.is_zero() {
+        bail!("Router {:?} has no liquidity for {:?}", router, token);
+    }
+    let tokens_before = fork.token_balance(token);
+    let buy = fork.execute(
+        router,
+        SwapExactETHForTokensSupportingFeeOnTransferTokensCall {
+            amount_out_min: U256::zero(),
+            path: vec![weth, token],
+            to: TRADER,
+            deadline,
+        }
+        .encode(),
+        amount_in,
+        true,
+    );
+    let tokens_received = fork.token_balance(token).saturating_sub(tokens_before);
+    let buy = TradeOutcome::new(tokens_expected, tokens_received, buy);
+
+    let sell = (!buy.reverted).then(|| {
+        let mut approve = id("approve(address,uint256)").to_vec();
+        approve.extend(abi::encode(&[
+            Token::Address(router),
+            Token::Uint(U256::MAX),
+        ]));
+        let approve = fork.execute(token, approve, U256::zero(), true);
+
+        let eth_expected = fork.amount_out(router, tokens_received, vec![token, weth]);
+        if let Some(reason) = approve.revert_reason {
+            let approve = Execution {
+                revert_reason: Some(format!("approve reverted: {}", reason)),
+                ..approve
+            };
+            return TradeOutcome::new(eth_expected, U256::zero(), approve);
+        }
+
+        let eth_before = fork.eth_balance();
+        let sell = fork.execute(
+            router,
+            SwapExactTokensForETHSupportingFeeOnTransferTokensCall {
+                amount_in: tokens_received,
+                amount_out_min: U256::zero(),
+                path: vec![token, weth],
+                to: TRADER,
+                deadline,
+            }
+            .encode(),
+            U256::zero(),
+            true,
+        );
+        let eth_received = fork.eth_balance().saturating_sub(eth_before);
+
+        TradeOutcome::new(eth_expected, eth_received, sell)
+    });
+
+    Ok(HoneypotReport {
+        token,
+        router,
+        weth,
+        block: snapshot.block.number,
+        amount_in,
+        max_tx,
+        max_wallet,
+        buy,
+        sell,
+    })
+}
+
+/// Check `token` for a honeypot or transfer taxes against a snapshot, without a node
+///
+/// # Errors
+///
+/// This function will return an error if the snapshot is missing state the simulation needs,
+/// or the router can't trade the token
+pub fn check_honeypot(
+    snapshot: &StateSnapshot,
+    router: Address,
+    token: Address,
+    amount_in: U256,
+) -> Result<HoneypotReport> {
+    let (report, misses) = round_trip(snapshot, router, token, amount_in);
+    if !misses.is_empty() {
+        bail!("The snapshot is missing {}", misses);
+    }
+
+    report
+}
+
+/// Check `token` for a honeypot or transfer taxes, reading the state the simulation needs
+/// into `snapshot`
+///
+/// Each round fetches what the previous one missed, calls depending on state that was just
+/// fetched can miss more.
+///
+/// # Errors
+///
+/// This function will return an error if the state could not be read, the simulation still
+/// misses state after `max_rounds` rounds or the router can't trade the token
+pub async fn check_honeypot_live<M: Middleware>(
+    provider: &M,
+    snapshot: &mut StateSnapshot,
+    router: Address,
+    token: Address,
+    amount_in: U256,
+    max_rounds: usize,
+) -> Result<HoneypotReport> {
+    for round in 1..=max_rounds {
+        let (report, misses) = round_trip(snapshot, router, token, amount_in);
+        if misses.is_empty() {
+            return report;
+        }
+
+        debug!("Round {} of {:?} missed {}", round, token, misses);
+        snapshot.fetch(provider, &misses).await?;
+    }
+
+    bail!(
+        "Simulating {:?} still misses state after {} rounds",
+        token,
+        max_rounds
+    )
+}
+
+/// A round trip recorded with the state it reads, replayed offline by the tests
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HoneypotFixture {
+    pub router: Address,
+    pub token: Address,
+    pub amount_in: U256,
+    pub report: HoneypotReport,
+    pub snapshot: StateSnapshot,
+}
+
+impl HoneypotFixture {
+    /// Record the round trip of `token` at `block`, or at the latest block
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the simulation fails, see `check_honeypot_live`
+    pub async fn record<M: Middleware>(
+        provider: &M,
+        router: Address,
+        token: Address,
+        amount_in: U256,
+        block: Option<u64>,
+        max_rounds: usize,
+    ) -> Result<Self> {
+        let mut snapshot = StateSnapshot::at_block(provider, block).await?;
+        let report = check_honeypot_live(
+            provider,
+            &mut snapshot,
+            router,
+            token,
+            amount_in,
+            max_rounds,
+        )
+        .await?;
+
+        Ok(Self {
+            router,
+            token,
+            amount_in,
+            report,
+            snapshot,
+        })
+    }
+
+    /// Replay the round trip against the recorded state
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the replay fails or differs from the recorded
+    /// report
+    pub fn replay(&self) -> Result<HoneypotReport> {
+        let report = check_honeypot(&self.snapshot, self.router, self.token, self.amount_in)?;
+        if report != self.report {
+            bail!("Replayed {:?}, recorded {:?}", report, self.report);
+        }
+
+        Ok(report)
+    }
+}
@@ -0,0 +1,2 @@
+pub mod fork;
+pub mod honeypot;
@@ -0,0 +1,668 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use ethers::abi::{self, Token};
+use ethers::types::{Address, Bytes, H256, U256};
+use ethers::utils::{id, WEI_IN_ETHER};
+use simulation::fork::{AccountSnapshot, BlockSnapshot, StateSnapshot};
+use simulation::honeypot::{check_honeypot, revert_reason, HoneypotFixture, TRADER};
+
+const STOP: u8 = 0x00;
+const ADD: u8 = 0x01;
+const MUL: u8 = 0x02;
+const SUB: u8 = 0x03;
+const DIV: u8 = 0x04;
+const GT: u8 = 0x11;
+const EQ: u8 = 0x14;
+const ISZERO: u8 = 0x15;
+const SHR: u8 = 0x1c;
+const ADDRESS: u8 = 0x30;
+const CALLER: u8 = 0x33;
+const CALLVALUE: u8 = 0x34;
+const CALLDATALOAD: u8 = 0x35;
+const RETURNDATASIZE: u8 = 0x3d;
+const RETURNDATACOPY: u8 = 0x3e;
+const MLOAD: u8 = 0x51;
+const MSTORE: u8 = 0x52;
+const SLOAD: u8 = 0x54;
+const SSTORE: u8 = 0x55;
+const JUMP: u8 = 0x56;
+const JUMPI: u8 = 0x57;
+const GAS: u8 = 0x5a;
+const JUMPDEST: u8 = 0x5b;
+const DUP1: u8 = 0x80;
+const CALL: u8 = 0xf1;
+const RETURN: u8 = 0xf3;
+const STATICCALL: u8 = 0xfa;
+const REVERT: u8 = 0xfd;
+
+/// Tokens the synthetic router trades per wei, and wei per token the other way
+const RATE: u64 = 1_000;
+
+fn address(value: u64) -> Address {
+    Address::from_low_u64_be(value)
+}
+
+fn router() -> Address {
+    address(0x10)
+}
+
+fn weth() -> Address {
+    address(0x30)
+}
+
+/// Selector of `signature` as the word a dispatcher compares the shifted calldata with
+fn selector(signature: &str) -> U256 {
+    U256::from_big_endian(&id(signature))
+}
+
+/// Assembler for the synthetic contracts, jumping to named labels
+#[derive(Default)]
+struct Asm {
+    code: Vec<u8>,
+    labels: HashMap<&'static str, usize>,
+    jumps: Vec<(usize, &'static str)>,
+}
+
+impl Asm {
+    fn op(&mut self, op: u8) -> &mut Self {
+        self.code.push(op);
+        self
+    }
+
+    fn push(&mut self, value: impl Into<U256>) -> &mut Self {
+        let mut word = [0; 32];
+        value.into().to_big_endian(&mut word);
+        let start = word.iter().position(|byte| *byte != 0).unwrap_or(31);
+
+        self.op(0x5f + (32 - start) as u8);
+        self.code.extend(&word[start..]);
+        self
+    }
+
+    fn push_address(&mut self, address: Address) -> &mut Self {
+        self.push(U256::from_big_endian(address.as_bytes()))
+    }
+
+    fn push_label(&mut self, label: &'static str) -> &mut Self {
+        // PUSH2, patched once the label is placed
+        self.op(0x61);
+        self.jumps.push((self.code.len(), label));
+        self.code.extend([0, 0]);
+        self
+    }
+
+    fn label(&mut self, label: &'static str) -> &mut Self {
+        self.labels.insert(label, self.code.len());
+        self.op(JUMPDEST)
+    }
+
+    fn jump(&mut self, label: &'static str) -> &mut Self {
+        self.push_label(label).op(JUMP)
+    }
+
+    /// Jump to `label` if the top of the stack is non-zero
+    fn jump_if(&mut self, label: &'static str) -> &mut Self {
+        self.push_label(label).op(JUMPI)
+    }
+
+    fn calldata(&mut self, offset: u64) -> &mut Self {
+        self.push(offset).op(CALLDATALOAD)
+    }
+
+    /// Store the top of the stack at `offset` in memory
+    fn store(&mut self, offset: u64) -> &mut Self {
+        self.push(offset).op(MSTORE)
+    }
+
+    fn load(&mut self, offset: u64) -> &mut Self {
+        self.push(offset).op(MLOAD)
+    }
+
+    /// Jump to `label` if the selector of the call is the one of `signature`
+    fn dispatch(&mut self, signature: &str, label: &'static str) -> &mut Self {
+        self.op(DUP1)
+            .push(selector(signature))
+            .op(EQ)
+            .jump_if(label)
+    }
+
+    /// Start a call with the selector of `signature` at the start of memory
+    fn selector(&mut self, signature: &str) -> &mut Self {
+        self.push(selector(signature) << 224).store(0)
+    }
+
+    /// Return the top of the stack as a word
+    fn return_word(&mut self) -> &mut Self {
+        self.store(0).push(32).push(0).op(RETURN)
+    }
+
+    /// Revert with the output of the last call, if it failed
+    fn bubble_revert(&mut self, ok: &'static str) -> &mut Self {
+        self.jump_if(ok)
+            .op(RETURNDATASIZE)
+            .push(0)
+            .push(0)
+            .op(RETURNDATACOPY)
+            .op(RETURNDATASIZE)
+            .push(0)
+            .op(REVERT)
+            .label(ok)
+    }
+
+    fn build(&mut self) -> Vec<u8> {
+        for (at, label) in &self.jumps {
+            let target = self.labels[label] as u16;
+            self.code[*at..*at + 2].copy_from_slice(&target.to_be_bytes());
+        }
+
+        self.code.clone()
+    }
+}
+
+/// An ERC-20 whose only pair is the synthetic router, taxing buys and sells
+///
+/// Balances are stored at the slot of their owner, allowances aren't checked and taxes are
+/// burnt.
+struct SyntheticToken {
+    address: Address,
+    /// Basis points of the bought tokens that are taxed
+    buy_tax: u64,
+    /// Basis points of the sold tokens that are taxed
+    sell_tax: u64,
+    /// Whether sells revert with `TRADING_BLOCKED`
+    block_sells: bool,
+    max_tx: Option<U256>,
+}
+
+impl SyntheticToken {
+    fn code(&self) -> Vec<u8> {
+        // Memory of the transfer being made
+        const FROM: u64 = 0x80;
+        const TO: u64 = 0xa0;
+        const AMOUNT: u64 = 0xc0;
+        const TAX: u64 = 0xe0;
+
+        let mut asm = Asm::default();
+        asm.calldata(0).push(224).op(SHR);
+        asm.dispatch("balanceOf(address)", "balance_of")
+            .dispatch("approve(address,uint256)", "approve")
+            .dispatch("transfer(address,uint256)", "transfer")
+            .dispatch("transferFrom(address,address,uint256)", "transfer_from");
+        if self.max_tx.is_some() {
+            asm.dispatch("_maxTxAmount()", "max_tx");
+        }
+        asm.push(0).op(DUP1).op(REVERT);
+
+        asm.label("balance_of").calldata(4).op(SLOAD).return_word();
+        asm.label("approve").push(1).return_word();
+        if let Some(max_tx) = self.max_tx {
+            asm.label("max_tx").push(max_tx).return_word();
+        }
+
+        asm.label("transfer")
+            .op(CALLER)
+            .store(FROM)
+            .calldata(4)
+            .store(TO)
+            .calldata(36)
+            .store(AMOUNT)
+            .jump("move");
+        asm.label("transfer_from")
+            .calldata(4)
+            .store(FROM)
+            .calldata(36)
+            .store(TO)
+            .calldata(68)
+            .store(AMOUNT)
+            .jump("move");
+
+        asm.label("move");
+        if self.block_sells {
+            asm.load(TO)
+                .push_address(router())
+                .op(EQ)
+                .jump_if("blocked");
+        }
+        // Debit the sender, reverting without a reason if it is short
+        asm.load(AMOUNT)
+            .load(FROM)
+            .op(SLOAD)
+            .load(AMOUNT)
+            .op(GT)
+            .jump_if("short")
+            .load(FROM)
+            .op(SLOAD)
+            .op(SUB)
+            .load(FROM)
+            .op(SSTORE);
+        // Tax transfers out of and into the pair
+        asm.push(0).store(TAX);
+        for (side, tax, skip) in [
+            (FROM, self.buy_tax, "not_buy"),
+            (TO, self.sell_tax, "not_sell"),
+        ] {
+            asm.load(side)
+                .push_address(router())
+                .op(EQ)
+                .op(ISZERO)
+                .jump_if(skip)
+                .push(10_000)
+                .push(tax)
+                .load(AMOUNT)
+                .op(MUL)
+                .op(DIV)
+                .store(TAX)
+                .label(skip);
+        }
+        // Credit the recipient with what is left
+        asm.load(TAX)
+            .load(AMOUNT)
+            .op(SUB)
+            .load(TO)
+            .op(SLOAD)
+            .op(ADD)
+            .load(TO)
+            .op(SSTORE)
+            .push(1)
+            .return_word();
+
+        asm.label("short").push(0).op(DUP1).op(REVERT);
+        // Error("TRADING_BLOCKED")
+        let mut reason = [0; 32];
+        reason[..15].copy_from_slice(b"TRADING_BLOCKED");
+        asm.label("blocked")
+            .selector("Error(string)")
+            .push(0x20)
+            .store(4)
+            .push(15)
+            .store(36)
+            .push(U256::from_big_endian(&reason))
+            .store(68)
+            .push(100)
+            .push(0)
+            .op(REVERT);
+
+        asm.build()
+    }
+}
+
+/// A V2 router trading at a fixed rate, holding the supply of the tokens and ETH to buy them
+/// back
+///
+/// Like the `SupportingFeeOnTransferTokens` swaps of Uniswap, sells pay out what the router
+/// received after taxes.
+fn router_code() -> Vec<u8> {
+    // Memory of a sell
+    const TOKEN: u64 = 0x100;
+    const TO: u64 = 0x120;
+    const BEFORE: u64 = 0x140;
+    const AFTER: u64 = 0x160;
+
+    let mut asm = Asm::default();
+    asm.calldata(0).push(224).op(SHR);
+    asm.dispatch("WETH()", "weth")
+        .dispatch("getAmountsOut(uint256,address[])", "amounts_out")
+        .dispatch(
+            "swapExactETHForTokensSupportingFeeOnTransferTokens(uint256,address[],address,uint256)",
+            "buy",
+        )
+        .dispatch(
+            "swapExactTokensForETHSupportingFeeOnTransferTokens(uint256,uint256,address[],address,uint256)",
+            "sell",
+        )
+        .push(0)
+        .op(DUP1)
+        .op(REVERT);
+
+    asm.label("weth").push_address(weth()).return_word();
+
+    // Buying when the path starts with WETH, selling otherwise
+    asm.label("amounts_out")
+        .calldata(36)
+        .push(36)
+        .op(ADD)
+        .op(CALLDATALOAD)
+        .push_address(weth())
+        .op(EQ)
+        .jump_if("quote_buy")
+        .push(RATE)
+        .calldata(4)
+        .op(DIV)
+        .store(96)
+        .jump("quoted")
+        .label("quote_buy")
+        .push(RATE)
+        .calldata(4)
+        .op(MUL)
+        .store(96)
+        .label("quoted")
+        .push(0x20)
+        .store(0)
+        .push(2)
+        .store(32)
+        .calldata(4)
+        .store(64)
+        .push(128)
+        .push(0)
+        .op(RETURN);
+
+    // transfer(to, value * RATE) on the last token of the path
+    asm.label("buy")
+        .selector("transfer(address,uint256)")
+        .calldata(68)
+        .store(4)
+        .push(RATE)
+        .op(CALLVALUE)
+        .op(MUL)
+        .store(36)
+        .push(0)
+        .push(0)
+        .push(68)
+        .push(0)
+        .push(0)
+        .calldata(36)
+        .push(68)
+        .op(ADD)
+        .op(CALLDATALOAD)
+        .op(GAS)
+        .op(CALL)
+        .bubble_revert("bought")
+        .op(STOP);
+
+    asm.label("sell")
+        .calldata(68)
+        .push(36)
+        .op(ADD)
+        .op(CALLDATALOAD)
+        .store(TOKEN)
+        .calldata(100)
+        .store(TO);
+    balance_of_self(&mut asm, TOKEN, BEFORE, "before");
+    asm.selector("transferFrom(address,address,uint256)")
+        .op(CALLER)
+        .store(4)
+        .op(ADDRESS)
+        .store(36)
+        .calldata(4)
+        .store(68)
+        .push(0)
+        .push(0)
+        .push(100)
+        .push(0)
+        .push(0)
+        .load(TOKEN)
+        .op(GAS)
+        .op(CALL)
+        .bubble_revert("pulled");
+    balance_of_self(&mut asm, TOKEN, AFTER, "after");
+    // Pay (after - before) / RATE
+    asm.push(0)
+        .push(0)
+        .push(0)
+        .push(0)
+        .push(RATE)
+        .load(BEFORE)
+        .load(AFTER)
+        .op(SUB)
+        .op(DIV)
+        .load(TO)
+        .op(GAS)
+        .op(CALL)
+        .bubble_revert("paid")
+        .op(STOP);
+
+    asm.build()
+}
+
+/// Store the balance of the running contract in `token` at `offset` in memory
+fn balance_of_self(asm: &mut Asm, token: u64, offset: u64, label: &'static str) {
+    asm.selector("balanceOf(address)")
+        .op(ADDRESS)
+        .store(4)
+        .push(32)
+        .push(0)
+        .push(36)
+        .push(0)
+        .load(token)
+        .op(GAS)
+        .op(STATICCALL)
+        .bubble_revert(label)
+        .load(0)
+        .store(offset);
+}
+
+/// The router and `token` deployed, the router holding the whole supply
+fn synthetic_snapshot(token: &SyntheticToken) -> StateSnapshot {
+    let supply = U256::exp10(30);
+    let slot = |account: Address| U256::from_big_endian(account.as_bytes());
+
+    let mut snapshot = snapshot();
+    snapshot.accounts.insert(
+        router(),
+        AccountSnapshot {
+            balance: U256::exp10(24),
+            code: Bytes::from(router_code()),
+            ..Default::default()
+        },
+    );
+    snapshot.accounts.insert(
+        token.address,
+        AccountSnapshot {
+            code: Bytes::from(token.code()),
+            storage: BTreeMap::from([(slot(router()), supply), (slot(TRADER), U256::zero())]),
+            ..Default::default()
+        },
+    );
+    snapshot
+        .accounts
+        .insert(address(0xc0), AccountSnapshot::default());
+
+    snapshot
+}
+
+fn taxed_token() -> SyntheticToken {
+    SyntheticToken {
+        address: address(0x20),
+        buy_tax: 500,
+        sell_tax: 1_000,
+        block_sells: false,
+        max_tx: Some(U256::exp10(22)),
+    }
+}
+
+fn blocking_token() -> SyntheticToken {
+    SyntheticToken {
+        address: address(0x21),
+        buy_tax: 0,
+        sell_tax: 0,
+        block_sells: true,
+        max_tx: None,
+    }
+}
+
+fn synthetic_fixture(token: &SyntheticToken) -> HoneypotFixture {
+    let snapshot = synthetic_snapshot(token);
+    let report = check_honeypot(&snapshot, router(), token.address, WEI_IN_ETHER).unwrap();
+
+    HoneypotFixture {
+        router: router(),
+        token: token.address,
+        amount_in: WEI_IN_ETHER,
+        report,
+        snapshot,
+    }
+}
+
+fn fixtures_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/honeypot")
+}
+
+fn snapshot() -> StateSnapshot {
+    StateSnapshot::new(BlockSnapshot {
+        number: 17_000_000,
+        timestamp: 1_680_911_891,
+        base_fee: U256::from(30_000_000_000u64),
+        gas_limit: U256::from(30_000_000),
+        coinbase: address(0xc0),
+        prevrandao: H256::zero(),
+        chain_id: 1,
+    })
+}
+
+#[test]
+fn revert_reasons_are_decoded() {
+    let mut error = id("Error(string)").to_vec();
+    error.extend(abi::encode(&[Token::String(String::from(
+        "TRANSFER_FAILED",
+    ))]));
+    assert_eq!(revert_reason(&error), "TRANSFER_FAILED");
+
+    let mut panic = id("Panic(uint256)").to_vec();
+    panic.extend(abi::encode(&[Token::Uint(U256::from(0x11))]));
+    assert_eq!(revert_reason(&panic), "panic 0x11");
+
+    assert_eq!(revert_reason(&[]), "no reason");
+    assert_eq!(revert_reason(&[0xde, 0xad]), "0xdead");
+}
+
+/// Offline checks fail on state the snapshot doesn't have instead of simulating without it
+#[test]
+fn missing_state_is_reported() {
+    let router = address(0x10);
+    let token = address(0x20);
+
+    let e = check_honeypot(&snapshot(), router, token, WEI_IN_ETHER).unwrap_err();
+    assert!(e.to_string().contains(&format!("{:?}", router)), "{}", e);
+}
+
+/// A router answering every call with the same word quotes nothing, whatever the token
+#[test]
+fn router_without_liquidity_is_an_error() {
+    let router = address(0x10);
+    let token = address(0x20);
+    let weth = address(0x30);
+
+    // PUSH20 <weth> PUSH1 0 MSTORE PUSH1 32 PUSH1 0 RETURN
+    let mut code = vec![0x73];
+    code.extend(weth.as_bytes());
+    code.extend([0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xf3]);
+
+    let mut snapshot = snapshot();
+    for (account, code) in [
+        (router, code),
+        (token, Vec::new()),
+        (address(0xc0), Vec::new()),
+    ] {
+        snapshot.accounts.insert(
+            account,
+            AccountSnapshot {
+                code: Bytes::from(code),
+                ..Default::default()
+            },
+        );
+    }
+
+    let e = check_honeypot(&snapshot, router, token, WEI_IN_ETHER).unwrap_err();
+    assert!(e.to_string().contains("no liquidity"), "{}", e);
+}
+
+#[test]
+fn taxed_token_is_not_a_honeypot() {
+    let token = taxed_token();
+    let report = check_honeypot(
+        &synthetic_snapshot(&token),
+        router(),
+        token.address,
+        WEI_IN_ETHER,
+    )
+    .unwrap();
+
+    assert_eq!(report.weth, weth());
+    assert_eq!(report.buy.expected, WEI_IN_ETHER * RATE);
+    assert_eq!(report.buy.received, WEI_IN_ETHER * RATE * 95 / 100);
+    assert!((report.buy.tax - 0.05).abs() < 1e-9, "{}", report);
+
+    let sell = report.sell.as_ref().unwrap();
+    assert!(!sell.reverted);
+    assert_eq!(sell.expected, WEI_IN_ETHER * 95 / 100);
+    assert_eq!(sell.received, WEI_IN_ETHER * 95 / 100 * 90 / 100);
+    assert!((sell.tax - 0.10).abs() < 1e-9, "{}", report);
+
+    assert_eq!(report.max_tx, token.max_tx);
+    assert_eq!(report.max_wallet, None);
+    assert!(!report.is_honeypot());
+    assert!(report.exceeds_tax(0.09));
+    assert!(!report.exceeds_tax(0.15));
+}
+
+#[test]
+fn blocked_sell_is_a_honeypot() {
+    let token = blocking_token();
+    let report = check_honeypot(
+        &synthetic_snapshot(&token),
+        router(),
+        token.address,
+        WEI_IN_ETHER,
+    )
+    .unwrap();
+
+    assert!(!report.buy.reverted);
+    assert_eq!(report.buy.received, WEI_IN_ETHER * RATE);
+    assert_eq!(report.buy.tax, 0.0);
+
+    let sell = report.sell.as_ref().unwrap();
+    assert!(sell.reverted);
+    assert_eq!(sell.revert_reason.as_deref(), Some("TRADING_BLOCKED"));
+    assert!(sell.received.is_zero());
+    assert!(report.is_honeypot());
+    assert!(report
+        .to_string()
+        .ends_with("sell reverted (TRADING_BLOCKED), HONEYPOT"));
+}
+
+/// The synthetic round trips are committed as fixtures too, so the replay always has cases
+///
+/// Run with `WRITE_FIXTURES` set to write them again after changing the contracts.
+#[test]
+fn synthetic_fixtures_are_current() {
+    for (name, token) in [("taxed", taxed_token()), ("blocked", blocking_token())] {
+        let path = fixtures_dir().join(format!("synthetic-{}.json", name));
+        let fixture = synthetic_fixture(&token);
+        if std::env::var_os("WRITE_FIXTURES").is_some() {
+            let json = serde_json::to_string_pretty(&fixture).unwrap();
+            fs::write(&path, json + "\n").unwrap();
+        }
+
+        let recorded: HoneypotFixture =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert!(
+            recorded == fixture,
+            "{} is out of date, run the test with WRITE_FIXTURES=1",
+            path.display()
+        );
+    }
+}
+
+/// Round trips recorded with the `honeypot` bin replay to the same report
+#[test]
+fn recorded_round_trips_replay() {
+    let dir = fixtures_dir();
+
+    let mut cases = 0;
+    for entry in fs::read_dir(&dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().and_then(|extension| extension.to_str()) != Some("json") {
+            continue;
+        }
+
+        let fixture: HoneypotFixture =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        if let Err(e) = fixture.replay() {
+            panic!("{}: {}", path.display(), e);
+        }
+        cases += 1;
+    }
+    assert!(cases > 0, "No round trip fixtures in {}", dir.display());
+}
@@ -0,0 +1,72 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use ethers::types::{Block, H256};
+use log::info;
+use tokio::sync::{broadcast::Receiver, oneshot, Mutex};
+
+use crate::writer::{Record, RecordStorage, Retryable, StorageWriter};
+
+pub trait BlockStorage<T> {
+    /// Store a block
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the block could not be stored
+    async fn store(&mut self, block: T) -> Result<()>;
+
+    /// Check if a block is stored
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the block could not be checked
+    async fn is_stored(&mut self, block: T) -> Result<bool>;
+
+    /// Delete a block from the store
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the block could not be deleted
+    async fn delete(&mut self, block: T) -> Result<()>;
+}
+
+impl Record for Block<H256> {
+    const KIND: &'static str = "block";
+
+    fn hash(&self) -> H256 {
+        self.hash.unwrap_or_default()
+    }
+}
+
+impl<C: BlockStorage<Block<H256>> + Retryable> RecordStorage<Block<H256>> for C {
+    async fn write_record(&mut self, block: &Block<H256>) -> Result<()> {
+        BlockStorage::store(self, block.clone()).await
+    }
+
+    fn can_retry(&self, error: &anyhow::Error) -> bool {
+        self.is_retryable(error)
+    }
+}
+
+/// Store the blocks from `receiver` until it closes or `shutdown` fires
+///
+/// Blocks go through `writer`, so a failed write is retried or dead lettered instead of
+/// stopping the updates.
+pub async fn block_store<C: BlockStorage<Block<H256>> + Retryable>(
+    block_storage: Arc<Mutex<C>>,
+    receiver: Receiver<Block<H256>>,
+    writer: Arc<StorageWriter>,
+    shutdown: oneshot::Receiver<()>,
+) -> Result<()> {
+    info!("Starting block storage updates...");
+
+    let stats = writer
+        .store_records(&block_storage, receiver, shutdown)
+        .await;
+    info!(
+        "Block storage updates stopped: {} written, {} dead lettered, {} missed",
+        stats.written, stats.dead_lettered, stats.missed
+    );
+
+    Ok(())
+}
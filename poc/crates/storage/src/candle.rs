@@ -0,0 +1,181 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::Result;
+use dex::candle::{build_candles, merge_candles, Candle, Interval, Trade};
+use dex::registry::{PairInfo, Registry};
+use ethers::types::{Address, H256};
+use log::{debug, warn};
+
+pub trait CandleStorage {
+    /// Insert trades, trades already stored are left as they are
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the trades could not be stored
+    async fn store_trades(&mut self, trades: &[Trade]) -> Result<()>;
+
+    /// Remove the trades orphaned by block `block_hash` becoming the head at `block_number`
+    ///
+    /// These are the trades of any other block at that height or above, they are returned so
+    /// their candles can be refreshed.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the trades could not be removed
+    async fn orphan_trades(&mut self, block_number: u64, block_hash: H256) -> Result<Vec<Trade>>;
+
+    /// Trades of blocks with a timestamp in `from..=to`, of a single pair or all of them, in chain
+    /// order
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the trades could not be queried
+    async fn trades(&mut self, pair: Option<Address>, from: u64, to: u64) -> Result<Vec<Trade>>;
+
+    /// Insert or replace a candle
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the candle could not be stored
+    async fn store_candle(&mut self, candle: &Candle) -> Result<()>;
+
+    /// Remove the candles of a pair and interval starting in `from..=to`, or of every pair and
+    /// interval if `pair` is `None`, returning how many there were
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the candles could not be removed
+    async fn delete_candles(
+        &mut self,
+        pair: Option<(Address, Interval)>,
+        from: u64,
+        to: u64,
+    ) -> Result<u64>;
+
+    /// Candles of a pair starting in `from..=to`, in time order
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the candles could not be queried
+    async fn candles(
+        &mut self,
+        pair: Address,
+        interval: Interval,
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<Candle>>;
+}
+
+/// Decimals of the tokens of `pair`, `None` if one of them isn't registered
+pub fn pair_decimals(registry: &Registry, pair: &PairInfo) -> Option<(u8, u8)> {
+    Some((
+        registry.token(pair.token0)?.decimals,
+        registry.token(pair.token1)?.decimals,
+    ))
+}
+
+/// Recompute every candle of `pair` containing `timestamp` from the stored trades
+///
+/// The minute candle is built from trades, the longer ones are merged from minute candles, so
+/// refreshing after every block stays cheap.
+///
+/// # Errors
+///
+/// This function will return an error if the trades or candles could not be read or stored
+pub async fn refresh_candles<C: CandleStorage>(
+    storage: &mut C,
+    pair: Address,
+    decimals: (u8, u8),
+    timestamp: u64,
+) -> Result<()> {
+    let minute = Interval::OneMinute;
+    let start = minute.start(timestamp);
+    let trades = storage.trades(Some(pair), start, minute.end(start)).await?;
+
+    match build_candles(minute, &trades, decimals.0, decimals.1).pop() {
+        Some(candle) => storage.store_candle(&candle).await?,
+        None => {
+            storage
+                .delete_candles(Some((pair, minute)), start, start)
+                .await?;
+        }
+    }
+
+    for interval in Interval::ALL.into_iter().skip(1) {
+        let start = interval.start(timestamp);
+        let minutes = storage
+            .candles(pair, minute, start, interval.end(start))
+            .await?;
+
+        match Candle::merge(interval, start, &minutes) {
+            Some(candle) => storage.store_candle(&candle).await?,
+            None => {
+                storage
+                    .delete_candles(Some((pair, interval)), start, start)
+                    .await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Recompute the candles of every registered pair over the days containing `from..=to`
+///
+/// Candles are rebuilt from the stored trades only, so a range without stored trades ends up
+/// without candles. Returns the number of candles stored.
+///
+/// # Errors
+///
+/// This function will return an error if the trades or candles could not be read or stored
+pub async fn rebuild_candles<C: CandleStorage>(
+    storage: &mut C,
+    registry: &Registry,
+    from: u64,
+    to: u64,
+) -> Result<usize> {
+    let day = Interval::OneDay;
+    let (from, to) = (day.start(from), day.end(day.start(to)));
+
+    let deleted = storage.delete_candles(None, from, to).await?;
+    debug!("Deleted {} candles between {} and {}", deleted, from, to);
+
+    let mut trades: BTreeMap<Address, Vec<Trade>> = BTreeMap::new();
+    for trade in storage.trades(None, from, to).await? {
+        trades.entry(trade.pair).or_default().push(trade);
+    }
+
+    let mut stored = 0;
+    let mut unpriced = BTreeSet::new();
+    for (pair, trades) in trades {
+        let Some((decimals0, decimals1)) = registry
+            .pair(pair)
+            .and_then(|info| pair_decimals(registry, info))
+        else {
+            unpriced.insert(pair);
+            continue;
+        };
+
+        let minutes = build_candles(Interval::OneMinute, &trades, decimals0, decimals1);
+        let mut candles = Interval::ALL
+            .into_iter()
+            .skip(1)
+            .flat_map(|interval| merge_candles(interval, &minutes))
+            .collect::<Vec<Candle>>();
+        candles.extend(minutes);
+
+        for candle in &candles {
+            storage.store_candle(candle).await?;
+        }
+        stored += candles.len();
+    }
+
+    if !unpriced.is_empty() {
+        warn!(
+            "Skipped the trades of {} pairs with unregistered tokens",
+            unpriced.len()
+        );
+    }
+
+    Ok(stored)
+}
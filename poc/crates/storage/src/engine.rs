@@ -0,0 +1,514 @@
+use std::collections::HashSet;
+
+use anyhow::{bail, Result};
+use ethers::types::{Address, Block, Transaction, H256};
+use settings::{DataClass, Settings, StorageBackend};
+
+use crate::block_storage::BlockStorage;
+use crate::candle::CandleStorage;
+use crate::lifecycle::{LifecycleStorage, RouterLatency, TxLifecycle};
+use crate::memory::MemoryStorage;
+#[cfg(feature = "postgres")]
+use crate::postgres::PostgresStorage;
+use crate::reader::ChainReader;
+use crate::retention::{Expired, ExpiringStorage};
+use crate::scylla::TXScyllaStorage;
+#[cfg(feature = "sqlite")]
+use crate::sqlite::SqliteStorage;
+use crate::swap::SwapStorage;
+use crate::tx_storage::TxStorage;
+use crate::writer::Retryable;
+use dex::candle::{Candle, Interval, Trade};
+use dex::registry::{PairInfo, RegistryStorage, TokenInfo};
+use dex::swap::SwapRecord;
+
+/// Storage backend selected through `Settings`
+///
+/// The storage traits use `async fn` and can't be made into trait objects, so the configured
+/// backend is dispatched through this enum instead.
+// Without the SQL backends the in-memory storage is a lot smaller than Scylla, but engines are
+// created once per task, so boxing isn't worth it
+#[allow(clippy::large_enum_variant)]
+pub enum StorageEngine {
+    Scylla(TXScyllaStorage),
+    #[cfg(feature = "postgres")]
+    Postgres(PostgresStorage),
+    #[cfg(feature = "sqlite")]
+    Sqlite(SqliteStorage),
+    Memory(MemoryStorage),
+}
+
+impl StorageEngine {
+    /// Connect to the storage backend configured in `settings`
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the backend is not configured, was not compiled in
+    /// or could not be connected to
+    pub async fn new(settings: &Settings) -> Result<Self> {
+        match settings.storage_backend() {
+            StorageBackend::Scylla => Ok(Self::Scylla(
+                TXScyllaStorage::new(
+                    settings.scylla.url.clone(),
+                    settings.scylla.keyspace.clone(),
+                    settings.scylla.username.clone(),
+                    settings.scylla.password.clone(),
+                    settings.retention(),
+                )
+                .await?,
+            )),
+            #[cfg(feature = "postgres")]
+            StorageBackend::Postgres => {
+                let postgres = settings
+                    .postgres
+                    .clone()
+                    .ok_or_else(|| anyhow::anyhow!("Missing [postgres] settings"))?;
+
+                Ok(Self::Postgres(
+                    PostgresStorage::new(postgres.url, postgres.schema).await?,
+                ))
+            }
+            #[cfg(feature = "sqlite")]
+            StorageBackend::Sqlite => {
+                let sqlite = settings
+                    .sqlite
+                    .clone()
+                    .ok_or_else(|| anyhow::anyhow!("Missing [sqlite] settings"))?;
+
+                Ok(Self::Sqlite(SqliteStorage::new(sqlite.path)?))
+            }
+            StorageBackend::Memory => Ok(Self::Memory(MemoryStorage::shared())),
+            #[allow(unreachable_patterns)]
+            backend => bail!("Storage backend {:?} is not enabled in this build", backend),
+        }
+    }
+
+    /// Another handle on the same store, for a task that needs its own engine
+    ///
+    /// The embedded backends share their database with the handle, so an in-memory store is the
+    /// same for every task. The database servers get a new connection, since their transactions
+    /// can't interleave on one.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a new connection could not be opened
+    pub async fn share(&self, settings: &Settings) -> Result<Self> {
+        match self {
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => Ok(Self::Sqlite(storage.clone())),
+            Self::Memory(storage) => Ok(Self::Memory(storage.clone())),
+            _ => Self::new(settings).await,
+        }
+    }
+
+    /// Whether the backend can store blocks as well as transactions
+    pub fn stores_blocks(&self) -> bool {
+        !matches!(self, Self::Scylla(_))
+    }
+}
+
+impl TxStorage<Transaction> for StorageEngine {
+    async fn store(&mut self, tx: Transaction) -> Result<()> {
+        match self {
+            Self::Scylla(storage) => storage.store(tx).await,
+            #[cfg(feature = "postgres")]
+            Self::Postgres(storage) => TxStorage::store(storage, tx).await,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => TxStorage::store(storage, tx).await,
+            Self::Memory(storage) => TxStorage::store(storage, tx).await,
+        }
+    }
+
+    async fn is_stored(&mut self, tx: Transaction) -> Result<bool> {
+        match self {
+            Self::Scylla(storage) => storage.is_stored(tx).await,
+            #[cfg(feature = "postgres")]
+            Self::Postgres(storage) => TxStorage::is_stored(storage, tx).await,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => TxStorage::is_stored(storage, tx).await,
+            Self::Memory(storage) => TxStorage::is_stored(storage, tx).await,
+        }
+    }
+
+    async fn get(&mut self, hash: H256) -> Result<Option<Transaction>> {
+        match self {
+            Self::Scylla(storage) => TxStorage::get(storage, hash).await,
+            #[cfg(feature = "postgres")]
+            Self::Postgres(storage) => TxStorage::get(storage, hash).await,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => TxStorage::get(storage, hash).await,
+            Self::Memory(storage) => TxStorage::get(storage, hash).await,
+        }
+    }
+
+    async fn store_many(&mut self, txs: Vec<Transaction>) -> Result<()> {
+        match self {
+            Self::Scylla(storage) => TxStorage::store_many(storage, txs).await,
+            #[cfg(feature = "postgres")]
+            Self::Postgres(storage) => TxStorage::store_many(storage, txs).await,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => TxStorage::store_many(storage, txs).await,
+            Self::Memory(storage) => TxStorage::store_many(storage, txs).await,
+        }
+    }
+
+    async fn exists_many(&mut self, hashes: &[H256]) -> Result<HashSet<H256>> {
+        match self {
+            Self::Scylla(storage) => TxStorage::exists_many(storage, hashes).await,
+            #[cfg(feature = "postgres")]
+            Self::Postgres(storage) => TxStorage::exists_many(storage, hashes).await,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => TxStorage::exists_many(storage, hashes).await,
+            Self::Memory(storage) => TxStorage::exists_many(storage, hashes).await,
+        }
+    }
+
+    async fn delete(&mut self, tx: Transaction) -> Result<()> {
+        match self {
+            Self::Scylla(storage) => storage.delete(tx).await,
+            #[cfg(feature = "postgres")]
+            Self::Postgres(storage) => TxStorage::delete(storage, tx).await,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => TxStorage::delete(storage, tx).await,
+            Self::Memory(storage) => TxStorage::delete(storage, tx).await,
+        }
+    }
+}
+
+impl Retryable for StorageEngine {
+    fn is_retryable(&self, error: &anyhow::Error) -> bool {
+        match self {
+            Self::Scylla(storage) => storage.is_retryable(error),
+            #[cfg(feature = "postgres")]
+            Self::Postgres(storage) => storage.is_retryable(error),
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => storage.is_retryable(error),
+            Self::Memory(storage) => storage.is_retryable(error),
+        }
+    }
+}
+
+impl BlockStorage<Block<H256>> for StorageEngine {
+    async fn store(&mut self, block: Block<H256>) -> Result<()> {
+        match self {
+            Self::Scylla(_) => bail!(
+                "Can't store block {:?}, block storage is not supported by the Scylla backend",
+                block.hash
+            ),
+            #[cfg(feature = "postgres")]
+            Self::Postgres(storage) => BlockStorage::store(storage, block).await,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => BlockStorage::store(storage, block).await,
+            Self::Memory(storage) => BlockStorage::store(storage, block).await,
+        }
+    }
+
+    async fn is_stored(&mut self, block: Block<H256>) -> Result<bool> {
+        match self {
+            Self::Scylla(_) => bail!(
+                "Can't check block {:?}, block storage is not supported by the Scylla backend",
+                block.hash
+            ),
+            #[cfg(feature = "postgres")]
+            Self::Postgres(storage) => BlockStorage::is_stored(storage, block).await,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => BlockStorage::is_stored(storage, block).await,
+            Self::Memory(storage) => BlockStorage::is_stored(storage, block).await,
+        }
+    }
+
+    async fn delete(&mut self, block: Block<H256>) -> Result<()> {
+        match self {
+            Self::Scylla(_) => bail!(
+                "Can't delete block {:?}, block storage is not supported by the Scylla backend",
+                block.hash
+            ),
+            #[cfg(feature = "postgres")]
+            Self::Postgres(storage) => BlockStorage::delete(storage, block).await,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => BlockStorage::delete(storage, block).await,
+            Self::Memory(storage) => BlockStorage::delete(storage, block).await,
+        }
+    }
+}
+
+impl ChainReader for StorageEngine {
+    async fn latest_block_number(&mut self) -> Result<Option<u64>> {
+        match self {
+            Self::Scylla(_) => bail!("Block reads are not supported by the Scylla backend"),
+            #[cfg(feature = "postgres")]
+            Self::Postgres(storage) => storage.latest_block_number().await,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => storage.latest_block_number().await,
+            Self::Memory(storage) => storage.latest_block_number().await,
+        }
+    }
+
+    async fn blocks_in_range(&mut self, from: u64, to: u64) -> Result<Vec<Block<H256>>> {
+        match self {
+            Self::Scylla(_) => bail!(
+                "Can't read blocks {}..={}, block reads are not supported by the Scylla backend",
+                from,
+                to
+            ),
+            #[cfg(feature = "postgres")]
+            Self::Postgres(storage) => storage.blocks_in_range(from, to).await,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => storage.blocks_in_range(from, to).await,
+            Self::Memory(storage) => storage.blocks_in_range(from, to).await,
+        }
+    }
+
+    async fn txs_in_range(&mut self, from: u64, to: u64) -> Result<Vec<Transaction>> {
+        match self {
+            Self::Scylla(_) => bail!(
+                "Can't read txs for blocks {}..={}, range reads are not supported by the Scylla backend",
+                from,
+                to
+            ),
+            #[cfg(feature = "postgres")]
+            Self::Postgres(storage) => storage.txs_in_range(from, to).await,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => storage.txs_in_range(from, to).await,
+            Self::Memory(storage) => storage.txs_in_range(from, to).await,
+        }
+    }
+
+    async fn block_range_for_time(&mut self, from: u64, to: u64) -> Result<Option<(u64, u64)>> {
+        match self {
+            Self::Scylla(_) => bail!(
+                "Can't read blocks between {} and {}, block reads are not supported by the Scylla backend",
+                from,
+                to
+            ),
+            #[cfg(feature = "postgres")]
+            Self::Postgres(storage) => storage.block_range_for_time(from, to).await,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => storage.block_range_for_time(from, to).await,
+            Self::Memory(storage) => storage.block_range_for_time(from, to).await,
+        }
+    }
+
+    async fn orphaned_blocks_in_range(&mut self, from: u64, to: u64) -> Result<Vec<Block<H256>>> {
+        match self {
+            Self::Scylla(_) => bail!(
+                "Can't read blocks {}..={}, block reads are not supported by the Scylla backend",
+                from,
+                to
+            ),
+            #[cfg(feature = "postgres")]
+            Self::Postgres(storage) => storage.orphaned_blocks_in_range(from, to).await,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => storage.orphaned_blocks_in_range(from, to).await,
+            Self::Memory(storage) => storage.orphaned_blocks_in_range(from, to).await,
+        }
+    }
+}
+
+impl LifecycleStorage for StorageEngine {
+    async fn store_lifecycle(&mut self, lifecycle: &TxLifecycle) -> Result<()> {
+        match self {
+            Self::Scylla(storage) => storage.store_lifecycle(lifecycle).await,
+            #[cfg(feature = "postgres")]
+            Self::Postgres(storage) => storage.store_lifecycle(lifecycle).await,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => storage.store_lifecycle(lifecycle).await,
+            Self::Memory(storage) => storage.store_lifecycle(lifecycle).await,
+        }
+    }
+
+    async fn lifecycle(&mut self, hash: H256) -> Result<Option<TxLifecycle>> {
+        match self {
+            Self::Scylla(storage) => storage.lifecycle(hash).await,
+            #[cfg(feature = "postgres")]
+            Self::Postgres(storage) => storage.lifecycle(hash).await,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => storage.lifecycle(hash).await,
+            Self::Memory(storage) => storage.lifecycle(hash).await,
+        }
+    }
+
+    async fn router_latency(&mut self, since: u64) -> Result<Vec<RouterLatency>> {
+        match self {
+            Self::Scylla(storage) => storage.router_latency(since).await,
+            #[cfg(feature = "postgres")]
+            Self::Postgres(storage) => storage.router_latency(since).await,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => storage.router_latency(since).await,
+            Self::Memory(storage) => storage.router_latency(since).await,
+        }
+    }
+}
+
+impl SwapStorage for StorageEngine {
+    async fn store_swap(&mut self, swap: &SwapRecord) -> Result<()> {
+        match self {
+            Self::Scylla(storage) => storage.store_swap(swap).await,
+            #[cfg(feature = "postgres")]
+            Self::Postgres(storage) => storage.store_swap(swap).await,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => storage.store_swap(swap).await,
+            Self::Memory(storage) => storage.store_swap(swap).await,
+        }
+    }
+
+    async fn swaps(&mut self, tx_hash: H256) -> Result<Vec<SwapRecord>> {
+        match self {
+            Self::Scylla(storage) => storage.swaps(tx_hash).await,
+            #[cfg(feature = "postgres")]
+            Self::Postgres(storage) => storage.swaps(tx_hash).await,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => storage.swaps(tx_hash).await,
+            Self::Memory(storage) => storage.swaps(tx_hash).await,
+        }
+    }
+
+    async fn swaps_in_range(&mut self, from: u64, to: u64) -> Result<Vec<SwapRecord>> {
+        match self {
+            Self::Scylla(storage) => storage.swaps_in_range(from, to).await,
+            #[cfg(feature = "postgres")]
+            Self::Postgres(storage) => storage.swaps_in_range(from, to).await,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => storage.swaps_in_range(from, to).await,
+            Self::Memory(storage) => storage.swaps_in_range(from, to).await,
+        }
+    }
+}
+
+impl RegistryStorage for StorageEngine {
+    async fn store_token(&mut self, token: &TokenInfo) -> Result<()> {
+        match self {
+            Self::Scylla(storage) => storage.store_token(token).await,
+            #[cfg(feature = "postgres")]
+            Self::Postgres(storage) => storage.store_token(token).await,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => storage.store_token(token).await,
+            Self::Memory(storage) => storage.store_token(token).await,
+        }
+    }
+
+    async fn store_pair(&mut self, pair: &PairInfo) -> Result<()> {
+        match self {
+            Self::Scylla(storage) => storage.store_pair(pair).await,
+            #[cfg(feature = "postgres")]
+            Self::Postgres(storage) => storage.store_pair(pair).await,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => storage.store_pair(pair).await,
+            Self::Memory(storage) => storage.store_pair(pair).await,
+        }
+    }
+
+    async fn tokens(&mut self) -> Result<Vec<TokenInfo>> {
+        match self {
+            Self::Scylla(storage) => storage.tokens().await,
+            #[cfg(feature = "postgres")]
+            Self::Postgres(storage) => storage.tokens().await,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => storage.tokens().await,
+            Self::Memory(storage) => storage.tokens().await,
+        }
+    }
+
+    async fn pairs(&mut self) -> Result<Vec<PairInfo>> {
+        match self {
+            Self::Scylla(storage) => storage.pairs().await,
+            #[cfg(feature = "postgres")]
+            Self::Postgres(storage) => storage.pairs().await,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => storage.pairs().await,
+            Self::Memory(storage) => storage.pairs().await,
+        }
+    }
+}
+
+impl CandleStorage for StorageEngine {
+    async fn store_trades(&mut self, trades: &[Trade]) -> Result<()> {
+        match self {
+            Self::Scylla(storage) => storage.store_trades(trades).await,
+            #[cfg(feature = "postgres")]
+            Self::Postgres(storage) => storage.store_trades(trades).await,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => storage.store_trades(trades).await,
+            Self::Memory(storage) => storage.store_trades(trades).await,
+        }
+    }
+
+    async fn orphan_trades(&mut self, block_number: u64, block_hash: H256) -> Result<Vec<Trade>> {
+        match self {
+            Self::Scylla(storage) => storage.orphan_trades(block_number, block_hash).await,
+            #[cfg(feature = "postgres")]
+            Self::Postgres(storage) => storage.orphan_trades(block_number, block_hash).await,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => storage.orphan_trades(block_number, block_hash).await,
+            Self::Memory(storage) => storage.orphan_trades(block_number, block_hash).await,
+        }
+    }
+
+    async fn trades(&mut self, pair: Option<Address>, from: u64, to: u64) -> Result<Vec<Trade>> {
+        match self {
+            Self::Scylla(storage) => storage.trades(pair, from, to).await,
+            #[cfg(feature = "postgres")]
+            Self::Postgres(storage) => storage.trades(pair, from, to).await,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => storage.trades(pair, from, to).await,
+            Self::Memory(storage) => storage.trades(pair, from, to).await,
+        }
+    }
+
+    async fn store_candle(&mut self, candle: &Candle) -> Result<()> {
+        match self {
+            Self::Scylla(storage) => storage.store_candle(candle).await,
+            #[cfg(feature = "postgres")]
+            Self::Postgres(storage) => storage.store_candle(candle).await,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => storage.store_candle(candle).await,
+            Self::Memory(storage) => storage.store_candle(candle).await,
+        }
+    }
+
+    async fn delete_candles(
+        &mut self,
+        pair: Option<(Address, Interval)>,
+        from: u64,
+        to: u64,
+    ) -> Result<u64> {
+        match self {
+            Self::Scylla(storage) => storage.delete_candles(pair, from, to).await,
+            #[cfg(feature = "postgres")]
+            Self::Postgres(storage) => storage.delete_candles(pair, from, to).await,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => storage.delete_candles(pair, from, to).await,
+            Self::Memory(storage) => storage.delete_candles(pair, from, to).await,
+        }
+    }
+
+    async fn candles(
+        &mut self,
+        pair: Address,
+        interval: Interval,
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<Candle>> {
+        match self {
+            Self::Scylla(storage) => storage.candles(pair, interval, from, to).await,
+            #[cfg(feature = "postgres")]
+            Self::Postgres(storage) => storage.candles(pair, interval, from, to).await,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => storage.candles(pair, interval, from, to).await,
+            Self::Memory(storage) => storage.candles(pair, interval, from, to).await,
+        }
+    }
+}
+
+impl ExpiringStorage for StorageEngine {
+    async fn expire(&mut self, class: DataClass, cutoff: u64, dry_run: bool) -> Result<Expired> {
+        match self {
+            Self::Scylla(storage) => storage.expire(class, cutoff, dry_run).await,
+            #[cfg(feature = "postgres")]
+            Self::Postgres(storage) => storage.expire(class, cutoff, dry_run).await,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => storage.expire(class, cutoff, dry_run).await,
+            Self::Memory(storage) => storage.expire(class, cutoff, dry_run).await,
+        }
+    }
+}
@@ -1,5 +1,17 @@
 #![feature(async_fn_in_trait)]
 
 pub mod block_storage;
+pub mod candle;
+pub mod engine;
+pub mod lifecycle;
+pub mod memory;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod reader;
+pub mod retention;
 pub mod scylla;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod swap;
 pub mod tx_storage;
+pub mod writer;
@@ -0,0 +1,210 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use ethers::types::{Address, H256, U256};
+use log::info;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast::Receiver, oneshot, Mutex};
+
+use crate::writer::{Record, RecordStorage, Retryable, StorageWriter};
+
+/// Where a pending transaction ended up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TxStatus {
+    Pending,
+    Mined,
+    /// Another tx with the same sender and nonce was seen
+    Replaced,
+    /// Neither mined nor replaced before the drop timeout
+    Dropped,
+}
+
+impl TxStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Mined => "mined",
+            Self::Replaced => "replaced",
+            Self::Dropped => "dropped",
+        }
+    }
+}
+
+impl fmt::Display for TxStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for TxStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pending" => Ok(Self::Pending),
+            "mined" => Ok(Self::Mined),
+            "replaced" => Ok(Self::Replaced),
+            "dropped" => Ok(Self::Dropped),
+            _ => bail!("Unknown tx status {}", s),
+        }
+    }
+}
+
+/// Lifecycle of a transaction seen in the mempool, from first sight to inclusion or drop
+///
+/// Times are unix milliseconds as observed locally, so latencies don't depend on block
+/// timestamps which only have second precision.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxLifecycle {
+    pub hash: H256,
+    pub from: Address,
+    pub nonce: U256,
+    pub to: Option<Address>,
+    /// Router the tx was sent to, as `<name>-v<version>`
+    pub router: Option<String>,
+    /// Node the tx was first seen on
+    pub node: String,
+    pub first_seen: u64,
+    pub status: TxStatus,
+    pub block_number: Option<u64>,
+    pub block_hash: Option<H256>,
+    pub transaction_index: Option<u64>,
+    /// When the including block was seen
+    pub mined_at: Option<u64>,
+    /// Hash of the tx that replaced this one
+    pub replaced_by: Option<H256>,
+    /// When the tx was replaced or dropped
+    pub closed_at: Option<u64>,
+}
+
+impl TxLifecycle {
+    /// Milliseconds between first sight and the including block
+    pub fn inclusion_latency(&self) -> Option<u64> {
+        self.mined_at
+            .map(|mined_at| mined_at.saturating_sub(self.first_seen))
+    }
+}
+
+/// Inclusion statistics for txs sent to one router
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RouterLatency {
+    /// Router the txs were sent to, `None` for txs to other contracts
+    pub router: Option<String>,
+    pub mined: u64,
+    pub replaced: u64,
+    pub dropped: u64,
+    pub pending: u64,
+    /// Inclusion latencies of the mined txs in milliseconds
+    pub min_latency: Option<u64>,
+    pub avg_latency: Option<f64>,
+    pub max_latency: Option<u64>,
+}
+
+/// Per-router inclusion stats of `lifecycles`, for backends that can't aggregate them in a query
+///
+/// Routers are sorted by name, with the txs to other contracts last.
+pub fn latency_by_router<'a>(
+    lifecycles: impl IntoIterator<Item = &'a TxLifecycle>,
+) -> Vec<RouterLatency> {
+    let mut stats: BTreeMap<(bool, Option<String>), (RouterLatency, Vec<u64>)> = BTreeMap::new();
+
+    for lifecycle in lifecycles {
+        let (router, latencies) = stats
+            .entry((lifecycle.router.is_none(), lifecycle.router.clone()))
+            .or_insert_with(|| {
+                (
+                    RouterLatency {
+                        router: lifecycle.router.clone(),
+                        ..Default::default()
+                    },
+                    Vec::new(),
+                )
+            });
+
+        match lifecycle.status {
+            TxStatus::Mined => router.mined += 1,
+            TxStatus::Replaced => router.replaced += 1,
+            TxStatus::Dropped => router.dropped += 1,
+            TxStatus::Pending => router.pending += 1,
+        }
+        latencies.extend(lifecycle.inclusion_latency());
+    }
+
+    stats
+        .into_values()
+        .map(|(router, latencies)| RouterLatency {
+            min_latency: latencies.iter().min().copied(),
+            max_latency: latencies.iter().max().copied(),
+            avg_latency: (!latencies.is_empty())
+                .then(|| latencies.iter().sum::<u64>() as f64 / latencies.len() as f64),
+            ..router
+        })
+        .collect()
+}
+
+pub trait LifecycleStorage {
+    /// Insert or update the lifecycle of a tx, keeping the earliest first-seen time
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the lifecycle could not be stored
+    async fn store_lifecycle(&mut self, lifecycle: &TxLifecycle) -> Result<()>;
+
+    /// Lifecycle of a tx, if it was ever seen pending
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the lifecycle could not be queried
+    async fn lifecycle(&mut self, hash: H256) -> Result<Option<TxLifecycle>>;
+
+    /// Per-router inclusion stats for txs first seen at or after `since` (unix milliseconds)
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the lifecycles could not be queried
+    async fn router_latency(&mut self, since: u64) -> Result<Vec<RouterLatency>>;
+}
+
+impl Record for TxLifecycle {
+    const KIND: &'static str = "lifecycle";
+
+    fn hash(&self) -> H256 {
+        self.hash
+    }
+}
+
+impl<C: LifecycleStorage + Retryable> RecordStorage<TxLifecycle> for C {
+    async fn write_record(&mut self, lifecycle: &TxLifecycle) -> Result<()> {
+        self.store_lifecycle(lifecycle).await
+    }
+
+    fn can_retry(&self, error: &anyhow::Error) -> bool {
+        self.is_retryable(error)
+    }
+}
+
+/// Store the lifecycles from `receiver` until it closes or `shutdown` fires
+///
+/// Lifecycles go through `writer`, so a failed write is retried or dead lettered instead of
+/// stopping the updates.
+pub async fn lifecycle_store<C: LifecycleStorage + Retryable>(
+    lifecycle_storage: Arc<Mutex<C>>,
+    receiver: Receiver<TxLifecycle>,
+    writer: Arc<StorageWriter>,
+    shutdown: oneshot::Receiver<()>,
+) -> Result<()> {
+    info!("Starting tx lifecycle updates...");
+
+    let stats = writer
+        .store_records(&lifecycle_storage, receiver, shutdown)
+        .await;
+    info!(
+        "Tx lifecycle updates stopped: {} written, {} dead lettered, {} missed",
+        stats.written, stats.dead_lettered, stats.missed
+    );
+
+    Ok(())
+}
@@ -0,0 +1,656 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use ethers::types::{Address, Block, Transaction, H256, U64};
+use log::{debug, warn};
+use settings::DataClass;
+
+use crate::block_storage::BlockStorage;
+use crate::candle::CandleStorage;
+use crate::lifecycle::{latency_by_router, LifecycleStorage, RouterLatency, TxLifecycle};
+use crate::reader::ChainReader;
+use crate::retention::{Expired, ExpiringStorage};
+use crate::swap::SwapStorage;
+use crate::tx_storage::TxStorage;
+use crate::writer::Retryable;
+use dex::candle::{Candle, Interval, Trade};
+use dex::registry::{PairInfo, Registry, RegistryStorage, TokenInfo};
+use dex::swap::{SwapRecord, SwapStatus};
+
+#[derive(Debug, Default)]
+struct MemoryState {
+    /// Transactions with the unix time they were first stored
+    txs: HashMap<H256, (Transaction, u64)>,
+    blocks: HashMap<H256, Block<H256>>,
+    /// Hash of the canonical block at each height
+    canonical: BTreeMap<u64, H256>,
+    lifecycles: HashMap<H256, TxLifecycle>,
+    /// Swaps by tx and position, with the unix time they were first stored
+    swaps: BTreeMap<(H256, u32), (SwapRecord, u64)>,
+    registry: Registry,
+    /// Trades by block and position in the block
+    trades: HashMap<(H256, u64), Trade>,
+    candles: BTreeMap<(Address, Interval, u64), Candle>,
+}
+
+impl MemoryState {
+    fn set_inclusion(&mut self, block: H256, number: Option<U64>, txs: &[H256]) {
+        for (index, hash) in txs.iter().enumerate() {
+            if let Some((tx, _)) = self.txs.get_mut(hash) {
+                tx.block_hash = Some(block);
+                tx.block_number = number;
+                tx.transaction_index = Some(U64::from(index));
+            }
+        }
+    }
+
+    /// Clear the inclusion of the txs that point at `block`
+    fn clear_inclusion(&mut self, block: H256) -> usize {
+        let Some(txs) = self.blocks.get(&block).map(|b| b.transactions.clone()) else {
+            return 0;
+        };
+
+        let mut cleared = 0;
+        for hash in txs {
+            if let Some((tx, _)) = self.txs.get_mut(&hash) {
+                if tx.block_hash == Some(block) {
+                    tx.block_hash = None;
+                    tx.block_number = None;
+                    tx.transaction_index = None;
+                    cleared += 1;
+                }
+            }
+        }
+
+        cleared
+    }
+
+    fn is_canonical(&self, block: &Block<H256>) -> bool {
+        block
+            .number
+            .and_then(|number| self.canonical.get(&number.as_u64()))
+            .is_some_and(|canonical| Some(*canonical) == block.hash)
+    }
+
+    /// Make `head` the canonical chain tip, like the SQL backends do
+    fn adopt_head(&mut self, head: &Block<H256>) {
+        let (Some(hash), Some(number)) = (head.hash, head.number) else {
+            return;
+        };
+
+        let number = number.as_u64();
+        let replaces = self
+            .canonical
+            .get(&number)
+            .is_some_and(|canonical| *canonical != hash);
+        let forks = number
+            .checked_sub(1)
+            .and_then(|parent| self.canonical.get(&parent))
+            .is_some_and(|parent| *parent != head.parent_hash);
+        if replaces || forks {
+            let above = self
+                .canonical
+                .split_off(&(number + 1))
+                .into_values()
+                .collect::<Vec<H256>>();
+            let orphaned = above
+                .into_iter()
+                .map(|block| self.clear_inclusion(block))
+                .sum::<usize>();
+            if orphaned > 0 {
+                debug!("Reorg to {:?} dropped {} txs above it", hash, orphaned);
+            }
+        }
+
+        let mut current = hash;
+        while let Some(block) = self.blocks.get(&current).cloned() {
+            let number = block.number.unwrap_or_default().as_u64();
+            if self.canonical.get(&number) == Some(&current) {
+                break;
+            }
+
+            if let Some(replaced) = self.canonical.insert(number, current) {
+                let orphaned = self.clear_inclusion(replaced);
+                warn!(
+                    "Reorg at block {}: {:?} is now canonical, {} txs lost their inclusion",
+                    number, current, orphaned
+                );
+            }
+            self.set_inclusion(current, block.number, &block.transactions);
+
+            current = block.parent_hash;
+        }
+    }
+
+    /// Whether a tx should be visible to readers, i.e. not included in an orphaned block
+    fn is_visible(&self, tx: &Transaction) -> bool {
+        tx.block_hash
+            .and_then(|hash| self.blocks.get(&hash))
+            .is_none_or(|block| self.is_canonical(block))
+    }
+}
+
+/// Storage that keeps everything in memory
+///
+/// Clones share the same data, so one store can be handed to several writers and read back
+/// from a test. `MemoryStorage::shared` returns the process wide store used by the `memory`
+/// backend in single-process dev mode. Nothing survives a restart.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStorage {
+    state: Arc<Mutex<MemoryState>>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The store shared by the whole process
+    pub fn shared() -> Self {
+        static SHARED: OnceLock<MemoryStorage> = OnceLock::new();
+
+        SHARED.get_or_init(MemoryStorage::new).clone()
+    }
+
+    fn state(&self) -> MutexGuard<'_, MemoryState> {
+        // A panic while holding the lock can't leave the maps half updated in a way that
+        // matters more than losing the store, so keep going with whatever is there
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Number of stored transactions
+    pub fn tx_count(&self) -> usize {
+        self.state().txs.len()
+    }
+
+    /// Number of stored blocks, canonical or not
+    pub fn block_count(&self) -> usize {
+        self.state().blocks.len()
+    }
+
+    /// A stored transaction, with its current inclusion
+    pub fn tx(&self, hash: H256) -> Option<Transaction> {
+        self.state().txs.get(&hash).map(|(tx, _)| tx.clone())
+    }
+
+    /// All stored transactions, in no particular order
+    pub fn txs(&self) -> Vec<Transaction> {
+        self.state()
+            .txs
+            .values()
+            .map(|(tx, _)| tx.clone())
+            .collect()
+    }
+
+    /// A stored block, canonical or not
+    pub fn block(&self, hash: H256) -> Option<Block<H256>> {
+        self.state().blocks.get(&hash).cloned()
+    }
+
+    /// Hash of the canonical block at `number`
+    pub fn canonical_hash(&self, number: u64) -> Option<H256> {
+        self.state().canonical.get(&number).copied()
+    }
+
+    /// All stored tx lifecycles, in no particular order
+    pub fn lifecycles(&self) -> Vec<TxLifecycle> {
+        self.state().lifecycles.values().cloned().collect()
+    }
+
+    /// All stored swaps, ordered by tx hash and position
+    pub fn all_swaps(&self) -> Vec<SwapRecord> {
+        self.state()
+            .swaps
+            .values()
+            .map(|(swap, _)| swap.clone())
+            .collect()
+    }
+
+    /// Remove everything from the store
+    pub fn clear(&self) {
+        *self.state() = MemoryState::default();
+    }
+}
+
+impl TxStorage<Transaction> for MemoryStorage {
+    async fn store(&mut self, tx: Transaction) -> Result<()> {
+        debug!("Storing tx: {:#?}", tx.hash);
+
+        let mut state = self.state();
+        match state.txs.get_mut(&tx.hash) {
+            // Like the SQL backends, only the inclusion of an already stored tx is updated
+            Some((stored, _)) => {
+                stored.block_hash = tx.block_hash;
+                stored.block_number = tx.block_number;
+                stored.transaction_index = tx.transaction_index;
+            }
+            None => {
+                state.txs.insert(tx.hash, (tx, now()));
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn is_stored(&mut self, tx: Transaction) -> Result<bool> {
+        debug!("Checking if tx is stored: {:#?}", tx.hash);
+
+        Ok(self.state().txs.contains_key(&tx.hash))
+    }
+
+    async fn get(&mut self, hash: H256) -> Result<Option<Transaction>> {
+        Ok(self.tx(hash))
+    }
+
+    async fn store_many(&mut self, txs: Vec<Transaction>) -> Result<()> {
+        for tx in txs {
+            TxStorage::store(self, tx).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn exists_many(&mut self, hashes: &[H256]) -> Result<HashSet<H256>> {
+        let state = self.state();
+
+        Ok(hashes
+            .iter()
+            .filter(|hash| state.txs.contains_key(hash))
+            .copied()
+            .collect())
+    }
+
+    async fn delete(&mut self, tx: Transaction) -> Result<()> {
+        debug!("Deleting tx: {:#?}", tx.hash);
+
+        self.state().txs.remove(&tx.hash);
+
+        Ok(())
+    }
+}
+
+impl Retryable for MemoryStorage {
+    /// Writes to memory can't fail transiently
+    fn is_retryable(&self, _error: &anyhow::Error) -> bool {
+        false
+    }
+}
+
+impl BlockStorage<Block<H256>> for MemoryStorage {
+    /// Store a block as the new chain head, marking it and its stored ancestors canonical
+    async fn store(&mut self, block: Block<H256>) -> Result<()> {
+        debug!("Storing block: {:#?}", block.hash);
+
+        let mut state = self.state();
+        state
+            .blocks
+            .insert(block.hash.unwrap_or_default(), block.clone());
+        state.adopt_head(&block);
+
+        Ok(())
+    }
+
+    async fn is_stored(&mut self, block: Block<H256>) -> Result<bool> {
+        debug!("Checking if block is stored: {:#?}", block.hash);
+
+        Ok(self
+            .state()
+            .blocks
+            .contains_key(&block.hash.unwrap_or_default()))
+    }
+
+    async fn delete(&mut self, block: Block<H256>) -> Result<()> {
+        debug!("Deleting block: {:#?}", block.hash);
+
+        let hash = block.hash.unwrap_or_default();
+        let mut state = self.state();
+        state.clear_inclusion(hash);
+        state.canonical.retain(|_, canonical| *canonical != hash);
+        state.blocks.remove(&hash);
+
+        Ok(())
+    }
+}
+
+impl ChainReader for MemoryStorage {
+    async fn latest_block_number(&mut self) -> Result<Option<u64>> {
+        Ok(self.state().canonical.keys().next_back().copied())
+    }
+
+    async fn blocks_in_range(&mut self, from: u64, to: u64) -> Result<Vec<Block<H256>>> {
+        let state = self.state();
+
+        Ok(state
+            .canonical
+            .range(from..=to)
+            .filter_map(|(_, hash)| state.blocks.get(hash).cloned())
+            .collect())
+    }
+
+    async fn txs_in_range(&mut self, from: u64, to: u64) -> Result<Vec<Transaction>> {
+        let state = self.state();
+        let mut txs = state
+            .txs
+            .values()
+            .map(|(tx, _)| tx)
+            .filter(|tx| {
+                tx.block_number
+                    .is_some_and(|n| (from..=to).contains(&n.as_u64()))
+            })
+            .filter(|tx| state.is_visible(tx))
+            .cloned()
+            .collect::<Vec<Transaction>>();
+        txs.sort_by_key(|tx| (tx.block_number, tx.transaction_index));
+
+        Ok(txs)
+    }
+
+    async fn block_range_for_time(&mut self, from: u64, to: u64) -> Result<Option<(u64, u64)>> {
+        let state = self.state();
+        let numbers = state
+            .canonical
+            .iter()
+            .filter(|(_, hash)| {
+                state
+                    .blocks
+                    .get(hash)
+                    .is_some_and(|b| (from..=to).contains(&b.timestamp.as_u64()))
+            })
+            .map(|(number, _)| *number)
+            .collect::<Vec<u64>>();
+
+        Ok(numbers.first().copied().zip(numbers.last().copied()))
+    }
+
+    async fn orphaned_blocks_in_range(&mut self, from: u64, to: u64) -> Result<Vec<Block<H256>>> {
+        let state = self.state();
+        let mut blocks = state
+            .blocks
+            .values()
+            .filter(|b| b.number.is_some_and(|n| (from..=to).contains(&n.as_u64())))
+            .filter(|b| !state.is_canonical(b))
+            .cloned()
+            .collect::<Vec<Block<H256>>>();
+        blocks.sort_by_key(|b| b.number);
+
+        Ok(blocks)
+    }
+}
+
+impl LifecycleStorage for MemoryStorage {
+    async fn store_lifecycle(&mut self, lifecycle: &TxLifecycle) -> Result<()> {
+        debug!(
+            "Storing lifecycle of tx {:#?}: {}",
+            lifecycle.hash, lifecycle.status
+        );
+
+        let mut state = self.state();
+        let stored = match state.lifecycles.get(&lifecycle.hash) {
+            Some(stored) => TxLifecycle {
+                first_seen: stored.first_seen.min(lifecycle.first_seen),
+                router: stored.router.clone().or_else(|| lifecycle.router.clone()),
+                node: stored.node.clone(),
+                ..lifecycle.clone()
+            },
+            None => lifecycle.clone(),
+        };
+        state.lifecycles.insert(lifecycle.hash, stored);
+
+        Ok(())
+    }
+
+    async fn lifecycle(&mut self, hash: H256) -> Result<Option<TxLifecycle>> {
+        Ok(self.state().lifecycles.get(&hash).cloned())
+    }
+
+    async fn router_latency(&mut self, since: u64) -> Result<Vec<RouterLatency>> {
+        Ok(latency_by_router(
+            self.state()
+                .lifecycles
+                .values()
+                .filter(|l| l.first_seen >= since),
+        ))
+    }
+}
+
+impl SwapStorage for MemoryStorage {
+    async fn store_swap(&mut self, swap: &SwapRecord) -> Result<()> {
+        debug!(
+            "Storing swap {} of tx {:#?}: {}",
+            swap.swap_index, swap.tx_hash, swap.status
+        );
+
+        let mut state = self.state();
+        match state.swaps.get_mut(&(swap.tx_hash, swap.swap_index)) {
+            Some((stored, _)) => {
+                if stored.status == SwapStatus::Pending || swap.status == SwapStatus::Mined {
+                    stored.status = swap.status;
+                    stored.block_number = swap.block_number;
+                    stored.block_hash = swap.block_hash;
+                    stored.transaction_index = swap.transaction_index;
+                }
+            }
+            None => {
+                state
+                    .swaps
+                    .insert((swap.tx_hash, swap.swap_index), (swap.clone(), now()));
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn swaps(&mut self, tx_hash: H256) -> Result<Vec<SwapRecord>> {
+        Ok(self
+            .state()
+            .swaps
+            .range((tx_hash, 0)..=(tx_hash, u32::MAX))
+            .map(|(_, (swap, _))| swap.clone())
+            .collect())
+    }
+
+    async fn swaps_in_range(&mut self, from: u64, to: u64) -> Result<Vec<SwapRecord>> {
+        let state = self.state();
+        let mut swaps = state
+            .swaps
+            .values()
+            .map(|(swap, _)| swap)
+            .filter(|swap| swap.status == SwapStatus::Mined)
+            .filter(|swap| swap.block_number.is_some_and(|n| (from..=to).contains(&n)))
+            .filter(|swap| {
+                swap.block_hash
+                    .and_then(|hash| state.blocks.get(&hash))
+                    .is_none_or(|block| state.is_canonical(block))
+            })
+            .cloned()
+            .collect::<Vec<SwapRecord>>();
+        swaps.sort_by_key(|swap| (swap.block_number, swap.transaction_index, swap.swap_index));
+
+        Ok(swaps)
+    }
+}
+
+impl RegistryStorage for MemoryStorage {
+    async fn store_token(&mut self, token: &TokenInfo) -> Result<()> {
+        self.state().registry.insert_token(token.clone());
+
+        Ok(())
+    }
+
+    async fn store_pair(&mut self, pair: &PairInfo) -> Result<()> {
+        let mut state = self.state();
+        if state.registry.pair(pair.address).is_none() {
+            state.registry.insert_pair(pair.clone());
+        }
+
+        Ok(())
+    }
+
+    async fn tokens(&mut self) -> Result<Vec<TokenInfo>> {
+        Ok(self.state().registry.tokens().cloned().collect())
+    }
+
+    async fn pairs(&mut self) -> Result<Vec<PairInfo>> {
+        let mut pairs = self
+            .state()
+            .registry
+            .pairs()
+            .cloned()
+            .collect::<Vec<PairInfo>>();
+        pairs.sort_by_key(|pair| pair.created_block);
+
+        Ok(pairs)
+    }
+}
+
+impl CandleStorage for MemoryStorage {
+    async fn store_trades(&mut self, trades: &[Trade]) -> Result<()> {
+        let mut state = self.state();
+        for trade in trades {
+            state
+                .trades
+                .entry((trade.block_hash, trade.log_index))
+                .or_insert_with(|| trade.clone());
+        }
+
+        Ok(())
+    }
+
+    async fn orphan_trades(&mut self, block_number: u64, block_hash: H256) -> Result<Vec<Trade>> {
+        let mut state = self.state();
+        let orphaned = state
+            .trades
+            .iter()
+            .filter(|(_, trade)| {
+                trade.block_number > block_number
+                    || (trade.block_number == block_number && trade.block_hash != block_hash)
+            })
+            .map(|(key, _)| *key)
+            .collect::<Vec<(H256, u64)>>();
+
+        Ok(orphaned
+            .iter()
+            .filter_map(|key| state.trades.remove(key))
+            .collect())
+    }
+
+    async fn trades(&mut self, pair: Option<Address>, from: u64, to: u64) -> Result<Vec<Trade>> {
+        let mut trades = self
+            .state()
+            .trades
+            .values()
+            .filter(|trade| (from..=to).contains(&trade.timestamp))
+            .filter(|trade| pair.is_none_or(|pair| trade.pair == pair))
+            .cloned()
+            .collect::<Vec<Trade>>();
+        trades.sort_by_key(|trade| (trade.block_number, trade.log_index));
+
+        Ok(trades)
+    }
+
+    async fn store_candle(&mut self, candle: &Candle) -> Result<()> {
+        self.state()
+            .candles
+            .insert((candle.pair, candle.interval, candle.start), candle.clone());
+
+        Ok(())
+    }
+
+    async fn delete_candles(
+        &mut self,
+        pair: Option<(Address, Interval)>,
+        from: u64,
+        to: u64,
+    ) -> Result<u64> {
+        let mut state = self.state();
+        let before = state.candles.len();
+
+        state.candles.retain(|(candle_pair, interval, start), _| {
+            !((from..=to).contains(start)
+                && pair.is_none_or(|pair| pair == (*candle_pair, *interval)))
+        });
+
+        Ok((before - state.candles.len()) as u64)
+    }
+
+    async fn candles(
+        &mut self,
+        pair: Address,
+        interval: Interval,
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<Candle>> {
+        Ok(self
+            .state()
+            .candles
+            .range((pair, interval, from)..=(pair, interval, to))
+            .map(|(_, candle)| candle.clone())
+            .collect())
+    }
+}
+
+impl ExpiringStorage for MemoryStorage {
+    async fn expire(&mut self, class: DataClass, cutoff: u64, dry_run: bool) -> Result<Expired> {
+        let mut state = self.state();
+
+        if class == DataClass::DecodedEvents {
+            let expired = state
+                .swaps
+                .iter()
+                .filter(|(_, (_, stored_at))| *stored_at < cutoff)
+                .map(|(key, _)| *key)
+                .collect::<Vec<(H256, u32)>>();
+
+            if !dry_run {
+                for key in &expired {
+                    state.swaps.remove(key);
+                }
+            }
+            debug!("Expired {} {} older than {}", expired.len(), class, cutoff);
+
+            return Ok(Expired::Rows(expired.len() as u64));
+        }
+
+        if !matches!(class, DataClass::PendingTxs | DataClass::MinedTxs) {
+            return Ok(Expired::NotStored);
+        }
+
+        // Mined txs age with their block, falling back to when they were stored
+        let expired = state
+            .txs
+            .values()
+            .filter(|(tx, stored_at)| match (class, tx.block_hash) {
+                (DataClass::PendingTxs, None) => *stored_at < cutoff,
+                (DataClass::MinedTxs, Some(block)) => {
+                    state
+                        .blocks
+                        .get(&block)
+                        .map_or(*stored_at, |b| b.timestamp.as_u64())
+                        < cutoff
+                }
+                _ => false,
+            })
+            .map(|(tx, _)| tx.hash)
+            .collect::<Vec<H256>>();
+
+        if !dry_run {
+            for hash in &expired {
+                state.txs.remove(hash);
+            }
+        }
+        debug!("Expired {} {} older than {}", expired.len(), class, cutoff);
+
+        Ok(Expired::Rows(expired.len() as u64))
+    }
+}
@@ -0,0 +1,1879 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::pin::pin;
+
+use anyhow::Result;
+use bytes::{Buf, BufMut, BytesMut};
+use ethers::types::{Address, Block, Bloom, Bytes, Transaction, H256, H64, U256, U64};
+use log::{debug, info, warn};
+use settings::DataClass;
+use tokio_postgres::{
+    binary_copy::BinaryCopyInWriter,
+    error::SqlState,
+    types::{accepts, to_sql_checked, FromSql, IsNull, ToSql, Type},
+    Client, NoTls, Row, Statement,
+};
+
+use crate::block_storage::BlockStorage;
+use crate::candle::CandleStorage;
+use crate::lifecycle::{LifecycleStorage, RouterLatency, TxLifecycle};
+use crate::reader::ChainReader;
+use crate::retention::{Expired, ExpiringStorage};
+use crate::swap::{fees_from_bytes, fees_to_bytes, path_from_bytes, path_to_bytes, SwapStorage};
+use crate::tx_storage::TxStorage;
+use crate::writer::Retryable;
+use dex::candle::{Candle, Interval, Trade};
+use dex::registry::{PairInfo, RegistryStorage, TokenInfo};
+use dex::swap::SwapRecord;
+
+/// Unsigned 256 bit integer stored as a postgres `numeric`
+///
+/// Wei amounts routinely overflow `bigint`, so they are written using the binary `numeric`
+/// wire format (base 10000 digits) instead of being truncated.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Numeric(pub U256);
+
+const NUMERIC_BASE: u64 = 10_000;
+const NUMERIC_POS: u16 = 0x0000;
+
+impl ToSql for Numeric {
+    fn to_sql(
+        &self,
+        _ty: &Type,
+        out: &mut BytesMut,
+    ) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        let base = U256::from(NUMERIC_BASE);
+
+        let mut digits = Vec::<i16>::new();
+        let mut value = self.0;
+        while !value.is_zero() {
+            let (quotient, remainder) = value.div_mod(base);
+            digits.push(remainder.as_u64() as i16);
+            value = quotient;
+        }
+        digits.reverse();
+
+        let weight = (digits.len() as i16 - 1).max(0);
+        while digits.last() == Some(&0) {
+            digits.pop();
+        }
+
+        out.put_i16(digits.len() as i16);
+        out.put_i16(weight);
+        out.put_u16(NUMERIC_POS);
+        out.put_i16(0);
+        for digit in digits {
+            out.put_i16(digit);
+        }
+
+        Ok(IsNull::No)
+    }
+
+    accepts!(NUMERIC);
+    to_sql_checked!();
+}
+
+impl<'a> FromSql<'a> for Numeric {
+    fn from_sql(_ty: &Type, mut raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        let ndigits = raw.get_i16();
+        let weight = raw.get_i16();
+        let sign = raw.get_u16();
+        let _dscale = raw.get_i16();
+
+        if sign != NUMERIC_POS {
+            return Err("numeric value is negative or NaN".into());
+        }
+
+        let digits = (0..ndigits).map(|_| raw.get_i16()).collect::<Vec<i16>>();
+        let integer_digits = (weight + 1).max(0) as usize;
+        if digits.iter().skip(integer_digits).any(|d| *d != 0) {
+            return Err("numeric value has a fractional part".into());
+        }
+
+        let mut value = U256::zero();
+        for i in 0..=weight {
+            let digit = digits.get(i as usize).copied().unwrap_or_default();
+            value = value
+                .checked_mul(U256::from(NUMERIC_BASE))
+                .and_then(|v| v.checked_add(U256::from(digit as u64)))
+                .ok_or("numeric value does not fit in 256 bits")?;
+        }
+
+        Ok(Self(value))
+    }
+
+    accepts!(NUMERIC);
+}
+
+/// A transaction laid out as a row of the `transactions` table
+struct TxRow {
+    hash: Vec<u8>,
+    nonce: Numeric,
+    block_hash: Option<Vec<u8>>,
+    block_number: Option<i64>,
+    transaction_index: Option<i64>,
+    from_address: Vec<u8>,
+    to_address: Option<Vec<u8>>,
+    value: Numeric,
+    gas_price: Option<Numeric>,
+    gas: Numeric,
+    input: Vec<u8>,
+    transaction_type: Option<i16>,
+    max_priority_fee_per_gas: Option<Numeric>,
+    max_fee_per_gas: Option<Numeric>,
+    chain_id: Option<i64>,
+}
+
+const TX_COLUMNS: &str = "hash, nonce, block_hash, block_number, transaction_index, from_address, \
+    to_address, value, gas_price, gas, input, type, max_priority_fee_per_gas, max_fee_per_gas, chain_id";
+
+const TX_TYPES: [Type; 15] = [
+    Type::BYTEA,
+    Type::NUMERIC,
+    Type::BYTEA,
+    Type::INT8,
+    Type::INT8,
+    Type::BYTEA,
+    Type::BYTEA,
+    Type::NUMERIC,
+    Type::NUMERIC,
+    Type::NUMERIC,
+    Type::BYTEA,
+    Type::INT2,
+    Type::NUMERIC,
+    Type::NUMERIC,
+    Type::INT8,
+];
+
+impl From<&Transaction> for TxRow {
+    fn from(tx: &Transaction) -> Self {
+        Self {
+            hash: tx.hash.as_bytes().to_vec(),
+            nonce: Numeric(tx.nonce),
+            block_hash: tx.block_hash.map(|h| h.as_bytes().to_vec()),
+            block_number: tx.block_number.map(|n| n.as_u64() as i64),
+            transaction_index: tx.transaction_index.map(|i| i.as_u64() as i64),
+            from_address: tx.from.as_bytes().to_vec(),
+            to_address: tx.to.map(|a| a.as_bytes().to_vec()),
+            value: Numeric(tx.value),
+            gas_price: tx.gas_price.map(Numeric),
+            gas: Numeric(tx.gas),
+            input: tx.input.to_vec(),
+            transaction_type: tx.transaction_type.map(|t| t.as_u64() as i16),
+            max_priority_fee_per_gas: tx.max_priority_fee_per_gas.map(Numeric),
+            max_fee_per_gas: tx.max_fee_per_gas.map(Numeric),
+            chain_id: tx.chain_id.map(|c| c.as_u64() as i64),
+        }
+    }
+}
+
+impl TxRow {
+    fn params(&self) -> [&(dyn ToSql + Sync); 15] {
+        [
+            &self.hash,
+            &self.nonce,
+            &self.block_hash,
+            &self.block_number,
+            &self.transaction_index,
+            &self.from_address,
+            &self.to_address,
+            &self.value,
+            &self.gas_price,
+            &self.gas,
+            &self.input,
+            &self.transaction_type,
+            &self.max_priority_fee_per_gas,
+            &self.max_fee_per_gas,
+            &self.chain_id,
+        ]
+    }
+}
+
+/// A block laid out as a row of the `blocks` table
+struct BlockRow {
+    hash: Vec<u8>,
+    number: Option<i64>,
+    parent_hash: Vec<u8>,
+    nonce: Option<Vec<u8>>,
+    uncles_hash: Vec<u8>,
+    logs_bloom: Option<Vec<u8>>,
+    transactions_root: Vec<u8>,
+    state_root: Vec<u8>,
+    receipts_root: Vec<u8>,
+    miner: Option<Vec<u8>>,
+    difficulty: Numeric,
+    total_difficulty: Option<Numeric>,
+    size: Option<i64>,
+    extra_data: Vec<u8>,
+    gas_limit: Numeric,
+    gas_used: Numeric,
+    base_fee_per_gas: Option<Numeric>,
+    timestamp: i64,
+    transactions: Vec<Vec<u8>>,
+    uncles: Vec<Vec<u8>>,
+}
+
+const BLOCK_COLUMNS: &str = "hash, number, parent_hash, nonce, uncles_hash, logs_bloom, \
+    transactions_root, state_root, receipts_root, miner, difficulty, total_difficulty, size, \
+    extra_data, gas_limit, gas_used, base_fee_per_gas, timestamp, transactions, uncles";
+
+const BLOCK_TYPES: [Type; 20] = [
+    Type::BYTEA,
+    Type::INT8,
+    Type::BYTEA,
+    Type::BYTEA,
+    Type::BYTEA,
+    Type::BYTEA,
+    Type::BYTEA,
+    Type::BYTEA,
+    Type::BYTEA,
+    Type::BYTEA,
+    Type::NUMERIC,
+    Type::NUMERIC,
+    Type::INT8,
+    Type::BYTEA,
+    Type::NUMERIC,
+    Type::NUMERIC,
+    Type::NUMERIC,
+    Type::INT8,
+    Type::BYTEA_ARRAY,
+    Type::BYTEA_ARRAY,
+];
+
+impl From<&Block<H256>> for BlockRow {
+    fn from(block: &Block<H256>) -> Self {
+        Self {
+            hash: block.hash.unwrap_or_default().as_bytes().to_vec(),
+            number: block.number.map(|n| n.as_u64() as i64),
+            parent_hash: block.parent_hash.as_bytes().to_vec(),
+            nonce: block.nonce.map(|n| n.as_bytes().to_vec()),
+            uncles_hash: block.uncles_hash.as_bytes().to_vec(),
+            logs_bloom: block.logs_bloom.map(|b| b.as_bytes().to_vec()),
+            transactions_root: block.transactions_root.as_bytes().to_vec(),
+            state_root: block.state_root.as_bytes().to_vec(),
+            receipts_root: block.receipts_root.as_bytes().to_vec(),
+            miner: block.author.map(|a| a.as_bytes().to_vec()),
+            difficulty: Numeric(block.difficulty),
+            total_difficulty: block.total_difficulty.map(Numeric),
+            size: block.size.map(|s| s.as_u64() as i64),
+            extra_data: block.extra_data.to_vec(),
+            gas_limit: Numeric(block.gas_limit),
+            gas_used: Numeric(block.gas_used),
+            base_fee_per_gas: block.base_fee_per_gas.map(Numeric),
+            timestamp: block.timestamp.as_u64() as i64,
+            transactions: block
+                .transactions
+                .iter()
+                .map(|tx| tx.as_bytes().to_vec())
+                .collect(),
+            uncles: block
+                .uncles
+                .iter()
+                .map(|uncle| uncle.as_bytes().to_vec())
+                .collect(),
+        }
+    }
+}
+
+impl BlockRow {
+    fn params(&self) -> [&(dyn ToSql + Sync); 20] {
+        [
+            &self.hash,
+            &self.number,
+            &self.parent_hash,
+            &self.nonce,
+            &self.uncles_hash,
+            &self.logs_bloom,
+            &self.transactions_root,
+            &self.state_root,
+            &self.receipts_root,
+            &self.miner,
+            &self.difficulty,
+            &self.total_difficulty,
+            &self.size,
+            &self.extra_data,
+            &self.gas_limit,
+            &self.gas_used,
+            &self.base_fee_per_gas,
+            &self.timestamp,
+            &self.transactions,
+            &self.uncles,
+        ]
+    }
+}
+
+fn tx_from_row(row: &Row) -> Transaction {
+    Transaction {
+        hash: H256::from_slice(row.get("hash")),
+        nonce: row.get::<_, Numeric>("nonce").0,
+        block_hash: row
+            .get::<_, Option<&[u8]>>("block_hash")
+            .map(H256::from_slice),
+        block_number: row
+            .get::<_, Option<i64>>("block_number")
+            .map(|n| U64::from(n as u64)),
+        transaction_index: row
+            .get::<_, Option<i64>>("transaction_index")
+            .map(|i| U64::from(i as u64)),
+        from: Address::from_slice(row.get("from_address")),
+        to: row
+            .get::<_, Option<&[u8]>>("to_address")
+            .map(Address::from_slice),
+        value: row.get::<_, Numeric>("value").0,
+        gas_price: row.get::<_, Option<Numeric>>("gas_price").map(|p| p.0),
+        gas: row.get::<_, Numeric>("gas").0,
+        input: Bytes::from(row.get::<_, Vec<u8>>("input")),
+        transaction_type: row
+            .get::<_, Option<i16>>("type")
+            .map(|t| U64::from(t as u64)),
+        max_priority_fee_per_gas: row
+            .get::<_, Option<Numeric>>("max_priority_fee_per_gas")
+            .map(|f| f.0),
+        max_fee_per_gas: row
+            .get::<_, Option<Numeric>>("max_fee_per_gas")
+            .map(|f| f.0),
+        chain_id: row
+            .get::<_, Option<i64>>("chain_id")
+            .map(|c| U256::from(c as u64)),
+        ..Default::default()
+    }
+}
+
+fn block_from_row(row: &Row) -> Block<H256> {
+    Block {
+        hash: Some(H256::from_slice(row.get("hash"))),
+        number: row
+            .get::<_, Option<i64>>("number")
+            .map(|n| U64::from(n as u64)),
+        parent_hash: H256::from_slice(row.get("parent_hash")),
+        nonce: row.get::<_, Option<&[u8]>>("nonce").map(H64::from_slice),
+        uncles_hash: H256::from_slice(row.get("uncles_hash")),
+        logs_bloom: row
+            .get::<_, Option<&[u8]>>("logs_bloom")
+            .map(Bloom::from_slice),
+        transactions_root: H256::from_slice(row.get("transactions_root")),
+        state_root: H256::from_slice(row.get("state_root")),
+        receipts_root: H256::from_slice(row.get("receipts_root")),
+        author: row
+            .get::<_, Option<&[u8]>>("miner")
+            .map(Address::from_slice),
+        difficulty: row.get::<_, Numeric>("difficulty").0,
+        total_difficulty: row
+            .get::<_, Option<Numeric>>("total_difficulty")
+            .map(|d| d.0),
+        size: row
+            .get::<_, Option<i64>>("size")
+            .map(|s| U256::from(s as u64)),
+        extra_data: Bytes::from(row.get::<_, Vec<u8>>("extra_data")),
+        gas_limit: row.get::<_, Numeric>("gas_limit").0,
+        gas_used: row.get::<_, Numeric>("gas_used").0,
+        base_fee_per_gas: row
+            .get::<_, Option<Numeric>>("base_fee_per_gas")
+            .map(|f| f.0),
+        timestamp: U256::from(row.get::<_, i64>("timestamp") as u64),
+        transactions: row
+            .get::<_, Vec<&[u8]>>("transactions")
+            .into_iter()
+            .map(H256::from_slice)
+            .collect(),
+        uncles: row
+            .get::<_, Vec<&[u8]>>("uncles")
+            .into_iter()
+            .map(H256::from_slice)
+            .collect(),
+        ..Default::default()
+    }
+}
+
+const LIFECYCLE_COLUMNS: &str = "hash, from_address, nonce, to_address, router, node, \
+    first_seen, status, block_number, block_hash, transaction_index, mined_at, replaced_by, closed_at";
+
+fn lifecycle_from_row(row: &Row) -> Result<TxLifecycle> {
+    Ok(TxLifecycle {
+        hash: H256::from_slice(row.get("hash")),
+        from: Address::from_slice(row.get("from_address")),
+        nonce: row.get::<_, Numeric>("nonce").0,
+        to: row
+            .get::<_, Option<&[u8]>>("to_address")
+            .map(Address::from_slice),
+        router: row.get("router"),
+        node: row.get("node"),
+        first_seen: row.get::<_, i64>("first_seen") as u64,
+        status: row.get::<_, &str>("status").parse()?,
+        block_number: row.get::<_, Option<i64>>("block_number").map(|n| n as u64),
+        block_hash: row
+            .get::<_, Option<&[u8]>>("block_hash")
+            .map(H256::from_slice),
+        transaction_index: row
+            .get::<_, Option<i64>>("transaction_index")
+            .map(|i| i as u64),
+        mined_at: row.get::<_, Option<i64>>("mined_at").map(|t| t as u64),
+        replaced_by: row
+            .get::<_, Option<&[u8]>>("replaced_by")
+            .map(H256::from_slice),
+        closed_at: row.get::<_, Option<i64>>("closed_at").map(|t| t as u64),
+    })
+}
+
+const SWAP_COLUMNS: &str = "tx_hash, swap_index, router, protocol_version, method, sender, \
+    recipient, path, fees, kind, amount_in, amount_out, deadline, status, block_number, \
+    block_hash, transaction_index";
+
+fn swap_from_row(row: &Row) -> Result<SwapRecord> {
+    Ok(SwapRecord {
+        tx_hash: H256::from_slice(row.get("tx_hash")),
+        swap_index: row.get::<_, i32>("swap_index") as u32,
+        router: row.get("router"),
+        protocol_version: row.get::<_, i16>("protocol_version") as u8,
+        method: row.get("method"),
+        sender: Address::from_slice(row.get("sender")),
+        recipient: row
+            .get::<_, Option<&[u8]>>("recipient")
+            .map(Address::from_slice),
+        path: path_from_bytes(row.get("path")),
+        fees: fees_from_bytes(row.get("fees")),
+        kind: row.get::<_, &str>("kind").parse()?,
+        amount_in: row.get::<_, Numeric>("amount_in").0,
+        amount_out: row.get::<_, Numeric>("amount_out").0,
+        deadline: row.get::<_, Option<i64>>("deadline").map(|d| d as u64),
+        status: row.get::<_, &str>("status").parse()?,
+        block_number: row.get::<_, Option<i64>>("block_number").map(|n| n as u64),
+        block_hash: row
+            .get::<_, Option<&[u8]>>("block_hash")
+            .map(H256::from_slice),
+        transaction_index: row
+            .get::<_, Option<i64>>("transaction_index")
+            .map(|i| i as u64),
+        score: None,
+        amounts: None,
+    })
+}
+
+const TOKEN_COLUMNS: &str = "address, name, symbol, decimals, first_seen_block";
+
+const PAIR_COLUMNS: &str = "address, factory, protocol_version, token0, token1, fee, created_block";
+
+fn token_from_row(row: &Row) -> TokenInfo {
+    TokenInfo {
+        address: Address::from_slice(row.get("address")),
+        name: row.get("name"),
+        symbol: row.get("symbol"),
+        decimals: row.get::<_, i16>("decimals") as u8,
+        first_seen_block: row
+            .get::<_, Option<i64>>("first_seen_block")
+            .map(|n| n as u64),
+    }
+}
+
+fn pair_from_row(row: &Row) -> PairInfo {
+    PairInfo {
+        address: Address::from_slice(row.get("address")),
+        factory: Address::from_slice(row.get("factory")),
+        protocol_version: row.get::<_, i16>("protocol_version") as u8,
+        token0: Address::from_slice(row.get("token0")),
+        token1: Address::from_slice(row.get("token1")),
+        fee: row.get::<_, Option<i32>>("fee").map(|fee| fee as u32),
+        created_block: row.get::<_, i64>("created_block") as u64,
+    }
+}
+
+const TRADE_COLUMNS: &str = "pair, tx_hash, block_number, block_hash, log_index, timestamp, \
+    amount0_in, amount1_in, amount0_out, amount1_out";
+
+const CANDLE_COLUMNS: &str =
+    "pair, interval, start, open, high, low, close, volume0, volume1, trades";
+
+fn trade_from_row(row: &Row) -> Trade {
+    Trade {
+        pair: Address::from_slice(row.get("pair")),
+        tx_hash: H256::from_slice(row.get("tx_hash")),
+        block_number: row.get::<_, i64>("block_number") as u64,
+        block_hash: H256::from_slice(row.get("block_hash")),
+        log_index: row.get::<_, i64>("log_index") as u64,
+        timestamp: row.get::<_, i64>("timestamp") as u64,
+        amount0_in: row.get::<_, Numeric>("amount0_in").0,
+        amount1_in: row.get::<_, Numeric>("amount1_in").0,
+        amount0_out: row.get::<_, Numeric>("amount0_out").0,
+        amount1_out: row.get::<_, Numeric>("amount1_out").0,
+    }
+}
+
+fn candle_from_row(row: &Row) -> Result<Candle> {
+    Ok(Candle {
+        pair: Address::from_slice(row.get("pair")),
+        interval: row.get::<_, &str>("interval").parse()?,
+        start: row.get::<_, i64>("start") as u64,
+        open: row.get("open"),
+        high: row.get("high"),
+        low: row.get("low"),
+        close: row.get("close"),
+        volume0: row.get("volume0"),
+        volume1: row.get("volume1"),
+        trades: row.get::<_, i32>("trades") as u32,
+    })
+}
+
+/// Build a `$1, $2, ...` placeholder list
+fn placeholders(count: usize) -> String {
+    (1..=count)
+        .map(|i| format!("${}", i))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Hash of the canonical block at `number`, if one is stored
+async fn canonical_hash(
+    db_tx: &tokio_postgres::Transaction<'_>,
+    schema: &str,
+    number: i64,
+) -> Result<Option<H256>> {
+    Ok(db_tx
+        .query_opt(
+            format!(
+                "SELECT hash FROM {}.blocks WHERE canonical AND number = $1",
+                schema
+            )
+            .as_str(),
+            &[&number],
+        )
+        .await?
+        .map(|row| H256::from_slice(row.get("hash"))))
+}
+
+/// Make `head` the canonical chain tip
+///
+/// Walks back from `head` through its stored ancestors until it meets the canonical chain. At every
+/// height along the way the competing block is orphaned, its transactions lose their inclusion and
+/// the transactions of the adopted block are pointed at it. When `head` forks from the canonical
+/// chain, anything above it is orphaned as well. Blocks delivered twice or late, whose parent is
+/// canonical, leave the chain above them alone.
+async fn adopt_head(
+    db_tx: &tokio_postgres::Transaction<'_>,
+    schema: &str,
+    head: &Block<H256>,
+) -> Result<()> {
+    let (Some(hash), Some(number)) = (head.hash, head.number) else {
+        return Ok(());
+    };
+    let number = number.as_u64() as i64;
+
+    let replaces = canonical_hash(db_tx, schema, number)
+        .await?
+        .is_some_and(|canonical| canonical != hash);
+    let forks = number > 0
+        && canonical_hash(db_tx, schema, number - 1)
+            .await?
+            .is_some_and(|parent| parent != head.parent_hash);
+    if replaces || forks {
+        let orphaned = db_tx
+            .execute(
+                format!(
+                    "UPDATE {schema}.transactions
+                    SET block_hash = NULL, block_number = NULL, transaction_index = NULL
+                    WHERE block_hash IN (
+                        SELECT hash FROM {schema}.blocks WHERE canonical AND number > $1
+                    )",
+                    schema = schema
+                )
+                .as_str(),
+                &[&number],
+            )
+            .await?;
+        db_tx
+            .execute(
+                format!(
+                    "UPDATE {}.blocks SET canonical = false WHERE canonical AND number > $1",
+                    schema
+                )
+                .as_str(),
+                &[&number],
+            )
+            .await?;
+        if orphaned > 0 {
+            info!("Reorg to {:?} dropped {} txs above it", hash, orphaned);
+        }
+    }
+
+    let mut current = hash;
+    loop {
+        let Some(row) = db_tx
+            .query_opt(
+                format!(
+                    "SELECT number, parent_hash, transactions, canonical FROM {}.blocks
+                    WHERE hash = $1",
+                    schema
+                )
+                .as_str(),
+                &[&current.as_bytes()],
+            )
+            .await?
+        else {
+            break;
+        };
+
+        if row.get::<_, bool>("canonical") {
+            break;
+        }
+
+        let number = row.get::<_, Option<i64>>("number");
+        let parent_hash = H256::from_slice(row.get("parent_hash"));
+        let transactions = row.get::<_, Vec<&[u8]>>("transactions");
+
+        let orphaned = db_tx
+            .execute(
+                format!(
+                    "UPDATE {schema}.transactions
+                    SET block_hash = NULL, block_number = NULL, transaction_index = NULL
+                    WHERE block_hash IN (
+                        SELECT hash FROM {schema}.blocks
+                        WHERE canonical AND number = $1 AND hash <> $2
+                    )",
+                    schema = schema
+                )
+                .as_str(),
+                &[&number, &current.as_bytes()],
+            )
+            .await?;
+        let replaced = db_tx
+            .execute(
+                format!(
+                    "UPDATE {}.blocks SET canonical = false
+                    WHERE canonical AND number = $1 AND hash <> $2",
+                    schema
+                )
+                .as_str(),
+                &[&number, &current.as_bytes()],
+            )
+            .await?;
+        db_tx
+            .execute(
+                format!(
+                    "UPDATE {}.blocks SET canonical = true WHERE hash = $1",
+                    schema
+                )
+                .as_str(),
+                &[&current.as_bytes()],
+            )
+            .await?;
+        db_tx
+            .execute(
+                format!(
+                    "UPDATE {schema}.transactions t
+                    SET block_hash = $1, block_number = $2, transaction_index = x.idx - 1
+                    FROM unnest($3::bytea[]) WITH ORDINALITY AS x(hash, idx)
+                    WHERE t.hash = x.hash",
+                    schema = schema
+                )
+                .as_str(),
+                &[&current.as_bytes(), &number, &transactions],
+            )
+            .await?;
+
+        if replaced > 0 {
+            warn!(
+                "Reorg at block {:?}: {:?} is now canonical, {} txs lost their inclusion",
+                number, current, orphaned
+            );
+        }
+
+        current = parent_hash;
+    }
+
+    Ok(())
+}
+
+pub struct PostgresStorage {
+    pub url: String,
+    pub schema: String,
+    client: Client,
+    /// Insert of `TxStorage::store`, prepared once rather than on every tx
+    insert_tx: Statement,
+    /// Insert of `BlockStorage::store`
+    insert_block: Statement,
+}
+
+impl PostgresStorage {
+    pub async fn new(url: String, schema: String) -> Result<Self> {
+        log::info!("Connecting to PostgreSQL at {}", url);
+
+        let (client, connection) = tokio_postgres::connect(url.as_str(), NoTls).await?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                warn!("PostgreSQL connection closed with error: {}", e);
+            }
+        });
+
+        Self::create_schema(&client, &schema).await?;
+
+        let insert_tx = client
+            .prepare_typed(
+                format!(
+                    "INSERT INTO {schema}.transactions ({columns}) VALUES ({values})
+                    ON CONFLICT (hash) DO UPDATE SET
+                        block_hash = EXCLUDED.block_hash,
+                        block_number = EXCLUDED.block_number,
+                        transaction_index = EXCLUDED.transaction_index",
+                    schema = schema,
+                    columns = TX_COLUMNS,
+                    values = placeholders(TX_TYPES.len())
+                )
+                .as_str(),
+                &TX_TYPES,
+            )
+            .await?;
+        let insert_block = client
+            .prepare_typed(
+                format!(
+                    "INSERT INTO {schema}.blocks ({columns}) VALUES ({values})
+                    ON CONFLICT (hash) DO NOTHING",
+                    schema = schema,
+                    columns = BLOCK_COLUMNS,
+                    values = placeholders(BLOCK_TYPES.len())
+                )
+                .as_str(),
+                &BLOCK_TYPES,
+            )
+            .await?;
+
+        Ok(Self {
+            url,
+            schema,
+            client,
+            insert_tx,
+            insert_block,
+        })
+    }
+
+    async fn create_schema(client: &Client, schema: &str) -> Result<()> {
+        client
+            .batch_execute(
+                format!(
+                    "CREATE SCHEMA IF NOT EXISTS {schema};
+
+                    CREATE TABLE IF NOT EXISTS {schema}.transactions (
+                        hash bytea PRIMARY KEY,
+                        nonce numeric(78, 0) NOT NULL,
+                        block_hash bytea,
+                        block_number bigint,
+                        transaction_index bigint,
+                        from_address bytea NOT NULL,
+                        to_address bytea,
+                        value numeric(78, 0) NOT NULL,
+                        gas_price numeric(78, 0),
+                        gas numeric(78, 0) NOT NULL,
+                        input bytea NOT NULL,
+                        type smallint,
+                        max_priority_fee_per_gas numeric(78, 0),
+                        max_fee_per_gas numeric(78, 0),
+                        chain_id bigint
+                    );
+                    ALTER TABLE {schema}.transactions ADD COLUMN IF NOT EXISTS
+                        stored_at bigint NOT NULL DEFAULT extract(epoch FROM now())::bigint;
+                    CREATE INDEX IF NOT EXISTS transactions_block_number_idx
+                        ON {schema}.transactions (block_number);
+                    CREATE INDEX IF NOT EXISTS transactions_stored_at_idx
+                        ON {schema}.transactions (stored_at);
+                    CREATE INDEX IF NOT EXISTS transactions_from_address_idx
+                        ON {schema}.transactions (from_address);
+                    CREATE INDEX IF NOT EXISTS transactions_to_address_idx
+                        ON {schema}.transactions (to_address);
+
+                    CREATE TABLE IF NOT EXISTS {schema}.blocks (
+                        hash bytea PRIMARY KEY,
+                        number bigint,
+                        parent_hash bytea NOT NULL,
+                        nonce bytea,
+                        uncles_hash bytea NOT NULL,
+                        logs_bloom bytea,
+                        transactions_root bytea NOT NULL,
+                        state_root bytea NOT NULL,
+                        receipts_root bytea NOT NULL,
+                        miner bytea,
+                        difficulty numeric(78, 0) NOT NULL,
+                        total_difficulty numeric(78, 0),
+                        size bigint,
+                        extra_data bytea NOT NULL,
+                        gas_limit numeric(78, 0) NOT NULL,
+                        gas_used numeric(78, 0) NOT NULL,
+                        base_fee_per_gas numeric(78, 0),
+                        timestamp bigint NOT NULL,
+                        transactions bytea[] NOT NULL,
+                        uncles bytea[] NOT NULL,
+                        canonical boolean NOT NULL DEFAULT false
+                    );
+                    ALTER TABLE {schema}.blocks
+                        ADD COLUMN IF NOT EXISTS canonical boolean NOT NULL DEFAULT false;
+                    CREATE INDEX IF NOT EXISTS blocks_number_idx
+                        ON {schema}.blocks (number);
+                    CREATE UNIQUE INDEX IF NOT EXISTS blocks_canonical_number_idx
+                        ON {schema}.blocks (number) WHERE canonical;
+
+                    CREATE TABLE IF NOT EXISTS {schema}.tx_lifecycle (
+                        hash bytea PRIMARY KEY,
+                        from_address bytea NOT NULL,
+                        nonce numeric(78, 0) NOT NULL,
+                        to_address bytea,
+                        router text,
+                        node text NOT NULL,
+                        first_seen bigint NOT NULL,
+                        status text NOT NULL,
+                        block_number bigint,
+                        block_hash bytea,
+                        transaction_index bigint,
+                        mined_at bigint,
+                        replaced_by bytea,
+                        closed_at bigint
+                    );
+                    CREATE INDEX IF NOT EXISTS tx_lifecycle_first_seen_idx
+                        ON {schema}.tx_lifecycle (first_seen);
+                    CREATE INDEX IF NOT EXISTS tx_lifecycle_sender_idx
+                        ON {schema}.tx_lifecycle (from_address, nonce);
+
+                    CREATE TABLE IF NOT EXISTS {schema}.swaps (
+                        tx_hash bytea NOT NULL,
+                        swap_index integer NOT NULL,
+                        router text NOT NULL,
+                        protocol_version smallint NOT NULL,
+                        method text NOT NULL,
+                        sender bytea NOT NULL,
+                        recipient bytea,
+                        path bytea NOT NULL,
+                        fees bytea NOT NULL DEFAULT '',
+                        token_in bytea,
+                        token_out bytea,
+                        kind text NOT NULL,
+                        amount_in numeric(78, 0) NOT NULL,
+                        amount_out numeric(78, 0) NOT NULL,
+                        deadline bigint,
+                        status text NOT NULL,
+                        block_number bigint,
+                        block_hash bytea,
+                        transaction_index bigint,
+                        stored_at bigint NOT NULL DEFAULT extract(epoch FROM now())::bigint,
+                        PRIMARY KEY (tx_hash, swap_index)
+                    );
+                    ALTER TABLE {schema}.swaps
+                        ADD COLUMN IF NOT EXISTS fees bytea NOT NULL DEFAULT '';
+                    CREATE INDEX IF NOT EXISTS swaps_block_number_idx
+                        ON {schema}.swaps (block_number);
+                    CREATE INDEX IF NOT EXISTS swaps_tokens_idx
+                        ON {schema}.swaps (token_in, token_out);
+                    CREATE INDEX IF NOT EXISTS swaps_stored_at_idx
+                        ON {schema}.swaps (stored_at);
+
+                    CREATE TABLE IF NOT EXISTS {schema}.tokens (
+                        address bytea PRIMARY KEY,
+                        name text NOT NULL,
+                        symbol text NOT NULL,
+                        decimals smallint NOT NULL,
+                        first_seen_block bigint
+                    );
+
+                    CREATE TABLE IF NOT EXISTS {schema}.pairs (
+                        address bytea PRIMARY KEY,
+                        factory bytea NOT NULL,
+                        protocol_version smallint NOT NULL,
+                        token0 bytea NOT NULL,
+                        token1 bytea NOT NULL,
+                        fee integer,
+                        created_block bigint NOT NULL
+                    );
+                    CREATE INDEX IF NOT EXISTS pairs_tokens_idx
+                        ON {schema}.pairs (token0, token1);
+
+                    CREATE TABLE IF NOT EXISTS {schema}.trades (
+                        block_hash bytea NOT NULL,
+                        log_index bigint NOT NULL,
+                        pair bytea NOT NULL,
+                        tx_hash bytea NOT NULL,
+                        block_number bigint NOT NULL,
+                        timestamp bigint NOT NULL,
+                        amount0_in numeric(78, 0) NOT NULL,
+                        amount1_in numeric(78, 0) NOT NULL,
+                        amount0_out numeric(78, 0) NOT NULL,
+                        amount1_out numeric(78, 0) NOT NULL,
+                        PRIMARY KEY (block_hash, log_index)
+                    );
+                    CREATE INDEX IF NOT EXISTS trades_pair_timestamp_idx
+                        ON {schema}.trades (pair, timestamp);
+                    CREATE INDEX IF NOT EXISTS trades_timestamp_idx
+                        ON {schema}.trades (timestamp);
+                    CREATE INDEX IF NOT EXISTS trades_block_number_idx
+                        ON {schema}.trades (block_number);
+
+                    CREATE TABLE IF NOT EXISTS {schema}.candles (
+                        pair bytea NOT NULL,
+                        interval text NOT NULL,
+                        start bigint NOT NULL,
+                        open double precision NOT NULL,
+                        high double precision NOT NULL,
+                        low double precision NOT NULL,
+                        close double precision NOT NULL,
+                        volume0 double precision NOT NULL,
+                        volume1 double precision NOT NULL,
+                        trades integer NOT NULL,
+                        PRIMARY KEY (pair, interval, start)
+                    );
+                    CREATE INDEX IF NOT EXISTS candles_start_idx
+                        ON {schema}.candles (start);",
+                    schema = schema
+                )
+                .as_str(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Bulk load transactions using `COPY ... FROM STDIN (FORMAT binary)`
+    ///
+    /// Rows are copied into a temporary staging table and merged from there, so transactions that
+    /// are already stored (e.g. seen in the mempool and again once mined) are updated rather than
+    /// aborting the whole copy.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the transactions could not be copied
+    pub async fn copy_txs(&mut self, txs: &[Transaction]) -> Result<u64> {
+        debug!("Copying {} txs", txs.len());
+
+        let db_tx = self.client.transaction().await?;
+        db_tx
+            .batch_execute(
+                format!(
+                    "CREATE TEMP TABLE transactions_staging
+                    (LIKE {schema}.transactions INCLUDING DEFAULTS) ON COMMIT DROP",
+                    schema = self.schema
+                )
+                .as_str(),
+            )
+            .await?;
+
+        let sink = db_tx
+            .copy_in(
+                format!(
+                    "COPY transactions_staging ({}) FROM STDIN (FORMAT binary)",
+                    TX_COLUMNS
+                )
+                .as_str(),
+            )
+            .await?;
+        let mut writer = pin!(BinaryCopyInWriter::new(sink, &TX_TYPES));
+        for tx in txs {
+            writer.as_mut().write(&TxRow::from(tx).params()).await?;
+        }
+        writer.finish().await?;
+
+        let copied = db_tx
+            .execute(
+                format!(
+                    "INSERT INTO {schema}.transactions ({columns})
+                    SELECT DISTINCT ON (hash) {columns} FROM transactions_staging
+                    ON CONFLICT (hash) DO UPDATE SET
+                        block_hash = EXCLUDED.block_hash,
+                        block_number = EXCLUDED.block_number,
+                        transaction_index = EXCLUDED.transaction_index",
+                    schema = self.schema,
+                    columns = TX_COLUMNS
+                )
+                .as_str(),
+                &[],
+            )
+            .await?;
+        db_tx.commit().await?;
+
+        info!("Copied {} txs", copied);
+        Ok(copied)
+    }
+
+    /// Bulk load blocks using `COPY ... FROM STDIN (FORMAT binary)`
+    ///
+    /// Copied blocks are assumed to come from the canonical chain (e.g. a backfill from a synced
+    /// node) and are marked canonical unless another block already holds their height.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the blocks could not be copied
+    pub async fn copy_blocks(&mut self, blocks: &[Block<H256>]) -> Result<u64> {
+        debug!("Copying {} blocks", blocks.len());
+
+        let db_tx = self.client.transaction().await?;
+        db_tx
+            .batch_execute(
+                format!(
+                    "CREATE TEMP TABLE blocks_staging
+                    (LIKE {schema}.blocks INCLUDING DEFAULTS) ON COMMIT DROP",
+                    schema = self.schema
+                )
+                .as_str(),
+            )
+            .await?;
+
+        let sink = db_tx
+            .copy_in(
+                format!(
+                    "COPY blocks_staging ({}) FROM STDIN (FORMAT binary)",
+                    BLOCK_COLUMNS
+                )
+                .as_str(),
+            )
+            .await?;
+        let mut writer = pin!(BinaryCopyInWriter::new(sink, &BLOCK_TYPES));
+        for block in blocks {
+            writer
+                .as_mut()
+                .write(&BlockRow::from(block).params())
+                .await?;
+        }
+        writer.finish().await?;
+
+        let copied = db_tx
+            .execute(
+                format!(
+                    "INSERT INTO {schema}.blocks ({columns})
+                    SELECT DISTINCT ON (hash) {columns} FROM blocks_staging
+                    ON CONFLICT (hash) DO NOTHING",
+                    schema = self.schema,
+                    columns = BLOCK_COLUMNS
+                )
+                .as_str(),
+                &[],
+            )
+            .await?;
+        db_tx
+            .execute(
+                format!(
+                    "UPDATE {schema}.blocks SET canonical = true
+                    WHERE hash IN (
+                        SELECT DISTINCT ON (number) hash FROM blocks_staging ORDER BY number, hash
+                    )
+                    AND NOT EXISTS (
+                        SELECT 1 FROM {schema}.blocks c
+                        WHERE c.number = {schema}.blocks.number AND c.canonical
+                    )",
+                    schema = self.schema
+                )
+                .as_str(),
+                &[],
+            )
+            .await?;
+        db_tx.commit().await?;
+
+        info!("Copied {} blocks", copied);
+        Ok(copied)
+    }
+
+    /// Fetch a stored transaction's value in wei
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the transaction could not be queried
+    pub async fn tx_value(&self, hash: H256) -> Result<Option<U256>> {
+        let row = self
+            .client
+            .query_opt(
+                format!(
+                    "SELECT value FROM {}.transactions WHERE hash = $1",
+                    self.schema
+                )
+                .as_str(),
+                &[&hash.as_bytes()],
+            )
+            .await?;
+
+        Ok(row.map(|row| row.get::<_, Numeric>(0).0))
+    }
+
+    /// Hashes of the transactions stored for a block
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the transactions could not be queried
+    pub async fn block_tx_hashes(&self, block_number: u64) -> Result<Vec<H256>> {
+        let rows = self
+            .client
+            .query(
+                format!(
+                    "SELECT t.hash FROM {schema}.transactions t
+                    JOIN {schema}.blocks b ON b.hash = t.block_hash AND b.canonical
+                    WHERE t.block_number = $1 ORDER BY t.transaction_index",
+                    schema = self.schema
+                )
+                .as_str(),
+                &[&(block_number as i64)],
+            )
+            .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| H256::from_slice(row.get::<_, &[u8]>(0)))
+            .collect())
+    }
+}
+
+impl TxStorage<Transaction> for PostgresStorage {
+    async fn store(&mut self, tx: Transaction) -> Result<()> {
+        debug!("Storing tx: {:#?}", tx.hash);
+
+        self.client
+            .execute(&self.insert_tx, &TxRow::from(&tx).params())
+            .await?;
+
+        Ok(())
+    }
+
+    async fn is_stored(&mut self, tx: Transaction) -> Result<bool> {
+        debug!("Checking if tx is stored: {:#?}", tx.hash);
+
+        let row = self
+            .client
+            .query_opt(
+                format!("SELECT 1 FROM {}.transactions WHERE hash = $1", self.schema).as_str(),
+                &[&tx.hash.as_bytes()],
+            )
+            .await?;
+
+        Ok(row.is_some())
+    }
+
+    async fn get(&mut self, hash: H256) -> Result<Option<Transaction>> {
+        debug!("Getting tx: {:#?}", hash);
+
+        let row = self
+            .client
+            .query_opt(
+                format!(
+                    "SELECT {} FROM {}.transactions WHERE hash = $1",
+                    TX_COLUMNS, self.schema
+                )
+                .as_str(),
+                &[&hash.as_bytes()],
+            )
+            .await?;
+
+        Ok(row.as_ref().map(tx_from_row))
+    }
+
+    /// Written with `COPY` through `copy_txs`
+    async fn store_many(&mut self, txs: Vec<Transaction>) -> Result<()> {
+        self.copy_txs(&txs).await?;
+
+        Ok(())
+    }
+
+    async fn exists_many(&mut self, hashes: &[H256]) -> Result<HashSet<H256>> {
+        debug!("Checking if {} txs are stored", hashes.len());
+
+        let rows = self
+            .client
+            .query(
+                format!(
+                    "SELECT hash FROM {}.transactions WHERE hash = ANY($1)",
+                    self.schema
+                )
+                .as_str(),
+                &[&hashes.iter().map(H256::as_bytes).collect::<Vec<&[u8]>>()],
+            )
+            .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| H256::from_slice(row.get::<_, &[u8]>(0)))
+            .collect())
+    }
+
+    async fn delete(&mut self, tx: Transaction) -> Result<()> {
+        debug!("Deleting tx: {:#?}", tx.hash);
+
+        self.client
+            .execute(
+                format!("DELETE FROM {}.transactions WHERE hash = $1", self.schema).as_str(),
+                &[&tx.hash.as_bytes()],
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl Retryable for PostgresStorage {
+    /// Lost connections, I/O errors and the connection, rollback, resource and operator
+    /// SQLSTATE classes are transient, everything else (bad data, constraint violations,
+    /// client side errors without a SQLSTATE) fails again
+    fn is_retryable(&self, error: &anyhow::Error) -> bool {
+        match error.downcast_ref::<tokio_postgres::Error>() {
+            Some(e) if e.is_closed() => true,
+            Some(e) => match e.code() {
+                Some(code) => {
+                    matches!(&code.code()[..2], "08" | "40" | "53" | "57")
+                        || *code == SqlState::LOCK_NOT_AVAILABLE
+                }
+                None => {
+                    std::error::Error::source(e).is_some_and(|source| source.is::<std::io::Error>())
+                }
+            },
+            None => error.downcast_ref::<std::io::Error>().is_some(),
+        }
+    }
+}
+
+impl BlockStorage<Block<H256>> for PostgresStorage {
+    /// Store a block as the new chain head, marking it and its stored ancestors canonical
+    async fn store(&mut self, block: Block<H256>) -> Result<()> {
+        debug!("Storing block: {:#?}", block.hash);
+
+        let db_tx = self.client.transaction().await?;
+        db_tx
+            .execute(&self.insert_block, &BlockRow::from(&block).params())
+            .await?;
+        adopt_head(&db_tx, &self.schema, &block).await?;
+        db_tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn is_stored(&mut self, block: Block<H256>) -> Result<bool> {
+        debug!("Checking if block is stored: {:#?}", block.hash);
+
+        let row = self
+            .client
+            .query_opt(
+                format!("SELECT 1 FROM {}.blocks WHERE hash = $1", self.schema).as_str(),
+                &[&block.hash.unwrap_or_default().as_bytes()],
+            )
+            .await?;
+
+        Ok(row.is_some())
+    }
+
+    async fn delete(&mut self, block: Block<H256>) -> Result<()> {
+        debug!("Deleting block: {:#?}", block.hash);
+
+        let db_tx = self.client.transaction().await?;
+        db_tx
+            .execute(
+                format!(
+                    "UPDATE {}.transactions
+                    SET block_hash = NULL, block_number = NULL, transaction_index = NULL
+                    WHERE block_hash = $1",
+                    self.schema
+                )
+                .as_str(),
+                &[&block.hash.unwrap_or_default().as_bytes()],
+            )
+            .await?;
+        db_tx
+            .execute(
+                format!("DELETE FROM {}.blocks WHERE hash = $1", self.schema).as_str(),
+                &[&block.hash.unwrap_or_default().as_bytes()],
+            )
+            .await?;
+        db_tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+impl ChainReader for PostgresStorage {
+    async fn latest_block_number(&mut self) -> Result<Option<u64>> {
+        let row = self
+            .client
+            .query_one(
+                format!(
+                    "SELECT max(number) FROM {}.blocks WHERE canonical",
+                    self.schema
+                )
+                .as_str(),
+                &[],
+            )
+            .await?;
+
+        Ok(row.get::<_, Option<i64>>(0).map(|n| n as u64))
+    }
+
+    async fn blocks_in_range(&mut self, from: u64, to: u64) -> Result<Vec<Block<H256>>> {
+        let rows = self
+            .client
+            .query(
+                format!(
+                    "SELECT {} FROM {}.blocks
+                    WHERE number BETWEEN $1 AND $2 AND canonical ORDER BY number",
+                    BLOCK_COLUMNS, self.schema
+                )
+                .as_str(),
+                &[&(from as i64), &(to as i64)],
+            )
+            .await?;
+
+        Ok(rows.iter().map(block_from_row).collect())
+    }
+
+    async fn txs_in_range(&mut self, from: u64, to: u64) -> Result<Vec<Transaction>> {
+        let rows = self
+            .client
+            .query(
+                format!(
+                    "SELECT {columns} FROM {schema}.transactions t
+                    WHERE block_number BETWEEN $1 AND $2
+                    AND NOT EXISTS (
+                        SELECT 1 FROM {schema}.blocks b WHERE b.hash = t.block_hash AND NOT b.canonical
+                    )
+                    ORDER BY block_number, transaction_index",
+                    columns = TX_COLUMNS,
+                    schema = self.schema
+                )
+                .as_str(),
+                &[&(from as i64), &(to as i64)],
+            )
+            .await?;
+
+        Ok(rows.iter().map(tx_from_row).collect())
+    }
+
+    async fn block_range_for_time(&mut self, from: u64, to: u64) -> Result<Option<(u64, u64)>> {
+        let row = self
+            .client
+            .query_one(
+                format!(
+                    "SELECT min(number), max(number) FROM {}.blocks
+                    WHERE timestamp BETWEEN $1 AND $2 AND canonical",
+                    self.schema
+                )
+                .as_str(),
+                &[&(from as i64), &(to as i64)],
+            )
+            .await?;
+
+        match (row.get::<_, Option<i64>>(0), row.get::<_, Option<i64>>(1)) {
+            (Some(first), Some(last)) => Ok(Some((first as u64, last as u64))),
+            _ => Ok(None),
+        }
+    }
+
+    async fn orphaned_blocks_in_range(&mut self, from: u64, to: u64) -> Result<Vec<Block<H256>>> {
+        let rows = self
+            .client
+            .query(
+                format!(
+                    "SELECT {} FROM {}.blocks
+                    WHERE number BETWEEN $1 AND $2 AND NOT canonical ORDER BY number",
+                    BLOCK_COLUMNS, self.schema
+                )
+                .as_str(),
+                &[&(from as i64), &(to as i64)],
+            )
+            .await?;
+
+        Ok(rows.iter().map(block_from_row).collect())
+    }
+}
+
+impl LifecycleStorage for PostgresStorage {
+    async fn store_lifecycle(&mut self, lifecycle: &TxLifecycle) -> Result<()> {
+        debug!(
+            "Storing lifecycle of tx {:#?}: {}",
+            lifecycle.hash, lifecycle.status
+        );
+
+        self.client
+            .execute(
+                format!(
+                    "INSERT INTO {schema}.tx_lifecycle ({columns}) VALUES ({values})
+                    ON CONFLICT (hash) DO UPDATE SET
+                        first_seen = LEAST(tx_lifecycle.first_seen, EXCLUDED.first_seen),
+                        router = COALESCE(tx_lifecycle.router, EXCLUDED.router),
+                        status = EXCLUDED.status,
+                        block_number = EXCLUDED.block_number,
+                        block_hash = EXCLUDED.block_hash,
+                        transaction_index = EXCLUDED.transaction_index,
+                        mined_at = EXCLUDED.mined_at,
+                        replaced_by = EXCLUDED.replaced_by,
+                        closed_at = EXCLUDED.closed_at",
+                    schema = self.schema,
+                    columns = LIFECYCLE_COLUMNS,
+                    values = placeholders(14)
+                )
+                .as_str(),
+                &[
+                    &lifecycle.hash.as_bytes(),
+                    &lifecycle.from.as_bytes(),
+                    &Numeric(lifecycle.nonce),
+                    &lifecycle.to.as_ref().map(|a| a.as_bytes()),
+                    &lifecycle.router,
+                    &lifecycle.node,
+                    &(lifecycle.first_seen as i64),
+                    &lifecycle.status.as_str(),
+                    &lifecycle.block_number.map(|n| n as i64),
+                    &lifecycle.block_hash.as_ref().map(|h| h.as_bytes()),
+                    &lifecycle.transaction_index.map(|i| i as i64),
+                    &lifecycle.mined_at.map(|t| t as i64),
+                    &lifecycle.replaced_by.as_ref().map(|h| h.as_bytes()),
+                    &lifecycle.closed_at.map(|t| t as i64),
+                ],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn lifecycle(&mut self, hash: H256) -> Result<Option<TxLifecycle>> {
+        let row = self
+            .client
+            .query_opt(
+                format!(
+                    "SELECT {} FROM {}.tx_lifecycle WHERE hash = $1",
+                    LIFECYCLE_COLUMNS, self.schema
+                )
+                .as_str(),
+                &[&hash.as_bytes()],
+            )
+            .await?;
+
+        row.as_ref().map(lifecycle_from_row).transpose()
+    }
+
+    async fn router_latency(&mut self, since: u64) -> Result<Vec<RouterLatency>> {
+        let rows = self
+            .client
+            .query(
+                format!(
+                    "SELECT router,
+                        count(*) FILTER (WHERE status = 'mined'),
+                        count(*) FILTER (WHERE status = 'replaced'),
+                        count(*) FILTER (WHERE status = 'dropped'),
+                        count(*) FILTER (WHERE status = 'pending'),
+                        min(GREATEST(mined_at - first_seen, 0)) FILTER (WHERE mined_at IS NOT NULL),
+                        avg(GREATEST(mined_at - first_seen, 0)) FILTER (WHERE mined_at IS NOT NULL)::float8,
+                        max(GREATEST(mined_at - first_seen, 0)) FILTER (WHERE mined_at IS NOT NULL)
+                    FROM {}.tx_lifecycle
+                    WHERE first_seen >= $1
+                    GROUP BY router ORDER BY router NULLS LAST",
+                    self.schema
+                )
+                .as_str(),
+                &[&(since as i64)],
+            )
+            .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| RouterLatency {
+                router: row.get(0),
+                mined: row.get::<_, i64>(1) as u64,
+                replaced: row.get::<_, i64>(2) as u64,
+                dropped: row.get::<_, i64>(3) as u64,
+                pending: row.get::<_, i64>(4) as u64,
+                min_latency: row.get::<_, Option<i64>>(5).map(|l| l as u64),
+                avg_latency: row.get(6),
+                max_latency: row.get::<_, Option<i64>>(7).map(|l| l as u64),
+            })
+            .collect())
+    }
+}
+
+impl SwapStorage for PostgresStorage {
+    async fn store_swap(&mut self, swap: &SwapRecord) -> Result<()> {
+        debug!(
+            "Storing swap {} of tx {:#?}: {}",
+            swap.swap_index, swap.tx_hash, swap.status
+        );
+
+        self.client
+            .execute(
+                format!(
+                    "INSERT INTO {schema}.swaps ({columns}, token_in, token_out) VALUES ({values})
+                    ON CONFLICT (tx_hash, swap_index) DO UPDATE SET
+                        status = EXCLUDED.status,
+                        block_number = EXCLUDED.block_number,
+                        block_hash = EXCLUDED.block_hash,
+                        transaction_index = EXCLUDED.transaction_index
+                    WHERE swaps.status = 'pending' OR EXCLUDED.status = 'mined'",
+                    schema = self.schema,
+                    columns = SWAP_COLUMNS,
+                    values = placeholders(19)
+                )
+                .as_str(),
+                &[
+                    &swap.tx_hash.as_bytes(),
+                    &(swap.swap_index as i32),
+                    &swap.router,
+                    &(swap.protocol_version as i16),
+                    &swap.method,
+                    &swap.sender.as_bytes(),
+                    &swap.recipient.as_ref().map(|a| a.as_bytes()),
+                    &path_to_bytes(&swap.path),
+                    &fees_to_bytes(&swap.fees),
+                    &swap.kind.as_str(),
+                    &Numeric(swap.amount_in),
+                    &Numeric(swap.amount_out),
+                    &swap.deadline.map(|d| d.min(i64::MAX as u64) as i64),
+                    &swap.status.as_str(),
+                    &swap.block_number.map(|n| n as i64),
+                    &swap.block_hash.as_ref().map(|h| h.as_bytes()),
+                    &swap.transaction_index.map(|i| i as i64),
+                    &swap.token_in().map(|a| a.as_bytes().to_vec()),
+                    &swap.token_out().map(|a| a.as_bytes().to_vec()),
+                ],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn swaps(&mut self, tx_hash: H256) -> Result<Vec<SwapRecord>> {
+        let rows = self
+            .client
+            .query(
+                format!(
+                    "SELECT {} FROM {}.swaps WHERE tx_hash = $1 ORDER BY swap_index",
+                    SWAP_COLUMNS, self.schema
+                )
+                .as_str(),
+                &[&tx_hash.as_bytes()],
+            )
+            .await?;
+
+        rows.iter().map(swap_from_row).collect()
+    }
+
+    async fn swaps_in_range(&mut self, from: u64, to: u64) -> Result<Vec<SwapRecord>> {
+        let rows = self
+            .client
+            .query(
+                format!(
+                    "SELECT {columns} FROM {schema}.swaps s
+                    WHERE status = 'mined' AND block_number BETWEEN $1 AND $2
+                    AND NOT EXISTS (
+                        SELECT 1 FROM {schema}.blocks b WHERE b.hash = s.block_hash AND NOT b.canonical
+                    )
+                    ORDER BY block_number, transaction_index, swap_index",
+                    columns = SWAP_COLUMNS,
+                    schema = self.schema
+                )
+                .as_str(),
+                &[&(from as i64), &(to as i64)],
+            )
+            .await?;
+
+        rows.iter().map(swap_from_row).collect()
+    }
+}
+
+impl RegistryStorage for PostgresStorage {
+    async fn store_token(&mut self, token: &TokenInfo) -> Result<()> {
+        // LEAST ignores nulls, so a token from the settings takes the block it's first seen in
+        self.client
+            .execute(
+                format!(
+                    "INSERT INTO {schema}.tokens ({columns}) VALUES ({values})
+                    ON CONFLICT (address) DO UPDATE SET
+                        name = EXCLUDED.name,
+                        symbol = EXCLUDED.symbol,
+                        decimals = EXCLUDED.decimals,
+                        first_seen_block = LEAST(tokens.first_seen_block, EXCLUDED.first_seen_block)",
+                    schema = self.schema,
+                    columns = TOKEN_COLUMNS,
+                    values = placeholders(5)
+                )
+                .as_str(),
+                &[
+                    &token.address.as_bytes(),
+                    &token.name,
+                    &token.symbol,
+                    &(token.decimals as i16),
+                    &token.first_seen_block.map(|n| n as i64),
+                ],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn store_pair(&mut self, pair: &PairInfo) -> Result<()> {
+        debug!(
+            "Storing pair {:#?} of factory {:#?}",
+            pair.address, pair.factory
+        );
+
+        self.client
+            .execute(
+                format!(
+                    "INSERT INTO {schema}.pairs ({columns}) VALUES ({values})
+                    ON CONFLICT (address) DO NOTHING",
+                    schema = self.schema,
+                    columns = PAIR_COLUMNS,
+                    values = placeholders(7)
+                )
+                .as_str(),
+                &[
+                    &pair.address.as_bytes(),
+                    &pair.factory.as_bytes(),
+                    &(pair.protocol_version as i16),
+                    &pair.token0.as_bytes(),
+                    &pair.token1.as_bytes(),
+                    &pair.fee.map(|fee| fee as i32),
+                    &(pair.created_block as i64),
+                ],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn tokens(&mut self) -> Result<Vec<TokenInfo>> {
+        let rows = self
+            .client
+            .query(
+                format!("SELECT {} FROM {}.tokens", TOKEN_COLUMNS, self.schema).as_str(),
+                &[],
+            )
+            .await?;
+
+        Ok(rows.iter().map(token_from_row).collect())
+    }
+
+    async fn pairs(&mut self) -> Result<Vec<PairInfo>> {
+        let rows = self
+            .client
+            .query(
+                format!(
+                    "SELECT {} FROM {}.pairs ORDER BY created_block",
+                    PAIR_COLUMNS, self.schema
+                )
+                .as_str(),
+                &[],
+            )
+            .await?;
+
+        Ok(rows.iter().map(pair_from_row).collect())
+    }
+}
+
+impl CandleStorage for PostgresStorage {
+    async fn store_trades(&mut self, trades: &[Trade]) -> Result<()> {
+        debug!("Storing {} trades", trades.len());
+
+        let db_tx = self.client.transaction().await?;
+        let statement = db_tx
+            .prepare(
+                format!(
+                    "INSERT INTO {schema}.trades ({columns}) VALUES ({values})
+                    ON CONFLICT (block_hash, log_index) DO NOTHING",
+                    schema = self.schema,
+                    columns = TRADE_COLUMNS,
+                    values = placeholders(10)
+                )
+                .as_str(),
+            )
+            .await?;
+        for trade in trades {
+            db_tx
+                .execute(
+                    &statement,
+                    &[
+                        &trade.pair.as_bytes(),
+                        &trade.tx_hash.as_bytes(),
+                        &(trade.block_number as i64),
+                        &trade.block_hash.as_bytes(),
+                        &(trade.log_index as i64),
+                        &(trade.timestamp as i64),
+                        &Numeric(trade.amount0_in),
+                        &Numeric(trade.amount1_in),
+                        &Numeric(trade.amount0_out),
+                        &Numeric(trade.amount1_out),
+                    ],
+                )
+                .await?;
+        }
+        db_tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn orphan_trades(&mut self, block_number: u64, block_hash: H256) -> Result<Vec<Trade>> {
+        let rows = self
+            .client
+            .query(
+                format!(
+                    "DELETE FROM {}.trades
+                    WHERE block_number > $1 OR (block_number = $1 AND block_hash != $2)
+                    RETURNING {}",
+                    self.schema, TRADE_COLUMNS
+                )
+                .as_str(),
+                &[&(block_number as i64), &block_hash.as_bytes()],
+            )
+            .await?;
+
+        if !rows.is_empty() {
+            info!(
+                "Removed {} trades orphaned by block {:#?}",
+                rows.len(),
+                block_hash
+            );
+        }
+
+        Ok(rows.iter().map(trade_from_row).collect())
+    }
+
+    async fn trades(&mut self, pair: Option<Address>, from: u64, to: u64) -> Result<Vec<Trade>> {
+        let rows = self
+            .client
+            .query(
+                format!(
+                    "SELECT {} FROM {}.trades
+                    WHERE timestamp BETWEEN $1 AND $2 AND ($3::bytea IS NULL OR pair = $3)
+                    ORDER BY block_number, log_index",
+                    TRADE_COLUMNS, self.schema
+                )
+                .as_str(),
+                &[
+                    &(from as i64),
+                    &(to as i64),
+                    &pair.as_ref().map(|a| a.as_bytes()),
+                ],
+            )
+            .await?;
+
+        Ok(rows.iter().map(trade_from_row).collect())
+    }
+
+    async fn store_candle(&mut self, candle: &Candle) -> Result<()> {
+        self.client
+            .execute(
+                format!(
+                    "INSERT INTO {schema}.candles ({columns}) VALUES ({values})
+                    ON CONFLICT (pair, interval, start) DO UPDATE SET
+                        open = EXCLUDED.open,
+                        high = EXCLUDED.high,
+                        low = EXCLUDED.low,
+                        close = EXCLUDED.close,
+                        volume0 = EXCLUDED.volume0,
+                        volume1 = EXCLUDED.volume1,
+                        trades = EXCLUDED.trades",
+                    schema = self.schema,
+                    columns = CANDLE_COLUMNS,
+                    values = placeholders(10)
+                )
+                .as_str(),
+                &[
+                    &candle.pair.as_bytes(),
+                    &candle.interval.as_str(),
+                    &(candle.start as i64),
+                    &candle.open,
+                    &candle.high,
+                    &candle.low,
+                    &candle.close,
+                    &candle.volume0,
+                    &candle.volume1,
+                    &(candle.trades as i32),
+                ],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete_candles(
+        &mut self,
+        pair: Option<(Address, Interval)>,
+        from: u64,
+        to: u64,
+    ) -> Result<u64> {
+        let rows = self
+            .client
+            .execute(
+                format!(
+                    "DELETE FROM {}.candles
+                    WHERE start BETWEEN $1 AND $2
+                    AND ($3::bytea IS NULL OR (pair = $3 AND interval = $4))",
+                    self.schema
+                )
+                .as_str(),
+                &[
+                    &(from as i64),
+                    &(to as i64),
+                    &pair.as_ref().map(|(pair, _)| pair.as_bytes()),
+                    &pair.map(|(_, interval)| interval.as_str()),
+                ],
+            )
+            .await?;
+
+        Ok(rows)
+    }
+
+    async fn candles(
+        &mut self,
+        pair: Address,
+        interval: Interval,
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<Candle>> {
+        let rows = self
+            .client
+            .query(
+                format!(
+                    "SELECT {} FROM {}.candles
+                    WHERE pair = $1 AND interval = $2 AND start BETWEEN $3 AND $4
+                    ORDER BY start",
+                    CANDLE_COLUMNS, self.schema
+                )
+                .as_str(),
+                &[
+                    &pair.as_bytes(),
+                    &interval.as_str(),
+                    &(from as i64),
+                    &(to as i64),
+                ],
+            )
+            .await?;
+
+        rows.iter().map(candle_from_row).collect()
+    }
+}
+
+impl ExpiringStorage for PostgresStorage {
+    async fn expire(&mut self, class: DataClass, cutoff: u64, dry_run: bool) -> Result<Expired> {
+        // Mined txs age with their block, falling back to when they were stored
+        let (table, condition) = match class {
+            DataClass::PendingTxs => (
+                "transactions",
+                "block_hash IS NULL AND stored_at < $1".to_string(),
+            ),
+            DataClass::MinedTxs => (
+                "transactions",
+                format!(
+                    "block_hash IS NOT NULL AND coalesce(
+                        (SELECT b.timestamp FROM {}.blocks b WHERE b.hash = t.block_hash),
+                        stored_at
+                    ) < $1",
+                    self.schema
+                ),
+            ),
+            DataClass::DecodedEvents => ("swaps", "stored_at < $1".to_string()),
+            _ => return Ok(Expired::NotStored),
+        };
+
+        let query = if dry_run {
+            format!(
+                "SELECT count(*) FROM {}.{} t WHERE {}",
+                self.schema, table, condition
+            )
+        } else {
+            format!(
+                "WITH expired AS (DELETE FROM {}.{} t WHERE {} RETURNING 1)
+                SELECT count(*) FROM expired",
+                self.schema, table, condition
+            )
+        };
+
+        let rows = self
+            .client
+            .query_one(query.as_str(), &[&(cutoff as i64)])
+            .await?
+            .get::<_, i64>(0);
+        debug!("Expired {} {} older than {}", rows, class, cutoff);
+
+        Ok(Expired::Rows(rows as u64))
+    }
+}
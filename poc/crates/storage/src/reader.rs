@@ -0,0 +1,43 @@
+use anyhow::Result;
+use ethers::types::{Block, Transaction, H256};
+
+/// Read access to stored chain data, used by exporters and other batch consumers
+///
+/// Reads only return the canonical chain: orphaned blocks and transactions included in them are
+/// left out unless asked for explicitly through `orphaned_blocks_in_range`.
+pub trait ChainReader {
+    /// Number of the highest stored block
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the blocks could not be queried
+    async fn latest_block_number(&mut self) -> Result<Option<u64>>;
+
+    /// Blocks with a number in `from..=to`, ordered by number
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the blocks could not be queried
+    async fn blocks_in_range(&mut self, from: u64, to: u64) -> Result<Vec<Block<H256>>>;
+
+    /// Transactions mined in a block with a number in `from..=to`, ordered by block and index
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the transactions could not be queried
+    async fn txs_in_range(&mut self, from: u64, to: u64) -> Result<Vec<Transaction>>;
+
+    /// First and last stored block numbers with a timestamp in `from..=to` (unix seconds)
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the blocks could not be queried
+    async fn block_range_for_time(&mut self, from: u64, to: u64) -> Result<Option<(u64, u64)>>;
+
+    /// Orphaned (reorged out) blocks with a number in `from..=to`, ordered by number
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the blocks could not be queried
+    async fn orphaned_blocks_in_range(&mut self, from: u64, to: u64) -> Result<Vec<Block<H256>>>;
+}
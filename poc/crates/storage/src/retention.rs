@@ -0,0 +1,85 @@
+use std::fmt;
+
+use anyhow::Result;
+use settings::{DataClass, Retention};
+
+/// What happened to the expired data of one class
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expired {
+    /// Rows removed by a cleanup, or that would be removed on a dry run
+    Rows(u64),
+    /// The backend expires rows itself through per-row TTLs
+    ByTtl,
+    /// The backend doesn't store this class
+    NotStored,
+}
+
+/// One line of an expiry report
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Expiry {
+    pub class: DataClass,
+    /// Data older than this (unix seconds) is expired
+    pub cutoff: u64,
+    pub expired: Expired,
+}
+
+impl fmt::Display for Expiry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.expired {
+            Expired::Rows(rows) => write!(
+                f,
+                "{}: {} rows older than {}",
+                self.class, rows, self.cutoff
+            ),
+            Expired::ByTtl => write!(f, "{}: expired by TTL", self.class),
+            Expired::NotStored => write!(f, "{}: not stored", self.class),
+        }
+    }
+}
+
+pub trait ExpiringStorage {
+    /// Delete the data of `class` older than `cutoff` (unix seconds)
+    ///
+    /// With `dry_run` set nothing is deleted and the matching rows are only counted.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the data could not be deleted or counted
+    async fn expire(&mut self, class: DataClass, cutoff: u64, dry_run: bool) -> Result<Expired>;
+}
+
+/// Apply the retention policies of the storage classes at `now` (unix seconds)
+///
+/// Classes that are kept forever, and the ABI cache which lives outside of storage, are left
+/// out of the report.
+///
+/// # Errors
+///
+/// This function will return an error if any class could not be expired
+pub async fn expire_storage<C: ExpiringStorage>(
+    storage: &mut C,
+    retention: &Retention,
+    now: u64,
+    dry_run: bool,
+) -> Result<Vec<Expiry>> {
+    let mut report = Vec::new();
+
+    for class in DataClass::ALL
+        .into_iter()
+        .filter(|class| *class != DataClass::AbiCache)
+    {
+        let Some(ttl) = retention.ttl(class) else {
+            continue;
+        };
+
+        let cutoff = now.saturating_sub(ttl.as_secs());
+        let expired = storage.expire(class, cutoff, dry_run).await?;
+        report.push(Expiry {
+            class,
+            cutoff,
+            expired,
+        });
+    }
+
+    Ok(report)
+}
@@ -1,13 +1,542 @@
+use std::collections::HashSet;
+
 use anyhow::Result;
-use ethers::types::Transaction;
-use log::debug;
-use scylla::{Session, SessionBuilder};
+use ethers::types::{Address, Bytes, Transaction, H256, U256, U64};
+use futures::stream::{self, StreamExt};
+use log::{debug, info};
+use scylla::transport::errors::{DbError, QueryError};
+use scylla::{FromRow, Session, SessionBuilder, ValueList};
+use settings::{DataClass, Retention};
 
+use crate::candle::CandleStorage;
+use crate::lifecycle::{latency_by_router, LifecycleStorage, RouterLatency, TxLifecycle};
+use crate::retention::{Expired, ExpiringStorage};
+use crate::swap::{fees_from_bytes, fees_to_bytes, path_from_bytes, path_to_bytes, SwapStorage};
 use crate::tx_storage::TxStorage;
+use crate::writer::Retryable;
+use dex::candle::{Candle, Interval, Trade};
+use dex::registry::{PairInfo, RegistryStorage, TokenInfo};
+use dex::swap::{SwapRecord, SwapStatus};
+
+/// Most queries in flight at once for bulk reads and writes
+const MAX_CONCURRENT_QUERIES: usize = 64;
+
+/// Most partition keys in one `IN` restriction, Scylla's default limit
+const MAX_IN_KEYS: usize = 100;
+
+const TX_COLUMNS: &str =
+    "hash, nonce, blockHash, blockNumber, transactionIndex, \"from\", \"to\", \
+    value, gasPrice, gas, input, type, maxPriorityFee, maxFeePerGas, chainId";
+
+const INSERT_TX: &str = "INSERT INTO {}.transactions (
+        hash,
+        nonce,
+        blockHash,
+        blockNumber,
+        transactionIndex,
+        \"from\",
+        \"to\",
+        value,
+        gasPrice,
+        gas,
+        input,
+        type,
+        maxPriorityFee,
+        maxFeePerGas,
+        chainId
+    ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+    USING TTL ?";
+
+/// Tables created on connect
+///
+/// Amounts in wei are stored as decimal text, they don't fit a `bigint`.
+const SCHEMA: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS {}.transactions (
+        hash text PRIMARY KEY,
+        nonce bigint,
+        blockHash text,
+        blockNumber bigint,
+        transactionIndex bigint,
+        \"from\" text,
+        \"to\" text,
+        value text,
+        gasPrice text,
+        gas bigint,
+        input blob,
+        type bigint,
+        maxPriorityFee text,
+        maxFeePerGas text,
+        chainId bigint
+    )",
+    "CREATE TABLE IF NOT EXISTS {}.tx_lifecycles (
+        hash text PRIMARY KEY,
+        \"from\" text,
+        nonce text,
+        \"to\" text,
+        router text,
+        node text,
+        first_seen bigint,
+        status text,
+        block_number bigint,
+        block_hash text,
+        transaction_index bigint,
+        mined_at bigint,
+        replaced_by text,
+        closed_at bigint
+    )",
+    "CREATE TABLE IF NOT EXISTS {}.swaps (
+        tx_hash text,
+        swap_index int,
+        router text,
+        protocol_version int,
+        method text,
+        sender text,
+        recipient text,
+        path blob,
+        fees blob,
+        kind text,
+        amount_in text,
+        amount_out text,
+        deadline bigint,
+        status text,
+        block_number bigint,
+        block_hash text,
+        transaction_index bigint,
+        PRIMARY KEY (tx_hash, swap_index)
+    )",
+    "CREATE TABLE IF NOT EXISTS {}.tokens (
+        address text PRIMARY KEY,
+        name text,
+        symbol text,
+        decimals int,
+        first_seen_block bigint
+    )",
+    "CREATE TABLE IF NOT EXISTS {}.pairs (
+        address text PRIMARY KEY,
+        factory text,
+        protocol_version int,
+        token0 text,
+        token1 text,
+        fee int,
+        created_block bigint
+    )",
+    // Trades by the hour of their block, in chain order
+    "CREATE TABLE IF NOT EXISTS {}.trades (
+        hour bigint,
+        timestamp bigint,
+        block_number bigint,
+        log_index bigint,
+        block_hash text,
+        pair text,
+        tx_hash text,
+        amount0_in text,
+        amount1_in text,
+        amount0_out text,
+        amount1_out text,
+        PRIMARY KEY (hour, timestamp, block_number, log_index, block_hash)
+    )",
+    // Trades again by block, to find the ones a reorg orphans
+    "CREATE TABLE IF NOT EXISTS {}.trades_by_block (
+        bucket bigint,
+        block_number bigint,
+        block_hash text,
+        log_index bigint,
+        timestamp bigint,
+        pair text,
+        tx_hash text,
+        amount0_in text,
+        amount1_in text,
+        amount0_out text,
+        amount1_out text,
+        PRIMARY KEY (bucket, block_number, block_hash, log_index)
+    )",
+    "CREATE TABLE IF NOT EXISTS {}.candles (
+        pair text,
+        interval text,
+        start bigint,
+        open double,
+        high double,
+        low double,
+        close double,
+        volume0 double,
+        volume1 double,
+        trades int,
+        PRIMARY KEY ((pair, interval), start)
+    )",
+    // Mined swaps again by block, to read them in chain order
+    "CREATE TABLE IF NOT EXISTS {}.swaps_by_block (
+        tx_hash text,
+        swap_index int,
+        router text,
+        protocol_version int,
+        method text,
+        sender text,
+        recipient text,
+        path blob,
+        fees blob,
+        kind text,
+        amount_in text,
+        amount_out text,
+        deadline bigint,
+        status text,
+        block_number bigint,
+        block_hash text,
+        transaction_index bigint,
+        PRIMARY KEY (block_number, transaction_index, tx_hash, swap_index)
+    )",
+];
+
+const LIFECYCLE_COLUMNS: &str =
+    "hash, \"from\", nonce, \"to\", router, node, first_seen, status, block_number, \
+    block_hash, transaction_index, mined_at, replaced_by, closed_at";
+
+/// A row of `LIFECYCLE_COLUMNS`, also bound to insert one
+type LifecycleRow = (
+    String,
+    String,
+    String,
+    Option<String>,
+    Option<String>,
+    String,
+    i64,
+    String,
+    Option<i64>,
+    Option<String>,
+    Option<i64>,
+    Option<i64>,
+    Option<String>,
+    Option<i64>,
+);
+
+fn lifecycle_row(lifecycle: &TxLifecycle) -> LifecycleRow {
+    (
+        format!("{:?}", lifecycle.hash),
+        format!("{:?}", lifecycle.from),
+        lifecycle.nonce.to_string(),
+        lifecycle.to.map(|to| format!("{:?}", to)),
+        lifecycle.router.clone(),
+        lifecycle.node.clone(),
+        lifecycle.first_seen as i64,
+        lifecycle.status.to_string(),
+        lifecycle.block_number.map(|n| n as i64),
+        lifecycle.block_hash.map(|hash| format!("{:?}", hash)),
+        lifecycle.transaction_index.map(|i| i as i64),
+        lifecycle.mined_at.map(|t| t as i64),
+        lifecycle.replaced_by.map(|hash| format!("{:?}", hash)),
+        lifecycle.closed_at.map(|t| t as i64),
+    )
+}
+
+fn lifecycle_from_row(row: LifecycleRow) -> Result<TxLifecycle> {
+    let (
+        hash,
+        from,
+        nonce,
+        to,
+        router,
+        node,
+        first_seen,
+        status,
+        block_number,
+        block_hash,
+        transaction_index,
+        mined_at,
+        replaced_by,
+        closed_at,
+    ) = row;
+
+    Ok(TxLifecycle {
+        hash: hash.parse()?,
+        from: from.parse()?,
+        nonce: U256::from_dec_str(nonce.as_str())?,
+        to: to.map(|to| to.parse()).transpose()?,
+        router,
+        node,
+        first_seen: first_seen as u64,
+        status: status.parse()?,
+        block_number: block_number.map(|n| n as u64),
+        block_hash: block_hash.map(|hash| hash.parse()).transpose()?,
+        transaction_index: transaction_index.map(|i| i as u64),
+        mined_at: mined_at.map(|t| t as u64),
+        replaced_by: replaced_by.map(|hash| hash.parse()).transpose()?,
+        closed_at: closed_at.map(|t| t as u64),
+    })
+}
+
+const TOKEN_COLUMNS: &str = "address, name, symbol, decimals, first_seen_block";
+
+type TokenRow = (String, String, String, i32, Option<i64>);
+
+fn token_from_row(row: TokenRow) -> Result<TokenInfo> {
+    let (address, name, symbol, decimals, first_seen_block) = row;
+
+    Ok(TokenInfo {
+        address: address.parse()?,
+        name,
+        symbol,
+        decimals: decimals as u8,
+        first_seen_block: first_seen_block.map(|n| n as u64),
+    })
+}
+
+const PAIR_COLUMNS: &str = "address, factory, protocol_version, token0, token1, fee, created_block";
+
+type PairRow = (String, String, i32, String, String, Option<i32>, i64);
+
+fn pair_from_row(row: PairRow) -> Result<PairInfo> {
+    let (address, factory, protocol_version, token0, token1, fee, created_block) = row;
+
+    Ok(PairInfo {
+        address: address.parse()?,
+        factory: factory.parse()?,
+        protocol_version: protocol_version as u8,
+        token0: token0.parse()?,
+        token1: token1.parse()?,
+        fee: fee.map(|fee| fee as u32),
+        created_block: created_block as u64,
+    })
+}
+
+/// Blocks per partition of `trades_by_block`, about a day and a half
+const TRADE_BLOCK_BUCKET: u64 = 10_000;
+
+const TRADE_COLUMNS: &str = "pair, tx_hash, block_number, block_hash, log_index, timestamp, \
+    amount0_in, amount1_in, amount0_out, amount1_out";
+
+type TradeRow = (
+    String,
+    String,
+    i64,
+    String,
+    i64,
+    i64,
+    String,
+    String,
+    String,
+    String,
+);
+
+fn trade_from_row(row: TradeRow) -> Result<Trade> {
+    let (
+        pair,
+        tx_hash,
+        block_number,
+        block_hash,
+        log_index,
+        timestamp,
+        amount0_in,
+        amount1_in,
+        amount0_out,
+        amount1_out,
+    ) = row;
+
+    Ok(Trade {
+        pair: pair.parse()?,
+        tx_hash: tx_hash.parse()?,
+        block_number: block_number as u64,
+        block_hash: block_hash.parse()?,
+        log_index: log_index as u64,
+        timestamp: timestamp as u64,
+        amount0_in: U256::from_dec_str(amount0_in.as_str())?,
+        amount1_in: U256::from_dec_str(amount1_in.as_str())?,
+        amount0_out: U256::from_dec_str(amount0_out.as_str())?,
+        amount1_out: U256::from_dec_str(amount1_out.as_str())?,
+    })
+}
+
+const CANDLE_COLUMNS: &str =
+    "pair, interval, start, open, high, low, close, volume0, volume1, trades";
+
+type CandleRow = (String, String, i64, f64, f64, f64, f64, f64, f64, i32);
+
+fn candle_from_row(row: CandleRow) -> Result<Candle> {
+    let (pair, interval, start, open, high, low, close, volume0, volume1, trades) = row;
+
+    Ok(Candle {
+        pair: pair.parse()?,
+        interval: interval.parse()?,
+        start: start as u64,
+        open,
+        high,
+        low,
+        close,
+        volume0,
+        volume1,
+        trades: trades as u32,
+    })
+}
+
+const SWAP_COLUMNS: &str =
+    "tx_hash, swap_index, router, protocol_version, method, sender, recipient, path, fees, \
+    kind, amount_in, amount_out, deadline, status, block_number, block_hash, transaction_index";
+
+/// A row of `SWAP_COLUMNS`, also bound to insert one
+#[derive(FromRow, ValueList)]
+struct SwapRow {
+    tx_hash: String,
+    swap_index: i32,
+    router: String,
+    protocol_version: i32,
+    method: String,
+    sender: String,
+    recipient: Option<String>,
+    path: Vec<u8>,
+    fees: Vec<u8>,
+    kind: String,
+    amount_in: String,
+    amount_out: String,
+    deadline: Option<i64>,
+    status: String,
+    block_number: Option<i64>,
+    block_hash: Option<String>,
+    transaction_index: Option<i64>,
+}
+
+fn swap_row(swap: &SwapRecord) -> SwapRow {
+    SwapRow {
+        tx_hash: format!("{:?}", swap.tx_hash),
+        swap_index: swap.swap_index as i32,
+        router: swap.router.clone(),
+        protocol_version: swap.protocol_version as i32,
+        method: swap.method.clone(),
+        sender: format!("{:?}", swap.sender),
+        recipient: swap.recipient.map(|recipient| format!("{:?}", recipient)),
+        path: path_to_bytes(&swap.path),
+        fees: fees_to_bytes(&swap.fees),
+        kind: swap.kind.as_str().to_string(),
+        amount_in: swap.amount_in.to_string(),
+        amount_out: swap.amount_out.to_string(),
+        deadline: swap.deadline.map(|d| d.min(i64::MAX as u64) as i64),
+        status: swap.status.as_str().to_string(),
+        block_number: swap.block_number.map(|n| n as i64),
+        block_hash: swap.block_hash.map(|hash| format!("{:?}", hash)),
+        transaction_index: swap.transaction_index.map(|i| i as i64),
+    }
+}
+
+fn swap_from_row(row: SwapRow) -> Result<SwapRecord> {
+    Ok(SwapRecord {
+        tx_hash: row.tx_hash.parse()?,
+        swap_index: row.swap_index as u32,
+        router: row.router,
+        protocol_version: row.protocol_version as u8,
+        method: row.method,
+        sender: row.sender.parse()?,
+        recipient: row
+            .recipient
+            .map(|recipient| recipient.parse())
+            .transpose()?,
+        path: path_from_bytes(&row.path),
+        fees: fees_from_bytes(&row.fees),
+        kind: row.kind.parse()?,
+        amount_in: U256::from_dec_str(row.amount_in.as_str())?,
+        amount_out: U256::from_dec_str(row.amount_out.as_str())?,
+        deadline: row.deadline.map(|d| d as u64),
+        status: row.status.parse()?,
+        block_number: row.block_number.map(|n| n as u64),
+        block_hash: row.block_hash.map(|hash| hash.parse()).transpose()?,
+        transaction_index: row.transaction_index.map(|i| i as u64),
+        score: None,
+        amounts: None,
+    })
+}
+
+type TxValues = (
+    String,
+    i64,
+    String,
+    i64,
+    i64,
+    String,
+    String,
+    String,
+    String,
+    i64,
+    Vec<u8>,
+    i64,
+    String,
+    String,
+    i64,
+    i32,
+);
+
+/// A row of `TX_COLUMNS`
+type TxRow = (
+    String,
+    i64,
+    String,
+    i64,
+    i64,
+    String,
+    String,
+    String,
+    String,
+    i64,
+    Option<Vec<u8>>,
+    i64,
+    String,
+    String,
+    i64,
+);
+
+/// A `bigint` column value, saturated for fields a tx can set above it, e.g. its gas
+fn saturating_i64(value: U256) -> i64 {
+    value.min(U256::from(i64::MAX)).as_u64() as i64
+}
+
+/// `None` for the zero a missing field is stored as, legacy txs (type 0) read back untyped
+fn unset_if_zero<T: Default + PartialEq>(value: T) -> Option<T> {
+    (value != T::default()).then_some(value)
+}
+
+/// Rebuild a transaction from a row, zero values written for missing fields read back as `None`
+fn tx_from_row(row: TxRow) -> Result<Transaction> {
+    let (
+        hash,
+        nonce,
+        block_hash,
+        block_number,
+        transaction_index,
+        from,
+        to,
+        value,
+        gas_price,
+        gas,
+        input,
+        transaction_type,
+        max_priority_fee_per_gas,
+        max_fee_per_gas,
+        chain_id,
+    ) = row;
+    let block_hash = block_hash.parse::<H256>()?;
+    let to = to.parse::<Address>()?;
+
+    Ok(Transaction {
+        hash: hash.parse()?,
+        nonce: U256::from(nonce as u64),
+        block_hash: (!block_hash.is_zero()).then_some(block_hash),
+        block_number: (!block_hash.is_zero()).then(|| U64::from(block_number as u64)),
+        transaction_index: (!block_hash.is_zero()).then(|| U64::from(transaction_index as u64)),
+        from: from.parse()?,
+        to: (!to.is_zero()).then_some(to),
+        value: U256::from_dec_str(value.as_str())?,
+        gas_price: unset_if_zero(U256::from_dec_str(gas_price.as_str())?),
+        gas: U256::from(gas as u64),
+        input: Bytes::from(input.unwrap_or_default()),
+        transaction_type: unset_if_zero(U64::from(transaction_type as u64)),
+        max_priority_fee_per_gas: unset_if_zero(U256::from_dec_str(
+            max_priority_fee_per_gas.as_str(),
+        )?),
+        max_fee_per_gas: unset_if_zero(U256::from_dec_str(max_fee_per_gas.as_str())?),
+        chain_id: unset_if_zero(U256::from(chain_id as u64)),
+        ..Default::default()
+    })
+}
 
 pub struct TXScyllaStorage {
     pub url: String,
     pub keyspace: String,
+    pub retention: Retention,
     session: Session,
 }
 
@@ -17,6 +546,7 @@ impl TXScyllaStorage {
         keyspace: String,
         username: Option<String>,
         password: Option<String>,
+        retention: Retention,
     ) -> Result<Self> {
         let session = {
             if let (Some(username), Some(password)) = (username, password) {
@@ -37,11 +567,128 @@ impl TXScyllaStorage {
             }
         };
 
-        Ok(Self {
+        let storage = Self {
             url,
             keyspace,
+            retention,
             session,
-        })
+        };
+        storage.create_tables().await?;
+
+        Ok(storage)
+    }
+
+    async fn create_tables(&self) -> Result<()> {
+        for table in SCHEMA {
+            self.session.query(self.table(table), ()).await?;
+        }
+
+        Ok(())
+    }
+
+    /// `query` with its `{}` placeholder replaced by the keyspace
+    fn table(&self, query: &str) -> String {
+        query.replacen("{}", self.keyspace.as_str(), 1)
+    }
+
+    /// Bind values of `INSERT_TX` for a transaction
+    ///
+    /// Hashes and addresses are written as full hex strings, the `Display` output of ethers
+    /// types is abbreviated.
+    fn tx_values(&self, tx: &Transaction) -> TxValues {
+        (
+            format!("{:?}", tx.hash),
+            saturating_i64(tx.nonce),
+            format!("{:?}", tx.block_hash.unwrap_or_default()),
+            tx.block_number.unwrap_or_default().as_u64() as i64,
+            tx.transaction_index.unwrap_or_default().as_u64() as i64,
+            format!("{:?}", tx.from),
+            format!("{:?}", tx.to.unwrap_or_default()),
+            tx.value.to_string(),
+            tx.gas_price.unwrap_or_default().to_string(),
+            saturating_i64(tx.gas),
+            tx.input.0.to_vec(),
+            // tx.v,
+            // tx.r,
+            // tx.s,
+            tx.transaction_type.unwrap_or_default().as_u64() as i64,
+            tx.max_priority_fee_per_gas.unwrap_or_default().to_string(),
+            tx.max_fee_per_gas.unwrap_or_default().to_string(),
+            saturating_i64(tx.chain_id.unwrap_or_default()),
+            self.tx_ttl(tx),
+        )
+    }
+
+    /// CQL TTL for a transaction in seconds, 0 keeps it forever
+    fn tx_ttl(&self, tx: &Transaction) -> i32 {
+        if tx.block_hash.is_some() {
+            self.ttl(DataClass::MinedTxs)
+        } else {
+            self.ttl(DataClass::PendingTxs)
+        }
+    }
+
+    /// CQL TTL for a class of data in seconds, 0 keeps it forever
+    fn ttl(&self, class: DataClass) -> i32 {
+        self.retention
+            .ttl(class)
+            .map(|ttl| ttl.as_secs().min(i32::MAX as u64) as i32)
+            .unwrap_or(0)
+    }
+
+    /// Insert a trade into `table` under the partition `key`, `trades` by hour or
+    /// `trades_by_block` by bucket
+    async fn insert_trade(&self, table: &str, key: (&str, u64), trade: &Trade) -> Result<()> {
+        self.session
+            .query(
+                self.table(
+                    format!(
+                        "INSERT INTO {{}}.{} ({}, {}) VALUES ({})",
+                        table,
+                        key.0,
+                        TRADE_COLUMNS,
+                        ["?"; 11].join(", ")
+                    )
+                    .as_str(),
+                ),
+                (
+                    key.1 as i64,
+                    format!("{:?}", trade.pair),
+                    format!("{:?}", trade.tx_hash),
+                    trade.block_number as i64,
+                    format!("{:?}", trade.block_hash),
+                    trade.log_index as i64,
+                    trade.timestamp as i64,
+                    trade.amount0_in.to_string(),
+                    trade.amount1_in.to_string(),
+                    trade.amount0_out.to_string(),
+                    trade.amount1_out.to_string(),
+                ),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Insert a swap into `table`, `swaps` or `swaps_by_block`
+    async fn insert_swap(&self, table: &str, swap: &SwapRecord, ttl: i32) -> Result<()> {
+        self.session
+            .query(
+                self.table(
+                    format!(
+                        "INSERT INTO {{}}.{} ({}) VALUES ({}) USING TTL {}",
+                        table,
+                        SWAP_COLUMNS,
+                        ["?"; 17].join(", "),
+                        ttl
+                    )
+                    .as_str(),
+                ),
+                swap_row(swap),
+            )
+            .await?;
+
+        Ok(())
     }
 }
 
@@ -49,51 +696,383 @@ impl TxStorage<Transaction> for TXScyllaStorage {
     async fn store(&mut self, tx: Transaction) -> Result<()> {
         debug!("Storing tx: {:#?}", tx.hash);
 
-        let prepared = self
+        let prepared = self.session.prepare(self.table(INSERT_TX)).await?;
+        self.session.execute(&prepared, self.tx_values(&tx)).await?;
+
+        Ok(())
+    }
+
+    async fn is_stored(&mut self, tx: Transaction) -> Result<bool> {
+        debug!("Checking if tx is stored: {:#?}", tx.hash);
+
+        let row = self
+            .session
+            .query(
+                self.table("SELECT hash FROM {}.transactions WHERE hash = ?"),
+                (format!("{:?}", tx.hash),),
+            )
+            .await?
+            .maybe_first_row()?;
+
+        Ok(row.is_some())
+    }
+
+    async fn get(&mut self, hash: H256) -> Result<Option<Transaction>> {
+        debug!("Getting tx: {:#?}", hash);
+
+        self.session
+            .query(
+                self.table(
+                    format!(
+                        "SELECT {} FROM {{}}.transactions WHERE hash = ?",
+                        TX_COLUMNS
+                    )
+                    .as_str(),
+                ),
+                (format!("{:?}", hash),),
+            )
+            .await?
+            .maybe_first_row_typed::<TxRow>()?
+            .map(tx_from_row)
+            .transpose()
+    }
+
+    /// Writes are sent concurrently rather than as a batch, Scylla handles multi-partition
+    /// batches poorly
+    async fn store_many(&mut self, txs: Vec<Transaction>) -> Result<()> {
+        debug!("Storing {} txs", txs.len());
+
+        let storage = &*self;
+        let prepared = storage.session.prepare(storage.table(INSERT_TX)).await?;
+        let mut writes = stream::iter(txs.iter())
+            .map(|tx| storage.session.execute(&prepared, storage.tx_values(tx)))
+            .buffer_unordered(MAX_CONCURRENT_QUERIES);
+
+        while let Some(written) = writes.next().await {
+            written?;
+        }
+
+        Ok(())
+    }
+
+    async fn exists_many(&mut self, hashes: &[H256]) -> Result<HashSet<H256>> {
+        debug!("Checking if {} txs are stored", hashes.len());
+
+        let session = &self.session;
+        let prepared = session
+            .prepare(self.table("SELECT hash FROM {}.transactions WHERE hash IN ?"))
+            .await?;
+        let mut lookups = stream::iter(hashes.chunks(MAX_IN_KEYS))
+            .map(|chunk| {
+                let keys = chunk
+                    .iter()
+                    .map(|hash| format!("{:?}", hash))
+                    .collect::<Vec<String>>();
+                let prepared = &prepared;
+
+                async move { session.execute(prepared, (keys,)).await }
+            })
+            .buffer_unordered(MAX_CONCURRENT_QUERIES);
+
+        let mut stored = HashSet::new();
+        while let Some(result) = lookups.next().await {
+            for row in result?.rows_typed_or_empty::<(String,)>() {
+                stored.insert(row?.0.parse::<H256>()?);
+            }
+        }
+
+        Ok(stored)
+    }
+
+    async fn delete(&mut self, tx: Transaction) -> Result<()> {
+        debug!("Deleting tx: {:#?}", tx.hash);
+
+        self.session
+            .query(
+                self.table("DELETE FROM {}.transactions WHERE hash = ?"),
+                (format!("{:?}", tx.hash),),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl Retryable for TXScyllaStorage {
+    /// Connection problems, timeouts and overloaded or unavailable nodes are transient
+    fn is_retryable(&self, error: &anyhow::Error) -> bool {
+        match error.downcast_ref::<QueryError>() {
+            Some(QueryError::DbError(e, _)) => matches!(
+                e,
+                DbError::Unavailable { .. }
+                    | DbError::Overloaded
+                    | DbError::IsBootstrapping
+                    | DbError::ReadTimeout { .. }
+                    | DbError::WriteTimeout { .. }
+                    | DbError::RateLimitReached { .. }
+            ),
+            Some(
+                QueryError::IoError(_)
+                | QueryError::TimeoutError
+                | QueryError::TooManyOrphanedStreamIds(_)
+                | QueryError::UnableToAllocStreamId
+                | QueryError::RequestTimeout(_),
+            ) => true,
+            Some(_) => false,
+            None => true,
+        }
+    }
+}
+
+impl LifecycleStorage for TXScyllaStorage {
+    /// The stored lifecycle is read first, Scylla can't keep the earliest sighting in an upsert
+    async fn store_lifecycle(&mut self, lifecycle: &TxLifecycle) -> Result<()> {
+        debug!(
+            "Storing lifecycle of tx {:#?}: {}",
+            lifecycle.hash, lifecycle.status
+        );
+
+        let lifecycle = match self.lifecycle(lifecycle.hash).await? {
+            Some(stored) => TxLifecycle {
+                first_seen: stored.first_seen.min(lifecycle.first_seen),
+                router: stored.router.or_else(|| lifecycle.router.clone()),
+                node: stored.node,
+                ..lifecycle.clone()
+            },
+            None => lifecycle.clone(),
+        };
+
+        self.session
+            .query(
+                self.table(
+                    format!(
+                        "INSERT INTO {{}}.tx_lifecycles ({}) VALUES ({})",
+                        LIFECYCLE_COLUMNS,
+                        ["?"; 14].join(", ")
+                    )
+                    .as_str(),
+                ),
+                lifecycle_row(&lifecycle),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn lifecycle(&mut self, hash: H256) -> Result<Option<TxLifecycle>> {
+        self.session
+            .query(
+                self.table(
+                    format!(
+                        "SELECT {} FROM {{}}.tx_lifecycles WHERE hash = ?",
+                        LIFECYCLE_COLUMNS
+                    )
+                    .as_str(),
+                ),
+                (format!("{:?}", hash),),
+            )
+            .await?
+            .maybe_first_row_typed::<LifecycleRow>()?
+            .map(lifecycle_from_row)
+            .transpose()
+    }
+
+    /// Lifecycles are keyed by hash only, so this scans the table, which is fine for reports
+    async fn router_latency(&mut self, since: u64) -> Result<Vec<RouterLatency>> {
+        let mut rows = self
             .session
+            .query_iter(
+                self.table(
+                    format!(
+                        "SELECT {} FROM {{}}.tx_lifecycles WHERE first_seen >= ? ALLOW FILTERING",
+                        LIFECYCLE_COLUMNS
+                    )
+                    .as_str(),
+                ),
+                (since as i64,),
+            )
+            .await?
+            .into_typed::<LifecycleRow>();
+
+        let mut lifecycles = Vec::new();
+        while let Some(row) = rows.next().await {
+            lifecycles.push(lifecycle_from_row(row?)?);
+        }
+
+        Ok(latency_by_router(&lifecycles))
+    }
+}
+
+impl SwapStorage for TXScyllaStorage {
+    /// Scylla can't make an upsert conditional on the stored status, so the stored swap is read
+    /// first. A swap moving to another block leaves its row of the previous block.
+    async fn store_swap(&mut self, swap: &SwapRecord) -> Result<()> {
+        debug!(
+            "Storing swap {} of tx {:#?}: {}",
+            swap.swap_index, swap.tx_hash, swap.status
+        );
+
+        let key = (format!("{:?}", swap.tx_hash), swap.swap_index as i32);
+        let stored = self
+            .session
+            .query(
+                self.table(
+                    format!(
+                        "SELECT {} FROM {{}}.swaps WHERE tx_hash = ? AND swap_index = ?",
+                        SWAP_COLUMNS
+                    )
+                    .as_str(),
+                ),
+                &key,
+            )
+            .await?
+            .maybe_first_row_typed::<SwapRow>()?
+            .map(swap_from_row)
+            .transpose()?;
+
+        let (swap, ttl) = match stored {
+            None => (swap.clone(), self.ttl(DataClass::DecodedEvents)),
+            Some(stored)
+                if stored.status == SwapStatus::Pending || swap.status == SwapStatus::Mined =>
+            {
+                // The swap keeps expiring when it was first stored
+                let ttl = self
+                    .session
+                    .query(
+                        self.table(
+                            "SELECT TTL(router) FROM {}.swaps WHERE tx_hash = ? AND swap_index = ?",
+                        ),
+                        &key,
+                    )
+                    .await?
+                    .maybe_first_row_typed::<(Option<i32>,)>()?
+                    .and_then(|(ttl,)| ttl)
+                    .unwrap_or(0);
+
+                if stored.status == SwapStatus::Mined
+                    && (stored.block_number, stored.transaction_index)
+                        != (swap.block_number, swap.transaction_index)
+                {
+                    self.session
+                        .query(
+                            self.table(
+                                "DELETE FROM {}.swaps_by_block WHERE block_number = ?
+                                AND transaction_index = ? AND tx_hash = ? AND swap_index = ?",
+                            ),
+                            (
+                                stored.block_number.map(|n| n as i64),
+                                stored.transaction_index.map(|i| i as i64),
+                                &key.0,
+                                key.1,
+                            ),
+                        )
+                        .await?;
+                }
+
+                (
+                    SwapRecord {
+                        status: swap.status,
+                        block_number: swap.block_number,
+                        block_hash: swap.block_hash,
+                        transaction_index: swap.transaction_index,
+                        ..stored
+                    },
+                    ttl,
+                )
+            }
+            Some(_) => return Ok(()),
+        };
+
+        self.insert_swap("swaps", &swap, ttl).await?;
+        if swap.status == SwapStatus::Mined {
+            self.insert_swap("swaps_by_block", &swap, ttl).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn swaps(&mut self, tx_hash: H256) -> Result<Vec<SwapRecord>> {
+        self.session
+            .query(
+                self.table(
+                    format!("SELECT {} FROM {{}}.swaps WHERE tx_hash = ?", SWAP_COLUMNS).as_str(),
+                ),
+                (format!("{:?}", tx_hash),),
+            )
+            .await?
+            .rows_typed_or_empty::<SwapRow>()
+            .map(|row| swap_from_row(row?))
+            .collect()
+    }
+
+    /// Blocks aren't stored by this backend, so swaps of blocks that were reorged out are only
+    /// left out once their tx is mined again
+    async fn swaps_in_range(&mut self, from: u64, to: u64) -> Result<Vec<SwapRecord>> {
+        let session = &self.session;
+        let prepared = session
             .prepare(
-                "INSERT INTO transactions (
-                hash,
-                nonce,
-                blockHash,
-                blockNumber,
-                transactionIndex,
-                from,
-                to,
-                value,
-                gasPrice,
-                gas,
-                input,
-                type,
-                maxPriorityFee,
-                maxFeePerGas,
-                chainId
-            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                self.table(
+                    format!(
+                        "SELECT {} FROM {{}}.swaps_by_block WHERE block_number = ?",
+                        SWAP_COLUMNS
+                    )
+                    .as_str(),
+                ),
             )
             .await?;
+        let mut blocks = stream::iter(from..=to)
+            .map(|number| {
+                let prepared = &prepared;
+
+                async move { session.execute(prepared, (number as i64,)).await }
+            })
+            .buffered(MAX_CONCURRENT_QUERIES);
+
+        let mut swaps = Vec::new();
+        while let Some(result) = blocks.next().await {
+            for row in result?.rows_typed_or_empty::<SwapRow>() {
+                swaps.push(swap_from_row(row?)?);
+            }
+        }
+
+        Ok(swaps)
+    }
+}
+
+impl RegistryStorage for TXScyllaStorage {
+    async fn store_token(&mut self, token: &TokenInfo) -> Result<()> {
+        let address = format!("{:?}", token.address);
+
+        // Scylla has no LEAST on upsert, the earliest block is taken from the stored token
+        let stored = self
+            .session
+            .query(
+                self.table("SELECT first_seen_block FROM {}.tokens WHERE address = ?"),
+                (&address,),
+            )
+            .await?
+            .maybe_first_row_typed::<(Option<i64>,)>()?
+            .and_then(|(first_seen_block,)| first_seen_block);
+        let first_seen_block = match (stored, token.first_seen_block.map(|n| n as i64)) {
+            (Some(stored), Some(seen)) => Some(stored.min(seen)),
+            (stored, seen) => stored.or(seen),
+        };
 
         self.session
-            .execute(
-                &prepared,
+            .query(
+                self.table(
+                    format!(
+                        "INSERT INTO {{}}.tokens ({}) VALUES (?, ?, ?, ?, ?)",
+                        TOKEN_COLUMNS
+                    )
+                    .as_str(),
+                ),
                 (
-                    tx.hash.to_string(),
-                    tx.nonce.as_u64() as i64,
-                    tx.block_hash.unwrap_or_default().to_string(),
-                    tx.block_number.unwrap_or_default().as_u64() as i64,
-                    tx.transaction_index.unwrap_or_default().as_u64() as i64,
-                    tx.from.to_string(),
-                    tx.to.unwrap_or_default().to_string(),
-                    tx.value.as_u64() as i64,
-                    tx.gas_price.unwrap_or_default().as_u64() as i64,
-                    tx.gas.as_u64() as i64,
-                    tx.input.0.to_vec(),
-                    // tx.v,
-                    // tx.r,
-                    // tx.s,
-                    tx.transaction_type.unwrap_or_default().as_u64() as i64,
-                    tx.max_priority_fee_per_gas.unwrap_or_default().to_string(),
-                    tx.max_fee_per_gas.unwrap_or_default().as_u64() as i64,
-                    tx.chain_id.unwrap_or_default().as_u64() as i64,
+                    &address,
+                    &token.name,
+                    &token.symbol,
+                    token.decimals as i32,
+                    first_seen_block,
                 ),
             )
             .await?;
@@ -101,15 +1080,343 @@ impl TxStorage<Transaction> for TXScyllaStorage {
         Ok(())
     }
 
-    async fn is_stored(&mut self, tx: Transaction) -> Result<bool> {
-        debug!("Checking if tx is stored: {:#?}", tx.hash);
+    async fn store_pair(&mut self, pair: &PairInfo) -> Result<()> {
+        debug!(
+            "Storing pair {:#?} of factory {:#?}",
+            pair.address, pair.factory
+        );
+
+        // Pairs never change, so inserting one again just writes the same row
+        self.session
+            .query(
+                self.table(
+                    format!(
+                        "INSERT INTO {{}}.pairs ({}) VALUES (?, ?, ?, ?, ?, ?, ?)",
+                        PAIR_COLUMNS
+                    )
+                    .as_str(),
+                ),
+                (
+                    format!("{:?}", pair.address),
+                    format!("{:?}", pair.factory),
+                    pair.protocol_version as i32,
+                    format!("{:?}", pair.token0),
+                    format!("{:?}", pair.token1),
+                    pair.fee.map(|fee| fee as i32),
+                    pair.created_block as i64,
+                ),
+            )
+            .await?;
 
-        Ok(false)
+        Ok(())
     }
 
-    async fn delete(&mut self, tx: Transaction) -> Result<()> {
-        debug!("Deleting tx: {:#?}", tx.hash);
+    async fn tokens(&mut self) -> Result<Vec<TokenInfo>> {
+        let mut rows = self
+            .session
+            .query_iter(
+                self.table(format!("SELECT {} FROM {{}}.tokens", TOKEN_COLUMNS).as_str()),
+                (),
+            )
+            .await?
+            .into_typed::<TokenRow>();
+
+        let mut tokens = Vec::new();
+        while let Some(row) = rows.next().await {
+            tokens.push(token_from_row(row?)?);
+        }
+
+        Ok(tokens)
+    }
+
+    /// Pairs are partitioned by address, so they are sorted by creation after the scan
+    async fn pairs(&mut self) -> Result<Vec<PairInfo>> {
+        let mut rows = self
+            .session
+            .query_iter(
+                self.table(format!("SELECT {} FROM {{}}.pairs", PAIR_COLUMNS).as_str()),
+                (),
+            )
+            .await?
+            .into_typed::<PairRow>();
+
+        let mut pairs = Vec::new();
+        while let Some(row) = rows.next().await {
+            pairs.push(pair_from_row(row?)?);
+        }
+        pairs.sort_by_key(|pair| pair.created_block);
+
+        Ok(pairs)
+    }
+}
+
+impl CandleStorage for TXScyllaStorage {
+    /// Trades are written again as they are, so storing one twice leaves it unchanged
+    async fn store_trades(&mut self, trades: &[Trade]) -> Result<()> {
+        debug!("Storing {} trades", trades.len());
+
+        for trade in trades {
+            let hour = Interval::OneHour.start(trade.timestamp);
+            let bucket = trade.block_number / TRADE_BLOCK_BUCKET;
+
+            self.insert_trade("trades", ("hour", hour), trade).await?;
+            self.insert_trade("trades_by_block", ("bucket", bucket), trade)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Only the bucket of `block_number` and the next one are searched, reorgs are never that
+    /// deep
+    async fn orphan_trades(&mut self, block_number: u64, block_hash: H256) -> Result<Vec<Trade>> {
+        let bucket = block_number / TRADE_BLOCK_BUCKET;
+
+        let mut orphaned = Vec::new();
+        for bucket in [bucket, bucket + 1] {
+            let rows = self
+                .session
+                .query(
+                    self.table(
+                        format!(
+                            "SELECT {} FROM {{}}.trades_by_block
+                            WHERE bucket = ? AND block_number >= ?",
+                            TRADE_COLUMNS
+                        )
+                        .as_str(),
+                    ),
+                    (bucket as i64, block_number as i64),
+                )
+                .await?
+                .rows_typed_or_empty::<TradeRow>();
+
+            for row in rows {
+                let trade = trade_from_row(row?)?;
+                if trade.block_number > block_number || trade.block_hash != block_hash {
+                    orphaned.push(trade);
+                }
+            }
+        }
+
+        for trade in &orphaned {
+            self.session
+                .query(
+                    self.table(
+                        "DELETE FROM {}.trades WHERE hour = ? AND timestamp = ?
+                        AND block_number = ? AND log_index = ? AND block_hash = ?",
+                    ),
+                    (
+                        Interval::OneHour.start(trade.timestamp) as i64,
+                        trade.timestamp as i64,
+                        trade.block_number as i64,
+                        trade.log_index as i64,
+                        format!("{:?}", trade.block_hash),
+                    ),
+                )
+                .await?;
+            self.session
+                .query(
+                    self.table(
+                        "DELETE FROM {}.trades_by_block WHERE bucket = ? AND block_number = ?
+                        AND block_hash = ? AND log_index = ?",
+                    ),
+                    (
+                        (trade.block_number / TRADE_BLOCK_BUCKET) as i64,
+                        trade.block_number as i64,
+                        format!("{:?}", trade.block_hash),
+                        trade.log_index as i64,
+                    ),
+                )
+                .await?;
+        }
+
+        if !orphaned.is_empty() {
+            info!(
+                "Removed {} trades orphaned by block {:#?}",
+                orphaned.len(),
+                block_hash
+            );
+        }
+
+        Ok(orphaned)
+    }
+
+    /// Every hour of the period is read, the pair is filtered here
+    async fn trades(&mut self, pair: Option<Address>, from: u64, to: u64) -> Result<Vec<Trade>> {
+        let session = &self.session;
+        let prepared = session
+            .prepare(
+                self.table(
+                    format!(
+                        "SELECT {} FROM {{}}.trades
+                        WHERE hour = ? AND timestamp >= ? AND timestamp <= ?",
+                        TRADE_COLUMNS
+                    )
+                    .as_str(),
+                ),
+            )
+            .await?;
+        let hour = Interval::OneHour;
+        let mut hours = stream::iter((hour.start(from)..=to).step_by(hour.seconds() as usize))
+            .map(|start| {
+                let prepared = &prepared;
+
+                async move {
+                    session
+                        .execute(prepared, (start as i64, from as i64, to as i64))
+                        .await
+                }
+            })
+            .buffered(MAX_CONCURRENT_QUERIES);
+
+        let mut trades = Vec::new();
+        while let Some(result) = hours.next().await {
+            for row in result?.rows_typed_or_empty::<TradeRow>() {
+                let trade = trade_from_row(row?)?;
+                if pair.is_none_or(|pair| trade.pair == pair) {
+                    trades.push(trade);
+                }
+            }
+        }
+
+        Ok(trades)
+    }
+
+    async fn store_candle(&mut self, candle: &Candle) -> Result<()> {
+        self.session
+            .query(
+                self.table(
+                    format!(
+                        "INSERT INTO {{}}.candles ({}) VALUES ({})",
+                        CANDLE_COLUMNS,
+                        ["?"; 10].join(", ")
+                    )
+                    .as_str(),
+                ),
+                (
+                    format!("{:?}", candle.pair),
+                    candle.interval.as_str(),
+                    candle.start as i64,
+                    candle.open,
+                    candle.high,
+                    candle.low,
+                    candle.close,
+                    candle.volume0,
+                    candle.volume1,
+                    candle.trades as i32,
+                ),
+            )
+            .await?;
 
         Ok(())
     }
+
+    /// Without a pair every stored pair and interval is listed first, deleting by start alone
+    /// isn't possible with candles partitioned by pair
+    async fn delete_candles(
+        &mut self,
+        pair: Option<(Address, Interval)>,
+        from: u64,
+        to: u64,
+    ) -> Result<u64> {
+        let partitions = match pair {
+            Some((pair, interval)) => vec![(format!("{:?}", pair), interval.as_str().to_string())],
+            None => {
+                let mut rows = self
+                    .session
+                    .query_iter(
+                        self.table("SELECT DISTINCT pair, interval FROM {}.candles"),
+                        (),
+                    )
+                    .await?
+                    .into_typed::<(String, String)>();
+
+                let mut partitions = Vec::new();
+                while let Some(row) = rows.next().await {
+                    partitions.push(row?);
+                }
+
+                partitions
+            }
+        };
+
+        let mut deleted = 0;
+        for (pair, interval) in partitions {
+            let values = (&pair, &interval, from as i64, to as i64);
+            let count = self
+                .session
+                .query(
+                    self.table(
+                        "SELECT COUNT(*) FROM {}.candles
+                        WHERE pair = ? AND interval = ? AND start >= ? AND start <= ?",
+                    ),
+                    values,
+                )
+                .await?
+                .maybe_first_row_typed::<(i64,)>()?
+                .map_or(0, |(count,)| count as u64);
+            if count == 0 {
+                continue;
+            }
+
+            self.session
+                .query(
+                    self.table(
+                        "DELETE FROM {}.candles
+                        WHERE pair = ? AND interval = ? AND start >= ? AND start <= ?",
+                    ),
+                    values,
+                )
+                .await?;
+            deleted += count;
+        }
+
+        Ok(deleted)
+    }
+
+    async fn candles(
+        &mut self,
+        pair: Address,
+        interval: Interval,
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<Candle>> {
+        self.session
+            .query(
+                self.table(
+                    format!(
+                        "SELECT {} FROM {{}}.candles
+                        WHERE pair = ? AND interval = ? AND start >= ? AND start <= ?",
+                        CANDLE_COLUMNS
+                    )
+                    .as_str(),
+                ),
+                (
+                    format!("{:?}", pair),
+                    interval.as_str(),
+                    from as i64,
+                    to as i64,
+                ),
+            )
+            .await?
+            .rows_typed_or_empty::<CandleRow>()
+            .map(|row| candle_from_row(row?))
+            .collect()
+    }
+}
+
+impl ExpiringStorage for TXScyllaStorage {
+    async fn expire(&mut self, class: DataClass, cutoff: u64, _dry_run: bool) -> Result<Expired> {
+        debug!(
+            "Not expiring {} older than {}, Scylla uses TTLs",
+            class, cutoff
+        );
+
+        match class {
+            DataClass::PendingTxs | DataClass::MinedTxs | DataClass::DecodedEvents => {
+                Ok(Expired::ByTtl)
+            }
+            _ => Ok(Expired::NotStored),
+        }
+    }
 }
@@ -0,0 +1,1476 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use anyhow::Result;
+use ethers::types::{Address, Block, Bytes, Transaction, H256, U256, U64};
+use log::{debug, info, warn};
+use rusqlite::{params, types::Type, Connection, ErrorCode, OptionalExtension, Row};
+use settings::DataClass;
+
+use crate::block_storage::BlockStorage;
+use crate::candle::CandleStorage;
+use crate::lifecycle::{LifecycleStorage, RouterLatency, TxLifecycle};
+use crate::reader::ChainReader;
+use crate::retention::{Expired, ExpiringStorage};
+use crate::swap::{fees_from_bytes, fees_to_bytes, path_from_bytes, path_to_bytes, SwapStorage};
+use crate::tx_storage::TxStorage;
+use crate::writer::Retryable;
+use dex::candle::{Candle, Interval, Trade};
+use dex::registry::{PairInfo, RegistryStorage, TokenInfo};
+use dex::swap::SwapRecord;
+
+/// Embedded storage backed by a single SQLite database
+///
+/// Wei amounts are stored as decimal `TEXT` since SQLite integers are limited to 64 bits. A path
+/// of `:memory:` keeps everything in memory, which is handy for tests.
+///
+/// Clones share the connection, so every subsystem of a process sees the same database, even an
+/// in-memory one, and their writes don't contend for the file lock.
+#[derive(Clone)]
+pub struct SqliteStorage {
+    pub path: String,
+    connection: Arc<Mutex<Connection>>,
+}
+
+/// How long a write waits for another process to release the database
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+const TX_COLUMNS: &str = "hash, nonce, block_hash, block_number, transaction_index, from_address, \
+    to_address, value, gas_price, gas, input, type, max_priority_fee_per_gas, max_fee_per_gas, chain_id";
+
+const BLOCK_COLUMNS: &str =
+    "hash, number, parent_hash, miner, difficulty, total_difficulty, size, \
+    extra_data, gas_limit, gas_used, base_fee_per_gas, timestamp, transactions, uncles";
+
+fn conversion_error<E: std::error::Error + Send + Sync + 'static>(e: E) -> rusqlite::Error {
+    rusqlite::Error::FromSqlConversionFailure(0, Type::Text, Box::new(e))
+}
+
+fn parse_u256(value: String) -> rusqlite::Result<U256> {
+    U256::from_dec_str(value.as_str()).map_err(conversion_error)
+}
+
+fn parse_opt_u256(value: Option<String>) -> rusqlite::Result<Option<U256>> {
+    value.map(parse_u256).transpose()
+}
+
+fn tx_from_row(row: &Row) -> rusqlite::Result<Transaction> {
+    Ok(Transaction {
+        hash: H256::from_slice(&row.get::<_, Vec<u8>>("hash")?),
+        nonce: parse_u256(row.get("nonce")?)?,
+        block_hash: row
+            .get::<_, Option<Vec<u8>>>("block_hash")?
+            .map(|h| H256::from_slice(&h)),
+        block_number: row
+            .get::<_, Option<i64>>("block_number")?
+            .map(|n| U64::from(n as u64)),
+        transaction_index: row
+            .get::<_, Option<i64>>("transaction_index")?
+            .map(|i| U64::from(i as u64)),
+        from: Address::from_slice(&row.get::<_, Vec<u8>>("from_address")?),
+        to: row
+            .get::<_, Option<Vec<u8>>>("to_address")?
+            .map(|a| Address::from_slice(&a)),
+        value: parse_u256(row.get("value")?)?,
+        gas_price: parse_opt_u256(row.get("gas_price")?)?,
+        gas: parse_u256(row.get("gas")?)?,
+        input: Bytes::from(row.get::<_, Vec<u8>>("input")?),
+        transaction_type: row
+            .get::<_, Option<i64>>("type")?
+            .map(|t| U64::from(t as u64)),
+        max_priority_fee_per_gas: parse_opt_u256(row.get("max_priority_fee_per_gas")?)?,
+        max_fee_per_gas: parse_opt_u256(row.get("max_fee_per_gas")?)?,
+        chain_id: row
+            .get::<_, Option<i64>>("chain_id")?
+            .map(|c| U256::from(c as u64)),
+        ..Default::default()
+    })
+}
+
+const LIFECYCLE_COLUMNS: &str = "hash, from_address, nonce, to_address, router, node, \
+    first_seen, status, block_number, block_hash, transaction_index, mined_at, replaced_by, closed_at";
+
+const SWAP_COLUMNS: &str = "tx_hash, swap_index, router, protocol_version, method, sender, \
+    recipient, path, fees, kind, amount_in, amount_out, deadline, status, block_number, \
+    block_hash, transaction_index";
+
+fn parse_text<T>(value: String) -> rusqlite::Result<T>
+where
+    T: std::str::FromStr<Err = anyhow::Error>,
+{
+    value.parse().map_err(|e: anyhow::Error| {
+        rusqlite::Error::FromSqlConversionFailure(0, Type::Text, e.into())
+    })
+}
+
+fn swap_from_row(row: &Row) -> rusqlite::Result<SwapRecord> {
+    Ok(SwapRecord {
+        tx_hash: H256::from_slice(&row.get::<_, Vec<u8>>("tx_hash")?),
+        swap_index: row.get("swap_index")?,
+        router: row.get("router")?,
+        protocol_version: row.get("protocol_version")?,
+        method: row.get("method")?,
+        sender: Address::from_slice(&row.get::<_, Vec<u8>>("sender")?),
+        recipient: row
+            .get::<_, Option<Vec<u8>>>("recipient")?
+            .map(|a| Address::from_slice(&a)),
+        path: path_from_bytes(&row.get::<_, Vec<u8>>("path")?),
+        fees: fees_from_bytes(&row.get::<_, Vec<u8>>("fees")?),
+        kind: parse_text(row.get("kind")?)?,
+        amount_in: parse_u256(row.get("amount_in")?)?,
+        amount_out: parse_u256(row.get("amount_out")?)?,
+        deadline: row.get::<_, Option<i64>>("deadline")?.map(|d| d as u64),
+        status: parse_text(row.get("status")?)?,
+        block_number: row.get::<_, Option<i64>>("block_number")?.map(|n| n as u64),
+        block_hash: row
+            .get::<_, Option<Vec<u8>>>("block_hash")?
+            .map(|h| H256::from_slice(&h)),
+        transaction_index: row
+            .get::<_, Option<i64>>("transaction_index")?
+            .map(|i| i as u64),
+        score: None,
+        amounts: None,
+    })
+}
+
+const TOKEN_COLUMNS: &str = "address, name, symbol, decimals, first_seen_block";
+
+const PAIR_COLUMNS: &str = "address, factory, protocol_version, token0, token1, fee, created_block";
+
+fn token_from_row(row: &Row) -> rusqlite::Result<TokenInfo> {
+    Ok(TokenInfo {
+        address: Address::from_slice(&row.get::<_, Vec<u8>>("address")?),
+        name: row.get("name")?,
+        symbol: row.get("symbol")?,
+        decimals: row.get("decimals")?,
+        first_seen_block: row
+            .get::<_, Option<i64>>("first_seen_block")?
+            .map(|n| n as u64),
+    })
+}
+
+fn pair_from_row(row: &Row) -> rusqlite::Result<PairInfo> {
+    Ok(PairInfo {
+        address: Address::from_slice(&row.get::<_, Vec<u8>>("address")?),
+        factory: Address::from_slice(&row.get::<_, Vec<u8>>("factory")?),
+        protocol_version: row.get("protocol_version")?,
+        token0: Address::from_slice(&row.get::<_, Vec<u8>>("token0")?),
+        token1: Address::from_slice(&row.get::<_, Vec<u8>>("token1")?),
+        fee: row.get("fee")?,
+        created_block: row.get::<_, i64>("created_block")? as u64,
+    })
+}
+
+const TRADE_COLUMNS: &str = "pair, tx_hash, block_number, block_hash, log_index, timestamp, \
+    amount0_in, amount1_in, amount0_out, amount1_out";
+
+const CANDLE_COLUMNS: &str =
+    "pair, interval, start, open, high, low, close, volume0, volume1, trades";
+
+fn trade_from_row(row: &Row) -> rusqlite::Result<Trade> {
+    Ok(Trade {
+        pair: Address::from_slice(&row.get::<_, Vec<u8>>("pair")?),
+        tx_hash: H256::from_slice(&row.get::<_, Vec<u8>>("tx_hash")?),
+        block_number: row.get::<_, i64>("block_number")? as u64,
+        block_hash: H256::from_slice(&row.get::<_, Vec<u8>>("block_hash")?),
+        log_index: row.get::<_, i64>("log_index")? as u64,
+        timestamp: row.get::<_, i64>("timestamp")? as u64,
+        amount0_in: parse_u256(row.get("amount0_in")?)?,
+        amount1_in: parse_u256(row.get("amount1_in")?)?,
+        amount0_out: parse_u256(row.get("amount0_out")?)?,
+        amount1_out: parse_u256(row.get("amount1_out")?)?,
+    })
+}
+
+fn candle_from_row(row: &Row) -> rusqlite::Result<Candle> {
+    Ok(Candle {
+        pair: Address::from_slice(&row.get::<_, Vec<u8>>("pair")?),
+        interval: parse_text(row.get("interval")?)?,
+        start: row.get::<_, i64>("start")? as u64,
+        open: row.get("open")?,
+        high: row.get("high")?,
+        low: row.get("low")?,
+        close: row.get("close")?,
+        volume0: row.get("volume0")?,
+        volume1: row.get("volume1")?,
+        trades: row.get("trades")?,
+    })
+}
+
+fn lifecycle_from_row(row: &Row) -> rusqlite::Result<TxLifecycle> {
+    Ok(TxLifecycle {
+        hash: H256::from_slice(&row.get::<_, Vec<u8>>("hash")?),
+        from: Address::from_slice(&row.get::<_, Vec<u8>>("from_address")?),
+        nonce: parse_u256(row.get("nonce")?)?,
+        to: row
+            .get::<_, Option<Vec<u8>>>("to_address")?
+            .map(|a| Address::from_slice(&a)),
+        router: row.get("router")?,
+        node: row.get("node")?,
+        first_seen: row.get::<_, i64>("first_seen")? as u64,
+        status: parse_text(row.get("status")?)?,
+        block_number: row.get::<_, Option<i64>>("block_number")?.map(|n| n as u64),
+        block_hash: row
+            .get::<_, Option<Vec<u8>>>("block_hash")?
+            .map(|h| H256::from_slice(&h)),
+        transaction_index: row
+            .get::<_, Option<i64>>("transaction_index")?
+            .map(|i| i as u64),
+        mined_at: row.get::<_, Option<i64>>("mined_at")?.map(|t| t as u64),
+        replaced_by: row
+            .get::<_, Option<Vec<u8>>>("replaced_by")?
+            .map(|h| H256::from_slice(&h)),
+        closed_at: row.get::<_, Option<i64>>("closed_at")?.map(|t| t as u64),
+    })
+}
+
+fn block_from_row(row: &Row) -> rusqlite::Result<Block<H256>> {
+    let hashes = |column: &str| -> rusqlite::Result<Vec<H256>> {
+        serde_json::from_str(row.get::<_, String>(column)?.as_str()).map_err(conversion_error)
+    };
+
+    Ok(Block {
+        hash: Some(H256::from_slice(&row.get::<_, Vec<u8>>("hash")?)),
+        number: row
+            .get::<_, Option<i64>>("number")?
+            .map(|n| U64::from(n as u64)),
+        parent_hash: H256::from_slice(&row.get::<_, Vec<u8>>("parent_hash")?),
+        author: row
+            .get::<_, Option<Vec<u8>>>("miner")?
+            .map(|a| Address::from_slice(&a)),
+        difficulty: parse_u256(row.get("difficulty")?)?,
+        total_difficulty: parse_opt_u256(row.get("total_difficulty")?)?,
+        size: row
+            .get::<_, Option<i64>>("size")?
+            .map(|s| U256::from(s as u64)),
+        extra_data: Bytes::from(row.get::<_, Vec<u8>>("extra_data")?),
+        gas_limit: parse_u256(row.get("gas_limit")?)?,
+        gas_used: parse_u256(row.get("gas_used")?)?,
+        base_fee_per_gas: parse_opt_u256(row.get("base_fee_per_gas")?)?,
+        timestamp: U256::from(row.get::<_, i64>("timestamp")? as u64),
+        transactions: hashes("transactions")?,
+        uncles: hashes("uncles")?,
+        ..Default::default()
+    })
+}
+
+/// Hash of the canonical block at `number`, if one is stored
+fn canonical_hash(connection: &Connection, number: i64) -> Result<Option<H256>> {
+    Ok(connection
+        .query_row(
+            "SELECT hash FROM blocks WHERE canonical AND number = ?1",
+            [number],
+            |row| row.get::<_, Vec<u8>>(0),
+        )
+        .optional()?
+        .map(|hash| H256::from_slice(&hash)))
+}
+
+/// Make `head` the canonical chain tip
+///
+/// Walks back from `head` through its stored ancestors until it meets the canonical chain,
+/// orphaning the competing block at every height and moving transaction inclusion over to the
+/// adopted blocks. When `head` forks from the canonical chain, anything above it is orphaned as
+/// well. Blocks delivered twice or late, whose parent is canonical, leave the chain above them
+/// alone.
+fn adopt_head(connection: &Connection, head: &Block<H256>) -> Result<()> {
+    let (Some(hash), Some(number)) = (head.hash, head.number) else {
+        return Ok(());
+    };
+    let number = number.as_u64() as i64;
+
+    let replaces = canonical_hash(connection, number)?.is_some_and(|canonical| canonical != hash);
+    let forks = number > 0
+        && canonical_hash(connection, number - 1)?.is_some_and(|parent| parent != head.parent_hash);
+    if replaces || forks {
+        let orphaned = connection.execute(
+            "UPDATE transactions
+            SET block_hash = NULL, block_number = NULL, transaction_index = NULL
+            WHERE block_hash IN (SELECT hash FROM blocks WHERE canonical AND number > ?1)",
+            [number],
+        )?;
+        connection.execute(
+            "UPDATE blocks SET canonical = 0 WHERE canonical AND number > ?1",
+            [number],
+        )?;
+        if orphaned > 0 {
+            info!("Reorg to {:?} dropped {} txs above it", hash, orphaned);
+        }
+    }
+
+    let mut current = hash;
+    loop {
+        let Some((number, parent_hash, transactions, canonical)) = connection
+            .query_row(
+                "SELECT number, parent_hash, transactions, canonical FROM blocks WHERE hash = ?1",
+                [current.as_bytes()],
+                |row| {
+                    Ok((
+                        row.get::<_, Option<i64>>(0)?,
+                        row.get::<_, Vec<u8>>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, bool>(3)?,
+                    ))
+                },
+            )
+            .optional()?
+        else {
+            break;
+        };
+
+        if canonical {
+            break;
+        }
+
+        let orphaned = connection.execute(
+            "UPDATE transactions
+            SET block_hash = NULL, block_number = NULL, transaction_index = NULL
+            WHERE block_hash IN (
+                SELECT hash FROM blocks WHERE canonical AND number = ?1 AND hash <> ?2
+            )",
+            params![number, current.as_bytes()],
+        )?;
+        let replaced = connection.execute(
+            "UPDATE blocks SET canonical = 0 WHERE canonical AND number = ?1 AND hash <> ?2",
+            params![number, current.as_bytes()],
+        )?;
+        connection.execute(
+            "UPDATE blocks SET canonical = 1 WHERE hash = ?1",
+            [current.as_bytes()],
+        )?;
+
+        let mut include = connection.prepare_cached(
+            "UPDATE transactions SET block_hash = ?1, block_number = ?2, transaction_index = ?3
+            WHERE hash = ?4",
+        )?;
+        for (index, tx) in serde_json::from_str::<Vec<H256>>(&transactions)?
+            .iter()
+            .enumerate()
+        {
+            include.execute(params![
+                current.as_bytes(),
+                number,
+                index as i64,
+                tx.as_bytes()
+            ])?;
+        }
+
+        if replaced > 0 {
+            warn!(
+                "Reorg at block {:?}: {:?} is now canonical, {} txs lost their inclusion",
+                number, current, orphaned
+            );
+        }
+
+        current = H256::from_slice(&parent_hash);
+    }
+
+    Ok(())
+}
+
+/// Insert a transaction, or update the inclusion of an already stored one
+fn upsert_tx(connection: &Connection, tx: &Transaction) -> Result<()> {
+    connection
+        .prepare_cached(
+            "INSERT INTO transactions (
+                hash,
+                nonce,
+                block_hash,
+                block_number,
+                transaction_index,
+                from_address,
+                to_address,
+                value,
+                gas_price,
+                gas,
+                input,
+                type,
+                max_priority_fee_per_gas,
+                max_fee_per_gas,
+                chain_id
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)
+            ON CONFLICT (hash) DO UPDATE SET
+                block_hash = excluded.block_hash,
+                block_number = excluded.block_number,
+                transaction_index = excluded.transaction_index",
+        )?
+        .execute(params![
+            tx.hash.as_bytes(),
+            tx.nonce.to_string(),
+            tx.block_hash.map(|h| h.as_bytes().to_vec()),
+            tx.block_number.map(|n| n.as_u64() as i64),
+            tx.transaction_index.map(|i| i.as_u64() as i64),
+            tx.from.as_bytes(),
+            tx.to.map(|a| a.as_bytes().to_vec()),
+            tx.value.to_string(),
+            tx.gas_price.map(|p| p.to_string()),
+            tx.gas.to_string(),
+            tx.input.to_vec(),
+            tx.transaction_type.map(|t| t.as_u64() as i64),
+            tx.max_priority_fee_per_gas.map(|f| f.to_string()),
+            tx.max_fee_per_gas.map(|f| f.to_string()),
+            tx.chain_id.map(|c| c.as_u64() as i64),
+        ])?;
+
+    Ok(())
+}
+
+fn lock(connection: &Mutex<Connection>) -> MutexGuard<'_, Connection> {
+    // A statement that panicked was rolled back by SQLite, the connection is still usable
+    connection
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl SqliteStorage {
+    pub fn new(path: String) -> Result<Self> {
+        log::info!("Opening SQLite database at {}", path);
+
+        let connection = if path == ":memory:" {
+            Connection::open_in_memory()?
+        } else {
+            if let Some(parent) = Path::new(&path).parent() {
+                fs::create_dir_all(parent)?;
+            }
+            Connection::open(path.as_str())?
+        };
+        // Other processes, like the export or retention tools, may hold the lock for a while.
+        // WAL lets them read while the pipeline writes.
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        // In-memory databases stay in their own journal mode
+        connection
+            .pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+
+        let storage = Self {
+            path,
+            connection: Arc::new(Mutex::new(connection)),
+        };
+        storage.create_schema()?;
+
+        Ok(storage)
+    }
+
+    fn connection(&self) -> MutexGuard<'_, Connection> {
+        lock(&self.connection)
+    }
+
+    /// Run `f` with the connection on the blocking thread pool
+    ///
+    /// A locked database makes statements wait up to `BUSY_TIMEOUT`, which must not park the
+    /// runtime threads the rest of the pipeline runs on.
+    async fn with_connection<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+
+        tokio::task::spawn_blocking(move || f(&mut lock(&connection))).await?
+    }
+
+    fn create_schema(&self) -> Result<()> {
+        let connection = self.connection();
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS transactions (
+                hash BLOB PRIMARY KEY,
+                nonce TEXT NOT NULL,
+                block_hash BLOB,
+                block_number INTEGER,
+                transaction_index INTEGER,
+                from_address BLOB NOT NULL,
+                to_address BLOB,
+                value TEXT NOT NULL,
+                gas_price TEXT,
+                gas TEXT NOT NULL,
+                input BLOB NOT NULL,
+                type INTEGER,
+                max_priority_fee_per_gas TEXT,
+                max_fee_per_gas TEXT,
+                chain_id INTEGER,
+                stored_at INTEGER NOT NULL DEFAULT (unixepoch())
+            );
+            CREATE INDEX IF NOT EXISTS transactions_block_number_idx
+                ON transactions (block_number);
+            CREATE INDEX IF NOT EXISTS transactions_stored_at_idx
+                ON transactions (stored_at);
+
+            CREATE TABLE IF NOT EXISTS blocks (
+                hash BLOB PRIMARY KEY,
+                number INTEGER,
+                parent_hash BLOB NOT NULL,
+                miner BLOB,
+                difficulty TEXT NOT NULL,
+                total_difficulty TEXT,
+                size INTEGER,
+                extra_data BLOB NOT NULL,
+                gas_limit TEXT NOT NULL,
+                gas_used TEXT NOT NULL,
+                base_fee_per_gas TEXT,
+                timestamp INTEGER NOT NULL,
+                transactions TEXT NOT NULL,
+                uncles TEXT NOT NULL,
+                canonical INTEGER NOT NULL DEFAULT 0
+            );
+            CREATE INDEX IF NOT EXISTS blocks_number_idx ON blocks (number);
+            CREATE UNIQUE INDEX IF NOT EXISTS blocks_canonical_number_idx
+                ON blocks (number) WHERE canonical;
+
+            CREATE TABLE IF NOT EXISTS tx_lifecycle (
+                hash BLOB PRIMARY KEY,
+                from_address BLOB NOT NULL,
+                nonce TEXT NOT NULL,
+                to_address BLOB,
+                router TEXT,
+                node TEXT NOT NULL,
+                first_seen INTEGER NOT NULL,
+                status TEXT NOT NULL,
+                block_number INTEGER,
+                block_hash BLOB,
+                transaction_index INTEGER,
+                mined_at INTEGER,
+                replaced_by BLOB,
+                closed_at INTEGER
+            );
+            CREATE INDEX IF NOT EXISTS tx_lifecycle_first_seen_idx
+                ON tx_lifecycle (first_seen);
+
+            CREATE TABLE IF NOT EXISTS swaps (
+                tx_hash BLOB NOT NULL,
+                swap_index INTEGER NOT NULL,
+                router TEXT NOT NULL,
+                protocol_version INTEGER NOT NULL,
+                method TEXT NOT NULL,
+                sender BLOB NOT NULL,
+                recipient BLOB,
+                path BLOB NOT NULL,
+                fees BLOB NOT NULL DEFAULT x'',
+                token_in BLOB,
+                token_out BLOB,
+                kind TEXT NOT NULL,
+                amount_in TEXT NOT NULL,
+                amount_out TEXT NOT NULL,
+                deadline INTEGER,
+                status TEXT NOT NULL,
+                block_number INTEGER,
+                block_hash BLOB,
+                transaction_index INTEGER,
+                stored_at INTEGER NOT NULL DEFAULT (unixepoch()),
+                PRIMARY KEY (tx_hash, swap_index)
+            );
+            CREATE INDEX IF NOT EXISTS swaps_block_number_idx ON swaps (block_number);
+            CREATE INDEX IF NOT EXISTS swaps_tokens_idx ON swaps (token_in, token_out);
+            CREATE INDEX IF NOT EXISTS swaps_stored_at_idx ON swaps (stored_at);
+
+            CREATE TABLE IF NOT EXISTS tokens (
+                address BLOB PRIMARY KEY,
+                name TEXT NOT NULL,
+                symbol TEXT NOT NULL,
+                decimals INTEGER NOT NULL,
+                first_seen_block INTEGER
+            );
+
+            CREATE TABLE IF NOT EXISTS pairs (
+                address BLOB PRIMARY KEY,
+                factory BLOB NOT NULL,
+                protocol_version INTEGER NOT NULL,
+                token0 BLOB NOT NULL,
+                token1 BLOB NOT NULL,
+                fee INTEGER,
+                created_block INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS pairs_tokens_idx ON pairs (token0, token1);
+
+            CREATE TABLE IF NOT EXISTS trades (
+                block_hash BLOB NOT NULL,
+                log_index INTEGER NOT NULL,
+                pair BLOB NOT NULL,
+                tx_hash BLOB NOT NULL,
+                block_number INTEGER NOT NULL,
+                timestamp INTEGER NOT NULL,
+                amount0_in TEXT NOT NULL,
+                amount1_in TEXT NOT NULL,
+                amount0_out TEXT NOT NULL,
+                amount1_out TEXT NOT NULL,
+                PRIMARY KEY (block_hash, log_index)
+            );
+            CREATE INDEX IF NOT EXISTS trades_pair_timestamp_idx ON trades (pair, timestamp);
+            CREATE INDEX IF NOT EXISTS trades_timestamp_idx ON trades (timestamp);
+            CREATE INDEX IF NOT EXISTS trades_block_number_idx ON trades (block_number);
+
+            CREATE TABLE IF NOT EXISTS candles (
+                pair BLOB NOT NULL,
+                interval TEXT NOT NULL,
+                start INTEGER NOT NULL,
+                open REAL NOT NULL,
+                high REAL NOT NULL,
+                low REAL NOT NULL,
+                close REAL NOT NULL,
+                volume0 REAL NOT NULL,
+                volume1 REAL NOT NULL,
+                trades INTEGER NOT NULL,
+                PRIMARY KEY (pair, interval, start)
+            );
+            CREATE INDEX IF NOT EXISTS candles_start_idx ON candles (start);",
+        )?;
+
+        Ok(())
+    }
+
+    /// Number of stored transactions
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the transactions could not be counted
+    pub fn tx_count(&self) -> Result<u64> {
+        let connection = self.connection();
+        let count: i64 =
+            connection.query_row("SELECT count(*) FROM transactions", [], |row| row.get(0))?;
+
+        Ok(count as u64)
+    }
+
+    /// Number of stored blocks
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the blocks could not be counted
+    pub fn block_count(&self) -> Result<u64> {
+        let connection = self.connection();
+        let count: i64 =
+            connection.query_row("SELECT count(*) FROM blocks", [], |row| row.get(0))?;
+
+        Ok(count as u64)
+    }
+
+    /// Hashes of all stored transactions
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the transactions could not be queried
+    pub fn tx_hashes(&self) -> Result<Vec<H256>> {
+        let connection = self.connection();
+        let mut statement = connection.prepare("SELECT hash FROM transactions ORDER BY rowid")?;
+        let hashes = statement
+            .query_map([], |row| row.get::<_, Vec<u8>>(0))?
+            .map(|hash| Ok(H256::from_slice(&hash?)))
+            .collect::<Result<Vec<H256>>>()?;
+
+        Ok(hashes)
+    }
+
+    /// Hashes of the transactions stored for a block
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the transactions could not be queried
+    pub fn block_tx_hashes(&self, block_number: u64) -> Result<Vec<H256>> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT t.hash FROM transactions t
+            JOIN blocks b ON b.hash = t.block_hash AND b.canonical
+            WHERE t.block_number = ?1 ORDER BY t.transaction_index",
+        )?;
+        let hashes = statement
+            .query_map([block_number as i64], |row| row.get::<_, Vec<u8>>(0))?
+            .map(|hash| Ok(H256::from_slice(&hash?)))
+            .collect::<Result<Vec<H256>>>()?;
+
+        Ok(hashes)
+    }
+}
+
+impl TxStorage<Transaction> for SqliteStorage {
+    async fn store(&mut self, tx: Transaction) -> Result<()> {
+        debug!("Storing tx: {:#?}", tx.hash);
+
+        self.with_connection(move |connection| upsert_tx(connection, &tx))
+            .await
+    }
+
+    async fn is_stored(&mut self, tx: Transaction) -> Result<bool> {
+        debug!("Checking if tx is stored: {:#?}", tx.hash);
+
+        self.with_connection(move |connection| {
+            let row = connection
+                .query_row(
+                    "SELECT 1 FROM transactions WHERE hash = ?1",
+                    [tx.hash.as_bytes()],
+                    |_| Ok(()),
+                )
+                .optional()?;
+
+            Ok(row.is_some())
+        })
+        .await
+    }
+
+    async fn get(&mut self, hash: H256) -> Result<Option<Transaction>> {
+        debug!("Getting tx: {:#?}", hash);
+
+        self.with_connection(move |connection| {
+            let tx = connection
+                .query_row(
+                    format!("SELECT {} FROM transactions WHERE hash = ?1", TX_COLUMNS).as_str(),
+                    [hash.as_bytes()],
+                    tx_from_row,
+                )
+                .optional()?;
+
+            Ok(tx)
+        })
+        .await
+    }
+
+    /// Written in a single transaction, so the batch is stored entirely or not at all
+    async fn store_many(&mut self, txs: Vec<Transaction>) -> Result<()> {
+        debug!("Storing {} txs", txs.len());
+
+        self.with_connection(move |connection| {
+            let db_tx = connection.transaction()?;
+            for tx in &txs {
+                upsert_tx(&db_tx, tx)?;
+            }
+            db_tx.commit()?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// Lookups are local, so a cached statement per hash is as cheap as a single query
+    async fn exists_many(&mut self, hashes: &[H256]) -> Result<HashSet<H256>> {
+        debug!("Checking if {} txs are stored", hashes.len());
+
+        let hashes = hashes.to_vec();
+        self.with_connection(move |connection| {
+            let mut statement =
+                connection.prepare_cached("SELECT 1 FROM transactions WHERE hash = ?1")?;
+            let mut stored = HashSet::new();
+            for hash in &hashes {
+                if statement.exists([hash.as_bytes()])? {
+                    stored.insert(*hash);
+                }
+            }
+
+            Ok(stored)
+        })
+        .await
+    }
+
+    async fn delete(&mut self, tx: Transaction) -> Result<()> {
+        debug!("Deleting tx: {:#?}", tx.hash);
+
+        self.with_connection(move |connection| {
+            connection.execute(
+                "DELETE FROM transactions WHERE hash = ?1",
+                [tx.hash.as_bytes()],
+            )?;
+
+            Ok(())
+        })
+        .await
+    }
+}
+
+impl Retryable for SqliteStorage {
+    /// Only lock contention with another connection to the same database is transient
+    fn is_retryable(&self, error: &anyhow::Error) -> bool {
+        matches!(
+            error.downcast_ref::<rusqlite::Error>(),
+            Some(rusqlite::Error::SqliteFailure(e, _))
+                if matches!(e.code, ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked)
+        )
+    }
+}
+
+impl BlockStorage<Block<H256>> for SqliteStorage {
+    /// Store a block as the new chain head, marking it and its stored ancestors canonical
+    async fn store(&mut self, block: Block<H256>) -> Result<()> {
+        debug!("Storing block: {:#?}", block.hash);
+
+        self.with_connection(move |connection| {
+            let db_tx = connection.transaction()?;
+            db_tx
+                .prepare_cached(
+                    "INSERT OR IGNORE INTO blocks (
+                        hash,
+                        number,
+                        parent_hash,
+                        miner,
+                        difficulty,
+                        total_difficulty,
+                        size,
+                        extra_data,
+                        gas_limit,
+                        gas_used,
+                        base_fee_per_gas,
+                        timestamp,
+                        transactions,
+                        uncles
+                    ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+                )?
+                .execute(params![
+                    block.hash.unwrap_or_default().as_bytes(),
+                    block.number.map(|n| n.as_u64() as i64),
+                    block.parent_hash.as_bytes(),
+                    block.author.map(|a| a.as_bytes().to_vec()),
+                    block.difficulty.to_string(),
+                    block.total_difficulty.map(|d| d.to_string()),
+                    block.size.map(|s| s.as_u64() as i64),
+                    block.extra_data.to_vec(),
+                    block.gas_limit.to_string(),
+                    block.gas_used.to_string(),
+                    block.base_fee_per_gas.map(|f| f.to_string()),
+                    block.timestamp.as_u64() as i64,
+                    serde_json::to_string(&block.transactions)?,
+                    serde_json::to_string(&block.uncles)?,
+                ])?;
+            adopt_head(&db_tx, &block)?;
+            db_tx.commit()?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn is_stored(&mut self, block: Block<H256>) -> Result<bool> {
+        debug!("Checking if block is stored: {:#?}", block.hash);
+
+        self.with_connection(move |connection| {
+            let row = connection
+                .query_row(
+                    "SELECT 1 FROM blocks WHERE hash = ?1",
+                    [block.hash.unwrap_or_default().as_bytes()],
+                    |_| Ok(()),
+                )
+                .optional()?;
+
+            Ok(row.is_some())
+        })
+        .await
+    }
+
+    async fn delete(&mut self, block: Block<H256>) -> Result<()> {
+        debug!("Deleting block: {:#?}", block.hash);
+
+        self.with_connection(move |connection| {
+            let db_tx = connection.transaction()?;
+            db_tx.execute(
+                "UPDATE transactions
+                SET block_hash = NULL, block_number = NULL, transaction_index = NULL
+                WHERE block_hash = ?1",
+                [block.hash.unwrap_or_default().as_bytes()],
+            )?;
+            db_tx.execute(
+                "DELETE FROM blocks WHERE hash = ?1",
+                [block.hash.unwrap_or_default().as_bytes()],
+            )?;
+            db_tx.commit()?;
+
+            Ok(())
+        })
+        .await
+    }
+}
+
+impl ChainReader for SqliteStorage {
+    async fn latest_block_number(&mut self) -> Result<Option<u64>> {
+        self.with_connection(move |connection| {
+            let number: Option<i64> = connection.query_row(
+                "SELECT max(number) FROM blocks WHERE canonical",
+                [],
+                |row| row.get(0),
+            )?;
+
+            Ok(number.map(|n| n as u64))
+        })
+        .await
+    }
+
+    async fn blocks_in_range(&mut self, from: u64, to: u64) -> Result<Vec<Block<H256>>> {
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare_cached(
+                format!(
+                    "SELECT {} FROM blocks
+                    WHERE number BETWEEN ?1 AND ?2 AND canonical ORDER BY number",
+                    BLOCK_COLUMNS
+                )
+                .as_str(),
+            )?;
+            let blocks = statement
+                .query_map([from as i64, to as i64], block_from_row)?
+                .collect::<rusqlite::Result<Vec<Block<H256>>>>()?;
+
+            Ok(blocks)
+        })
+        .await
+    }
+
+    async fn txs_in_range(&mut self, from: u64, to: u64) -> Result<Vec<Transaction>> {
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare_cached(
+                format!(
+                    "SELECT {} FROM transactions t
+                    WHERE block_number BETWEEN ?1 AND ?2
+                    AND NOT EXISTS (
+                        SELECT 1 FROM blocks b WHERE b.hash = t.block_hash AND NOT b.canonical
+                    )
+                    ORDER BY block_number, transaction_index",
+                    TX_COLUMNS
+                )
+                .as_str(),
+            )?;
+            let txs = statement
+                .query_map([from as i64, to as i64], tx_from_row)?
+                .collect::<rusqlite::Result<Vec<Transaction>>>()?;
+
+            Ok(txs)
+        })
+        .await
+    }
+
+    async fn block_range_for_time(&mut self, from: u64, to: u64) -> Result<Option<(u64, u64)>> {
+        self.with_connection(move |connection| {
+            let range: (Option<i64>, Option<i64>) = connection.query_row(
+                "SELECT min(number), max(number) FROM blocks
+                WHERE timestamp BETWEEN ?1 AND ?2 AND canonical",
+                [from as i64, to as i64],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )?;
+
+            match range {
+                (Some(first), Some(last)) => Ok(Some((first as u64, last as u64))),
+                _ => Ok(None),
+            }
+        })
+        .await
+    }
+
+    async fn orphaned_blocks_in_range(&mut self, from: u64, to: u64) -> Result<Vec<Block<H256>>> {
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare_cached(
+                format!(
+                    "SELECT {} FROM blocks
+                    WHERE number BETWEEN ?1 AND ?2 AND NOT canonical ORDER BY number",
+                    BLOCK_COLUMNS
+                )
+                .as_str(),
+            )?;
+            let blocks = statement
+                .query_map([from as i64, to as i64], block_from_row)?
+                .collect::<rusqlite::Result<Vec<Block<H256>>>>()?;
+
+            Ok(blocks)
+        })
+        .await
+    }
+}
+
+impl LifecycleStorage for SqliteStorage {
+    async fn store_lifecycle(&mut self, lifecycle: &TxLifecycle) -> Result<()> {
+        debug!(
+            "Storing lifecycle of tx {:#?}: {}",
+            lifecycle.hash, lifecycle.status
+        );
+
+        let lifecycle = lifecycle.clone();
+        self.with_connection(move |connection| {
+            connection
+                .prepare_cached(
+                    format!(
+                        "INSERT INTO tx_lifecycle ({})
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)
+                        ON CONFLICT (hash) DO UPDATE SET
+                            first_seen = min(tx_lifecycle.first_seen, excluded.first_seen),
+                            router = coalesce(tx_lifecycle.router, excluded.router),
+                            status = excluded.status,
+                            block_number = excluded.block_number,
+                            block_hash = excluded.block_hash,
+                            transaction_index = excluded.transaction_index,
+                            mined_at = excluded.mined_at,
+                            replaced_by = excluded.replaced_by,
+                            closed_at = excluded.closed_at",
+                        LIFECYCLE_COLUMNS
+                    )
+                    .as_str(),
+                )?
+                .execute(params![
+                    lifecycle.hash.as_bytes(),
+                    lifecycle.from.as_bytes(),
+                    lifecycle.nonce.to_string(),
+                    lifecycle.to.map(|a| a.as_bytes().to_vec()),
+                    lifecycle.router,
+                    lifecycle.node,
+                    lifecycle.first_seen as i64,
+                    lifecycle.status.as_str(),
+                    lifecycle.block_number.map(|n| n as i64),
+                    lifecycle.block_hash.map(|h| h.as_bytes().to_vec()),
+                    lifecycle.transaction_index.map(|i| i as i64),
+                    lifecycle.mined_at.map(|t| t as i64),
+                    lifecycle.replaced_by.map(|h| h.as_bytes().to_vec()),
+                    lifecycle.closed_at.map(|t| t as i64),
+                ])?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn lifecycle(&mut self, hash: H256) -> Result<Option<TxLifecycle>> {
+        self.with_connection(move |connection| {
+            let lifecycle = connection
+                .prepare_cached(
+                    format!(
+                        "SELECT {} FROM tx_lifecycle WHERE hash = ?1",
+                        LIFECYCLE_COLUMNS
+                    )
+                    .as_str(),
+                )?
+                .query_row([hash.as_bytes()], lifecycle_from_row)
+                .optional()?;
+
+            Ok(lifecycle)
+        })
+        .await
+    }
+
+    async fn router_latency(&mut self, since: u64) -> Result<Vec<RouterLatency>> {
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare_cached(
+                "SELECT router,
+                    count(*) FILTER (WHERE status = 'mined'),
+                    count(*) FILTER (WHERE status = 'replaced'),
+                    count(*) FILTER (WHERE status = 'dropped'),
+                    count(*) FILTER (WHERE status = 'pending'),
+                    min(max(mined_at - first_seen, 0)),
+                    avg(max(mined_at - first_seen, 0)),
+                    max(max(mined_at - first_seen, 0))
+                FROM tx_lifecycle
+                WHERE first_seen >= ?1
+                GROUP BY router ORDER BY router IS NULL, router",
+            )?;
+            let stats = statement
+                .query_map([since as i64], |row| {
+                    Ok(RouterLatency {
+                        router: row.get(0)?,
+                        mined: row.get::<_, i64>(1)? as u64,
+                        replaced: row.get::<_, i64>(2)? as u64,
+                        dropped: row.get::<_, i64>(3)? as u64,
+                        pending: row.get::<_, i64>(4)? as u64,
+                        min_latency: row.get::<_, Option<i64>>(5)?.map(|l| l as u64),
+                        avg_latency: row.get(6)?,
+                        max_latency: row.get::<_, Option<i64>>(7)?.map(|l| l as u64),
+                    })
+                })?
+                .collect::<rusqlite::Result<Vec<RouterLatency>>>()?;
+
+            Ok(stats)
+        })
+        .await
+    }
+}
+
+impl SwapStorage for SqliteStorage {
+    async fn store_swap(&mut self, swap: &SwapRecord) -> Result<()> {
+        debug!(
+            "Storing swap {} of tx {:#?}: {}",
+            swap.swap_index, swap.tx_hash, swap.status
+        );
+
+        let swap = swap.clone();
+        self.with_connection(move |connection| {
+            connection
+                .prepare_cached(
+                    format!(
+                        "INSERT INTO swaps ({}, token_in, token_out)
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19)
+                        ON CONFLICT (tx_hash, swap_index) DO UPDATE SET
+                            status = excluded.status,
+                            block_number = excluded.block_number,
+                            block_hash = excluded.block_hash,
+                            transaction_index = excluded.transaction_index
+                        WHERE swaps.status = 'pending' OR excluded.status = 'mined'",
+                        SWAP_COLUMNS
+                    )
+                    .as_str(),
+                )?
+                .execute(params![
+                    swap.tx_hash.as_bytes(),
+                    swap.swap_index,
+                    swap.router,
+                    swap.protocol_version,
+                    swap.method,
+                    swap.sender.as_bytes(),
+                    swap.recipient.map(|a| a.as_bytes().to_vec()),
+                    path_to_bytes(&swap.path),
+                    fees_to_bytes(&swap.fees),
+                    swap.kind.as_str(),
+                    swap.amount_in.to_string(),
+                    swap.amount_out.to_string(),
+                    swap.deadline.map(|d| d.min(i64::MAX as u64) as i64),
+                    swap.status.as_str(),
+                    swap.block_number.map(|n| n as i64),
+                    swap.block_hash.map(|h| h.as_bytes().to_vec()),
+                    swap.transaction_index.map(|i| i as i64),
+                    swap.token_in().map(|a| a.as_bytes().to_vec()),
+                    swap.token_out().map(|a| a.as_bytes().to_vec()),
+                ])?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn swaps(&mut self, tx_hash: H256) -> Result<Vec<SwapRecord>> {
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare_cached(
+                format!(
+                    "SELECT {} FROM swaps WHERE tx_hash = ?1 ORDER BY swap_index",
+                    SWAP_COLUMNS
+                )
+                .as_str(),
+            )?;
+            let swaps = statement
+                .query_map([tx_hash.as_bytes()], swap_from_row)?
+                .collect::<rusqlite::Result<Vec<SwapRecord>>>()?;
+
+            Ok(swaps)
+        })
+        .await
+    }
+
+    async fn swaps_in_range(&mut self, from: u64, to: u64) -> Result<Vec<SwapRecord>> {
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare_cached(
+                format!(
+                    "SELECT {} FROM swaps s
+                    WHERE status = 'mined' AND block_number BETWEEN ?1 AND ?2
+                    AND NOT EXISTS (
+                        SELECT 1 FROM blocks b WHERE b.hash = s.block_hash AND NOT b.canonical
+                    )
+                    ORDER BY block_number, transaction_index, swap_index",
+                    SWAP_COLUMNS
+                )
+                .as_str(),
+            )?;
+            let swaps = statement
+                .query_map([from as i64, to as i64], swap_from_row)?
+                .collect::<rusqlite::Result<Vec<SwapRecord>>>()?;
+
+            Ok(swaps)
+        })
+        .await
+    }
+}
+
+impl RegistryStorage for SqliteStorage {
+    async fn store_token(&mut self, token: &TokenInfo) -> Result<()> {
+        let token = token.clone();
+        self.with_connection(move |connection| {
+            connection
+                .prepare_cached(
+                    format!(
+                        "INSERT INTO tokens ({}) VALUES (?1, ?2, ?3, ?4, ?5)
+                        ON CONFLICT (address) DO UPDATE SET
+                            name = excluded.name,
+                            symbol = excluded.symbol,
+                            decimals = excluded.decimals,
+                            first_seen_block = coalesce(
+                                min(tokens.first_seen_block, excluded.first_seen_block),
+                                tokens.first_seen_block,
+                                excluded.first_seen_block
+                            )",
+                        TOKEN_COLUMNS
+                    )
+                    .as_str(),
+                )?
+                .execute(params![
+                    token.address.as_bytes(),
+                    token.name,
+                    token.symbol,
+                    token.decimals,
+                    token.first_seen_block.map(|n| n as i64),
+                ])?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn store_pair(&mut self, pair: &PairInfo) -> Result<()> {
+        debug!(
+            "Storing pair {:#?} of factory {:#?}",
+            pair.address, pair.factory
+        );
+
+        let pair = pair.clone();
+        self.with_connection(move |connection| {
+            connection
+                .prepare_cached(
+                    format!(
+                        "INSERT INTO pairs ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                        ON CONFLICT (address) DO NOTHING",
+                        PAIR_COLUMNS
+                    )
+                    .as_str(),
+                )?
+                .execute(params![
+                    pair.address.as_bytes(),
+                    pair.factory.as_bytes(),
+                    pair.protocol_version,
+                    pair.token0.as_bytes(),
+                    pair.token1.as_bytes(),
+                    pair.fee,
+                    pair.created_block as i64,
+                ])?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn tokens(&mut self) -> Result<Vec<TokenInfo>> {
+        self.with_connection(move |connection| {
+            let mut statement = connection
+                .prepare_cached(format!("SELECT {} FROM tokens", TOKEN_COLUMNS).as_str())?;
+            let tokens = statement
+                .query_map([], token_from_row)?
+                .collect::<rusqlite::Result<Vec<TokenInfo>>>()?;
+
+            Ok(tokens)
+        })
+        .await
+    }
+
+    async fn pairs(&mut self) -> Result<Vec<PairInfo>> {
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare_cached(
+                format!("SELECT {} FROM pairs ORDER BY created_block", PAIR_COLUMNS).as_str(),
+            )?;
+            let pairs = statement
+                .query_map([], pair_from_row)?
+                .collect::<rusqlite::Result<Vec<PairInfo>>>()?;
+
+            Ok(pairs)
+        })
+        .await
+    }
+}
+
+impl CandleStorage for SqliteStorage {
+    async fn store_trades(&mut self, trades: &[Trade]) -> Result<()> {
+        debug!("Storing {} trades", trades.len());
+
+        let trades = trades.to_vec();
+        self.with_connection(move |connection| {
+            let db_tx = connection.transaction()?;
+            for trade in &trades {
+                db_tx
+                    .prepare_cached(
+                        format!(
+                            "INSERT INTO trades ({})
+                            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+                            ON CONFLICT (block_hash, log_index) DO NOTHING",
+                            TRADE_COLUMNS
+                        )
+                        .as_str(),
+                    )?
+                    .execute(params![
+                        trade.pair.as_bytes(),
+                        trade.tx_hash.as_bytes(),
+                        trade.block_number as i64,
+                        trade.block_hash.as_bytes(),
+                        trade.log_index as i64,
+                        trade.timestamp as i64,
+                        trade.amount0_in.to_string(),
+                        trade.amount1_in.to_string(),
+                        trade.amount0_out.to_string(),
+                        trade.amount1_out.to_string(),
+                    ])?;
+            }
+            db_tx.commit()?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn orphan_trades(&mut self, block_number: u64, block_hash: H256) -> Result<Vec<Trade>> {
+        let condition = "block_number > ?1 OR (block_number = ?1 AND block_hash != ?2)";
+        self.with_connection(move |connection| {
+            let db_tx = connection.transaction()?;
+
+            let trades = db_tx
+                .prepare_cached(
+                    format!("SELECT {} FROM trades WHERE {}", TRADE_COLUMNS, condition).as_str(),
+                )?
+                .query_map(
+                    params![block_number as i64, block_hash.as_bytes()],
+                    trade_from_row,
+                )?
+                .collect::<rusqlite::Result<Vec<Trade>>>()?;
+            db_tx.execute(
+                format!("DELETE FROM trades WHERE {}", condition).as_str(),
+                params![block_number as i64, block_hash.as_bytes()],
+            )?;
+            db_tx.commit()?;
+
+            if !trades.is_empty() {
+                info!(
+                    "Removed {} trades orphaned by block {:#?}",
+                    trades.len(),
+                    block_hash
+                );
+            }
+
+            Ok(trades)
+        })
+        .await
+    }
+
+    async fn trades(&mut self, pair: Option<Address>, from: u64, to: u64) -> Result<Vec<Trade>> {
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare_cached(
+                format!(
+                    "SELECT {} FROM trades
+                    WHERE timestamp BETWEEN ?1 AND ?2 AND (?3 IS NULL OR pair = ?3)
+                    ORDER BY block_number, log_index",
+                    TRADE_COLUMNS
+                )
+                .as_str(),
+            )?;
+            let trades = statement
+                .query_map(
+                    params![from as i64, to as i64, pair.map(|a| a.as_bytes().to_vec())],
+                    trade_from_row,
+                )?
+                .collect::<rusqlite::Result<Vec<Trade>>>()?;
+
+            Ok(trades)
+        })
+        .await
+    }
+
+    async fn store_candle(&mut self, candle: &Candle) -> Result<()> {
+        let candle = candle.clone();
+        self.with_connection(move |connection| {
+            connection
+                .prepare_cached(
+                    format!(
+                        "INSERT OR REPLACE INTO candles ({})
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                        CANDLE_COLUMNS
+                    )
+                    .as_str(),
+                )?
+                .execute(params![
+                    candle.pair.as_bytes(),
+                    candle.interval.as_str(),
+                    candle.start as i64,
+                    candle.open,
+                    candle.high,
+                    candle.low,
+                    candle.close,
+                    candle.volume0,
+                    candle.volume1,
+                    candle.trades,
+                ])?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_candles(
+        &mut self,
+        pair: Option<(Address, Interval)>,
+        from: u64,
+        to: u64,
+    ) -> Result<u64> {
+        self.with_connection(move |connection| {
+            let rows = connection
+                .prepare_cached(
+                    "DELETE FROM candles
+                    WHERE start BETWEEN ?1 AND ?2 AND (?3 IS NULL OR (pair = ?3 AND interval = ?4))",
+                )?
+                .execute(params![
+                    from as i64,
+                    to as i64,
+                    pair.map(|(pair, _)| pair.as_bytes().to_vec()),
+                    pair.map(|(_, interval)| interval.as_str()),
+                ])?;
+
+            Ok(rows as u64)
+        })
+        .await
+    }
+
+    async fn candles(
+        &mut self,
+        pair: Address,
+        interval: Interval,
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<Candle>> {
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare_cached(
+                format!(
+                    "SELECT {} FROM candles
+                    WHERE pair = ?1 AND interval = ?2 AND start BETWEEN ?3 AND ?4
+                    ORDER BY start",
+                    CANDLE_COLUMNS
+                )
+                .as_str(),
+            )?;
+            let candles = statement
+                .query_map(
+                    params![pair.as_bytes(), interval.as_str(), from as i64, to as i64],
+                    candle_from_row,
+                )?
+                .collect::<rusqlite::Result<Vec<Candle>>>()?;
+
+            Ok(candles)
+        })
+        .await
+    }
+}
+
+impl ExpiringStorage for SqliteStorage {
+    async fn expire(&mut self, class: DataClass, cutoff: u64, dry_run: bool) -> Result<Expired> {
+        // Mined txs age with their block, falling back to when they were stored
+        let (table, condition) = match class {
+            DataClass::PendingTxs => ("transactions", "block_hash IS NULL AND stored_at < ?1"),
+            DataClass::MinedTxs => (
+                "transactions",
+                "block_hash IS NOT NULL AND coalesce(
+                    (SELECT b.timestamp FROM blocks b WHERE b.hash = t.block_hash),
+                    stored_at
+                ) < ?1",
+            ),
+            DataClass::DecodedEvents => ("swaps", "stored_at < ?1"),
+            _ => return Ok(Expired::NotStored),
+        };
+
+        self.with_connection(move |connection| {
+            let rows = if dry_run {
+                connection.query_row(
+                    format!("SELECT count(*) FROM {} t WHERE {}", table, condition).as_str(),
+                    [cutoff as i64],
+                    |row| row.get::<_, i64>(0),
+                )? as usize
+            } else {
+                connection.execute(
+                    format!("DELETE FROM {} AS t WHERE {}", table, condition).as_str(),
+                    [cutoff as i64],
+                )?
+            };
+            debug!("Expired {} {} older than {}", rows, class, cutoff);
+
+            Ok(Expired::Rows(rows as u64))
+        })
+        .await
+    }
+}
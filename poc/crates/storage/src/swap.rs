@@ -0,0 +1,100 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use dex::swap::SwapRecord;
+use ethers::types::{Address, H256};
+use log::info;
+use tokio::sync::{broadcast::Receiver, oneshot, Mutex};
+
+use crate::writer::{Record, RecordStorage, Retryable, StorageWriter};
+
+/// Path as stored, the addresses of the tokens concatenated
+pub fn path_to_bytes(path: &[Address]) -> Vec<u8> {
+    path.iter().flat_map(|a| a.as_bytes().to_vec()).collect()
+}
+
+pub fn path_from_bytes(bytes: &[u8]) -> Vec<Address> {
+    bytes.chunks_exact(20).map(Address::from_slice).collect()
+}
+
+/// Fee tiers as stored, 3 big endian bytes each like in a packed V3 path
+pub fn fees_to_bytes(fees: &[u32]) -> Vec<u8> {
+    fees.iter()
+        .flat_map(|fee| fee.to_be_bytes()[1..].to_vec())
+        .collect()
+}
+
+pub fn fees_from_bytes(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks_exact(3)
+        .map(|fee| u32::from_be_bytes([0, fee[0], fee[1], fee[2]]))
+        .collect()
+}
+
+pub trait SwapStorage {
+    /// Insert a swap, or move an already stored one to the block it was mined in
+    ///
+    /// A pending swap never overwrites a mined one, so the order in which the mempool and
+    /// block decoders report a swap doesn't matter.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the swap could not be stored
+    async fn store_swap(&mut self, swap: &SwapRecord) -> Result<()>;
+
+    /// Swaps made by a tx, in order
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the swaps could not be queried
+    async fn swaps(&mut self, tx_hash: H256) -> Result<Vec<SwapRecord>>;
+
+    /// Mined swaps in canonical blocks with a number in `from..=to`, in chain order
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the swaps could not be queried
+    async fn swaps_in_range(&mut self, from: u64, to: u64) -> Result<Vec<SwapRecord>>;
+}
+
+impl Record for SwapRecord {
+    const KIND: &'static str = "swap";
+
+    fn hash(&self) -> H256 {
+        self.tx_hash
+    }
+}
+
+/// Backends tell transient errors apart the same way for swaps as for txs
+impl<C: SwapStorage + Retryable> RecordStorage<SwapRecord> for C {
+    async fn write_record(&mut self, swap: &SwapRecord) -> Result<()> {
+        self.store_swap(swap).await
+    }
+
+    fn can_retry(&self, error: &anyhow::Error) -> bool {
+        self.is_retryable(error)
+    }
+}
+
+/// Store the swaps from `receiver` until it closes or `shutdown` fires
+///
+/// Swaps go through `writer`, which retries transient failures and dead letters the swaps that
+/// can't be stored instead of stopping.
+pub async fn swap_store<C: SwapStorage + Retryable>(
+    swap_storage: Arc<Mutex<C>>,
+    receiver: Receiver<SwapRecord>,
+    writer: Arc<StorageWriter>,
+    shutdown: oneshot::Receiver<()>,
+) -> Result<()> {
+    info!("Starting swap updates...");
+
+    let stats = writer
+        .store_records(&swap_storage, receiver, shutdown)
+        .await;
+    info!(
+        "Swap updates stopped: {} written, {} dead lettered, {} missed",
+        stats.written, stats.dead_lettered, stats.missed
+    );
+
+    Ok(())
+}
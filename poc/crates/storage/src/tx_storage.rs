@@ -1,9 +1,7 @@
-use std::sync::Arc;
+use std::collections::HashSet;
 
 use anyhow::Result;
-use ethers::types::Transaction;
-use log::info;
-use tokio::sync::{broadcast::Receiver, Mutex};
+use ethers::types::H256;
 
 pub trait TxStorage<T> {
     /// Store a transaction
@@ -20,26 +18,36 @@ pub trait TxStorage<T> {
     /// This function will return an error if the transaction could not be checked
     async fn is_stored(&mut self, tx: T) -> Result<bool>;
 
-    /// Delete a transaction from the store
+    /// Get a stored transaction by hash
     ///
     /// # Errors
     ///
-    /// This function will return an error if the transaction could not be deleted
-    async fn delete(&mut self, tx: T) -> Result<()>;
-}
-
-pub async fn tx_store<C: TxStorage<Transaction>>(
-    tx_storage: Arc<Mutex<C>>,
-    mut receiver: Arc<Receiver<Transaction>>,
-) -> Result<()> {
-    let receiver = Arc::get_mut(&mut receiver).unwrap();
-    let mut tx_storage = tx_storage.lock().await;
+    /// This function will return an error if the transaction could not be queried
+    async fn get(&mut self, hash: H256) -> Result<Option<T>>;
 
-    info!("Starting tx storage updates...");
+    /// Store several transactions at once
+    ///
+    /// Backends write the whole batch in as few round-trips as they can.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the transactions could not be stored
+    async fn store_many(&mut self, txs: Vec<T>) -> Result<()>;
 
-    while let Ok(tx) = receiver.recv().await {
-        tx_storage.store(tx).await?;
-    }
+    /// The hashes among `hashes` that are stored
+    ///
+    /// Meant for deduplicating on the hot path, so backends answer with a single query (or a
+    /// few, for very large batches) rather than one per hash.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the transactions could not be checked
+    async fn exists_many(&mut self, hashes: &[H256]) -> Result<HashSet<H256>>;
 
-    Ok(())
+    /// Delete a transaction from the store
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the transaction could not be deleted
+    async fn delete(&mut self, tx: T) -> Result<()>;
 }
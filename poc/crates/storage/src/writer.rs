@@ -0,0 +1,508 @@
+use std::collections::VecDeque;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use ethers::types::{Transaction, H256};
+use futures::future::{join_all, select_all};
+use log::{debug, info, warn};
+use serde::Serialize;
+use tokio::sync::{
+    broadcast::{
+        self,
+        error::{RecvError, TryRecvError},
+    },
+    mpsc::{self, error::TrySendError},
+    oneshot, Mutex,
+};
+
+use crate::tx_storage::TxStorage;
+
+pub const DEFAULT_WORKERS: usize = 4;
+pub const DEFAULT_QUEUE_SIZE: usize = 100;
+pub const DEFAULT_MAX_RETRIES: u32 = 5;
+pub const DEFAULT_BACKOFF: Duration = Duration::from_millis(100);
+pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct WriterConfig {
+    pub workers: usize,
+    pub queue_size: usize,
+    pub max_retries: u32,
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    pub dead_letter_path: Option<PathBuf>,
+}
+
+impl Default for WriterConfig {
+    fn default() -> Self {
+        Self {
+            workers: DEFAULT_WORKERS,
+            queue_size: DEFAULT_QUEUE_SIZE,
+            max_retries: DEFAULT_MAX_RETRIES,
+            backoff: DEFAULT_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            dead_letter_path: None,
+        }
+    }
+}
+
+impl From<&settings::Writer> for WriterConfig {
+    fn from(writer: &settings::Writer) -> Self {
+        let defaults = Self::default();
+
+        Self {
+            workers: writer.workers.unwrap_or(defaults.workers).max(1),
+            queue_size: writer.queue_size.unwrap_or(defaults.queue_size).max(1),
+            max_retries: writer.max_retries.unwrap_or(defaults.max_retries),
+            backoff: writer
+                .backoff_ms
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.backoff),
+            max_backoff: writer
+                .max_backoff_ms
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.max_backoff),
+            dead_letter_path: writer.dead_letter_path.clone().map(PathBuf::from),
+        }
+    }
+}
+
+/// Counters reported by a writer once it has flushed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriterStats {
+    pub written: u64,
+    pub retries: u64,
+    pub dead_lettered: u64,
+    /// Txs the writer fell too far behind to receive
+    pub missed: u64,
+}
+
+impl std::ops::Add for WriterStats {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            written: self.written + other.written,
+            retries: self.retries + other.retries,
+            dead_lettered: self.dead_lettered + other.dead_lettered,
+            missed: self.missed + other.missed,
+        }
+    }
+}
+
+/// A record written by the `StorageWriter`
+pub trait Record: Serialize {
+    /// Key of the record in the dead letter file, e.g. `tx`
+    const KIND: &'static str;
+
+    /// Hash of the tx or the block the record is about
+    fn hash(&self) -> H256;
+}
+
+impl Record for Transaction {
+    const KIND: &'static str = "tx";
+
+    fn hash(&self) -> H256 {
+        self.hash
+    }
+}
+
+/// A storage that can tell transient write errors apart
+pub trait Retryable {
+    /// Whether a failed write may succeed if it is retried
+    ///
+    /// Backends override this to tell transient errors (timeouts, lost connections, lock
+    /// contention) apart from errors that will fail again, such as constraint violations.
+    fn is_retryable(&self, error: &anyhow::Error) -> bool {
+        let _ = error;
+        true
+    }
+}
+
+/// A storage the `StorageWriter` writes records of `T` to
+pub trait RecordStorage<T> {
+    /// Write a record
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the record could not be written
+    async fn write_record(&mut self, record: &T) -> Result<()>;
+
+    /// Whether a failed write may succeed if it is retried
+    fn can_retry(&self, error: &anyhow::Error) -> bool;
+}
+
+impl<C: TxStorage<Transaction> + Retryable> RecordStorage<Transaction> for C {
+    async fn write_record(&mut self, tx: &Transaction) -> Result<()> {
+        self.store(tx.clone()).await
+    }
+
+    fn can_retry(&self, error: &anyhow::Error) -> bool {
+        self.is_retryable(error)
+    }
+}
+
+/// A record that could not be written, as recorded in the dead letter file under its kind
+#[derive(Debug, Serialize)]
+struct DeadLetter {
+    #[serde(flatten)]
+    record: serde_json::Map<String, serde_json::Value>,
+    error: String,
+    attempts: u32,
+    retryable: bool,
+    failed_at: u64,
+}
+
+/// First worker of `waiting` with room in its queue again, never ready when none is waiting
+async fn spill_room(
+    senders: &[mpsc::Sender<Transaction>],
+    waiting: Vec<usize>,
+) -> (
+    usize,
+    Result<mpsc::Permit<'_, Transaction>, mpsc::error::SendError<()>>,
+) {
+    if waiting.is_empty() {
+        return std::future::pending().await;
+    }
+
+    let reserves = waiting
+        .into_iter()
+        .map(|worker| Box::pin(async move { (worker, senders[worker].reserve().await) }));
+    select_all(reserves).await.0
+}
+
+/// Writes txs to storage with a pool of workers, retrying transient failures
+///
+/// Every worker owns its own storage, so one slow write only holds up the txs queued behind it
+/// on the same worker. Txs are routed to workers by hash, keeping the writes of a tx (e.g.
+/// pending and later mined) in order. Txs for a worker whose queue is full are spilled, up to
+/// another `queue_size`, so the other workers keep receiving; only a worker that falls behind
+/// its spill too holds up the dispatch. Writes that fail with an error the storage doesn't
+/// consider retryable, or that keep failing after `max_retries`, are dead lettered instead of
+/// stopping the writer.
+pub struct StorageWriter {
+    pub config: WriterConfig,
+}
+
+impl StorageWriter {
+    pub fn new(config: WriterConfig) -> Self {
+        Self { config }
+    }
+
+    /// Write txs from `receiver` until it closes or `shutdown` fires
+    ///
+    /// On shutdown the writer stops receiving and returns once every queued tx has been
+    /// written or dead lettered. One worker is started per storage in `storages`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if no storage was given
+    pub async fn run<C: TxStorage<Transaction> + Retryable>(
+        &self,
+        storages: Vec<C>,
+        mut receiver: broadcast::Receiver<Transaction>,
+        mut shutdown: oneshot::Receiver<()>,
+    ) -> Result<WriterStats> {
+        anyhow::ensure!(!storages.is_empty(), "The storage writer needs a storage");
+
+        let (senders, queues): (Vec<_>, Vec<_>) = storages
+            .iter()
+            .map(|_| mpsc::channel::<Transaction>(self.config.queue_size))
+            .unzip();
+        let workers = storages
+            .into_iter()
+            .zip(queues)
+            .map(|(storage, queue)| self.work(storage, queue));
+
+        info!("Starting {} storage writers...", senders.len());
+
+        let spill_size = self.config.queue_size;
+        let dispatch = async move {
+            let mut missed = 0;
+            let mut stopping = false;
+            let mut spills = senders
+                .iter()
+                .map(|_| VecDeque::new())
+                .collect::<Vec<VecDeque<Transaction>>>();
+
+            loop {
+                // Txs already received before shutdown are still written
+                let tx = if stopping {
+                    match receiver.try_recv() {
+                        Ok(tx) => tx,
+                        Err(TryRecvError::Lagged(skipped)) => {
+                            missed += skipped;
+                            continue;
+                        }
+                        Err(_) => break,
+                    }
+                } else {
+                    let waiting = spills
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, spill)| !spill.is_empty())
+                        .map(|(worker, _)| worker)
+                        .collect::<Vec<usize>>();
+
+                    tokio::select! {
+                        _ = &mut shutdown => {
+                            stopping = true;
+                            continue;
+                        }
+                        (worker, permit) = spill_room(&senders, waiting) => {
+                            let Ok(permit) = permit else {
+                                break;
+                            };
+                            if let Some(tx) = spills[worker].pop_front() {
+                                permit.send(tx);
+                            }
+                            continue;
+                        }
+                        tx = receiver.recv() => match tx {
+                            Ok(tx) => tx,
+                            Err(RecvError::Lagged(skipped)) => {
+                                warn!("Storage writers lagged, {} txs were not written", skipped);
+                                missed += skipped;
+                                continue;
+                            }
+                            Err(RecvError::Closed) => break,
+                        },
+                    }
+                };
+
+                let worker = tx.hash.to_low_u64_be() as usize % senders.len();
+                let spill = &mut spills[worker];
+                if spill.is_empty() {
+                    match senders[worker].try_send(tx) {
+                        Ok(()) => continue,
+                        Err(TrySendError::Full(tx)) => spill.push_back(tx),
+                        Err(TrySendError::Closed(_)) => break,
+                    }
+                } else {
+                    spill.push_back(tx);
+                }
+                if spill.len() > spill_size {
+                    if let Some(tx) = spill.pop_front() {
+                        if senders[worker].send(tx).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+
+            info!("Flushing storage writers...");
+            for (sender, spill) in senders.iter().zip(spills) {
+                for tx in spill {
+                    if sender.send(tx).await.is_err() {
+                        break;
+                    }
+                }
+            }
+            missed
+        };
+
+        let (missed, stats) = tokio::join!(dispatch, join_all(workers));
+        let stats = stats.into_iter().fold(
+            WriterStats {
+                missed,
+                ..Default::default()
+            },
+            |total, stats| total + stats,
+        );
+
+        info!(
+            "Storage writers stopped: {} written, {} retries, {} dead lettered, {} missed",
+            stats.written, stats.retries, stats.dead_lettered, stats.missed
+        );
+
+        Ok(stats)
+    }
+
+    async fn work<C: TxStorage<Transaction> + Retryable>(
+        &self,
+        storage: C,
+        mut queue: mpsc::Receiver<Transaction>,
+    ) -> WriterStats {
+        let storage = Mutex::new(storage);
+        let mut stats = WriterStats::default();
+
+        while let Some(tx) = queue.recv().await {
+            self.write(&storage, &tx, &mut stats).await;
+        }
+
+        stats
+    }
+
+    /// Write the records from `receiver` to `storage` until it closes or `shutdown` fires
+    ///
+    /// Used for the records other than txs, e.g. swaps, which are written in the order they
+    /// are received. Records the writer fell too far behind to receive are counted as missed.
+    /// `storage` is only locked while a record is written, so it can be shared with other
+    /// tasks. On shutdown the records already received are still written before returning.
+    pub async fn store_records<T: Record + Clone, C: RecordStorage<T>>(
+        &self,
+        storage: &Mutex<C>,
+        mut receiver: broadcast::Receiver<T>,
+        mut shutdown: oneshot::Receiver<()>,
+    ) -> WriterStats {
+        let mut stats = WriterStats::default();
+        let mut stopping = false;
+
+        loop {
+            let received = if stopping {
+                match receiver.try_recv() {
+                    Ok(record) => Ok(record),
+                    Err(TryRecvError::Lagged(skipped)) => Err(RecvError::Lagged(skipped)),
+                    Err(_) => break,
+                }
+            } else {
+                tokio::select! {
+                    _ = &mut shutdown => {
+                        info!("Flushing {} records...", T::KIND);
+                        stopping = true;
+                        continue;
+                    }
+                    record = receiver.recv() => record,
+                }
+            };
+
+            let record = match received {
+                Ok(record) => record,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!(
+                        "Storage writer lagged, {} {} records were not written",
+                        skipped,
+                        T::KIND
+                    );
+                    stats.missed += skipped;
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+
+            self.write(storage, &record, &mut stats).await;
+        }
+
+        stats
+    }
+
+    /// Write a record, retrying transient failures and dead lettering the ones that persist
+    ///
+    /// `storage` is locked for each attempt and released while backing off.
+    async fn write<T: Record, C: RecordStorage<T>>(
+        &self,
+        storage: &Mutex<C>,
+        record: &T,
+        stats: &mut WriterStats,
+    ) {
+        let mut attempts = 0;
+
+        loop {
+            attempts += 1;
+
+            let (e, retryable) = {
+                let mut storage = storage.lock().await;
+                let Err(e) = storage.write_record(record).await else {
+                    stats.written += 1;
+                    break;
+                };
+                let retryable = storage.can_retry(&e);
+
+                (e, retryable)
+            };
+
+            if !retryable || attempts > self.config.max_retries {
+                warn!(
+                    "Failed to store {} {:?} after {} attempts: {}",
+                    T::KIND,
+                    record.hash(),
+                    attempts,
+                    e
+                );
+                self.dead_letter(record, &e, attempts, retryable).await;
+                stats.dead_lettered += 1;
+                break;
+            }
+
+            let backoff = self.backoff(attempts);
+            debug!(
+                "Retrying {} {:?} in {:?}: {}",
+                T::KIND,
+                record.hash(),
+                backoff,
+                e
+            );
+            stats.retries += 1;
+            tokio::time::sleep(backoff).await;
+        }
+    }
+
+    /// Delay before retry number `attempt`, doubling from `backoff` up to `max_backoff`
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.config
+            .backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.config.max_backoff)
+    }
+
+    /// Append a failed record to the dead letter file, on the blocking thread pool
+    async fn dead_letter<T: Record>(
+        &self,
+        record: &T,
+        error: &anyhow::Error,
+        attempts: u32,
+        retryable: bool,
+    ) {
+        let Some(path) = &self.config.dead_letter_path else {
+            return;
+        };
+
+        let mut keyed = serde_json::Map::new();
+        match serde_json::to_value(record) {
+            Ok(value) => {
+                keyed.insert(T::KIND.to_string(), value);
+            }
+            Err(e) => warn!("Failed to serialize {} {:?}: {}", T::KIND, record.hash(), e),
+        }
+        let letter = DeadLetter {
+            record: keyed,
+            error: error.to_string(),
+            attempts,
+            retryable,
+            failed_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+        };
+
+        let file_path = path.clone();
+        let written = match serde_json::to_string(&letter) {
+            Ok(line) => tokio::task::spawn_blocking(move || -> Result<()> {
+                // One write per line, so the workers' letters don't interleave
+                let mut file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(file_path)?;
+                file.write_all(format!("{}\n", line).as_bytes())?;
+                Ok(())
+            })
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|written| written),
+            Err(e) => Err(e.into()),
+        };
+
+        if let Err(e) = written {
+            warn!(
+                "Failed to dead letter {} {:?} to {:?}: {}",
+                T::KIND,
+                record.hash(),
+                path,
+                e
+            );
+        }
+    }
+}
@@ -0,0 +1,96 @@
+mod common;
+
+use common::{block, hash, tx};
+use ethers::types::{Block, Transaction, H256};
+use storage::block_storage::BlockStorage;
+use storage::memory::MemoryStorage;
+use storage::reader::ChainReader;
+use storage::tx_storage::TxStorage;
+
+fn numbers(blocks: &[Block<H256>]) -> Vec<(u64, H256)> {
+    blocks
+        .iter()
+        .map(|block| (block.number.unwrap().as_u64(), block.hash.unwrap()))
+        .collect()
+}
+
+/// Blocks delivered twice or late keep the chain above them, a competing block orphans it
+async fn replays_and_reorgs<S>(storage: &mut S)
+where
+    S: BlockStorage<Block<H256>> + TxStorage<Transaction> + ChainReader,
+{
+    for byte in [0x21, 0x31] {
+        TxStorage::store(storage, tx(byte)).await.unwrap();
+    }
+    let chain = [
+        block(1, 0x10, 0x00, &[]),
+        block(2, 0x20, 0x10, &[0x21]),
+        block(3, 0x30, 0x20, &[0x31]),
+    ];
+    for block in &chain {
+        BlockStorage::store(storage, block.clone()).await.unwrap();
+    }
+    assert_eq!(storage.latest_block_number().await.unwrap(), Some(3));
+    assert_eq!(storage.txs_in_range(1, 3).await.unwrap().len(), 2);
+
+    // A duplicate of the previous block, then a late delivery of an older one
+    BlockStorage::store(storage, chain[1].clone())
+        .await
+        .unwrap();
+    BlockStorage::store(storage, chain[0].clone())
+        .await
+        .unwrap();
+    assert_eq!(storage.latest_block_number().await.unwrap(), Some(3));
+    assert_eq!(
+        numbers(&storage.blocks_in_range(1, 3).await.unwrap()),
+        vec![(1, hash(0x10)), (2, hash(0x20)), (3, hash(0x30))]
+    );
+    let txs = storage.txs_in_range(1, 3).await.unwrap();
+    assert_eq!(
+        txs.iter()
+            .map(|tx| (tx.hash, tx.block_hash))
+            .collect::<Vec<_>>(),
+        vec![
+            (hash(0x21), Some(hash(0x20))),
+            (hash(0x31), Some(hash(0x30)))
+        ]
+    );
+    assert!(storage
+        .orphaned_blocks_in_range(1, 3)
+        .await
+        .unwrap()
+        .is_empty());
+
+    // A competing block 2 reorgs out blocks 2 and 3, the tx of block 2 moves over
+    BlockStorage::store(storage, block(2, 0x22, 0x10, &[0x21]))
+        .await
+        .unwrap();
+    assert_eq!(storage.latest_block_number().await.unwrap(), Some(2));
+    assert_eq!(
+        numbers(&storage.orphaned_blocks_in_range(1, 3).await.unwrap()),
+        vec![(2, hash(0x20)), (3, hash(0x30))]
+    );
+    let txs = storage.txs_in_range(1, 3).await.unwrap();
+    assert_eq!(
+        txs.iter()
+            .map(|tx| (tx.hash, tx.block_hash))
+            .collect::<Vec<_>>(),
+        vec![(hash(0x21), Some(hash(0x22)))]
+    );
+    assert_eq!(
+        storage.get(hash(0x31)).await.unwrap().unwrap().block_hash,
+        None
+    );
+}
+
+#[tokio::test]
+async fn memory_blocks_replay_and_reorg() {
+    replays_and_reorgs(&mut MemoryStorage::new()).await;
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn sqlite_blocks_replay_and_reorg() {
+    let mut storage = storage::sqlite::SqliteStorage::new(String::from(":memory:")).unwrap();
+    replays_and_reorgs(&mut storage).await;
+}
@@ -0,0 +1,106 @@
+//! Blocks, txs, swaps and lifecycles shared by the storage tests
+#![allow(dead_code)]
+
+use dex::swap::{SwapKind, SwapRecord, SwapStatus};
+use ethers::types::{Address, Block, Bytes, Transaction, H256, U256, U64};
+use storage::lifecycle::{TxLifecycle, TxStatus};
+
+pub fn hash(byte: u8) -> H256 {
+    H256::repeat_byte(byte)
+}
+
+/// Block `number` with a hash of repeated `byte`s, mining the txs of the given bytes
+pub fn block(number: u64, byte: u8, parent: u8, txs: &[u8]) -> Block<H256> {
+    Block {
+        hash: Some(hash(byte)),
+        number: Some(U64::from(number)),
+        parent_hash: hash(parent),
+        timestamp: U256::from(1_700_000_000 + number * 12),
+        transactions: txs.iter().copied().map(hash).collect(),
+        ..Default::default()
+    }
+}
+
+pub fn tx(byte: u8) -> Transaction {
+    Transaction {
+        hash: hash(byte),
+        ..Default::default()
+    }
+}
+
+/// A pending tx with every field the backends store
+pub fn sample_tx() -> Transaction {
+    Transaction {
+        hash: H256::repeat_byte(0x21),
+        nonce: U256::from(7),
+        from: Address::repeat_byte(0xf0),
+        to: Some(Address::repeat_byte(0xa0)),
+        // Above 64 bits, wei amounts must not go through an integer column
+        value: U256::exp10(20),
+        gas: U256::from(210_000),
+        gas_price: Some(U256::from(30_000_000_000u64)),
+        input: Bytes::from(vec![0x38, 0xed, 0x17, 0x39]),
+        ..Default::default()
+    }
+}
+
+/// The block that mined `sample_tx`
+pub fn sample_block() -> Block<H256> {
+    Block {
+        hash: Some(H256::repeat_byte(0x20)),
+        number: Some(U64::from(2)),
+        parent_hash: H256::repeat_byte(0x10),
+        timestamp: U256::from(1_700_000_024),
+        transactions: vec![sample_tx().hash],
+        ..Default::default()
+    }
+}
+
+/// A pending V3 swap of `sample_tx`
+pub fn sample_swap() -> SwapRecord {
+    SwapRecord {
+        tx_hash: sample_tx().hash,
+        swap_index: 0,
+        router: String::from("uniswap"),
+        protocol_version: 3,
+        method: String::from("exactInput"),
+        sender: Address::repeat_byte(0xf0),
+        recipient: Some(Address::repeat_byte(0xf1)),
+        path: vec![
+            Address::repeat_byte(1),
+            Address::repeat_byte(2),
+            Address::repeat_byte(3),
+        ],
+        fees: vec![500, 3000],
+        kind: SwapKind::ExactIn,
+        amount_in: U256::exp10(20),
+        amount_out: U256::from(900),
+        deadline: Some(1_700_000_000),
+        status: SwapStatus::Pending,
+        block_number: None,
+        block_hash: None,
+        transaction_index: None,
+        score: None,
+        amounts: None,
+    }
+}
+
+/// The lifecycle of `sample_tx`, still pending
+pub fn sample_lifecycle() -> TxLifecycle {
+    TxLifecycle {
+        hash: sample_tx().hash,
+        from: Address::repeat_byte(0xf0),
+        nonce: U256::from(7),
+        to: Some(Address::repeat_byte(0xa0)),
+        router: Some(String::from("uniswap-v3")),
+        node: String::from("node-1"),
+        first_seen: 1_700_000_000_000,
+        status: TxStatus::Pending,
+        block_number: None,
+        block_hash: None,
+        transaction_index: None,
+        mined_at: None,
+        replaced_by: None,
+        closed_at: None,
+    }
+}
@@ -0,0 +1,243 @@
+mod common;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use common::{block, hash, tx};
+use dex::swap::{SwapKind, SwapRecord, SwapStatus};
+use ethers::types::{Address, H256, U256};
+use settings::{DataClass, Retention};
+use storage::block_storage::BlockStorage;
+use storage::lifecycle::{LifecycleStorage, TxLifecycle, TxStatus};
+use storage::memory::MemoryStorage;
+use storage::reader::ChainReader;
+use storage::retention::{expire_storage, Expired, Expiry};
+use storage::swap::SwapStorage;
+use storage::tx_storage::TxStorage;
+
+const HOUR: u64 = 60 * 60;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn swap(tx: u8, status: SwapStatus, block: Option<(u64, u8)>) -> SwapRecord {
+    SwapRecord {
+        tx_hash: hash(tx),
+        swap_index: 0,
+        router: String::from("uniswap"),
+        protocol_version: 2,
+        method: String::from("swapExactTokensForTokens"),
+        sender: Address::repeat_byte(0xf0),
+        recipient: None,
+        path: vec![Address::repeat_byte(1), Address::repeat_byte(2)],
+        fees: Vec::new(),
+        kind: SwapKind::ExactIn,
+        amount_in: U256::from(1_000),
+        amount_out: U256::from(900),
+        deadline: None,
+        status,
+        block_number: block.map(|(number, _)| number),
+        block_hash: block.map(|(_, byte)| hash(byte)),
+        transaction_index: block.map(|_| 0),
+        score: None,
+        amounts: None,
+    }
+}
+
+fn lifecycle(byte: u8, router: Option<&str>, first_seen: u64) -> TxLifecycle {
+    TxLifecycle {
+        hash: hash(byte),
+        from: Address::repeat_byte(0xf0),
+        nonce: U256::from(byte),
+        to: None,
+        router: router.map(String::from),
+        node: String::from("node-1"),
+        first_seen,
+        status: TxStatus::Pending,
+        block_number: None,
+        block_hash: None,
+        transaction_index: None,
+        mined_at: None,
+        replaced_by: None,
+        closed_at: None,
+    }
+}
+
+#[tokio::test]
+async fn blocks_adopt_their_stored_ancestors() {
+    let mut storage = MemoryStorage::new();
+    TxStorage::store(&mut storage, tx(0x21)).await.unwrap();
+
+    // A head delivered before its parent is canonical on its own, the parent joins it later
+    BlockStorage::store(&mut storage, block(1, 0x10, 0x00, &[]))
+        .await
+        .unwrap();
+    BlockStorage::store(&mut storage, block(3, 0x30, 0x20, &[]))
+        .await
+        .unwrap();
+    assert_eq!(storage.canonical_hash(3), Some(hash(0x30)));
+    assert_eq!(storage.canonical_hash(2), None);
+
+    BlockStorage::store(&mut storage, block(2, 0x20, 0x10, &[0x21]))
+        .await
+        .unwrap();
+    assert_eq!(storage.canonical_hash(2), Some(hash(0x20)));
+    assert_eq!(storage.canonical_hash(3), Some(hash(0x30)));
+    assert_eq!(storage.latest_block_number().await.unwrap(), Some(3));
+    assert_eq!(storage.tx(hash(0x21)).unwrap().block_hash, Some(hash(0x20)));
+
+    // A fork off block 1 orphans blocks 2 and 3 and the tx they included
+    BlockStorage::store(&mut storage, block(2, 0x22, 0x10, &[]))
+        .await
+        .unwrap();
+    assert_eq!(storage.canonical_hash(3), None);
+    assert_eq!(
+        storage
+            .orphaned_blocks_in_range(1, 3)
+            .await
+            .unwrap()
+            .iter()
+            .map(|block| block.hash.unwrap())
+            .collect::<Vec<H256>>(),
+        vec![hash(0x20), hash(0x30)]
+    );
+    assert_eq!(storage.tx(hash(0x21)).unwrap().block_hash, None);
+}
+
+#[tokio::test]
+async fn mined_swaps_are_not_overwritten_by_pending_ones() {
+    let mut storage = MemoryStorage::new();
+    BlockStorage::store(&mut storage, block(1, 0x10, 0x00, &[0x21]))
+        .await
+        .unwrap();
+
+    // The block decoder may report a swap before the mempool does
+    storage
+        .store_swap(&swap(0x21, SwapStatus::Mined, Some((1, 0x10))))
+        .await
+        .unwrap();
+    storage
+        .store_swap(&swap(0x21, SwapStatus::Pending, None))
+        .await
+        .unwrap();
+    storage
+        .store_swap(&swap(0x31, SwapStatus::Pending, None))
+        .await
+        .unwrap();
+
+    let stored = storage.swaps(hash(0x21)).await.unwrap();
+    assert_eq!(stored, vec![swap(0x21, SwapStatus::Mined, Some((1, 0x10)))]);
+    assert_eq!(
+        storage.swaps_in_range(1, 1).await.unwrap(),
+        vec![swap(0x21, SwapStatus::Mined, Some((1, 0x10)))]
+    );
+
+    // Once its block is orphaned the swap is no longer in the chain
+    BlockStorage::store(&mut storage, block(1, 0x11, 0x00, &[]))
+        .await
+        .unwrap();
+    assert!(storage.swaps_in_range(1, 1).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn lifecycles_keep_their_first_sighting() {
+    let mut storage = MemoryStorage::new();
+
+    storage
+        .store_lifecycle(&lifecycle(0x21, None, 2_000))
+        .await
+        .unwrap();
+    // Seen earlier on another node, then mined
+    storage
+        .store_lifecycle(&TxLifecycle {
+            node: String::from("node-2"),
+            status: TxStatus::Mined,
+            mined_at: Some(5_000),
+            ..lifecycle(0x21, Some("uniswap-v2"), 1_000)
+        })
+        .await
+        .unwrap();
+    storage
+        .store_lifecycle(&TxLifecycle {
+            status: TxStatus::Dropped,
+            closed_at: Some(9_000),
+            ..lifecycle(0x31, Some("uniswap-v2"), 3_000)
+        })
+        .await
+        .unwrap();
+
+    let stored = storage.lifecycle(hash(0x21)).await.unwrap().unwrap();
+    assert_eq!(
+        (stored.first_seen, stored.node.as_str(), stored.status),
+        (1_000, "node-1", TxStatus::Mined)
+    );
+    assert_eq!(stored.router.as_deref(), Some("uniswap-v2"));
+
+    let latency = storage.router_latency(0).await.unwrap();
+    assert_eq!(latency.len(), 1);
+    assert_eq!(latency[0].router.as_deref(), Some("uniswap-v2"));
+    assert_eq!((latency[0].mined, latency[0].dropped), (1, 1));
+    assert_eq!(latency[0].min_latency, Some(4_000));
+    // Txs first seen before `since` are left out
+    assert_eq!(storage.router_latency(2_500).await.unwrap()[0].mined, 0);
+}
+
+#[tokio::test]
+async fn retention_expires_old_txs_and_swaps() {
+    let mut storage = MemoryStorage::new();
+    for byte in [0x21, 0x31] {
+        TxStorage::store(&mut storage, tx(byte)).await.unwrap();
+    }
+    // Mined long ago, the tx ages with its block rather than when it was stored
+    BlockStorage::store(&mut storage, block(1, 0x10, 0x00, &[0x31]))
+        .await
+        .unwrap();
+    storage
+        .store_swap(&swap(0x21, SwapStatus::Pending, None))
+        .await
+        .unwrap();
+
+    let retention = Retention {
+        pending_txs: Some(HOUR),
+        mined_txs: Some(365 * 24 * HOUR),
+        traces: Some(HOUR),
+        decoded_events: Some(HOUR),
+        abi_cache: Some(HOUR),
+        cleanup_interval: None,
+    };
+    let now = now();
+
+    let report = expire_storage(&mut storage, &retention, now, true)
+        .await
+        .unwrap();
+    let expired = |class| {
+        report
+            .iter()
+            .find(|expiry: &&Expiry| expiry.class == class)
+            .map(|expiry| expiry.expired)
+    };
+    assert_eq!(expired(DataClass::PendingTxs), Some(Expired::Rows(0)));
+    assert_eq!(expired(DataClass::MinedTxs), Some(Expired::Rows(1)));
+    assert_eq!(expired(DataClass::Traces), Some(Expired::NotStored));
+    assert_eq!(expired(DataClass::DecodedEvents), Some(Expired::Rows(0)));
+    // The ABI cache doesn't live in storage
+    assert_eq!(expired(DataClass::AbiCache), None);
+    // A dry run only counts
+    assert_eq!(storage.tx_count(), 2);
+
+    expire_storage(&mut storage, &retention, now, false)
+        .await
+        .unwrap();
+    assert_eq!(storage.tx(hash(0x31)), None);
+    assert!(storage.tx(hash(0x21)).is_some());
+
+    // Two hours later the pending tx and its swap are gone too
+    expire_storage(&mut storage, &retention, now + 2 * HOUR, false)
+        .await
+        .unwrap();
+    assert_eq!(storage.tx_count(), 0);
+    assert!(storage.all_swaps().is_empty());
+}
@@ -0,0 +1,212 @@
+#![cfg(feature = "postgres")]
+
+//! Runs against the server of `POSTGRES_URL`, e.g. `postgresql://postgres@localhost/postgres`,
+//! and is skipped without it. Every run uses a schema of its own and drops it.
+
+mod common;
+
+use common::{sample_block, sample_lifecycle, sample_swap, sample_tx};
+use dex::candle::{Candle, Interval, Trade};
+use dex::registry::{PairInfo, RegistryStorage, TokenInfo};
+use ethers::types::{Address, H256, U256};
+use storage::block_storage::BlockStorage;
+use storage::candle::CandleStorage;
+use storage::lifecycle::LifecycleStorage;
+use storage::postgres::PostgresStorage;
+use storage::reader::ChainReader;
+use storage::swap::SwapStorage;
+use storage::tx_storage::TxStorage;
+use tokio_postgres::NoTls;
+
+fn trade() -> Trade {
+    Trade {
+        pair: Address::repeat_byte(0x12),
+        tx_hash: sample_tx().hash,
+        block_number: 2,
+        block_hash: H256::repeat_byte(0x20),
+        log_index: 3,
+        timestamp: 1_700_000_024,
+        amount0_in: U256::exp10(20),
+        amount1_in: U256::zero(),
+        amount0_out: U256::zero(),
+        amount1_out: U256::from(2_000_000_000u64),
+    }
+}
+
+/// Connect with a schema of the test's own, `None` without `POSTGRES_URL`
+async fn connect(test: &str) -> Option<(String, String)> {
+    let url = std::env::var("POSTGRES_URL").ok()?;
+    let schema = format!("storage_{}_{}", test, std::process::id());
+    drop_schema(&url, &schema).await;
+
+    Some((url, schema))
+}
+
+async fn drop_schema(url: &str, schema: &str) {
+    let (client, connection) = tokio_postgres::connect(url, NoTls).await.unwrap();
+    tokio::spawn(connection);
+    client
+        .batch_execute(format!("DROP SCHEMA IF EXISTS {} CASCADE", schema).as_str())
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn round_trips_through_separate_connections() {
+    let Some((url, schema)) = connect("round_trip").await else {
+        return;
+    };
+    let mut writer = PostgresStorage::new(url.clone(), schema.clone())
+        .await
+        .unwrap();
+    // A second connection, like the one of another subsystem, reads what the first one wrote
+    let mut reader = PostgresStorage::new(url.clone(), schema.clone())
+        .await
+        .unwrap();
+
+    TxStorage::store(&mut writer, sample_tx()).await.unwrap();
+    BlockStorage::store(&mut writer, sample_block())
+        .await
+        .unwrap();
+    writer.store_swap(&sample_swap()).await.unwrap();
+    writer.store_lifecycle(&sample_lifecycle()).await.unwrap();
+
+    let stored = reader.get(sample_tx().hash).await.unwrap().unwrap();
+    assert_eq!(
+        (stored.nonce, stored.from, stored.to, stored.value),
+        (
+            sample_tx().nonce,
+            sample_tx().from,
+            sample_tx().to,
+            sample_tx().value
+        )
+    );
+    assert_eq!(
+        (stored.gas, stored.gas_price, stored.input),
+        (sample_tx().gas, sample_tx().gas_price, sample_tx().input)
+    );
+    // Stored before its block, the tx was moved into it
+    assert_eq!(
+        (stored.block_hash, stored.block_number),
+        (sample_block().hash, sample_block().number)
+    );
+
+    let blocks = reader.blocks_in_range(1, 3).await.unwrap();
+    assert_eq!(blocks.len(), 1);
+    assert_eq!(
+        (blocks[0].hash, blocks[0].parent_hash, blocks[0].timestamp),
+        (
+            sample_block().hash,
+            sample_block().parent_hash,
+            sample_block().timestamp
+        )
+    );
+    assert_eq!(blocks[0].transactions, sample_block().transactions);
+    assert_eq!(reader.latest_block_number().await.unwrap(), Some(2));
+
+    assert_eq!(
+        reader.swaps(sample_tx().hash).await.unwrap(),
+        vec![sample_swap()]
+    );
+    assert_eq!(
+        reader.lifecycle(sample_tx().hash).await.unwrap(),
+        Some(sample_lifecycle())
+    );
+
+    drop((writer, reader));
+    drop_schema(&url, &schema).await;
+}
+
+#[tokio::test]
+async fn stores_the_registry_trades_and_candles() {
+    let Some((url, schema)) = connect("markets").await else {
+        return;
+    };
+    let mut storage = PostgresStorage::new(url.clone(), schema.clone())
+        .await
+        .unwrap();
+
+    let token = |byte, first_seen_block| TokenInfo {
+        address: Address::repeat_byte(byte),
+        name: format!("Token {}", byte),
+        symbol: format!("T{}", byte),
+        decimals: 18,
+        first_seen_block,
+    };
+    let pair = PairInfo {
+        address: Address::repeat_byte(0x12),
+        factory: Address::repeat_byte(0xa1),
+        protocol_version: 2,
+        token0: Address::repeat_byte(1),
+        token1: Address::repeat_byte(2),
+        fee: None,
+        created_block: 2,
+    };
+    storage.store_token(&token(1, Some(5))).await.unwrap();
+    // The earliest block a token was seen in is kept
+    storage.store_token(&token(1, Some(2))).await.unwrap();
+    storage.store_token(&token(1, Some(9))).await.unwrap();
+    storage.store_token(&token(2, None)).await.unwrap();
+    storage.store_pair(&pair).await.unwrap();
+    storage.store_pair(&pair).await.unwrap();
+
+    let mut tokens = storage.tokens().await.unwrap();
+    tokens.sort_by_key(|token| token.address);
+    assert_eq!(tokens, vec![token(1, Some(2)), token(2, None)]);
+    assert_eq!(storage.pairs().await.unwrap(), vec![pair]);
+
+    // Storing a trade twice keeps one
+    storage.store_trades(&[trade()]).await.unwrap();
+    storage.store_trades(&[trade()]).await.unwrap();
+    assert_eq!(
+        storage
+            .trades(Some(trade().pair), 1_700_000_000, 1_700_000_059)
+            .await
+            .unwrap(),
+        vec![trade()]
+    );
+
+    let candle = Candle {
+        pair: trade().pair,
+        interval: Interval::OneMinute,
+        start: 1_699_999_980,
+        open: 2_000.0,
+        high: 2_000.0,
+        low: 2_000.0,
+        close: 2_000.0,
+        volume0: 100.0,
+        volume1: 2_000.0,
+        trades: 1,
+    };
+    storage.store_candle(&candle).await.unwrap();
+    assert_eq!(
+        storage
+            .candles(candle.pair, Interval::OneMinute, candle.start, candle.start)
+            .await
+            .unwrap(),
+        vec![candle.clone()]
+    );
+
+    // Another block at the height of the trade orphans it
+    let orphaned = storage
+        .orphan_trades(2, H256::repeat_byte(0x21))
+        .await
+        .unwrap();
+    assert_eq!(orphaned, vec![trade()]);
+    assert!(storage
+        .trades(None, 1_700_000_000, 1_700_000_059)
+        .await
+        .unwrap()
+        .is_empty());
+
+    assert_eq!(
+        storage
+            .delete_candles(None, candle.start, candle.start)
+            .await
+            .unwrap(),
+        1
+    );
+
+    drop(storage);
+    drop_schema(&url, &schema).await;
+}
@@ -0,0 +1,87 @@
+#![cfg(feature = "sqlite")]
+
+mod common;
+
+use common::{sample_block, sample_lifecycle, sample_swap, sample_tx};
+use storage::block_storage::BlockStorage;
+use storage::lifecycle::LifecycleStorage;
+use storage::reader::ChainReader;
+use storage::sqlite::SqliteStorage;
+use storage::swap::SwapStorage;
+use storage::tx_storage::TxStorage;
+
+#[tokio::test]
+async fn memory_database_round_trips_through_shared_handles() {
+    let mut writer = SqliteStorage::new(String::from(":memory:")).unwrap();
+    // A second handle, like the one of another subsystem, reads what the first one wrote
+    let mut reader = writer.clone();
+
+    TxStorage::store(&mut writer, sample_tx()).await.unwrap();
+    BlockStorage::store(&mut writer, sample_block())
+        .await
+        .unwrap();
+    writer.store_swap(&sample_swap()).await.unwrap();
+    writer.store_lifecycle(&sample_lifecycle()).await.unwrap();
+
+    let stored = reader.get(sample_tx().hash).await.unwrap().unwrap();
+    assert_eq!(
+        (stored.nonce, stored.from, stored.to, stored.value),
+        (
+            sample_tx().nonce,
+            sample_tx().from,
+            sample_tx().to,
+            sample_tx().value
+        )
+    );
+    assert_eq!(
+        (stored.gas, stored.gas_price, stored.input),
+        (sample_tx().gas, sample_tx().gas_price, sample_tx().input)
+    );
+    // Stored before its block, the tx was moved into it
+    assert_eq!(
+        (stored.block_hash, stored.block_number),
+        (sample_block().hash, sample_block().number)
+    );
+
+    let blocks = reader.blocks_in_range(1, 3).await.unwrap();
+    assert_eq!(blocks.len(), 1);
+    assert_eq!(
+        (blocks[0].hash, blocks[0].parent_hash, blocks[0].timestamp),
+        (
+            sample_block().hash,
+            sample_block().parent_hash,
+            sample_block().timestamp
+        )
+    );
+    assert_eq!(blocks[0].transactions, sample_block().transactions);
+    assert_eq!(reader.latest_block_number().await.unwrap(), Some(2));
+
+    assert_eq!(
+        reader.swaps(sample_tx().hash).await.unwrap(),
+        vec![sample_swap()]
+    );
+    assert_eq!(
+        reader.lifecycle(sample_tx().hash).await.unwrap(),
+        Some(sample_lifecycle())
+    );
+}
+
+#[tokio::test]
+async fn file_database_creates_its_directory() {
+    let dir = std::env::temp_dir().join(format!("storage-sqlite-{}", std::process::id()));
+    let path = dir.join("nested").join("poc.db");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let mut storage = SqliteStorage::new(path.to_string_lossy().into_owned()).unwrap();
+    TxStorage::store(&mut storage, sample_tx()).await.unwrap();
+    drop(storage);
+
+    // Reopened, the tx is still there
+    let mut storage = SqliteStorage::new(path.to_string_lossy().into_owned()).unwrap();
+    assert!(TxStorage::is_stored(&mut storage, sample_tx())
+        .await
+        .unwrap());
+    drop(storage);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
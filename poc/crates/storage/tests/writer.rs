@@ -0,0 +1,204 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use dex::swap::{SwapKind, SwapRecord, SwapStatus};
+use ethers::types::{Address, Block, Transaction, H256, U256, U64};
+use storage::block_storage::block_store;
+use storage::memory::MemoryStorage;
+use storage::swap::swap_store;
+use storage::tx_storage::TxStorage;
+use storage::writer::{Retryable, StorageWriter, WriterConfig};
+use tokio::sync::{broadcast, oneshot, watch, Mutex};
+
+fn tx(n: u64) -> Transaction {
+    Transaction {
+        hash: H256::from_low_u64_be(n),
+        ..Default::default()
+    }
+}
+
+fn block(n: u64) -> Block<H256> {
+    Block {
+        hash: Some(H256::from_low_u64_be(0xb000 + n)),
+        parent_hash: H256::from_low_u64_be(0xb000 + n - 1),
+        number: Some(U64::from(n)),
+        ..Default::default()
+    }
+}
+
+fn swap(n: u64) -> SwapRecord {
+    SwapRecord {
+        tx_hash: H256::from_low_u64_be(n),
+        swap_index: 0,
+        router: String::from("uniswap"),
+        protocol_version: 2,
+        method: String::from("swapExactTokensForTokens"),
+        sender: Address::repeat_byte(0xf0),
+        recipient: None,
+        path: vec![Address::repeat_byte(1), Address::repeat_byte(2)],
+        fees: Vec::new(),
+        kind: SwapKind::ExactIn,
+        amount_in: U256::from(1_000),
+        amount_out: U256::from(900),
+        deadline: None,
+        status: SwapStatus::Pending,
+        block_number: None,
+        block_hash: None,
+        transaction_index: None,
+        score: None,
+        amounts: None,
+    }
+}
+
+/// Memory storage whose writes of `stuck` wait until the gate opens
+struct Gated {
+    inner: MemoryStorage,
+    stuck: H256,
+    gate: watch::Receiver<bool>,
+}
+
+impl TxStorage<Transaction> for Gated {
+    async fn store(&mut self, tx: Transaction) -> Result<()> {
+        if tx.hash == self.stuck {
+            self.gate.wait_for(|open| *open).await?;
+        }
+        TxStorage::store(&mut self.inner, tx).await
+    }
+
+    async fn is_stored(&mut self, tx: Transaction) -> Result<bool> {
+        self.inner.is_stored(tx).await
+    }
+
+    async fn get(&mut self, hash: H256) -> Result<Option<Transaction>> {
+        self.inner.get(hash).await
+    }
+
+    async fn store_many(&mut self, txs: Vec<Transaction>) -> Result<()> {
+        self.inner.store_many(txs).await
+    }
+
+    async fn exists_many(&mut self, hashes: &[H256]) -> Result<HashSet<H256>> {
+        self.inner.exists_many(hashes).await
+    }
+
+    async fn delete(&mut self, tx: Transaction) -> Result<()> {
+        TxStorage::delete(&mut self.inner, tx).await
+    }
+}
+
+impl Retryable for Gated {
+    fn is_retryable(&self, error: &anyhow::Error) -> bool {
+        self.inner.is_retryable(error)
+    }
+}
+
+#[tokio::test]
+async fn stuck_worker_does_not_hold_up_the_others() {
+    let memory = MemoryStorage::new();
+    let (open, gate) = watch::channel(false);
+    let storages = (0..2)
+        .map(|_| Gated {
+            inner: memory.clone(),
+            stuck: tx(2).hash,
+            gate: gate.clone(),
+        })
+        .collect::<Vec<Gated>>();
+    let writer = StorageWriter::new(WriterConfig {
+        workers: 2,
+        queue_size: 1,
+        ..Default::default()
+    });
+    let (sender, receiver) = broadcast::channel(64);
+    let (stop, shutdown) = oneshot::channel();
+
+    let driver = async {
+        // Txs are routed by hash, the even ones go to the worker stuck on the first of them
+        for n in [2, 4, 6, 1, 3, 5, 7] {
+            sender.send(tx(n)).unwrap();
+        }
+        let odd = async {
+            while memory.tx_count() < 4 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), odd)
+            .await
+            .expect("The stuck worker held up the others");
+        assert!(memory.tx(tx(2).hash).is_none());
+
+        open.send(true).unwrap();
+        stop.send(()).unwrap();
+    };
+
+    let (stats, ()) = tokio::join!(writer.run(storages, receiver, shutdown), driver);
+    let stats = stats.unwrap();
+    assert_eq!((stats.written, stats.missed), (7, 0));
+    assert_eq!(memory.tx_count(), 7);
+}
+
+#[tokio::test]
+async fn swap_store_keeps_going_after_lagging() {
+    let memory = MemoryStorage::new();
+    let (sender, receiver) = broadcast::channel(2);
+    for n in 1..=4 {
+        sender.send(swap(n)).unwrap();
+    }
+    drop(sender);
+    let (_stop, shutdown) = oneshot::channel();
+
+    swap_store(
+        Arc::new(Mutex::new(memory.clone())),
+        receiver,
+        Arc::new(StorageWriter::new(WriterConfig::default())),
+        shutdown,
+    )
+    .await
+    .unwrap();
+
+    // The two oldest swaps were overwritten before the store started
+    assert_eq!(
+        memory
+            .all_swaps()
+            .iter()
+            .map(|swap| swap.tx_hash)
+            .collect::<Vec<H256>>(),
+        vec![H256::from_low_u64_be(3), H256::from_low_u64_be(4)]
+    );
+}
+
+#[tokio::test]
+async fn block_store_flushes_on_shutdown_without_holding_the_storage() {
+    let memory = MemoryStorage::new();
+    let storage = Arc::new(Mutex::new(memory.clone()));
+    let (sender, receiver) = broadcast::channel(16);
+    let (stop, shutdown) = oneshot::channel();
+    let store = tokio::spawn(block_store(
+        storage.clone(),
+        receiver,
+        Arc::new(StorageWriter::new(WriterConfig::default())),
+        shutdown,
+    ));
+
+    // Other tasks sharing the storage aren't locked out while the store waits for blocks
+    drop(
+        tokio::time::timeout(Duration::from_secs(5), storage.lock())
+            .await
+            .expect("The block store held the storage lock"),
+    );
+
+    // Blocks received before the shutdown are written even though the sender is still open
+    for n in 1..=3 {
+        sender.send(block(n)).unwrap();
+    }
+    stop.send(()).unwrap();
+    tokio::time::timeout(Duration::from_secs(5), store)
+        .await
+        .expect("The block store didn't stop on shutdown")
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(memory.block_count(), 3);
+    drop(sender);
+}
@@ -0,0 +1,83 @@
+use anyhow::{bail, Result};
+use dex::candle::Interval;
+use dex::registry::Registry;
+use ethers::types::Address;
+use lazy_static::lazy_static;
+use settings::Settings;
+use storage::candle::{rebuild_candles, CandleStorage};
+use storage::engine::StorageEngine;
+
+lazy_static! {
+    static ref SETTINGS: Settings =
+        Settings::new(String::from("sniper")).expect("Failed to load settings");
+}
+
+const USAGE: &str = "Usage: candles rebuild [--from <unix>] [--to <unix>]\n       \
+    candles show <pair> <1m|5m|1h|1d> [--from <unix>] [--to <unix>]";
+
+/// Parse `--from` and `--to` (unix seconds), defaulting to the last 24 hours
+fn parse_period(args: &[String]) -> Result<(u64, u64)> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+    let (mut from, mut to) = (now.saturating_sub(86_400), now);
+
+    for option in args.chunks(2) {
+        match option {
+            [flag, value] if flag == "--from" => from = value.parse()?,
+            [flag, value] if flag == "--to" => to = value.parse()?,
+            _ => bail!(USAGE),
+        }
+    }
+
+    Ok((from, to))
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let settings = SETTINGS.clone();
+    // Setup logging
+    env_logger::Builder::from_env(
+        env_logger::Env::default().default_filter_or(settings.log.level.clone()),
+    )
+    .init();
+
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    let mut storage = StorageEngine::new(&settings).await?;
+
+    match args.as_slice() {
+        [command, options @ ..] if command == "rebuild" => {
+            let (from, to) = parse_period(options)?;
+            let registry = Registry::load(&mut storage).await?;
+            let stored = rebuild_candles(&mut storage, &registry, from, to).await?;
+
+            println!("Rebuilt {} candles", stored);
+        }
+        [command, pair, interval, options @ ..] if command == "show" => {
+            let pair = pair.parse::<Address>()?;
+            let interval = interval.parse::<Interval>()?;
+            let (from, to) = parse_period(options)?;
+
+            println!(
+                "{:<12} {:>14} {:>14} {:>14} {:>14} {:>16} {:>16} {:>7}",
+                "start", "open", "high", "low", "close", "volume0", "volume1", "trades"
+            );
+            for candle in storage.candles(pair, interval, from, to).await? {
+                println!(
+                    "{:<12} {:>14.6e} {:>14.6e} {:>14.6e} {:>14.6e} {:>16.4} {:>16.4} {:>7}",
+                    candle.start,
+                    candle.open,
+                    candle.high,
+                    candle.low,
+                    candle.close,
+                    candle.volume0,
+                    candle.volume1,
+                    candle.trades,
+                );
+            }
+        }
+        _ => bail!(USAGE),
+    }
+
+    Ok(())
+}
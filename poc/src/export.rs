@@ -0,0 +1,77 @@
+use anyhow::{anyhow, bail, Result};
+use export::{ExportRange, ParquetExporter};
+use lazy_static::lazy_static;
+use log::info;
+use settings::{Settings, StorageBackend};
+use storage::engine::StorageEngine;
+
+lazy_static! {
+    static ref SETTINGS: Settings =
+        Settings::new(String::from("sniper")).expect("Failed to load settings");
+}
+
+const USAGE: &str =
+    "Usage: export [--from <block> --to <block>] [--from-time <unix> --to-time <unix>]";
+
+/// Parse the export range from the command line, defaulting to an incremental export
+fn parse_range(args: Vec<String>) -> Result<ExportRange> {
+    let mut args = args.into_iter();
+    let (mut from, mut to, mut from_time, mut to_time) = (None, None, None, None);
+
+    while let Some(arg) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| anyhow!("Missing value for {}\n{}", arg, USAGE))?
+            .parse::<u64>()?;
+
+        match arg.as_str() {
+            "--from" => from = Some(value),
+            "--to" => to = Some(value),
+            "--from-time" => from_time = Some(value),
+            "--to-time" => to_time = Some(value),
+            _ => bail!("Unknown argument {}\n{}", arg, USAGE),
+        }
+    }
+
+    match (from, to, from_time, to_time) {
+        (None, None, None, None) => Ok(ExportRange::Incremental),
+        (Some(from), Some(to), None, None) => Ok(ExportRange::Blocks { from, to }),
+        (None, None, Some(from), Some(to)) => Ok(ExportRange::Time { from, to }),
+        _ => bail!(USAGE),
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let settings = SETTINGS.clone();
+    // Setup logging
+    env_logger::Builder::from_env(
+        env_logger::Env::default().default_filter_or(settings.log.level.clone()),
+    )
+    .init();
+
+    let range = parse_range(std::env::args().skip(1).collect())?;
+    // Blocks are only stored by the relational and in-memory backends, fail before writing
+    // anything rather than on the first read
+    if settings.storage_backend() == StorageBackend::Scylla {
+        bail!("The Scylla backend doesn't store blocks, export from a postgres or sqlite storage");
+    }
+    let export = settings
+        .export
+        .clone()
+        .ok_or_else(|| anyhow!("Missing [export] settings"))?;
+
+    let exporter = ParquetExporter::new(export.path, export.blocks_per_file)?;
+    let mut storage = StorageEngine::new(&settings).await?;
+
+    let summary = exporter.export(&mut storage, range).await?;
+    info!(
+        "Export finished: {} blocks, {} txs, {} swaps, {} files",
+        summary.blocks,
+        summary.txs,
+        summary.swaps,
+        summary.files.len()
+    );
+
+    Ok(())
+}
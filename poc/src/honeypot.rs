@@ -0,0 +1,89 @@
+use anyhow::{anyhow, bail, Result};
+use ethers::providers::{Http, Provider};
+use ethers::types::Address;
+use ethers::utils::parse_ether;
+use lazy_static::lazy_static;
+use settings::Settings;
+use simulation::fork::StateSnapshot;
+use simulation::honeypot::{check_honeypot_live, HoneypotFixture};
+
+lazy_static! {
+    static ref SETTINGS: Settings =
+        Settings::new(String::from("sniper")).expect("Failed to load settings");
+}
+
+const USAGE: &str = "Usage: honeypot <token> [--block <n>] [--record]";
+
+/// ETH spent on the simulated buy when `honeypot.buy_amount` isn't configured
+const DEFAULT_BUY_AMOUNT: f64 = 0.1;
+/// Rounds of state fetching when `honeypot.max_rounds` isn't configured
+const DEFAULT_MAX_ROUNDS: usize = 20;
+
+/// Buy a token and sell it back in a local EVM forked from the node, printing the taxes and
+/// limits found, or with `--record` the round trip as a fixture for `crates/simulation/tests`
+#[tokio::main]
+async fn main() -> Result<()> {
+    let settings = SETTINGS.clone();
+    // Setup logging
+    env_logger::Builder::from_env(
+        env_logger::Env::default().default_filter_or(settings.log.level.clone()),
+    )
+    .init();
+
+    let mut args = std::env::args().skip(1);
+    let token = args
+        .next()
+        .ok_or_else(|| anyhow!(USAGE))?
+        .parse::<Address>()?;
+    let (mut block, mut record) = (None, false);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--block" => block = Some(args.next().ok_or_else(|| anyhow!(USAGE))?.parse()?),
+            "--record" => record = true,
+            _ => bail!(USAGE),
+        }
+    }
+
+    let honeypot = settings.honeypot.clone().unwrap_or_default();
+    let router = settings
+        .dex
+        .routers
+        .iter()
+        .filter(|router| router.version == 2)
+        .find(|router| {
+            honeypot
+                .router
+                .as_ref()
+                .is_none_or(|name| *name == router.name)
+        })
+        .ok_or_else(|| anyhow!("No V2 router {:?} in the settings", honeypot.router))?;
+    let router = router
+        .addresses
+        .first()
+        .ok_or_else(|| anyhow!("Router {} has no address", router.name))?
+        .parse::<Address>()?;
+    let amount_in = parse_ether(honeypot.buy_amount.unwrap_or(DEFAULT_BUY_AMOUNT))?;
+    let max_rounds = honeypot.max_rounds.unwrap_or(DEFAULT_MAX_ROUNDS);
+
+    let provider = Provider::<Http>::try_from(settings.ethereum.node_http.clone())?;
+    if record {
+        let fixture =
+            HoneypotFixture::record(&provider, router, token, amount_in, block, max_rounds).await?;
+        println!("{}", serde_json::to_string_pretty(&fixture)?);
+    } else {
+        let mut snapshot = StateSnapshot::at_block(&provider, block).await?;
+        let report = check_honeypot_live(
+            &provider,
+            &mut snapshot,
+            router,
+            token,
+            amount_in,
+            max_rounds,
+        )
+        .await?;
+        println!("{}", report);
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,63 @@
+use anyhow::{bail, Result};
+use lazy_static::lazy_static;
+use settings::Settings;
+use storage::engine::StorageEngine;
+use storage::lifecycle::LifecycleStorage;
+
+lazy_static! {
+    static ref SETTINGS: Settings =
+        Settings::new(String::from("sniper")).expect("Failed to load settings");
+}
+
+const USAGE: &str = "Usage: latency [--since <unix>]";
+
+/// Parse the start of the reported period (unix seconds), defaulting to the last 24 hours
+fn parse_since(args: Vec<String>) -> Result<u64> {
+    match args.as_slice() {
+        [] => Ok(std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs()
+            .saturating_sub(86_400)),
+        [flag, since] if flag == "--since" => Ok(since.parse::<u64>()?),
+        _ => bail!(USAGE),
+    }
+}
+
+fn format_latency(latency: Option<u64>) -> String {
+    latency
+        .map(|l| format!("{:.1}s", l as f64 / 1000.0))
+        .unwrap_or_else(|| String::from("-"))
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let settings = SETTINGS.clone();
+    // Setup logging
+    env_logger::Builder::from_env(
+        env_logger::Env::default().default_filter_or(settings.log.level.clone()),
+    )
+    .init();
+
+    let since = parse_since(std::env::args().skip(1).collect())?;
+    let mut storage = StorageEngine::new(&settings).await?;
+
+    println!(
+        "{:<16} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8}",
+        "router", "mined", "replaced", "dropped", "pending", "min", "avg", "max"
+    );
+    for stats in storage.router_latency(since * 1000).await? {
+        println!(
+            "{:<16} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8}",
+            stats.router.unwrap_or_else(|| String::from("other")),
+            stats.mined,
+            stats.replaced,
+            stats.dropped,
+            stats.pending,
+            format_latency(stats.min_latency),
+            format_latency(stats.avg_latency.map(|l| l as u64)),
+            format_latency(stats.max_latency),
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,5 @@
+#![feature(async_fn_in_trait)]
+
+pub mod publish;
+pub mod registry;
+pub mod retention;
@@ -0,0 +1,53 @@
+use anyhow::Result;
+use log::{info, warn};
+use serde::Serialize;
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+
+/// Connect to NATS, or run without publishing if the server can't be reached
+pub async fn connect(nats: &settings::Nats) -> Option<async_nats::Client> {
+    info!("Connecting to NATS at {}", nats.url);
+
+    match async_nats::connect(nats.url.as_str()).await {
+        Ok(client) => Some(client),
+        Err(e) => {
+            warn!(
+                "Failed to connect to NATS at {}, not publishing: {}",
+                nats.url, e
+            );
+            None
+        }
+    }
+}
+
+/// Publish every message from `receiver` as JSON on `subject`
+///
+/// # Errors
+///
+/// This function will return an error if a message could not be serialized or published
+pub async fn publish<T: Serialize + Clone>(
+    client: async_nats::Client,
+    subject: String,
+    mut receiver: Receiver<T>,
+) -> Result<()> {
+    info!("Publishing on {}...", subject);
+
+    loop {
+        let message = match receiver.recv().await {
+            Ok(message) => message,
+            Err(RecvError::Lagged(skipped)) => {
+                warn!(
+                    "Publisher lagged, {} messages were not published on {}",
+                    skipped, subject
+                );
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+
+        client
+            .publish(subject.clone(), serde_json::to_vec(&message)?.into())
+            .await?;
+    }
+
+    Ok(())
+}
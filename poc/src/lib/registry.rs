@@ -0,0 +1,101 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use anyhow::Result;
+use dex::factory::Factory;
+use dex::registry::{sync_factory, Registry, RegistryStorage};
+use eth_node::token_resolver::TokenResolver;
+use ethers::providers::{Http, Middleware, Provider};
+use log::{info, warn};
+use tokio::sync::Mutex;
+
+/// Blocks per `eth_getLogs` request when `log_chunk_size` isn't configured
+pub const DEFAULT_LOG_CHUNK_SIZE: u64 = 2000;
+
+/// Load the registry from storage, adding the tokens configured in the settings
+///
+/// Configured tokens with missing fields are completed by `resolver`.
+///
+/// # Errors
+///
+/// This function will return an error if the registry could not be loaded or stored, or if a
+/// configured token has an invalid address or no decimals
+pub async fn load_registry<C: RegistryStorage>(
+    storage: &mut C,
+    tokens: &[settings::Token],
+    resolver: &mut TokenResolver,
+) -> Result<Registry> {
+    let mut registry = Registry::load(storage).await?;
+
+    for token in tokens {
+        let resolved = if token.is_complete() {
+            None
+        } else {
+            Some(resolver.resolve(token.address.parse()?).await?)
+        };
+        let token = token.token_info(resolved.as_ref())?;
+        if registry.token(token.address).is_none() {
+            storage.store_token(&token).await?;
+            registry.insert_token(token);
+        }
+    }
+
+    Ok(registry)
+}
+
+/// Register the pairs created by `factories` up to the current block
+///
+/// Each factory is synced from its newest registered pair, or from `from_block` if nothing
+/// newer is registered yet.
+///
+/// # Errors
+///
+/// This function will return an error if the node could not be reached or the registry could
+/// not be stored
+pub async fn registry_backfill<C: RegistryStorage>(
+    registry: Arc<Mutex<Registry>>,
+    storage: Arc<Mutex<C>>,
+    node_http: String,
+    factories: Vec<Factory>,
+    registry_settings: settings::Registry,
+) -> Result<()> {
+    let Some(from_block) = registry_settings.backfill_from else {
+        return Ok(());
+    };
+    let chunk_size = registry_settings
+        .log_chunk_size
+        .unwrap_or(DEFAULT_LOG_CHUNK_SIZE);
+    let provider = Provider::<Http>::try_from(node_http)?;
+    let head = provider.get_block_number().await?.as_u64();
+    let mut synced = HashSet::new();
+
+    for factory in factories {
+        if !synced.insert(factory.address) {
+            continue;
+        }
+
+        let mut registry = registry.lock().await;
+        let from = registry
+            .last_created_block(factory.address)
+            .map_or(from_block, |last| from_block.max(last + 1));
+
+        info!("Backfilling pairs of {} from block {}...", factory, from);
+
+        match sync_factory(
+            &mut registry,
+            &mut *storage.lock().await,
+            &provider,
+            &factory,
+            from,
+            head,
+            chunk_size,
+        )
+        .await
+        {
+            Ok(created) => info!("Registered {} new pairs of {}", created, factory),
+            Err(e) => warn!("Failed to backfill pairs of {}: {}", factory, e),
+        }
+    }
+
+    Ok(())
+}
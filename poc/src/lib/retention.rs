@@ -0,0 +1,68 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use log::info;
+use settings::{DataClass, Retention};
+use storage::retention::{expire_storage, Expired, ExpiringStorage, Expiry};
+use tokio::sync::Mutex;
+
+/// Time between scheduled cleanups when `cleanup_interval` isn't configured
+pub const DEFAULT_CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Apply every retention policy once, to storage and to the ABI cache in `cache_path`
+///
+/// # Errors
+///
+/// This function will return an error if any class could not be expired
+pub async fn expire<C: ExpiringStorage>(
+    storage: &mut C,
+    retention: &Retention,
+    cache_path: &str,
+    dry_run: bool,
+) -> Result<Vec<Expiry>> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let mut report = expire_storage(storage, retention, now, dry_run).await?;
+
+    if let Some(ttl) = retention.ttl(DataClass::AbiCache) {
+        let expired = block_explorer::expire_cache(
+            PathBuf::from(cache_path),
+            ttl.as_secs() as usize,
+            dry_run,
+        )?;
+
+        report.push(Expiry {
+            class: DataClass::AbiCache,
+            cutoff: now.saturating_sub(ttl.as_secs()),
+            expired: Expired::Rows(expired),
+        });
+    }
+
+    Ok(report)
+}
+
+/// Periodically apply the retention policies and log what was expired
+pub async fn retention_cleanup<C: ExpiringStorage>(
+    storage: Arc<Mutex<C>>,
+    retention: Retention,
+    cache_path: String,
+) -> Result<()> {
+    let mut interval = tokio::time::interval(
+        retention
+            .cleanup_interval
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_CLEANUP_INTERVAL),
+    );
+
+    info!("Starting retention cleanups...");
+
+    loop {
+        interval.tick().await;
+
+        let report = expire(&mut *storage.lock().await, &retention, &cache_path, false).await?;
+        for expiry in report {
+            info!("Expired {}", expiry);
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use anyhow::{bail, Result};
+use lazy_static::lazy_static;
+use poc_eth::retention::expire;
+use settings::Settings;
+use storage::engine::StorageEngine;
+
+lazy_static! {
+    static ref SETTINGS: Settings =
+        Settings::new(String::from("sniper")).expect("Failed to load settings");
+}
+
+const USAGE: &str = "Usage: retention [--dry-run]";
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let settings = SETTINGS.clone();
+    // Setup logging
+    env_logger::Builder::from_env(
+        env_logger::Env::default().default_filter_or(settings.log.level.clone()),
+    )
+    .init();
+
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    let dry_run = match args.as_slice() {
+        [] => false,
+        [flag] if flag == "--dry-run" => true,
+        _ => bail!(USAGE),
+    };
+
+    let mut storage = StorageEngine::new(&settings).await?;
+    let report = expire(
+        &mut storage,
+        &settings.retention(),
+        &settings.cache_path,
+        dry_run,
+    )
+    .await?;
+
+    if report.is_empty() {
+        println!("No retention policies configured, everything is kept");
+    }
+    for expiry in report {
+        if dry_run {
+            println!("Would expire {}", expiry);
+        } else {
+            println!("Expired {}", expiry);
+        }
+    }
+
+    Ok(())
+}
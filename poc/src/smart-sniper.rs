@@ -1,16 +1,38 @@
 use block_explorer::blockexplorerapi::BlockExplorerApi;
+use cache::memory::MemoryCache;
 use cache::redis::TxCacheRedis;
+use cache::token_caching::TokenCacheEngine;
 use cache::tx_cache_updates;
+use dex::decoded::DecodedCall;
+use dex::liquidity::LiquidityEvent;
+use dex::protocol::ProtocolRegistry;
+use dex::registry::NewMarket;
+use dex::reserves::ReserveTracker;
+use dex::swap::SwapRecord;
+use eth_node::candle_aggregator::CandleAggregator;
+use eth_node::launch_watcher::{LaunchDetector, LaunchWatcher};
+use eth_node::market_watcher::MarketWatcher;
+use eth_node::mempool_tracker::{MempoolTracker, DEFAULT_DROP_AFTER};
+use eth_node::reserve_watcher::{ReserveWatcher, SwapSimulator};
+use eth_node::swap_watcher::SwapWatcher;
+use eth_node::token_resolver::{TokenResolver, DEFAULT_REFRESH_AFTER};
 use eth_node::{block_watcher::BlockWatcher, tx_pool::TxPool, tx_processor::TxProcessor};
-use ethers::types::{Block, Transaction, H256};
+use ethers::types::{Address, Block, Transaction, H256};
 use lazy_static::lazy_static;
 use log::{debug, info, warn};
-use settings::Settings;
+use poc_eth::publish;
+use poc_eth::registry::{load_registry, registry_backfill};
+use poc_eth::retention::retention_cleanup;
+use settings::{CacheBackend, DataClass, Settings};
 use std::sync::Arc;
-use storage::scylla::TXScyllaStorage;
-use storage::tx_storage::tx_store;
+use std::time::Duration;
+use storage::block_storage::block_store;
+use storage::engine::StorageEngine;
+use storage::lifecycle::{lifecycle_store, TxLifecycle};
+use storage::swap::swap_store;
+use storage::writer::{StorageWriter, WriterConfig};
 use tokio::sync::broadcast;
-use tokio::sync::Mutex;
+use tokio::sync::{oneshot, Mutex};
 
 lazy_static! {
     static ref SETTINGS: Settings =
@@ -20,22 +42,112 @@ lazy_static! {
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let settings = SETTINGS.clone();
+    let retention = settings.retention();
     // Setup logging
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(settings.log.level))
-        .init();
+    env_logger::Builder::from_env(
+        env_logger::Env::default().default_filter_or(settings.log.level.clone()),
+    )
+    .init();
 
-    // Create indexer instance
-    let indexer = block_explorer::etherscan::EtherscanBlockExplorer::new(settings.cache_path, None);
+    // Create indexer instance, ABIs without a retention policy are cached forever
+    let indexer = block_explorer::etherscan::EtherscanBlockExplorer::new(
+        settings.cache_path.clone(),
+        Some(
+            retention
+                .ttl(DataClass::AbiCache)
+                .map_or(usize::MAX, |ttl| ttl.as_secs() as usize),
+        ),
+    );
 
     // Get routers
-    let routers = dex::dex::load_dex_routers(indexer, settings.dex.routers)
+    let routers = dex::dex::load_dex_routers(indexer, settings.dex.routers.clone())
         .await
         .expect("Failed to load dex routers");
+    // Decoders of the routers and of the protocols that don't fit them
+    let protocols = ProtocolRegistry::new(
+        &routers,
+        settings.dex.protocols.as_deref().unwrap_or_default(),
+    )?;
+
+    // Launched tokens are paired with the configured tokens, their LP tokens locked in lockers
+    let base_tokens = settings
+        .dex
+        .tokens
+        .iter()
+        .map(|token| token.address.parse())
+        .collect::<Result<Vec<Address>, _>>()?;
+    let lockers = settings
+        .launch
+        .clone()
+        .unwrap_or_default()
+        .lockers
+        .unwrap_or_default()
+        .iter()
+        .map(|locker| locker.parse())
+        .collect::<Result<Vec<Address>, _>>()?;
+
+    // Token metadata, cached by the tx cache backend, to show amounts in whole tokens
+    let token_resolver = Arc::new(Mutex::new(TokenResolver::new(
+        settings.ethereum.node_http.clone(),
+        TokenCacheEngine::new(settings.cache_backend(), &settings.redis),
+        settings
+            .tokens
+            .as_ref()
+            .and_then(|tokens| tokens.refresh_after)
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_REFRESH_AFTER),
+    )?));
 
     // Create channels
     let (block_sender, _block_receiver) = broadcast::channel::<Block<H256>>(100);
     let (tx_pool_sender, tx_pool_receiver) = broadcast::channel::<Transaction>(100);
     let (tx_processor_sender, _tx_processor_receiver) = broadcast::channel::<Transaction>(100);
+    let (lifecycle_sender, _lifecycle_receiver) = broadcast::channel::<TxLifecycle>(1000);
+    let (swap_sender, _swap_receiver) = broadcast::channel::<SwapRecord>(1000);
+    let (decoded_sender, _decoded_receiver) = broadcast::channel::<DecodedCall>(1000);
+    let (market_sender, _market_receiver) = broadcast::channel::<NewMarket>(1000);
+    let (liquidity_sender, _liquidity_receiver) = broadcast::channel::<LiquidityEvent>(1000);
+
+    // Mempool lifecycle tracker, subscribed before the pool and blocks start flowing
+    let mempool_tracker = Arc::new(MempoolTracker::new(
+        settings.ethereum.node_ws.clone(),
+        routers.clone(),
+        settings
+            .mempool
+            .as_ref()
+            .and_then(|mempool| mempool.drop_after)
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_DROP_AFTER),
+        tx_pool_sender.subscribe(),
+        block_sender.subscribe(),
+        lifecycle_sender.clone(),
+    ));
+    let lifecycle_store_receiver = lifecycle_sender.subscribe();
+
+    // Swaps are decoded from pending txs by the processor and from mined ones by the watcher
+    let swap_watcher = Arc::new(SwapWatcher::new(
+        settings.ethereum.node_ws.clone(),
+        protocols.clone(),
+        block_sender.subscribe(),
+        swap_sender.clone(),
+        Some(token_resolver.clone()),
+    ));
+    let swap_store_receiver = swap_sender.subscribe();
+    let swap_publish_receiver = swap_sender.subscribe();
+
+    // Candles of the registered pairs, from the swap events of every block
+    let candle_block_receiver = block_sender.subscribe();
+
+    // Reserves of the registered V2 pairs, to simulate pending swaps
+    let reserve_block_receiver = block_sender.subscribe();
+
+    // New pairs and pools, pending from the processor and mined from the watcher
+    let market_block_receiver = block_sender.subscribe();
+    let market_publish_receiver = market_sender.subscribe();
+
+    // Launches and removals of liquidity, pending from the processor and mined from the watcher
+    let launch_block_receiver = block_sender.subscribe();
+    let liquidity_publish_receiver = liquidity_sender.subscribe();
 
     // TX Pool monitor
     let tx_pool = Arc::new(TxPool::new(
@@ -43,14 +155,10 @@ async fn main() -> anyhow::Result<()> {
         tx_pool_sender.clone(),
     ));
 
-    // TX Pool processor
-    let tx_pool_processor = Arc::new(TxProcessor::new(
-        tx_pool_receiver,
-        tx_processor_sender.clone(),
-        routers.clone(),
-    ));
+    let decoded_publish_receiver = decoded_sender.subscribe();
 
     // Block Creation Watcher
+    let block_store_receiver = block_sender.subscribe();
     let block_watcher = Arc::new(BlockWatcher {
         ws_url: Arc::new(settings.ethereum.node_ws.clone()),
         sender: Arc::new(block_sender),
@@ -59,38 +167,271 @@ async fn main() -> anyhow::Result<()> {
     // Spawn a task to process txs
 
     let tx_cache_receiver = Arc::new(tx_processor_sender.subscribe());
-    let tx_store_receiver = Arc::new(tx_processor_sender.subscribe());
+    let tx_store_receiver = tx_processor_sender.subscribe();
 
-    // Create Redis client
-    let cache = Arc::new(Mutex::new(TxCacheRedis::new(settings.redis.clone())));
+    // Create the tx cache for the configured backend
+    info!("Using {:?} tx cache", settings.cache_backend());
+    let cache_ttl = retention.ttl(DataClass::PendingTxs);
 
-    // Create tx storage
-    let tx_storage = Arc::new(Mutex::new(
-        TXScyllaStorage::new(settings.scylla.url, settings.scylla.keyspace, None, None).await?,
+    // Create storage for the configured backend
+    info!("Using {:?} storage", settings.storage_backend());
+    let writer = Arc::new(StorageWriter::new(
+        settings
+            .writer
+            .as_ref()
+            .map(WriterConfig::from)
+            .unwrap_or_default(),
+    ));
+    let storage = StorageEngine::new(&settings).await?;
+    let mut writer_storages = Vec::new();
+    for _ in 0..writer.config.workers {
+        writer_storages.push(storage.share(&settings).await?);
+    }
+    let block_storage = if storage.stores_blocks() {
+        Some(Arc::new(Mutex::new(storage.share(&settings).await?)))
+    } else {
+        None
+    };
+    let retention_storage = Arc::new(Mutex::new(storage.share(&settings).await?));
+    let lifecycle_storage = Arc::new(Mutex::new(storage.share(&settings).await?));
+    let swap_storage = Arc::new(Mutex::new(storage.share(&settings).await?));
+    let registry_storage = Arc::new(Mutex::new(storage.share(&settings).await?));
+    let registry = Arc::new(Mutex::new(
+        load_registry(
+            &mut *registry_storage.lock().await,
+            &settings.dex.tokens,
+            &mut *token_resolver.lock().await,
+        )
+        .await?,
+    ));
+    let market_storage = Arc::new(Mutex::new(storage.share(&settings).await?));
+    let candle_storage = Arc::new(Mutex::new(storage.share(&settings).await?));
+
+    // TX Pool processor, scoring pending swaps against the tracked reserves and pools
+    let reserves = Arc::new(Mutex::new(ReserveTracker::default()));
+    let reserve_watcher = ReserveWatcher::new(
+        settings.ethereum.node_ws.clone(),
+        registry.clone(),
+        reserves.clone(),
+        reserve_block_receiver,
+    );
+    let launch_detector = LaunchDetector::new(
+        settings.ethereum.node_http.clone(),
+        registry.clone(),
+        reserves.clone(),
+        base_tokens.clone(),
+        lockers.clone(),
+    )?;
+    let launch_watcher = LaunchWatcher::new(
+        settings.ethereum.node_ws.clone(),
+        routers.clone(),
+        registry.clone(),
+        base_tokens,
+        lockers,
+        launch_block_receiver,
+        liquidity_sender.clone(),
+    );
+    let tx_pool_processor = Arc::new(TxProcessor::new(
+        tx_pool_receiver,
+        tx_processor_sender.clone(),
+        swap_sender.clone(),
+        decoded_sender.clone(),
+        market_sender.clone(),
+        liquidity_sender,
+        routers.clone(),
+        protocols,
+        Some(SwapSimulator::new(
+            settings.ethereum.node_http.clone(),
+            registry.clone(),
+            reserves,
+            &routers,
+        )?),
+        Some(token_resolver),
+        Some(launch_detector),
     ));
 
+    let nats = publish::connect(&settings.nats).await;
+
     info!("Starting Sniper Bot...");
 
     // Spawn tasks
 
-    let tx_cache_handle = tokio::spawn(tx_cache_updates(cache, tx_cache_receiver));
-    // let tx_store_handle = tokio::spawn(tx_store(tx_storage, tx_store_receiver));
+    let tx_cache_handle = match settings.cache_backend() {
+        CacheBackend::Redis => tokio::spawn(tx_cache_updates(
+            Arc::new(Mutex::new(TxCacheRedis::new(
+                settings.redis.clone(),
+                cache_ttl,
+            ))),
+            tx_cache_receiver,
+        )),
+        CacheBackend::Memory => tokio::spawn(tx_cache_updates(
+            Arc::new(Mutex::new(MemoryCache::shared(cache_ttl))),
+            tx_cache_receiver,
+        )),
+    };
+    let (writer_shutdown, writer_shutdown_receiver) = oneshot::channel();
+    let tx_writer = writer.clone();
+    let tx_store_handle = tokio::spawn(async move {
+        tx_writer
+            .run(writer_storages, tx_store_receiver, writer_shutdown_receiver)
+            .await
+    });
+    let (block_store_shutdown, block_store_shutdown_receiver) = oneshot::channel();
+    let block_store_handle = block_storage.map(|block_storage| {
+        tokio::spawn(block_store(
+            block_storage,
+            block_store_receiver,
+            writer.clone(),
+            block_store_shutdown_receiver,
+        ))
+    });
+    let mempool_tracker_handle = tokio::spawn(async move { mempool_tracker.track().await });
+    let (lifecycle_store_shutdown, lifecycle_store_shutdown_receiver) = oneshot::channel();
+    let lifecycle_store_handle = tokio::spawn(lifecycle_store(
+        lifecycle_storage,
+        lifecycle_store_receiver,
+        writer.clone(),
+        lifecycle_store_shutdown_receiver,
+    ));
+    let swap_watcher_handle = tokio::spawn(async move {
+        if let Err(e) = swap_watcher.watch().await {
+            warn!("Swap watcher stopped: {}", e);
+        }
+    });
+    let (swap_store_shutdown, swap_store_shutdown_receiver) = oneshot::channel();
+    let swap_store_handle = tokio::spawn(swap_store(
+        swap_storage,
+        swap_store_receiver,
+        writer.clone(),
+        swap_store_shutdown_receiver,
+    ));
+    let decoded_publish_handle = nats.clone().map(|nats| {
+        tokio::spawn(publish::publish(
+            nats,
+            settings.nats.subject("calls"),
+            decoded_publish_receiver,
+        ))
+    });
+    let market_publish_handle = nats.clone().map(|nats| {
+        tokio::spawn(publish::publish(
+            nats,
+            settings.nats.subject("markets"),
+            market_publish_receiver,
+        ))
+    });
+    let liquidity_publish_handle = nats.clone().map(|nats| {
+        tokio::spawn(publish::publish(
+            nats,
+            settings.nats.subject("liquidity"),
+            liquidity_publish_receiver,
+        ))
+    });
+    let swap_publish_handle = nats.map(|nats| {
+        tokio::spawn(publish::publish(
+            nats,
+            settings.nats.subject("swaps"),
+            swap_publish_receiver,
+        ))
+    });
+    let registry_backfill_handle = tokio::spawn(registry_backfill(
+        registry.clone(),
+        registry_storage,
+        settings.ethereum.node_http.clone(),
+        routers
+            .iter()
+            .map(|router| router.factory.clone())
+            .collect(),
+        settings.registry.clone().unwrap_or_default(),
+    ));
+    let candle_aggregator = CandleAggregator::new(
+        settings.ethereum.node_ws.clone(),
+        registry.clone(),
+        candle_block_receiver,
+    );
+    let candle_handle =
+        tokio::spawn(async move { candle_aggregator.aggregate(candle_storage).await });
+    let market_watcher = MarketWatcher::new(
+        settings.ethereum.node_ws.clone(),
+        routers
+            .iter()
+            .map(|router| router.factory.clone())
+            .collect(),
+        registry.clone(),
+        market_block_receiver,
+        market_sender,
+    );
+    let market_handle = tokio::spawn(async move { market_watcher.watch(market_storage).await });
+    let reserve_watcher_handle = tokio::spawn(async move { reserve_watcher.watch().await });
+    let launch_watcher_handle = tokio::spawn(async move { launch_watcher.watch().await });
+    let retention_handle = tokio::spawn(retention_cleanup(
+        retention_storage,
+        retention,
+        settings.cache_path.clone(),
+    ));
     let tx_processor_handle = tokio::spawn(async move { tx_pool_processor.process().await });
     let tx_pool_handle = tokio::spawn(async move { tx_pool.watch().await });
     let block_watcher_handle = tokio::spawn(async move { block_watcher.watch().await });
 
-    // Wait for ctrl-c and abort all tasks
+    // Wait for ctrl-c, flush the storage writers and abort all other tasks
     tokio::signal::ctrl_c()
         .await
         .expect("Failed to listen for Ctrl+C");
 
-    info!("Received Ctrl+C, aborting tasks...");
+    info!("Received Ctrl+C, flushing storage and aborting tasks...");
+
+    // Every writer stops receiving, then writes what it already received
+    if writer_shutdown.send(()).is_err() {
+        warn!("Storage writers already stopped");
+    }
+    let _ = block_store_shutdown.send(());
+    let _ = lifecycle_store_shutdown.send(());
+    let _ = swap_store_shutdown.send(());
+
+    match tx_store_handle.await {
+        Ok(Ok(stats)) => debug!("Tx store updates exited: {:?}", stats),
+        Ok(Err(e)) => warn!("Tx store updates exited with error: {}", e),
+        Err(e) => warn!("Tx store updates exited with error: {}", e),
+    }
+    let record_store_handles = [
+        ("Block store", block_store_handle),
+        ("Tx lifecycle store", Some(lifecycle_store_handle)),
+        ("Swap store", Some(swap_store_handle)),
+    ];
+    for (name, handle) in record_store_handles {
+        let Some(handle) = handle else {
+            continue;
+        };
+        match handle.await {
+            Ok(Ok(())) => debug!("{} updates exited", name),
+            Ok(Err(e)) => warn!("{} updates exited with error: {}", name, e),
+            Err(e) => warn!("{} updates exited with error: {}", name, e),
+        }
+    }
 
     block_watcher_handle.abort();
     tx_pool_handle.abort();
     tx_processor_handle.abort();
     tx_cache_handle.abort();
-    // tx_store_handle.abort();
+    retention_handle.abort();
+    swap_watcher_handle.abort();
+    reserve_watcher_handle.abort();
+    launch_watcher_handle.abort();
+    candle_handle.abort();
+    market_handle.abort();
+    registry_backfill_handle.abort();
+    if let Some(swap_publish_handle) = swap_publish_handle {
+        swap_publish_handle.abort();
+    }
+    if let Some(decoded_publish_handle) = decoded_publish_handle {
+        decoded_publish_handle.abort();
+    }
+    if let Some(market_publish_handle) = market_publish_handle {
+        market_publish_handle.abort();
+    }
+    if let Some(liquidity_publish_handle) = liquidity_publish_handle {
+        liquidity_publish_handle.abort();
+    }
+    mempool_tracker_handle.abort();
 
     // Join threads and log errors
     tokio::select! {
@@ -100,12 +441,6 @@ async fn main() -> anyhow::Result<()> {
                 Err(e) => warn!("Tx cache updates exited with error: {}", e),
             }
         },
-        // res = tx_store_handle => {
-        //     match res {
-        //         Ok(_) => debug!("Tx store updates exited"),
-        //         Err(e) => warn!("Tx store updates exited with error: {}", e),
-        //     }
-        // },
         res = tx_processor_handle => {
             match res {
                 Ok(_) => debug!("Tx processor exited"),
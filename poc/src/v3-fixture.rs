@@ -0,0 +1,57 @@
+use anyhow::{anyhow, bail, Result};
+use dex::v3_pool::{swap_topic, SwapFixture};
+use ethers::providers::{Http, Middleware, Provider};
+use ethers::types::H256;
+use lazy_static::lazy_static;
+use settings::Settings;
+
+lazy_static! {
+    static ref SETTINGS: Settings =
+        Settings::new(String::from("sniper")).expect("Failed to load settings");
+}
+
+const USAGE: &str = "Usage: v3-fixture <tx hash> [--words <n>]";
+
+/// Words of the tick bitmap read on each side of the current tick when `--words` isn't given
+const DEFAULT_WORDS: i32 = 4;
+
+/// Record the V3 swaps of a mined tx as fixtures for `crates/dex/tests`, printed as JSON
+#[tokio::main]
+async fn main() -> Result<()> {
+    let settings = SETTINGS.clone();
+    // Setup logging
+    env_logger::Builder::from_env(
+        env_logger::Env::default().default_filter_or(settings.log.level.clone()),
+    )
+    .init();
+
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    let (tx_hash, words) = match args.as_slice() {
+        [tx_hash] => (tx_hash, DEFAULT_WORDS),
+        [tx_hash, flag, words] if flag == "--words" => (tx_hash, words.parse()?),
+        _ => bail!(USAGE),
+    };
+    let tx_hash = tx_hash.parse::<H256>()?;
+
+    let provider = Provider::<Http>::try_from(settings.ethereum.node_http.clone())?;
+    let receipt = provider
+        .get_transaction_receipt(tx_hash)
+        .await?
+        .ok_or_else(|| anyhow!("Tx {:?} is not mined", tx_hash))?;
+
+    let mut fixtures = Vec::new();
+    for log in receipt
+        .logs
+        .iter()
+        .filter(|log| log.topics.first() == Some(&swap_topic()))
+    {
+        fixtures.push(SwapFixture::record(&provider, log, words).await?);
+    }
+    if fixtures.is_empty() {
+        bail!("Tx {:?} has no V3 swap", tx_hash);
+    }
+
+    println!("{}", serde_json::to_string_pretty(&fixtures)?);
+
+    Ok(())
+}
@@ -1,5 +1,11 @@
+use std::collections::HashSet;
+use std::str::FromStr;
+
 use anyhow::Result;
 use ethers::types::{Block, Transaction, H256};
+use log::warn;
+use scylla::batch::{Batch, BatchType};
+use scylla::frame::value::ValueList;
 use scylla::{Session, SessionBuilder};
 
 //pub mod engine;
@@ -25,10 +31,12 @@ pub async fn init_blocks_session() -> Result<Session> {
     // Create eth keyspace
     create_database(&session).await?;
 
-    // Create blocks table based on ethers-rs Block type
+    // Create blocks table based on ethers-rs Block type, every block is kept, orphans included.
+    // `eth.blocks` kept one block per number under its shortened hash, its rows can't be keyed by
+    // the full hash so it's left as it is and blocks go to a table of their own
     session
         .query(
-            "CREATE TABLE IF NOT EXISTS eth.blocks (
+            "CREATE TABLE IF NOT EXISTS eth.blocks_by_hash (
                 number bigint,
                 hash text,
                 parent_hash text,
@@ -48,6 +56,18 @@ pub async fn init_blocks_session() -> Result<Session> {
                 timestamp bigint,
                 transactions list<text>,
                 uncles list<text>,
+                PRIMARY KEY (hash)
+            );",
+            (),
+        )
+        .await?;
+
+    // Hash of the canonical block at each height, blocks that lose their height are orphans
+    session
+        .query(
+            "CREATE TABLE IF NOT EXISTS eth.canonical_blocks (
+                number bigint,
+                hash text,
                 PRIMARY KEY (number)
             );",
             (),
@@ -61,7 +81,7 @@ pub async fn store_block(session: &Session, block: Block<H256>) -> Result<()> {
     // Store the block in the database using the ethers-rs Block type
     session
         .query(
-            "INSERT INTO eth.blocks (
+            "INSERT INTO eth.blocks_by_hash (
                 number,
                 hash,
                 parent_hash,
@@ -81,8 +101,8 @@ pub async fn store_block(session: &Session, block: Block<H256>) -> Result<()> {
             ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
             (
                 block.number.unwrap_or_default().as_usize() as i64,
-                block.hash.unwrap().to_string(),
-                block.parent_hash.to_string(),
+                hash_key(block.hash.unwrap()),
+                hash_key(block.parent_hash),
                 block.nonce.unwrap().to_string(),
                 block.uncles_hash.to_string(),
                 block.logs_bloom.unwrap().to_string(),
@@ -97,20 +117,236 @@ pub async fn store_block(session: &Session, block: Block<H256>) -> Result<()> {
                 block
                     .transactions
                     .iter()
-                    .map(|tx| tx.to_string())
+                    .map(|tx| hash_key(*tx))
                     .collect::<Vec<String>>(),
                 block
                     .uncles
                     .iter()
-                    .map(|uncle| uncle.to_string())
+                    .map(|uncle| hash_key(*uncle))
                     .collect::<Vec<String>>(),
             ),
         )
         .await?;
 
+    adopt_block(session, &block).await?;
+
     Ok(())
 }
 
+/// Key of a block or a tx, the full hash
+fn hash_key(hash: H256) -> String {
+    format!("{:?}", hash)
+}
+
+/// Most keys Scylla accepts in a single `IN` restriction
+const MAX_IN_KEYS: usize = 100;
+
+/// Mark `head` canonical along with the stored ancestors it brings back, orphaning the blocks
+/// it replaces and the canonical blocks above it
+async fn adopt_block(session: &Session, head: &Block<H256>) -> Result<()> {
+    let number = head.number.unwrap_or_default().as_u64();
+    let hash = hash_key(head.hash.unwrap());
+
+    let replaces = canonical_block_hash(session, number)
+        .await?
+        .is_some_and(|canonical| canonical != hash);
+    let forks = number > 0
+        && canonical_block_hash(session, number - 1)
+            .await?
+            .is_some_and(|parent| parent != hash_key(head.parent_hash));
+    if replaces || forks {
+        let mut above = number + 1;
+        while let Some(orphan) = canonical_block_hash(session, above).await? {
+            warn!("Reorg to {} drops block {} ({})", hash, above, orphan);
+
+            orphan_block(session, &orphan, &[]).await?;
+            session
+                .query(
+                    "DELETE FROM eth.canonical_blocks WHERE number = ?",
+                    (above as i64,),
+                )
+                .await?;
+            above += 1;
+        }
+    }
+
+    let mut current = Some((number, hash, head.parent_hash, head.transactions.clone()));
+    while let Some((number, hash, parent_hash, transactions)) = current {
+        match canonical_block_hash(session, number).await? {
+            Some(canonical) if canonical == hash => break,
+            Some(orphan) => {
+                warn!("Reorg at block {}: {} replaces {}", number, hash, orphan);
+                orphan_block(session, &orphan, &transactions).await?;
+            }
+            None => {}
+        }
+
+        session
+            .query(
+                "INSERT INTO eth.canonical_blocks (number, hash) VALUES (?, ?)",
+                (number as i64, &hash),
+            )
+            .await?;
+        include_txs(session, &hash, number, &transactions).await?;
+
+        current = match number {
+            0 => None,
+            _ => stored_block(session, parent_hash).await?,
+        };
+    }
+
+    Ok(())
+}
+
+/// Number, hash, parent hash and txs of a stored block
+async fn stored_block(
+    session: &Session,
+    hash: H256,
+) -> Result<Option<(u64, String, H256, Vec<H256>)>> {
+    let row = session
+        .query(
+            "SELECT number, parent_hash, transactions FROM eth.blocks_by_hash WHERE hash = ?",
+            (hash_key(hash),),
+        )
+        .await?
+        .maybe_first_row_typed::<(i64, String, Option<Vec<String>>)>()?;
+    let Some((number, parent_hash, transactions)) = row else {
+        return Ok(None);
+    };
+
+    let transactions = transactions
+        .unwrap_or_default()
+        .iter()
+        .map(|tx| H256::from_str(tx))
+        .collect::<Result<Vec<H256>, _>>()?;
+
+    Ok(Some((
+        number as u64,
+        hash_key(hash),
+        H256::from_str(&parent_hash)?,
+        transactions,
+    )))
+}
+
+/// Clear the inclusion of the txs of `orphan` that still point at it, except the ones `kept` by
+/// the block replacing it
+async fn orphan_block(session: &Session, orphan: &str, kept: &[H256]) -> Result<()> {
+    let orphaned = session
+        .query(
+            "SELECT transactions FROM eth.blocks_by_hash WHERE hash = ?",
+            (orphan,),
+        )
+        .await?
+        .maybe_first_row_typed::<(Option<Vec<String>>,)>()?
+        .and_then(|(transactions,)| transactions)
+        .unwrap_or_default()
+        .iter()
+        .map(|tx| H256::from_str(tx))
+        .collect::<Result<Vec<H256>, _>>()?
+        .into_iter()
+        .filter(|tx| !kept.contains(tx))
+        .collect::<Vec<H256>>();
+
+    let included = stored_txs(session, &orphaned)
+        .await?
+        .into_iter()
+        .filter(|(_, block_hash)| block_hash.as_deref() == Some(orphan))
+        .map(|(tx, _)| (tx,))
+        .collect::<Vec<(String,)>>();
+
+    run_batch(
+        session,
+        "DELETE block_hash, block_number, transaction_index
+        FROM eth.transactions_by_hash WHERE hash = ?",
+        included,
+    )
+    .await
+}
+
+/// Point the stored txs of a canonical block at it, txs that weren't stored aren't created with
+/// only their inclusion
+async fn include_txs(
+    session: &Session,
+    hash: &str,
+    number: u64,
+    transactions: &[H256],
+) -> Result<()> {
+    let stored = stored_txs(session, transactions)
+        .await?
+        .into_iter()
+        .map(|(tx, _)| tx)
+        .collect::<HashSet<String>>();
+
+    let updates = transactions
+        .iter()
+        .enumerate()
+        .map(|(index, tx)| (index, hash_key(*tx)))
+        .filter(|(_, tx)| stored.contains(tx))
+        .map(|(index, tx)| (hash, number as i64, index as i64, tx))
+        .collect::<Vec<_>>();
+
+    run_batch(
+        session,
+        "UPDATE eth.transactions_by_hash
+        SET block_hash = ?, block_number = ?, transaction_index = ?
+        WHERE hash = ?",
+        updates,
+    )
+    .await
+}
+
+/// Keys and block hashes of the stored txs among `hashes`
+async fn stored_txs(session: &Session, hashes: &[H256]) -> Result<Vec<(String, Option<String>)>> {
+    let mut stored = Vec::new();
+    for chunk in hashes.chunks(MAX_IN_KEYS) {
+        let keys = chunk
+            .iter()
+            .map(|tx| hash_key(*tx))
+            .collect::<Vec<String>>();
+        let rows = session
+            .query(
+                "SELECT hash, block_hash FROM eth.transactions_by_hash WHERE hash IN ?",
+                (keys,),
+            )
+            .await?
+            .rows_typed_or_empty::<(String, Option<String>)>()
+            .collect::<Result<Vec<_>, _>>()?;
+        stored.extend(rows);
+    }
+
+    Ok(stored)
+}
+
+/// Run `statement` once for each of `values` in unlogged batches
+async fn run_batch<V: ValueList>(session: &Session, statement: &str, values: Vec<V>) -> Result<()> {
+    let mut values = values.into_iter().peekable();
+    while values.peek().is_some() {
+        let chunk = values.by_ref().take(MAX_IN_KEYS).collect::<Vec<V>>();
+
+        let mut batch = Batch::new(BatchType::Unlogged);
+        for _ in &chunk {
+            batch.append_statement(statement);
+        }
+        session.batch(&batch, chunk).await?;
+    }
+
+    Ok(())
+}
+
+/// Hash of the canonical block at `number`, if one was stored
+pub async fn canonical_block_hash(session: &Session, number: u64) -> Result<Option<String>> {
+    let hash = session
+        .query(
+            "SELECT hash FROM eth.canonical_blocks WHERE number = ?",
+            (number as i64,),
+        )
+        .await?
+        .maybe_first_row_typed::<(String,)>()?
+        .map(|(hash,)| hash);
+
+    Ok(hash)
+}
+
 pub async fn init_tx_session() -> Result<Session> {
     // Get the SCYLLA_URI from the environment
     let scylla_uri = std::env::var("SCYLLA_URI")
@@ -120,10 +356,11 @@ pub async fn init_tx_session() -> Result<Session> {
     // Create eth keyspace
     create_database(&session).await?;
 
-    // Create transactions table
+    // Create transactions table. `eth.transactions` kept txs under their shortened hash, which
+    // can't be turned back into the full one, so it's left as it is
     session
         .query(
-            "CREATE TABLE IF NOT EXISTS eth.transactions (
+            "CREATE TABLE IF NOT EXISTS eth.transactions_by_hash (
                 hash text,
                 nonce bigint,
                 block_hash text,
@@ -148,7 +385,7 @@ pub async fn store_tx(session: &Session, tx: Transaction) -> Result<()> {
     // Store the transaction in the database
     session
         .query(
-            "INSERT INTO eth.transactions (
+            "INSERT INTO eth.transactions_by_hash (
                 hash,
                 nonce,
                 block_hash,
@@ -162,9 +399,9 @@ pub async fn store_tx(session: &Session, tx: Transaction) -> Result<()> {
                 input
             ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
             (
-                tx.hash.to_string(),
+                hash_key(tx.hash),
                 tx.nonce.as_usize() as i64,
-                tx.block_hash.unwrap_or_default().to_string(),
+                hash_key(tx.block_hash.unwrap_or_default()),
                 tx.block_number.unwrap_or_default().as_usize() as i64,
                 tx.transaction_index.unwrap_or_default().as_usize() as i64,
                 tx.from.to_string(),